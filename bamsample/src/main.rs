@@ -0,0 +1,371 @@
+use anyhow::{self, bail, Context, Result};
+use clap::{ArgGroup, Parser};
+use htslite::{AlignmentReader, AlignmentWriter, Record, FLAG_PAIRED};
+use progress::{info, warn, LogArgs, Progress};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let mut reader = AlignmentReader::open(args.i.as_deref())?;
+    let mut writer = AlignmentWriter::create(args.o.as_deref(), reader.format(), reader.header())?;
+
+    let sampler = match (args.fraction, args.depth) {
+        (Some(f), None) => {
+            if !(0.0..=1.0).contains(&f) {
+                bail!("Fraction must be in 0-1, got {}", f);
+            }
+            Sampler::Fraction(f)
+        }
+        (None, Some(d)) => {
+            if !(d.is_finite() && d > 0.0) {
+                bail!("Target depth must be a positive number, got {}", d);
+            }
+            Sampler::Depth(DepthEstimator::new(
+                d,
+                reader
+                    .header()
+                    .references
+                    .iter()
+                    .map(|r| r.length)
+                    .collect(),
+            ))
+        }
+        // Clap makes sure exactly one is passed
+        _ => unreachable!(),
+    };
+
+    let mut subsampler = Subsampler::new(sampler, args.seed, args.max_names);
+    let mut record = Record::default();
+    let mut n_out = 0usize;
+    let progress = Progress::new("Sampling", "records", None);
+    while reader.read_record(&mut record)? {
+        progress.inc(1);
+        if subsampler.keep(&record)? {
+            n_out += 1;
+            writer
+                .write_record(&record)
+                .context("Failed to write record")?;
+        }
+    }
+    writer.finish()?;
+    progress.finish();
+    info!("Kept {} of {} records", n_out, progress.position());
+    if let Sampler::Depth(estimator) = &subsampler.sampler {
+        info!("Estimated input depth: {:.2}", estimator.depth());
+    }
+    if subsampler.n_forgotten > 0 {
+        warn!(
+            "{} paired records were sampled without knowing the decision for their mate, \
+            which was placed before them. If the mates are in the input, increase \
+            --max-names to keep them together",
+            subsampler.n_forgotten
+        );
+    }
+    Ok(())
+}
+
+// Decides which records to keep. In depth mode, the fraction changes as we go,
+// so to keep mates and secondary alignments together, the decision for each read
+// name is remembered until `max_names` newer names have been seen.
+struct Subsampler {
+    sampler: Sampler,
+    seed: u64,
+    decisions: HashMap<Vec<u8>, bool>,
+    // Names in `decisions`, oldest first
+    order: VecDeque<Vec<u8>>,
+    max_names: usize,
+    // Number of paired records whose mate came first, but was not remembered
+    n_forgotten: usize,
+}
+
+impl Subsampler {
+    fn new(sampler: Sampler, seed: u64, max_names: usize) -> Self {
+        Self {
+            sampler,
+            seed,
+            decisions: HashMap::new(),
+            order: VecDeque::new(),
+            max_names,
+            n_forgotten: 0,
+        }
+    }
+
+    fn keep(&mut self, record: &Record) -> Result<bool> {
+        let estimator = match &mut self.sampler {
+            Sampler::Fraction(f) => return Ok(sample_value(&record.qname, self.seed) < *f),
+            Sampler::Depth(estimator) => estimator,
+        };
+        let fraction = estimator.update(record)?;
+        if let Some(&keep) = self.decisions.get(&record.qname) {
+            return Ok(keep);
+        }
+        if record.has_flag(FLAG_PAIRED)
+            && !record.is_non_primary()
+            && matches!(
+                (record.next_ref_id, record.next_pos, record.ref_id, record.pos),
+                (Some(mate_ref), Some(mate_pos), Some(ref_id), Some(pos)) if (mate_ref, mate_pos) < (ref_id, pos)
+            )
+        {
+            self.n_forgotten += 1;
+        }
+        let keep = sample_value(&record.qname, self.seed) < fraction;
+        if self.order.len() == self.max_names {
+            if let Some(oldest) = self.order.pop_front() {
+                self.decisions.remove(&oldest);
+            }
+        }
+        if self.max_names > 0 {
+            self.decisions.insert(record.qname.clone(), keep);
+            self.order.push_back(record.qname.clone());
+        }
+        Ok(keep)
+    }
+}
+
+enum Sampler {
+    Fraction(f64),
+    Depth(DepthEstimator),
+}
+
+// Map the read name to a number in [0, 1). Since the value only depends on the
+// name and seed, both mates (and any secondary alignments) get the same value,
+// and the subsampling is reproducible.
+fn sample_value(name: &[u8], seed: u64) -> f64 {
+    // FNV-1a, followed by the splitmix64 finalizer to spread the bits
+    let mut h: u64 = 0xcbf29ce484222325 ^ seed;
+    for &b in name {
+        h = (h ^ b as u64).wrapping_mul(0x100000001b3);
+    }
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^= h >> 31;
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Estimates the mean depth of the file from the reads seen so far in a coordinate
+// sorted file, as the number of aligned bases divided by the number of reference
+// positions passed.
+struct DepthEstimator {
+    target: f64,
+    lengths: Vec<u64>,
+    // Number of references entirely passed, and their summed length
+    done_refs: usize,
+    done_length: u64,
+    last: Option<(usize, u32)>,
+    bases: u64,
+}
+
+impl DepthEstimator {
+    fn new(target: f64, lengths: Vec<u64>) -> Self {
+        Self {
+            target,
+            lengths,
+            done_refs: 0,
+            done_length: 0,
+            last: None,
+            bases: 0,
+        }
+    }
+
+    fn positions(&self) -> u64 {
+        self.done_length + self.last.map_or(0, |(_, pos)| pos as u64 + 1)
+    }
+
+    fn depth(&self) -> f64 {
+        self.bases as f64 / self.positions().max(1) as f64
+    }
+
+    // Returns the fraction of reads to keep at this point
+    fn update(&mut self, record: &Record) -> Result<f64> {
+        // Unmapped reads are placed at the end of sorted files (or next to their mate)
+        if let (Some(ref_id), Some(pos)) = (record.ref_id, record.pos) {
+            if self.last.is_some_and(|last| last > (ref_id, pos)) {
+                bail!("Input must be coordinate sorted when using --depth");
+            }
+            if ref_id >= self.done_refs {
+                self.done_length += self.lengths[self.done_refs..ref_id].iter().sum::<u64>();
+                self.done_refs = ref_id;
+            }
+            self.last = Some((ref_id, pos));
+            if !(record.is_unmapped() || record.is_non_primary()) {
+                self.bases += record
+                    .aligned_blocks()
+                    .map(|(start, stop)| (stop - start) as u64)
+                    .sum::<u64>();
+            }
+        }
+        Ok((self.target / self.depth()).min(1.0))
+    }
+}
+
+const LONG_ABOUT: &str =
+    "Subsample a SAM or BAM file to a fraction of reads or a target mean depth.
+Whether a read is kept is decided from a hash of its name, so mates and secondary
+alignments are kept or discarded together, and the output is reproducible for a given seed.
+The output has the same format (SAM, gzipped SAM or BAM) as the input.
+Usage: bamsample -i in.bam -o out.bam --fraction 0.1";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+#[command(group(ArgGroup::new("target").required(true).args(["fraction", "depth"])))]
+struct Cli {
+    /// Input SAM or BAM file [stdin]
+    #[arg(short)]
+    i: Option<PathBuf>,
+
+    /// Output file [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    /// Fraction of reads to keep
+    #[arg(short, long)]
+    fraction: Option<f64>,
+
+    /// Target mean depth. Requires coordinate sorted input
+    #[arg(short, long)]
+    depth: Option<f64>,
+
+    /// Seed for the subsampling
+    #[arg(short, long, default_value_t = 0)]
+    seed: u64,
+
+    /// With --depth, the number of most recent read names to remember whether
+    /// they were kept, so their mates and secondary alignments are kept with them
+    #[arg(long, default_value_t = 1_000_000)]
+    max_names: usize,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
+
+#[cfg(test)]
+mod test_bamsample {
+    use crate::{DepthEstimator, Sampler, Subsampler};
+    use htslite::{AlignmentReader, Record};
+    use std::collections::HashMap;
+
+    // Pairs of 100 bp reads every 10 bp on a 10 kbp reference, with 300 bp inserts,
+    // so the depth is 20. Every fifth read also has a secondary alignment after its mates.
+    fn pairs() -> Vec<Record> {
+        let mut lines = Vec::new();
+        for i in 0..950u32 {
+            let (pos, mate_pos) = (10 * i + 1, 10 * i + 201);
+            let pair = format!("=\t{}\t300\t*\t*", mate_pos);
+            lines.push((pos, format!("r{}\t99\tc1\t{}\t60\t100M\t{}", i, pos, pair)));
+            let pair = format!("=\t{}\t-300\t*\t*", pos);
+            lines.push((
+                mate_pos,
+                format!("r{}\t147\tc1\t{}\t60\t100M\t{}", i, mate_pos, pair),
+            ));
+            if i % 5 == 0 {
+                let pos = mate_pos + 50;
+                lines.push((
+                    pos,
+                    format!("r{}\t355\tc1\t{}\t0\t100M\t*\t0\t0\t*\t*", i, pos),
+                ));
+            }
+        }
+        lines.sort_by_key(|(pos, _)| *pos);
+        let mut sam = String::from("@SQ\tSN:c1\tLN:10000\n");
+        for (_, line) in lines {
+            sam.push_str(&line);
+            sam.push('\n');
+        }
+        let mut reader = AlignmentReader::new(Box::new(std::io::Cursor::new(sam))).unwrap();
+        let mut records = Vec::new();
+        let mut record = Record::default();
+        while reader.read_record(&mut record).unwrap() {
+            records.push(record.clone());
+        }
+        records
+    }
+
+    // Returns the number of kept records, and the decision for each name
+    fn sample(subsampler: &mut Subsampler, records: &[Record]) -> (usize, HashMap<Vec<u8>, bool>) {
+        let mut n_kept = 0;
+        let mut decisions: HashMap<Vec<u8>, bool> = HashMap::new();
+        for record in records {
+            let keep = subsampler.keep(record).unwrap();
+            n_kept += keep as usize;
+            let decision = decisions.entry(record.qname.clone()).or_insert(keep);
+            assert_eq!(
+                *decision,
+                keep,
+                "{:?}",
+                String::from_utf8_lossy(&record.qname)
+            );
+        }
+        (n_kept, decisions)
+    }
+
+    fn depth_sampler(target: f64) -> Sampler {
+        Sampler::Depth(DepthEstimator::new(target, vec![10000]))
+    }
+
+    #[test]
+    fn test_pairs() {
+        let records = pairs();
+        assert_eq!(records.len(), 2090);
+        let (_, decisions) = sample(&mut Subsampler::new(Sampler::Fraction(0.3), 1, 0), &records);
+        let n_kept = decisions.values().filter(|&&k| k).count();
+        assert!((200..370).contains(&n_kept), "{}", n_kept);
+        // Same seed, same result, and a different seed gives a different result
+        let (_, again) = sample(&mut Subsampler::new(Sampler::Fraction(0.3), 1, 0), &records);
+        assert_eq!(decisions, again);
+        let (_, other) = sample(&mut Subsampler::new(Sampler::Fraction(0.3), 2, 0), &records);
+        assert_ne!(decisions, other);
+
+        let (n_kept, _) = sample(&mut Subsampler::new(Sampler::Fraction(1.0), 1, 0), &records);
+        assert_eq!(n_kept, records.len());
+        let (n_kept, _) = sample(&mut Subsampler::new(Sampler::Fraction(0.0), 1, 0), &records);
+        assert_eq!(n_kept, 0);
+    }
+
+    #[test]
+    fn test_secondary() {
+        // The decisions of the mates and the secondary alignment of each read agree,
+        // even though the fraction changes while reading
+        let records = pairs();
+        let mut subsampler = Subsampler::new(depth_sampler(5.0), 0, 1000);
+        sample(&mut subsampler, &records);
+        assert_eq!(subsampler.n_forgotten, 0);
+        assert!(subsampler.decisions.len() <= 1000);
+    }
+
+    #[test]
+    fn test_depth() {
+        let records = pairs();
+        let mut subsampler = Subsampler::new(depth_sampler(5.0), 0, 1000);
+        let (_, decisions) = sample(&mut subsampler, &records);
+        let Sampler::Depth(estimator) = &subsampler.sampler else {
+            unreachable!()
+        };
+        assert!(
+            (estimator.depth() - 19.0).abs() < 1.0,
+            "{}",
+            estimator.depth()
+        );
+        // About a quarter of the pairs are kept
+        let n_kept = decisions.values().filter(|&&k| k).count();
+        assert!((180..300).contains(&n_kept), "{}", n_kept);
+
+        // Only the last 10 names are remembered, so the mates 20 pairs apart
+        // are sampled without knowing the decision for the first mate
+        let mut subsampler = Subsampler::new(depth_sampler(5.0), 0, 10);
+        for record in records.iter() {
+            subsampler.keep(record).unwrap();
+            assert!(subsampler.decisions.len() <= 10);
+            assert_eq!(subsampler.decisions.len(), subsampler.order.len());
+        }
+        assert_eq!(subsampler.n_forgotten, 950);
+
+        // Records must be sorted
+        let mut subsampler = Subsampler::new(depth_sampler(5.0), 0, 20);
+        subsampler.keep(&records[1]).unwrap();
+        assert!(subsampler.keep(&records[0]).is_err());
+    }
+}
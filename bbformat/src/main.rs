@@ -0,0 +1,686 @@
+use anyhow::{self, bail, Context, Result};
+use bio::io::fasta::Reader;
+use clap::{Parser, ValueEnum};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use progress::{error, info, warn, LogArgs, Progress};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, stdin, stdout, BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+const LONG_ABOUT: &str = "Convert a Vamb .tsv output binning file to CAMI Bioboxes binning format,
+which is used as input to AMBER. Each line of the input has a bin and a contig name, separated by
+a tab, optionally with the header clustername, contigname.
+The output of other binners, like MetaBAT2, can be converted with --input-format.
+Usage: bbformat -i vamb_clusters.tsv -o amber.binning";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Clusters file (may be gzipped), or directory of bins with --input-format fasta-dir [stdin]
+    #[arg(short)]
+    i: Option<PathBuf>,
+
+    /// Format of the input
+    #[arg(long, value_enum, default_value_t = InputFormat::Vamb)]
+    input_format: InputFormat,
+
+    /// Output file, gzipped if the name ends with .gz [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    /// The @SampleID of the output
+    #[arg(long, default_value = "all", conflicts_with = "separator")]
+    sample_id: String,
+
+    /// The @Version of the Bioboxes format
+    #[arg(long, default_value = "0.9.1")]
+    format_version: String,
+
+    /// Write the bin ID in the first column and the contig name in the second,
+    /// with the header @@BINID SEQUENCEID
+    #[arg(long)]
+    bin_first: bool,
+
+    /// Contig names are prefixed by their sample, like S1C12 with the separator C.
+    /// Write one Bioboxes section per sample, with its @SampleID, instead of a single
+    /// section for all samples. The contigs keep their full names
+    #[arg(long)]
+    separator: Option<String>,
+
+    /// TSV file (may be gzipped) mapping contig or bin names to a taxonomic ID, written in a TAXID column.
+    /// Contig names are looked up first, then bin names
+    #[arg(long, value_name = "FILE")]
+    taxonomy: Option<PathBuf>,
+
+    /// Assembly FASTA file (may be gzipped). Check that every contig is in the assembly
+    #[arg(long, value_name = "FASTA", conflicts_with = "lengths")]
+    contigs: Option<PathBuf>,
+
+    /// TSV file (may be gzipped) with the name and length of each contig, used instead of --contigs.
+    /// Check that every contig is in the file
+    #[arg(long, value_name = "FILE")]
+    lengths: Option<PathBuf>,
+
+    /// Check that no contig is in more than one bin, or listed twice
+    #[arg(long)]
+    unique_contigs: bool,
+
+    /// Leave out bins with fewer contigs
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    min_bin_contigs: usize,
+
+    /// Leave out bins with fewer base pairs. Requires --contigs or --lengths
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_bin_bp: u64,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum InputFormat {
+    /// TSV file with a bin and a contig name on each line
+    Vamb,
+    /// TSV file with a contig and a bin name on each line, as written by MetaBAT2 --saveCls
+    Metabat,
+    /// Directory with a FASTA file of the contigs of each bin, e.g. bin.1.fa
+    FastaDir,
+}
+
+// The contigs of a sample, with the bin of each
+struct Sample {
+    name: String,
+    rows: Vec<(String, String)>,
+}
+
+// Read the contig and bin names from a TSV file with a bin and a contig name on each line,
+// or a contig and a bin name if `contig_first` is set.
+// Vamb's header clustername, contigname is skipped.
+fn read_tsv(reader: impl BufRead, contig_first: bool) -> Result<Vec<(String, String)>> {
+    let mut rows = Vec::new();
+    for (lineno, line) in reader.lines().enumerate() {
+        // Note that the output format MUST be UTF8 per the specs, and so must the input also be.
+        let line = line.with_context(|| format!("Could not read line {} as UTF-8", lineno + 1))?;
+        // Skip the header if it exists, and empty lines
+        if line.is_empty() || lineno == 0 && line == "clustername\tcontigname" {
+            continue;
+        }
+        let Some((first, second)) = line.split_once('\t') else {
+            bail!("Expected a tab character on line {}", lineno + 1);
+        };
+        if second.contains('\t') {
+            bail!("Line {} has more than two tab-separated fields", lineno + 1);
+        }
+        let (contig, cluster) = if contig_first {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        rows.push((contig.to_owned(), cluster.to_owned()));
+    }
+    Ok(rows)
+}
+
+// Read the contig and bin names from a directory with a FASTA file per bin, as written by
+// MetaBAT2 and MaxBin2. The bin is named after its file, without the extension.
+fn read_bin_dir(dir: &Path) -> Result<Vec<(String, String)>> {
+    let mut bins = Vec::new();
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Could not read directory \"{}\"", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        // FASTA files may be gzipped
+        let name = Path::new(name.strip_suffix(".gz").unwrap_or(name));
+        let Some(bin) = name.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if datafiles::is_fasta(name) && path.is_file() {
+            bins.push((bin.to_owned(), path));
+        }
+    }
+    if bins.is_empty() {
+        bail!("No FASTA files found in directory \"{}\"", dir.display());
+    }
+    // The order of directory entries is arbitrary
+    bins.sort_unstable();
+    let mut rows = Vec::new();
+    for (bin, path) in bins {
+        for record in Reader::from_bufread(open_input(&path)?).records() {
+            let record = record.with_context(|| {
+                format!(
+                    "Failed to parse record from FASTA file \"{}\"",
+                    path.display()
+                )
+            })?;
+            rows.push((record.id().to_owned(), bin.clone()));
+        }
+    }
+    Ok(rows)
+}
+
+// Group the contigs by sample, in the order the samples are first seen.
+// Without a separator, all contigs are in one sample, named `sample_id`.
+fn group_samples(
+    rows: Vec<(String, String)>,
+    separator: Option<&str>,
+    sample_id: &str,
+) -> Result<Vec<Sample>> {
+    let Some(sep) = separator else {
+        return Ok(vec![Sample {
+            name: sample_id.to_owned(),
+            rows,
+        }]);
+    };
+    let mut samples: Vec<Sample> = Vec::new();
+    let mut sample_index: HashMap<String, usize> = HashMap::new();
+    for (contig, cluster) in rows {
+        let Some((sample, _)) = contig.split_once(sep) else {
+            bail!(
+                "Contig name {} does not contain the separator {}",
+                contig,
+                sep
+            );
+        };
+        let index = *sample_index.entry(sample.to_owned()).or_insert_with(|| {
+            samples.push(Sample {
+                name: sample.to_owned(),
+                rows: Vec::new(),
+            });
+            samples.len() - 1
+        });
+        samples[index].rows.push((contig, cluster));
+    }
+    Ok(samples)
+}
+
+// Read the taxonomic ID of each contig or bin name. Lines have a name and an ID, separated
+// by a tab. A header line is read like any other, but does not match any contig or bin.
+fn read_taxonomy(reader: impl BufRead) -> Result<HashMap<String, String>> {
+    let mut taxonomy = HashMap::new();
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line
+            .with_context(|| format!("Could not read line {} of taxonomy as UTF-8", lineno + 1))?;
+        if line.is_empty() {
+            continue;
+        }
+        let Some((name, taxid)) = line.split_once('\t') else {
+            bail!(
+                "Expected a tab character on line {} of taxonomy",
+                lineno + 1
+            );
+        };
+        if taxonomy.insert(name.to_owned(), taxid.to_owned()).is_some() {
+            bail!("Name {} occurs more than once in taxonomy", name);
+        }
+    }
+    Ok(taxonomy)
+}
+
+// Write the samples. With a taxonomy, contigs with no taxonomic ID, and whose bin has
+// none either, get an empty TAXID, and the number of such contigs is returned.
+fn write_bioboxes(
+    out: &mut impl Write,
+    samples: &[Sample],
+    format_version: &str,
+    bin_first: bool,
+    taxonomy: Option<&HashMap<String, String>>,
+) -> io::Result<usize> {
+    let mut n_missing = 0;
+    for (i, sample) in samples.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        let columns = if bin_first {
+            "BINID\tSEQUENCEID"
+        } else {
+            "SEQUENCEID\tBINID"
+        };
+        let taxid_column = if taxonomy.is_some() { "\tTAXID" } else { "" };
+        write!(
+            out,
+            "@Version:{}\n@SampleID:{}\n\n@@{}{}\n",
+            format_version, sample.name, columns, taxid_column
+        )?;
+        for (contig, cluster) in &sample.rows {
+            if bin_first {
+                write!(out, "{}\t{}", cluster, contig)?;
+            } else {
+                write!(out, "{}\t{}", contig, cluster)?;
+            }
+            if let Some(taxonomy) = taxonomy {
+                let taxid = taxonomy.get(contig).or_else(|| taxonomy.get(cluster));
+                if taxid.is_none() {
+                    n_missing += 1;
+                }
+                write!(out, "\t{}", taxid.map_or("", |s| s.as_str()))?;
+            }
+            writeln!(out)?;
+        }
+    }
+    out.flush()?;
+    Ok(n_missing)
+}
+
+// Decompress the input if it starts with the gzip magic bytes, so gzipped files are read
+// whatever their name, and gzipped data can be piped to stdin
+fn maybe_gzipped(mut reader: impl BufRead + 'static) -> io::Result<Box<dyn BufRead>> {
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+// Read from a file, which may be gzipped
+fn open_input(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path)
+        .with_context(|| format!("Could not open input file \"{}\"", path.display()))?;
+    maybe_gzipped(BufReader::new(file))
+        .with_context(|| format!("Could not read input file \"{}\"", path.display()))
+}
+
+// The output must be finished, such that errors writing the gzip trailer are not lost
+enum Output {
+    Plain(Box<dyn Write>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Output {
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut w) => w.flush(),
+            Output::Gzip(w) => w.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(w) => w.write(buf),
+            Output::Gzip(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(w) => w.flush(),
+            Output::Gzip(w) => w.flush(),
+        }
+    }
+}
+
+// Write to stdout, to a file, or to a gzipped file if the name ends with .gz
+fn open_output(path: Option<&Path>) -> Result<Output> {
+    let Some(p) = path else {
+        return Ok(Output::Plain(Box::new(BufWriter::new(stdout().lock()))));
+    };
+    let file = File::create(p)
+        .with_context(|| format!("Could not create output file \"{}\"", p.display()))?;
+    if p.extension().is_some_and(|e| e == "gz") {
+        Ok(Output::Gzip(GzEncoder::new(
+            BufWriter::new(file),
+            Compression::default(),
+        )))
+    } else {
+        Ok(Output::Plain(Box::new(BufWriter::new(file))))
+    }
+}
+
+// The length of each contig in the assembly
+fn read_fasta(path: &Path) -> Result<HashMap<String, u64>> {
+    let mut lengths = HashMap::new();
+    let progress = Progress::new("Reading contigs", "contigs", None);
+    for record in Reader::from_bufread(open_input(path)?).records() {
+        let record = record.context("Failed to parse record from FASTA file")?;
+        progress.inc(1);
+        lengths.insert(record.id().to_owned(), record.seq().len() as u64);
+    }
+    progress.finish();
+    Ok(lengths)
+}
+
+// The length of each contig from a TSV file with the contig name and length on each line.
+// If the length on the first line is not a number, it is the header.
+fn read_lengths(reader: impl BufRead) -> Result<HashMap<String, u64>> {
+    let mut lengths = HashMap::new();
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line
+            .with_context(|| format!("Could not read line {} of lengths as UTF-8", lineno + 1))?;
+        if line.is_empty() {
+            continue;
+        }
+        let Some((name, length)) = line.split_once('\t') else {
+            bail!("Expected a tab character on line {} of lengths", lineno + 1);
+        };
+        let length = match length.parse::<u64>() {
+            Ok(length) => length,
+            Err(_) if lineno == 0 => continue,
+            Err(_) => bail!("Invalid length on line {} of lengths", lineno + 1),
+        };
+        lengths.insert(name.to_owned(), length);
+    }
+    Ok(lengths)
+}
+
+// Remove the bins with fewer than `min_contigs` contigs or `min_bp` base pairs, where the
+// contigs of a bin are counted across all samples. Return the number of bins removed.
+// All contigs must be in `lengths`, if given.
+fn filter_bins(
+    samples: &mut [Sample],
+    lengths: Option<&HashMap<String, u64>>,
+    min_contigs: usize,
+    min_bp: u64,
+) -> usize {
+    let mut sizes: HashMap<String, (usize, u64)> = HashMap::new();
+    for (contig, cluster) in samples.iter().flat_map(|s| s.rows.iter()) {
+        let size = sizes.entry(cluster.clone()).or_default();
+        size.0 += 1;
+        size.1 += lengths.map_or(0, |l| l[contig]);
+    }
+    let is_kept = |cluster: &String| {
+        let (n_contigs, bp) = sizes[cluster];
+        n_contigs >= min_contigs && bp >= min_bp
+    };
+    for sample in samples.iter_mut() {
+        sample.rows.retain(|(_, cluster)| is_kept(cluster));
+    }
+    sizes.keys().filter(|cluster| !is_kept(cluster)).count()
+}
+
+// How many contig names are logged for each problem found by `check_contigs`
+const MAX_LOGGED: usize = 10;
+
+// The contigs that are not in the assembly, if given, and the contigs that are listed more
+// than once, if `unique` is set. Each contig is returned once, in the order first seen.
+fn check_contigs<'a>(
+    samples: &'a [Sample],
+    lengths: Option<&HashMap<String, u64>>,
+    unique: bool,
+) -> (Vec<&'a str>, Vec<&'a str>) {
+    let mut missing = Vec::new();
+    let mut duplicated = Vec::new();
+    let mut seen = HashSet::new();
+    let mut reported = HashSet::new();
+    for (contig, _) in samples.iter().flat_map(|s| s.rows.iter()) {
+        let first_time = seen.insert(contig.as_str());
+        if lengths.is_some_and(|l| !l.contains_key(contig)) && first_time {
+            missing.push(contig.as_str());
+        }
+        if unique && !first_time && reported.insert(contig.as_str()) {
+            duplicated.push(contig.as_str());
+        }
+    }
+    (missing, duplicated)
+}
+
+fn log_contigs(contigs: &[&str], problem: &str) {
+    if contigs.is_empty() {
+        return;
+    }
+    let shown = &contigs[..contigs.len().min(MAX_LOGGED)];
+    let more = if contigs.len() > shown.len() {
+        ", ..."
+    } else {
+        ""
+    };
+    error!(
+        "{} contigs {}: {}{}",
+        contigs.len(),
+        problem,
+        shown.join(", "),
+        more
+    );
+}
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let taxonomy = match &args.taxonomy {
+        None => None,
+        Some(p) => Some(read_taxonomy(open_input(p)?)?),
+    };
+    let rows = match (args.input_format, &args.i) {
+        (InputFormat::FastaDir, None) => {
+            bail!("--input-format fasta-dir requires a directory given with -i")
+        }
+        (InputFormat::FastaDir, Some(p)) => read_bin_dir(p)?,
+        (format, None) => read_tsv(
+            maybe_gzipped(stdin().lock()).context("Could not read from stdin")?,
+            format == InputFormat::Metabat,
+        )?,
+        (format, Some(p)) => read_tsv(open_input(p)?, format == InputFormat::Metabat)?,
+    };
+    if args.min_bin_bp > 0 && args.contigs.is_none() && args.lengths.is_none() {
+        bail!("--min-bin-bp requires the contig lengths from --contigs or --lengths");
+    }
+    let mut samples = group_samples(rows, args.separator.as_deref(), &args.sample_id)?;
+    let lengths = match (&args.contigs, &args.lengths) {
+        (Some(p), _) => Some(read_fasta(p)?),
+        (None, Some(p)) => Some(read_lengths(open_input(p)?)?),
+        (None, None) => None,
+    };
+    let (missing, duplicated) = check_contigs(&samples, lengths.as_ref(), args.unique_contigs);
+    let source = if args.contigs.is_some() {
+        "the assembly"
+    } else {
+        "the lengths file"
+    };
+    log_contigs(&missing, &format!("are not in {}", source));
+    log_contigs(&duplicated, "are listed more than once");
+    if !missing.is_empty() || !duplicated.is_empty() {
+        bail!(
+            "Found {} missing and {} duplicated contigs",
+            missing.len(),
+            duplicated.len()
+        );
+    }
+    if args.min_bin_contigs > 1 || args.min_bin_bp > 0 {
+        let n_removed = filter_bins(
+            &mut samples,
+            lengths.as_ref(),
+            args.min_bin_contigs,
+            args.min_bin_bp,
+        );
+        info!("Left out {} bins below the minimum size", n_removed);
+    }
+    let mut out = open_output(args.o.as_deref())?;
+    let n_missing = match write_bioboxes(
+        &mut out,
+        &samples,
+        &args.format_version,
+        args.bin_first,
+        taxonomy.as_ref(),
+    )
+    .and_then(|n| out.finish().map(|_| n))
+    {
+        // The output was piped to a program that exited, like head
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => return Ok(()),
+        result => result.context("Could not write output")?,
+    };
+    if n_missing > 0 {
+        warn!(
+            "{} contigs have no taxonomic ID, nor does their bin, so their TAXID is empty",
+            n_missing
+        );
+    }
+    let n_contigs: usize = samples.iter().map(|s| s.rows.len()).sum();
+    info!("Wrote {} contigs in {} samples", n_contigs, samples.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_bbformat {
+    use crate::{
+        check_contigs, filter_bins, group_samples, maybe_gzipped, open_input, open_output,
+        read_bin_dir, read_lengths, read_taxonomy, read_tsv, write_bioboxes, Sample,
+    };
+    use flate2::{write::GzEncoder, Compression};
+    use std::{
+        collections::HashMap,
+        io::{Read, Write},
+    };
+
+    fn read_clusters(input: &str, separator: Option<&str>) -> Vec<Sample> {
+        group_samples(read_tsv(input.as_bytes(), false).unwrap(), separator, "all").unwrap()
+    }
+
+    fn convert(input: &str, separator: Option<&str>, bin_first: bool) -> String {
+        let samples = read_clusters(input, separator);
+        let mut out = Vec::new();
+        write_bioboxes(&mut out, &samples, "0.9.1", bin_first, None).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_convert() {
+        let input = "clustername\tcontigname\nb1\tS1C1\nb2\tS2C5\nb1\tS1C2\n";
+        assert_eq!(
+            convert(input, None, false),
+            "@Version:0.9.1\n@SampleID:all\n\n@@SEQUENCEID\tBINID\nS1C1\tb1\nS2C5\tb2\nS1C2\tb1\n"
+        );
+        assert_eq!(
+            convert(input, Some("C"), true),
+            "@Version:0.9.1\n@SampleID:S1\n\n@@BINID\tSEQUENCEID\nb1\tS1C1\nb1\tS1C2\n\n\
+             @Version:0.9.1\n@SampleID:S2\n\n@@BINID\tSEQUENCEID\nb2\tS2C5\n"
+        );
+        assert_eq!(
+            convert("", None, false),
+            "@Version:0.9.1\n@SampleID:all\n\n@@SEQUENCEID\tBINID\n"
+        );
+    }
+
+    #[test]
+    fn test_taxonomy() {
+        let samples = read_clusters("b1\tc1\nb2\tc2\nb3\tc3\n", None);
+        let taxonomy =
+            read_taxonomy("contigs\tpredictions\nc1\t562\nb2\t1280\n".as_bytes()).unwrap();
+        let mut out = Vec::new();
+        let n_missing =
+            write_bioboxes(&mut out, &samples, "0.9.1", false, Some(&taxonomy)).unwrap();
+        assert_eq!(n_missing, 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "@Version:0.9.1\n@SampleID:all\n\n@@SEQUENCEID\tBINID\tTAXID\n\
+             c1\tb1\t562\nc2\tb2\t1280\nc3\tb3\t\n"
+        );
+        assert!(read_taxonomy("c1\t1\nc1\t2\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_check_contigs() {
+        let input = "b1\tc1\nb2\tc2\nb2\tc1\nb1\tc3\nb3\tc1\nb3\tc3\n";
+        let samples = read_clusters(input, None);
+        let lengths: HashMap<String, u64> = [("c1".to_owned(), 100), ("c2".to_owned(), 50)].into();
+        assert_eq!(
+            check_contigs(&samples, Some(&lengths), true),
+            (vec!["c3"], vec!["c1", "c3"])
+        );
+        assert_eq!(
+            check_contigs(&samples, None, false),
+            (Vec::new(), Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_filter_bins() {
+        let input = "b1\tS1C1\nb2\tS1C2\nb1\tS2C1\nb3\tS2C2\nb3\tS2C3\n";
+        let lengths = read_lengths(
+            "contig\tlength\nS1C1\t100\nS1C2\t900\nS2C1\t50\nS2C2\t10\nS2C3\t20\n".as_bytes(),
+        )
+        .unwrap();
+        let mut samples = read_clusters(input, Some("C"));
+        assert_eq!(filter_bins(&mut samples, Some(&lengths), 2, 100), 2);
+        assert_eq!(samples[0].rows, [("S1C1".to_owned(), "b1".to_owned())]);
+        assert_eq!(samples[1].rows, [("S2C1".to_owned(), "b1".to_owned())]);
+        let mut samples = read_clusters(input, None);
+        assert_eq!(filter_bins(&mut samples, None, 2, 0), 1);
+        assert_eq!(samples[0].rows.len(), 4);
+        assert!(read_lengths("c1\t10\nc2\tx\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_metabat() {
+        let rows = read_tsv("c1\tbin.1\nc2\tbin.2\n".as_bytes(), true).unwrap();
+        assert_eq!(
+            rows,
+            [
+                ("c1".to_owned(), "bin.1".to_owned()),
+                ("c2".to_owned(), "bin.2".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gzipped() {
+        let input = "b1\tc1\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(input.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        for data in [input.as_bytes().to_vec(), gzipped] {
+            let reader = maybe_gzipped(std::io::Cursor::new(data)).unwrap();
+            assert_eq!(
+                read_tsv(reader, false).unwrap(),
+                [("c1".to_owned(), "b1".to_owned())]
+            );
+        }
+    }
+
+    #[test]
+    fn test_gzipped_output() {
+        let samples = read_clusters("b1\tc1\nb2\tc2\n", None);
+        let path =
+            std::env::temp_dir().join(format!("bbformat_test_{}.tsv.gz", std::process::id()));
+        let mut out = open_output(Some(&path)).unwrap();
+        write_bioboxes(&mut out, &samples, "0.9.1", false, None).unwrap();
+        out.finish().unwrap();
+        let compressed = std::fs::read(&path).unwrap();
+        let mut text = String::new();
+        open_input(&path)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(compressed.starts_with(&[0x1f, 0x8b]));
+        assert_eq!(
+            text,
+            "@Version:0.9.1\n@SampleID:all\n\n@@SEQUENCEID\tBINID\nc1\tb1\nc2\tb2\n"
+        );
+    }
+
+    #[test]
+    fn test_read_bin_dir() {
+        let dir = std::env::temp_dir().join(format!("bbformat_test_bins_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("bin.1.fa"), ">c1\nACGT\n>c2\nAC\n").unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b">c3\nACGT\n").unwrap();
+        std::fs::write(dir.join("bin.2.fna.gz"), encoder.finish().unwrap()).unwrap();
+        // Not FASTA extensions
+        std::fs::write(dir.join("bin.3.fas"), ">c4\nACGT\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "bins\n").unwrap();
+        let rows = read_bin_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let rows: Vec<(&str, &str)> = rows
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|(a, b)| (a.as_str(), b.as_str()))
+            .collect();
+        assert_eq!(rows, [("c1", "bin.1"), ("c2", "bin.1"), ("c3", "bin.2")]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(read_tsv("b1 c1\n".as_bytes(), false).is_err());
+        assert!(read_tsv("b1\tc1\tx\n".as_bytes(), false).is_err());
+        let rows = read_tsv("b1\tS1X1\n".as_bytes(), false).unwrap();
+        assert!(group_samples(rows, Some("C"), "all").is_err());
+    }
+}
@@ -0,0 +1,420 @@
+use anyhow::{self, bail, Context, Result};
+use clap::Parser;
+use htslite::{AlignmentReader, Header, Record, FLAG_MATE_UNMAPPED, FLAG_PAIRED};
+use progress::{info, LogArgs, Progress};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{stdout, BufRead, BufReader, BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    thread,
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let mut reader = AlignmentReader::open(args.i.as_deref())?;
+    let intervals = read_bed(&args.bed, reader.header())?;
+
+    // Indices into `intervals` for each reference
+    let mut by_reference: Vec<Vec<usize>> = vec![Vec::new(); reader.header().references.len()];
+    for (i, interval) in intervals.iter().enumerate() {
+        by_reference[interval.ref_id].push(i);
+    }
+
+    let mut results: Vec<Option<Coverage>> = vec![None; intervals.len()];
+    // The main thread parses the alignments and adds the aligned blocks of each reference
+    // to its depth changes. Once all alignments of a reference have been read, the changes
+    // are sent to a worker which computes the coverage of the intervals on that reference.
+    thread::scope(|s| -> Result<()> {
+        // The channels are created inside the scope, so they are dropped if we return early
+        // with an error, which makes the workers exit.
+        let (changes_sender, changes_receiver) =
+            crossbeam_channel::bounded::<DepthChanges>(args.threads.get());
+        let (result_sender, result_receiver) = crossbeam_channel::unbounded::<(usize, Coverage)>();
+        for _ in 0..args.threads.get() {
+            let (receiver, sender) = (changes_receiver.clone(), result_sender.clone());
+            let (intervals, by_reference) = (&intervals, &by_reference);
+            s.spawn(move || {
+                for changes in receiver.iter() {
+                    let indices = &by_reference[changes.ref_id];
+                    for (i, coverage) in
+                        compute_coverage(&changes, intervals, indices, args.min_depth)
+                    {
+                        // Only fails if the main thread returned with an error
+                        if sender.send((i, coverage)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+        drop(result_sender);
+
+        let mut record = Record::default();
+        let mut current: Option<usize> = None;
+        let mut seen = vec![false; by_reference.len()];
+        // None if the current reference has no intervals
+        let mut changes: Option<DepthChanges> = None;
+        // Aligned blocks of reads whose mate overlaps them and has not been read yet, so
+        // that the bases covered by both mates of a fragment are only counted once
+        let mut pending_mates: HashMap<Vec<u8>, Vec<(u32, u32)>> = HashMap::new();
+        let progress = Progress::new("Reading alignments", "records", None);
+        while reader.read_record(&mut record)? {
+            progress.inc(1);
+            if record.is_unmapped()
+                || record.has_flag(args.exclude_flags)
+                || record.mapq < args.min_mapq
+            {
+                continue;
+            }
+            // Checked by is_unmapped
+            let ref_id = record.ref_id.unwrap();
+            if current != Some(ref_id) {
+                if seen[ref_id] {
+                    bail!("Input alignments must be sorted by reference");
+                }
+                seen[ref_id] = true;
+                if let Some(c) = changes.take() {
+                    changes_sender.send(c)?;
+                }
+                changes = DepthChanges::new(ref_id, &intervals, &by_reference[ref_id]);
+                current = Some(ref_id);
+                // Mates which were filtered out
+                pending_mates.clear();
+            }
+            if let Some(c) = &mut changes {
+                if let Some(mate_blocks) = pending_mates.remove(&record.qname) {
+                    for (start, stop) in subtract_blocks(record.aligned_blocks(), &mate_blocks) {
+                        c.add(start, stop);
+                    }
+                } else {
+                    if overlaps_later_mate(&record) {
+                        pending_mates
+                            .insert(record.qname.clone(), record.aligned_blocks().collect());
+                    }
+                    for (start, stop) in record.aligned_blocks() {
+                        c.add(start, stop);
+                    }
+                }
+            }
+        }
+        progress.finish();
+        if let Some(c) = changes {
+            changes_sender.send(c)?;
+        }
+        // References with intervals but no alignments
+        for (ref_id, indices) in by_reference.iter().enumerate() {
+            if !seen[ref_id] {
+                if let Some(c) = DepthChanges::new(ref_id, &intervals, indices) {
+                    changes_sender.send(c)?;
+                }
+            }
+        }
+        drop(changes_sender);
+        for (i, coverage) in result_receiver.iter() {
+            results[i] = Some(coverage);
+        }
+        Ok(())
+    })?;
+
+    let mut out = BufWriter::new(stdout().lock());
+    writeln!(out, "#chrom\tstart\tend\tname\tmean\tmedian\tbreadth")?;
+    for (interval, coverage) in intervals.iter().zip(results) {
+        let coverage = coverage.unwrap();
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{:.4}\t{}\t{:.4}",
+            reader.header().references[interval.ref_id].name,
+            interval.start,
+            interval.end,
+            interval.name.as_deref().unwrap_or("."),
+            coverage.mean,
+            coverage.median,
+            coverage.breadth
+        )?;
+    }
+    out.flush()?;
+    info!("Computed coverage of {} intervals", intervals.len());
+    Ok(())
+}
+
+struct Interval {
+    ref_id: usize,
+    // 0-based half-open, like in the BED file
+    start: u32,
+    end: u32,
+    name: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+struct Coverage {
+    mean: f64,
+    // The mean of the two middle depths if the interval has an even length
+    median: f64,
+    // Fraction of positions with at least the minimum depth
+    breadth: f64,
+}
+
+fn read_bed(path: &Path, header: &Header) -> Result<Vec<Interval>> {
+    let file = File::open(path)
+        .with_context(|| format!("Could not open BED file \"{}\"", path.to_string_lossy()))?;
+    let reader: Box<dyn BufRead> = if path.extension().is_some_and(|e| e == "gz") {
+        Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    let mut intervals = Vec::new();
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line.context("Failed to read line from BED file")?;
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let parse = || -> Result<Interval> {
+            let mut fields = line.split('\t');
+            let chrom = fields.next().unwrap();
+            let (Some(start), Some(end)) = (fields.next(), fields.next()) else {
+                bail!("Expected at least 3 tab-separated fields");
+            };
+            let start: u32 = start.parse().context("Could not parse start")?;
+            let end: u32 = end.parse().context("Could not parse end")?;
+            let ref_id = header.reference_index(chrom).with_context(|| {
+                format!("Reference \"{}\" is not in the alignment header", chrom)
+            })?;
+            if start >= end || end as u64 > header.references[ref_id].length {
+                bail!("Interval is empty or extends past the end of the reference");
+            }
+            Ok(Interval {
+                ref_id,
+                start,
+                end,
+                name: fields.next().map(|s| s.to_owned()),
+            })
+        };
+        intervals
+            .push(parse().with_context(|| format!("Invalid BED line {}: {}", lineno + 1, line))?);
+    }
+    Ok(intervals)
+}
+
+// Whether the mate of a mapped read starts within its alignment, so it comes later in
+// the sorted input and may cover some of the same positions.
+fn overlaps_later_mate(record: &Record) -> bool {
+    record.has_flag(FLAG_PAIRED)
+        && !record.has_flag(FLAG_MATE_UNMAPPED)
+        && record.next_ref_id == record.ref_id
+        && record
+            .next_pos
+            .is_some_and(|p| p >= record.pos.unwrap() && p < record.end().unwrap())
+}
+
+// The parts of the blocks not covered by any of the sorted, non-overlapping `covered` blocks
+fn subtract_blocks(
+    blocks: impl Iterator<Item = (u32, u32)>,
+    covered: &[(u32, u32)],
+) -> Vec<(u32, u32)> {
+    let mut result = Vec::new();
+    for (mut start, stop) in blocks {
+        for &(covered_start, covered_stop) in covered {
+            if start >= stop {
+                break;
+            }
+            if covered_stop <= start || covered_start >= stop {
+                continue;
+            }
+            if covered_start > start {
+                result.push((start, covered_start));
+            }
+            start = covered_stop;
+        }
+        if start < stop {
+            result.push((start, stop));
+        }
+    }
+    result
+}
+
+// Difference array over the positions spanned by the intervals of a reference: The depth
+// increases at block starts and decreases at block ends. Its size does not depend on the
+// number of alignments, so the alignments are not kept in memory.
+struct DepthChanges {
+    ref_id: usize,
+    span_start: u32,
+    changes: Vec<i32>,
+}
+
+impl DepthChanges {
+    // Returns None if the reference has no intervals
+    fn new(ref_id: usize, intervals: &[Interval], indices: &[usize]) -> Option<Self> {
+        let span_start = indices.iter().map(|&i| intervals[i].start).min()?;
+        let span_end = indices.iter().map(|&i| intervals[i].end).max().unwrap();
+        Some(Self {
+            ref_id,
+            span_start,
+            changes: vec![0; (span_end - span_start) as usize + 1],
+        })
+    }
+
+    fn span_end(&self) -> u32 {
+        self.span_start + self.changes.len() as u32 - 1
+    }
+
+    fn add(&mut self, start: u32, stop: u32) {
+        let (start, stop) = (start.max(self.span_start), stop.min(self.span_end()));
+        if start < stop {
+            self.changes[(start - self.span_start) as usize] += 1;
+            self.changes[(stop - self.span_start) as usize] -= 1;
+        }
+    }
+}
+
+// Compute the depth at every position spanned by the intervals, then summarize each interval.
+fn compute_coverage(
+    changes: &DepthChanges,
+    intervals: &[Interval],
+    indices: &[usize],
+    min_depth: u32,
+) -> Vec<(usize, Coverage)> {
+    let span_start = changes.span_start;
+    let mut depth: i64 = 0;
+    let depths: Vec<u32> = changes.changes[..changes.changes.len() - 1]
+        .iter()
+        .map(|&d| {
+            depth += d as i64;
+            depth as u32
+        })
+        .collect();
+
+    let mut buffer: Vec<u32> = Vec::new();
+    indices
+        .iter()
+        .map(|&i| {
+            let interval = &intervals[i];
+            let slice = &depths
+                [(interval.start - span_start) as usize..(interval.end - span_start) as usize];
+            let len = slice.len() as f64;
+            let sum: u64 = slice.iter().map(|&d| d as u64).sum();
+            let covered = slice.iter().filter(|&&d| d >= min_depth).count();
+            buffer.clear();
+            buffer.extend_from_slice(slice);
+            let mid = buffer.len() / 2;
+            let (lower, &mut upper, _) = buffer.select_nth_unstable(mid);
+            let median = if slice.len() % 2 == 1 {
+                upper as f64
+            } else {
+                // The lower middle value is the largest below the upper one
+                (*lower.iter().max().unwrap() as f64 + upper as f64) / 2.0
+            };
+            (
+                i,
+                Coverage {
+                    mean: sum as f64 / len,
+                    median,
+                    breadth: covered as f64 / len,
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test_coverage {
+    use crate::{compute_coverage, subtract_blocks, DepthChanges, Interval};
+
+    #[test]
+    fn test_compute_coverage() {
+        let intervals = [(10, 20), (15, 40), (100, 101)].map(|(start, end)| Interval {
+            ref_id: 0,
+            start,
+            end,
+            name: None,
+        });
+        let indices = [0, 1, 2];
+        let mut changes = DepthChanges::new(0, &intervals, &indices).unwrap();
+        for (start, stop) in [(0, 15), (12, 30), (12, 13), (200, 300)] {
+            changes.add(start, stop);
+        }
+        assert_eq!((changes.span_start, changes.span_end()), (10, 101));
+        let result = compute_coverage(&changes, &intervals, &indices, 1);
+        let (i, c) = result[0];
+        assert_eq!(i, 0);
+        // Depths: 10-11: 1, 12: 3, 13-14: 2, 15-19: 1
+        assert_eq!(c.mean, 1.4);
+        assert_eq!(c.median, 1.0);
+        assert_eq!(c.breadth, 1.0);
+        let c = result[1].1;
+        assert_eq!((c.mean, c.median, c.breadth), (0.6, 1.0, 0.6));
+        let c = result[2].1;
+        assert_eq!((c.mean, c.median, c.breadth), (0.0, 0.0, 0.0));
+        assert!(DepthChanges::new(0, &intervals, &[]).is_none());
+    }
+
+    #[test]
+    fn test_even_median() {
+        let intervals = [Interval {
+            ref_id: 0,
+            start: 0,
+            end: 4,
+            name: None,
+        }];
+        let mut changes = DepthChanges::new(0, &intervals, &[0]).unwrap();
+        changes.add(0, 2);
+        changes.add(1, 2);
+        // Depths: 1, 2, 0, 0
+        let c = compute_coverage(&changes, &intervals, &[0], 1)[0].1;
+        assert_eq!((c.mean, c.median, c.breadth), (0.75, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_subtract_blocks() {
+        let covered = [(10, 20), (30, 40)];
+        let subtract = |blocks: &[(u32, u32)]| subtract_blocks(blocks.iter().copied(), &covered);
+        assert_eq!(subtract(&[(0, 5), (45, 50)]), [(0, 5), (45, 50)]);
+        assert_eq!(subtract(&[(15, 35)]), [(20, 30)]);
+        assert_eq!(subtract(&[(5, 45)]), [(5, 10), (20, 30), (40, 45)]);
+        assert_eq!(subtract(&[(12, 18), (30, 40)]), []);
+        assert_eq!(subtract(&[(18, 22), (38, 42)]), [(20, 22), (40, 42)]);
+    }
+}
+
+const LONG_ABOUT: &str = "Compute mean depth, median depth and breadth of coverage of each interval
+in a BED file from a SAM or BAM file, which must be sorted by reference.
+By default, unmapped, secondary, QC-failed and duplicate reads are ignored (like mosdepth).
+Prints a TSV file to stdout with a line per interval, in the order of the BED file.
+Usage: bedcov -i sorted.bam -b genes.bed > coverage.tsv";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Input SAM or BAM file [stdin]
+    #[arg(short)]
+    i: Option<PathBuf>,
+
+    /// BED file of intervals (may be gzipped)
+    #[arg(short)]
+    bed: PathBuf,
+
+    /// Minimum mapping quality of reads
+    #[arg(short = 'Q', long, default_value_t = 0)]
+    min_mapq: u8,
+
+    /// Ignore reads with any of these flags set
+    #[arg(short = 'F', long, default_value_t = 1796)]
+    exclude_flags: u16,
+
+    /// Minimum depth of a position to count towards breadth
+    #[arg(short = 'd', long, default_value_t = 1)]
+    min_depth: u32,
+
+    /// Number of worker threads
+    #[arg(short, long, default_value = "1")]
+    threads: NonZeroUsize,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
@@ -0,0 +1,169 @@
+use anyhow::{self, bail, Context, Result};
+use bio::io::fasta::{Reader, Writer};
+use clap::Parser;
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use progress::{info, LogArgs, Progress};
+use std::{
+    fs::File,
+    io::{stdin, stdout, BufRead, BufReader, BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    if args.overlap >= args.chunk_size.get() {
+        bail!("Overlap must be smaller than the chunk size");
+    }
+    let reader = Reader::from_bufread(open_input(args.i.as_deref())?);
+    let mut writer = Writer::new(open_output(args.o.as_deref())?);
+    let mut table = BufWriter::new(File::create(&args.table).with_context(|| {
+        format!(
+            "Could not create table file \"{}\"",
+            args.table.to_string_lossy()
+        )
+    })?);
+    table.write_all(b"chunk\tcontig\tstart\tend\n")?;
+
+    let mut n_chunks = 0usize;
+    let mut name = String::new();
+    let progress = Progress::new("Cutting contigs", "contigs", None);
+    for record in reader.records() {
+        let record = record.context("Failed to parse record from FASTA file")?;
+        let seq = record.seq();
+        progress.inc(1);
+        // Short contigs are kept whole, under their original name
+        if seq.len() <= args.chunk_size.get() {
+            writer.write(record.id(), None, seq)?;
+            writeln!(table, "{}\t{}\t0\t{}", record.id(), record.id(), seq.len())?;
+            n_chunks += 1;
+            continue;
+        }
+        for (i, (start, end)) in chunks(seq.len(), args.chunk_size.get(), args.overlap).enumerate()
+        {
+            name.clear();
+            name.push_str(record.id());
+            name.push_str(&args.separator);
+            name.push_str(&i.to_string());
+            writer.write(&name, None, &seq[start..end])?;
+            writeln!(table, "{}\t{}\t{}\t{}", name, record.id(), start, end)?;
+            n_chunks += 1;
+        }
+    }
+    writer.flush()?;
+    table.flush()?;
+    progress.finish();
+    info!(
+        "Cut {} contigs into {} chunks",
+        progress.position(),
+        n_chunks
+    );
+    Ok(())
+}
+
+// The 0-based half-open intervals of each chunk. Chunks begin every (size - overlap) bases.
+// The remainder at the end, which is shorter than a full chunk, is merged into the last
+// chunk, like CONCOCT's cut_up_fasta.py with --merge_last.
+fn chunks(len: usize, size: usize, overlap: usize) -> impl Iterator<Item = (usize, usize)> {
+    let step = size - overlap;
+    let n_chunks = if len <= size {
+        1
+    } else {
+        (len - size) / step + 1
+    };
+    (0..n_chunks).map(move |i| {
+        let start = i * step;
+        let end = if i + 1 == n_chunks { len } else { start + size };
+        (start, end)
+    })
+}
+
+#[cfg(test)]
+mod test_chunks {
+    use crate::chunks;
+
+    #[test]
+    fn test_chunks() {
+        assert_eq!(chunks(10, 10, 0).collect::<Vec<_>>(), [(0, 10)]);
+        assert_eq!(chunks(25, 10, 0).collect::<Vec<_>>(), [(0, 10), (10, 25)]);
+        assert_eq!(
+            chunks(30, 10, 0).collect::<Vec<_>>(),
+            [(0, 10), (10, 20), (20, 30)]
+        );
+        assert_eq!(
+            chunks(25, 10, 4).collect::<Vec<_>>(),
+            [(0, 10), (6, 16), (12, 25)]
+        );
+    }
+}
+
+// Read from stdin, from a file, or from a gzipped file if the name ends with .gz
+fn open_input(path: Option<&Path>) -> Result<Box<dyn BufRead>> {
+    let Some(p) = path else {
+        return Ok(Box::new(stdin().lock()));
+    };
+    let file = File::open(p)
+        .with_context(|| format!("Could not open input file \"{}\"", p.to_string_lossy()))?;
+    if p.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+// Write to stdout, to a file, or to a gzipped file if the name ends with .gz
+fn open_output(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    let Some(p) = path else {
+        return Ok(Box::new(stdout().lock()));
+    };
+    let file = File::create(p)
+        .with_context(|| format!("Could not create output file \"{}\"", p.to_string_lossy()))?;
+    if p.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(GzEncoder::new(
+            BufWriter::new(file),
+            Compression::default(),
+        )))
+    } else {
+        Ok(Box::new(BufWriter::new(file)))
+    }
+}
+
+const LONG_ABOUT: &str = "Cut contigs longer than the chunk size into chunks, CONCOCT-style.
+Chunk N of contig X is named X.N (with the default separator). Contigs no longer than
+the chunk size are written unchanged. The remainder at the end of a contig is merged into
+its last chunk, so no chunk is shorter than the chunk size.
+A TSV table with the columns chunk, contig, start and end (0-based, half-open) is written
+to the table file. Use `cunchunk` to assign contigs to bins from the binned chunks.
+Usage: cchunk -i contigs.fna.gz -o chunks.fna.gz -t chunks.tsv -c 10000";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Input FASTA file (may be gzipped) [stdin]
+    #[arg(short)]
+    i: Option<PathBuf>,
+
+    /// Output FASTA file (gzipped if ending in .gz) [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    /// Output chunk to contig table
+    #[arg(short, long)]
+    table: PathBuf,
+
+    /// Chunk size in bp
+    #[arg(short, long, default_value = "10000")]
+    chunk_size: NonZeroUsize,
+
+    /// Overlap between consecutive chunks in bp
+    #[arg(long, default_value_t = 0)]
+    overlap: usize,
+
+    /// Separator between contig name and chunk number
+    #[arg(short, long, default_value = ".")]
+    separator: String,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
@@ -0,0 +1,554 @@
+use anyhow::{self, bail, Context, Result};
+use bio::io::fasta::Reader;
+use clap::Parser;
+use flate2::read::MultiGzDecoder;
+use progress::{info, LogArgs, Progress};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{stdout, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    if args.min_containment.is_some() && args.fasta.is_none() {
+        bail!("--min-containment requires --fasta");
+    }
+    if args.min_size > 0 && args.fasta.is_none() {
+        bail!("--min-size requires --fasta");
+    }
+    if args.gfa.is_some() != args.min_links.is_some() {
+        bail!("--gfa and --min-links must be given together");
+    }
+    if !(1..=32).contains(&args.k) {
+        bail!("k must be in 1-32");
+    }
+    if args.scale == 0 {
+        bail!("Scale must be at least 1");
+    }
+    if args
+        .min_containment
+        .is_some_and(|c| !(0.0..=1.0).contains(&c))
+    {
+        bail!("Minimum containment must be in 0-1");
+    }
+    let mut log = Log::new(args.log.as_deref())?;
+    let clusters = Clusters::read(&args.clusters)?;
+
+    let (lengths, sketches) = match &args.fasta {
+        None => (None, None),
+        Some(p) => {
+            let (lengths, sketches) = read_fasta(
+                p,
+                &clusters,
+                args.min_containment.map(|_| (args.k, args.scale)),
+            )?;
+            (Some(lengths), sketches)
+        }
+    };
+
+    // Merge clusters by graph links and shared kmers, using union-find
+    let mut forest = UnionFind::new(clusters.names.len());
+    if let (Some(p), Some(min_links)) = (&args.gfa, args.min_links) {
+        for ((a, b), n) in count_links(p, &clusters)? {
+            if n >= min_links && forest.union(a, b) {
+                log.write(
+                    "merge_links",
+                    &clusters.names[a],
+                    &format!("with={} links={}", clusters.names[b], n),
+                )?;
+            }
+        }
+    }
+    if let (Some(sketches), Some(min_containment)) = (&sketches, args.min_containment) {
+        for ((a, b), containment) in sketch_containments(sketches) {
+            if containment >= min_containment && forest.union(a, b) {
+                log.write(
+                    "merge_kmers",
+                    &clusters.names[a],
+                    &format!("with={} containment={:.4}", clusters.names[b], containment),
+                )?;
+            }
+        }
+    }
+    let size = |members: &[usize]| -> u64 {
+        match &lengths {
+            Some(l) => members.iter().map(|&i| l[i]).sum(),
+            None => members.len() as u64,
+        }
+    };
+    let mut refined: Vec<(String, Vec<usize>)> = Vec::new();
+    for group in forest.groups() {
+        // The merged cluster keeps the name of its largest member
+        let largest = *group
+            .iter()
+            .max_by_key(|&&c| (size(&clusters.members[c]), std::cmp::Reverse(c)))
+            .unwrap();
+        let members: Vec<usize> = group
+            .iter()
+            .flat_map(|&c| clusters.members[c].iter().copied())
+            .collect();
+        if group.len() > 1 {
+            let names: Vec<&str> = group.iter().map(|&c| clusters.names[c].as_str()).collect();
+            log.write(
+                "merged",
+                &clusters.names[largest],
+                &format!("members={}", names.join(",")),
+            )?;
+        }
+        refined.push((clusters.names[largest].clone(), members));
+    }
+
+    // Split by sample
+    if let Some(separator) = &args.separator {
+        let mut split: Vec<(String, Vec<usize>)> = Vec::new();
+        for (name, members) in refined {
+            let mut by_sample: Vec<(&str, Vec<usize>)> = Vec::new();
+            for &contig in members.iter() {
+                let contig_name = &clusters.contig_names[contig];
+                let (sample, _) =
+                    contig_name
+                        .split_once(separator.as_str())
+                        .with_context(|| {
+                            format!(
+                                "Separator \"{}\" not found in contig name \"{}\"",
+                                separator, contig_name
+                            )
+                        })?;
+                match by_sample.iter_mut().find(|(s, _)| *s == sample) {
+                    Some((_, v)) => v.push(contig),
+                    None => by_sample.push((sample, vec![contig])),
+                }
+            }
+            if by_sample.len() > 1 {
+                log.write("split", &name, &format!("samples={}", by_sample.len()))?;
+            }
+            for (sample, contigs) in by_sample {
+                split.push((format!("{}{}{}", sample, separator, name), contigs));
+            }
+        }
+        refined = split;
+    }
+
+    // Remove small clusters
+    let mut kept: Vec<(String, Vec<usize>)> = Vec::with_capacity(refined.len());
+    for (name, members) in refined {
+        let bp = lengths
+            .as_ref()
+            .map_or(0, |l| members.iter().map(|&i| l[i]).sum::<u64>());
+        if bp >= args.min_size && members.len() >= args.min_contigs {
+            kept.push((name, members));
+        } else {
+            log.write(
+                "remove",
+                &name,
+                &format!("contigs={} bp={}", members.len(), bp),
+            )?;
+        }
+    }
+
+    let mut out: Box<dyn Write> = match &args.o {
+        None => Box::new(BufWriter::new(stdout().lock())),
+        Some(p) => Box::new(BufWriter::new(File::create(p).with_context(|| {
+            format!("Could not create output file \"{}\"", p.to_string_lossy())
+        })?)),
+    };
+    out.write_all(b"clustername\tcontigname\n")?;
+    for (name, members) in kept.iter() {
+        for &contig in members {
+            writeln!(out, "{}\t{}", name, clusters.contig_names[contig])?;
+        }
+    }
+    out.flush()?;
+    log.finish()?;
+    info!(
+        "Refined {} clusters into {} clusters",
+        clusters.names.len(),
+        kept.len()
+    );
+    Ok(())
+}
+
+// Log of every operation done to the clusters, as a TSV file
+struct Log(Option<BufWriter<File>>);
+
+impl Log {
+    fn new(path: Option<&Path>) -> Result<Self> {
+        let Some(p) = path else { return Ok(Self(None)) };
+        let mut w =
+            BufWriter::new(File::create(p).with_context(|| {
+                format!("Could not create log file \"{}\"", p.to_string_lossy())
+            })?);
+        w.write_all(b"operation\tcluster\tdetails\n")?;
+        Ok(Self(Some(w)))
+    }
+
+    fn write(&mut self, operation: &str, cluster: &str, details: &str) -> Result<()> {
+        if let Some(w) = self.0.as_mut() {
+            writeln!(w, "{}\t{}\t{}", operation, cluster, details)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        if let Some(mut w) = self.0 {
+            w.flush().context("Failed to write log file")?;
+        }
+        Ok(())
+    }
+}
+
+struct Clusters {
+    names: Vec<String>,
+    // Contig indices of each cluster
+    members: Vec<Vec<usize>>,
+    contig_names: Vec<String>,
+    // Contig name to (contig index, cluster index)
+    contig_index: HashMap<String, (usize, usize)>,
+}
+
+impl Clusters {
+    fn read(path: &Path) -> Result<Self> {
+        let mut names: Vec<String> = Vec::new();
+        let mut name_index: HashMap<String, usize> = HashMap::new();
+        let mut members: Vec<Vec<usize>> = Vec::new();
+        let mut contig_names: Vec<String> = Vec::new();
+        let mut contig_index: HashMap<String, (usize, usize)> = HashMap::new();
+        for (lineno, line) in open_input(path)?.lines().enumerate() {
+            let line = line.context("Failed to read line from cluster file")?;
+            if (lineno == 0 && line == "clustername\tcontigname") || line.is_empty() {
+                continue;
+            }
+            let (cluster, contig) = line
+                .split_once('\t')
+                .filter(|(_, contig)| !contig.contains('\t'))
+                .with_context(|| {
+                    format!(
+                        "Expected two tab-separated fields on line {} of cluster file",
+                        lineno + 1
+                    )
+                })?;
+            let cluster_index = *name_index.entry(cluster.to_owned()).or_insert_with(|| {
+                names.push(cluster.to_owned());
+                members.push(Vec::new());
+                names.len() - 1
+            });
+            let index = contig_names.len();
+            if contig_index
+                .insert(contig.to_owned(), (index, cluster_index))
+                .is_some()
+            {
+                bail!(
+                    "Contig \"{}\" is present multiple times in cluster file",
+                    contig
+                );
+            }
+            contig_names.push(contig.to_owned());
+            members[cluster_index].push(index);
+        }
+        Ok(Self {
+            names,
+            members,
+            contig_names,
+            contig_index,
+        })
+    }
+}
+
+fn open_input(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path)
+        .with_context(|| format!("Could not open file \"{}\"", path.to_string_lossy()))?;
+    if path.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+// Returns the length of each clustered contig, and, if (k, scale) is passed, the
+// kmer sketch of each cluster
+fn read_fasta(
+    path: &Path,
+    clusters: &Clusters,
+    sketch_params: Option<(u8, u64)>,
+) -> Result<(Vec<u64>, Option<Vec<Sketch>>)> {
+    let mut lengths: Vec<Option<u64>> = vec![None; clusters.contig_names.len()];
+    let mut sketches: Option<Vec<Sketch>> =
+        sketch_params.map(|_| vec![Vec::new(); clusters.names.len()]);
+    let progress = Progress::new("Reading contigs", "contigs", None);
+    for record in Reader::from_bufread(open_input(path)?).records() {
+        let record = record.context("Failed to parse record from FASTA file")?;
+        progress.inc(1);
+        let Some(&(contig, cluster)) = clusters.contig_index.get(record.id()) else {
+            continue;
+        };
+        lengths[contig] = Some(record.seq().len() as u64);
+        if let (Some(sketches), Some((k, scale))) = (sketches.as_mut(), sketch_params) {
+            add_to_sketch(record.seq(), k, scale, &mut sketches[cluster]);
+        }
+    }
+    progress.finish();
+    if let Some(sketches) = sketches.as_mut() {
+        for sketch in sketches.iter_mut() {
+            sketch.sort_unstable();
+            sketch.dedup();
+        }
+    }
+    let lengths = lengths
+        .into_iter()
+        .enumerate()
+        .map(|(i, l)| {
+            l.with_context(|| {
+                format!(
+                    "Contig \"{}\" from cluster file not found in FASTA file",
+                    clusters.contig_names[i]
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((lengths, sketches))
+}
+
+// Sorted, deduplicated kmer hashes
+type Sketch = Vec<u64>;
+
+// Murmur3's 64-bit finalizer, to make kmer hashes uniformly distributed
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51afd7ed558ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ceb9fe1a85ec53);
+    x ^ (x >> 33)
+}
+
+// FracMinHash: Keep the hashes of canonical kmers below u64::MAX / scale.
+fn add_to_sketch(seq: &[u8], k: u8, scale: u64, sketch: &mut Vec<u64>) {
+    let k = k as usize;
+    let mask: u64 = if k == 32 {
+        u64::MAX
+    } else {
+        (1 << (2 * k)) - 1
+    };
+    let shift = 2 * (k - 1);
+    let max_hash = u64::MAX / scale;
+    let (mut fw, mut rc, mut valid) = (0u64, 0u64, 0usize);
+    for &b in seq {
+        let code = match b {
+            b'A' | b'a' => 0,
+            b'C' | b'c' => 1,
+            b'G' | b'g' => 2,
+            b'T' | b't' => 3,
+            _ => {
+                valid = 0;
+                continue;
+            }
+        };
+        fw = ((fw << 2) | code) & mask;
+        rc = (rc >> 2) | ((3 - code) << shift);
+        valid += 1;
+        if valid >= k {
+            let hash = mix(fw.min(rc));
+            if hash <= max_hash {
+                sketch.push(hash);
+            }
+        }
+    }
+}
+
+// For each pair of clusters sharing any hashes, the number of shared hashes divided by
+// the size of the smaller sketch.
+fn sketch_containments(sketches: &[Sketch]) -> Vec<((usize, usize), f64)> {
+    let mut index: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (cluster, sketch) in sketches.iter().enumerate() {
+        for &hash in sketch {
+            index.entry(hash).or_default().push(cluster);
+        }
+    }
+    let mut shared: HashMap<(usize, usize), usize> = HashMap::new();
+    for clusters in index.values() {
+        for (i, &a) in clusters.iter().enumerate() {
+            for &b in &clusters[i + 1..] {
+                *shared.entry((a, b)).or_default() += 1;
+            }
+        }
+    }
+    let mut result: Vec<_> = shared
+        .into_iter()
+        .map(|((a, b), n)| {
+            let smallest = sketches[a].len().min(sketches[b].len());
+            ((a, b), n as f64 / smallest as f64)
+        })
+        .collect();
+    // Sort, so the order of merges in the log is deterministic
+    result.sort_unstable_by_key(|&(pair, _)| pair);
+    result
+}
+
+// Number of distinct pairs of contigs linked by GFA L lines between each pair of clusters
+fn count_links(path: &Path, clusters: &Clusters) -> Result<Vec<((usize, usize), usize)>> {
+    // Each linked pair of contigs (and their clusters), counted only once
+    let mut linked: HashSet<((usize, usize), (usize, usize))> = HashSet::new();
+    for line in open_input(path)?.lines() {
+        let line = line.context("Failed to read line from GFA file")?;
+        let Some(rest) = line.strip_prefix("L\t") else {
+            continue;
+        };
+        let fields: Vec<&str> = rest.split('\t').collect();
+        if fields.len() < 4 {
+            bail!("L line in GFA file has too few fields: {}", line);
+        }
+        if let (Some(&a), Some(&b)) = (
+            clusters.contig_index.get(fields[0]),
+            clusters.contig_index.get(fields[2]),
+        ) {
+            linked.insert((a.min(b), a.max(b)));
+        }
+    }
+    let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+    for ((_, ca), (_, cb)) in linked {
+        if ca != cb {
+            *counts.entry((ca.min(cb), ca.max(cb))).or_default() += 1;
+        }
+    }
+    let mut result: Vec<_> = counts.into_iter().collect();
+    result.sort_unstable();
+    Ok(result)
+}
+
+struct UnionFind(Vec<usize>);
+
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        Self((0..n).collect())
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.0[i] != i {
+            self.0[i] = self.0[self.0[i]];
+            i = self.0[i];
+        }
+        i
+    }
+
+    // Returns whether the two were in different sets before
+    fn union(&mut self, a: usize, b: usize) -> bool {
+        let (ra, rb) = (self.find(a), self.find(b));
+        if ra != rb {
+            self.0[ra.max(rb)] = ra.min(rb);
+        }
+        ra != rb
+    }
+
+    // Groups of elements in the same set, in order of their smallest element
+    fn groups(&mut self) -> Vec<Vec<usize>> {
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut root_group: HashMap<usize, usize> = HashMap::new();
+        for i in 0..self.0.len() {
+            let root = self.find(i);
+            let g = *root_group.entry(root).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[g].push(i);
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod test_sketch {
+    use crate::add_to_sketch;
+
+    #[test]
+    fn test_canonical_sketch() {
+        let seq = b"ACGTTGCAAGGCTTAGCCATGACNNACGATCAGGATCCATTTAGC";
+        let rc: Vec<u8> = seq
+            .iter()
+            .rev()
+            .map(|b| match b {
+                b'A' => b'T',
+                b'C' => b'G',
+                b'G' => b'C',
+                b'T' => b'A',
+                x => *x,
+            })
+            .collect();
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        add_to_sketch(seq, 5, 1, &mut a);
+        add_to_sketch(&rc, 5, 1, &mut b);
+        a.sort_unstable();
+        b.sort_unstable();
+        // 45 bases, with NN at 23-24: 23 + 20 bases, giving 19 + 16 kmers
+        assert_eq!(a.len(), 35);
+        assert_eq!(a, b);
+    }
+}
+
+const LONG_ABOUT: &str = "Refine a Vamb cluster file by merging, splitting and filtering clusters.
+Operations are done in this order:
+1. Clusters are merged if they are linked by at least --min-links contig pairs
+   in the GFA file (e.g. from megagfa), or if the kmer sketch of the smaller cluster
+   is contained at least --min-containment in the other.
+   Merged clusters keep the name of the largest cluster.
+2. If --separator is given, clusters are split by sample, and renamed to
+   {sample}{separator}{cluster}, where the sample is the contig name up to the separator.
+3. Clusters smaller than --min-size bp or --min-contigs contigs are removed.
+Every operation is written to the log file.
+Usage: clusterpost -c clusters.tsv -f contigs.fna --gfa links.gfa --min-links 2 -s C -l log.tsv";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Vamb cluster file (may be gzipped)
+    #[arg(short, long)]
+    clusters: PathBuf,
+
+    /// FASTA file of contigs (may be gzipped). Needed for kmer merging and --min-size
+    #[arg(short, long)]
+    fasta: Option<PathBuf>,
+
+    /// GFA file with links between contigs
+    #[arg(long)]
+    gfa: Option<PathBuf>,
+
+    /// Merge clusters linked by at least this many contig pairs
+    #[arg(long)]
+    min_links: Option<usize>,
+
+    /// Merge clusters with at least this kmer containment (0-1)
+    #[arg(long)]
+    min_containment: Option<f64>,
+
+    /// Kmer size for sketches
+    #[arg(short, default_value_t = 21)]
+    k: u8,
+
+    /// Keep one in this many kmers in sketches
+    #[arg(long, default_value_t = 200)]
+    scale: u64,
+
+    /// Split clusters by sample, with this separator in contig names
+    #[arg(short, long)]
+    separator: Option<String>,
+
+    /// Remove clusters smaller than this many bp
+    #[arg(long, default_value_t = 0)]
+    min_size: u64,
+
+    /// Remove clusters with fewer contigs than this
+    #[arg(long, default_value_t = 1)]
+    min_contigs: usize,
+
+    /// Write a log of all operations to this file
+    #[arg(short, long)]
+    log: Option<PathBuf>,
+
+    /// Output cluster file [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
@@ -0,0 +1,269 @@
+use anyhow::{self, bail, Context, Result};
+use clap::{ArgGroup, Parser};
+use flate2::read::MultiGzDecoder;
+use progress::{info, LogArgs};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    for fraction in [args.bin_fraction, args.contig_fraction]
+        .into_iter()
+        .flatten()
+    {
+        if !(0.0..=1.0).contains(&fraction) {
+            bail!("Fraction must be in 0-1, got {}", fraction);
+        }
+    }
+    let clusters = read_clusters(&args.clusters)?;
+    let split = if let Some(f) = args.bin_fraction {
+        Split::Bins(f)
+    } else if let Some(f) = args.contig_fraction {
+        Split::Contigs(f)
+    } else {
+        Split::Samples(args.holdout_sample.iter().map(|s| s.as_str()).collect())
+    };
+    let is_test = split_clusters(&clusters, &split, &args.separator, args.seed)?;
+
+    let mut train = create(&args.train)?;
+    let mut test = create(&args.test)?;
+    train.write_all(b"clustername\tcontigname\n")?;
+    test.write_all(b"clustername\tcontigname\n")?;
+    let (mut n_train, mut n_test) = (0usize, 0usize);
+    for ((name, contigs), flags) in clusters.iter().zip(is_test.iter()) {
+        for (contig, &to_test) in contigs.iter().zip(flags.iter()) {
+            if to_test {
+                n_test += 1;
+                writeln!(test, "{}\t{}", name, contig)?;
+            } else {
+                n_train += 1;
+                writeln!(train, "{}\t{}", name, contig)?;
+            }
+        }
+    }
+    train.flush()?;
+    test.flush()?;
+    info!(
+        "Wrote {} contigs to train set and {} contigs to test set",
+        n_train, n_test
+    );
+    Ok(())
+}
+
+enum Split<'a> {
+    // Fraction of whole clusters in the test set
+    Bins(f64),
+    // Fraction of the contigs of each cluster in the test set
+    Contigs(f64),
+    // Samples whose contigs are in the test set
+    Samples(HashSet<&'a str>),
+}
+
+// Map a name to a number in [0, 1), which only depends on the name and seed,
+// so the split does not depend on the order of the cluster file.
+fn sample_value(name: &str, seed: u64) -> f64 {
+    // FNV-1a, followed by the splitmix64 finalizer to spread the bits
+    let mut h: u64 = 0xcbf29ce484222325 ^ seed;
+    for &b in name.as_bytes() {
+        h = (h ^ b as u64).wrapping_mul(0x100000001b3);
+    }
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^= h >> 31;
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Indices of the names with the lowest sample values, `fraction` of them rounded
+fn select<'a>(names: impl Iterator<Item = &'a str>, fraction: f64, seed: u64) -> HashSet<usize> {
+    let mut values: Vec<(f64, usize)> = names
+        .enumerate()
+        .map(|(i, name)| (sample_value(name, seed), i))
+        .collect();
+    let n = (values.len() as f64 * fraction).round() as usize;
+    values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    values.into_iter().take(n).map(|(_, i)| i).collect()
+}
+
+// For every contig of every cluster, whether it goes into the test set
+fn split_clusters(
+    clusters: &[(String, Vec<String>)],
+    split: &Split,
+    separator: &str,
+    seed: u64,
+) -> Result<Vec<Vec<bool>>> {
+    Ok(match split {
+        Split::Bins(fraction) => {
+            let test = select(clusters.iter().map(|(n, _)| n.as_str()), *fraction, seed);
+            clusters
+                .iter()
+                .enumerate()
+                .map(|(i, (_, contigs))| vec![test.contains(&i); contigs.len()])
+                .collect()
+        }
+        Split::Contigs(fraction) => clusters
+            .iter()
+            .map(|(_, contigs)| {
+                let test = select(contigs.iter().map(|c| c.as_str()), *fraction, seed);
+                (0..contigs.len()).map(|i| test.contains(&i)).collect()
+            })
+            .collect(),
+        Split::Samples(samples) => {
+            let mut seen: HashSet<&str> = HashSet::new();
+            let mut result = Vec::with_capacity(clusters.len());
+            for (_, contigs) in clusters.iter() {
+                let mut flags = Vec::with_capacity(contigs.len());
+                for contig in contigs.iter() {
+                    let Some((sample, _)) = contig.split_once(separator) else {
+                        bail!(
+                            "Contig \"{}\" has no separator \"{}\" to get its sample from",
+                            contig,
+                            separator
+                        );
+                    };
+                    seen.insert(sample);
+                    flags.push(samples.contains(sample));
+                }
+                result.push(flags);
+            }
+            if let Some(missing) = samples.iter().find(|s| !seen.contains(*s)) {
+                bail!("No contigs in cluster file from sample \"{}\"", missing);
+            }
+            result
+        }
+    })
+}
+
+#[cfg(test)]
+mod test_split {
+    use crate::{split_clusters, Split};
+
+    #[test]
+    fn test_split() {
+        let clusters: Vec<(String, Vec<String>)> = (0..10)
+            .map(|i| {
+                let contigs = (0..4).map(|j| format!("S{}C{}_{}", j % 2, i, j)).collect();
+                (format!("bin{}", i), contigs)
+            })
+            .collect();
+        let count = |flags: &[Vec<bool>]| flags.iter().flatten().filter(|&&b| b).count();
+
+        let bins = split_clusters(&clusters, &Split::Bins(0.3), "C", 1).unwrap();
+        assert_eq!(count(&bins), 12);
+        assert!(bins.iter().all(|f| f.iter().all(|&b| b == f[0])));
+        assert_eq!(
+            bins,
+            split_clusters(&clusters, &Split::Bins(0.3), "C", 1).unwrap()
+        );
+
+        let contigs = split_clusters(&clusters, &Split::Contigs(0.5), "C", 1).unwrap();
+        assert!(contigs
+            .iter()
+            .all(|f| f.iter().filter(|&&b| b).count() == 2));
+
+        let samples = Split::Samples(["S1"].into_iter().collect());
+        let by_sample = split_clusters(&clusters, &samples, "C", 1).unwrap();
+        assert!(by_sample.iter().all(|f| f == &[false, true, false, true]));
+        let missing = Split::Samples(["S2"].into_iter().collect());
+        assert!(split_clusters(&clusters, &missing, "C", 1).is_err());
+    }
+}
+
+// The clusters and their contigs, in the order of the cluster file
+fn read_clusters(path: &Path) -> Result<Vec<(String, Vec<String>)>> {
+    let mut clusters: Vec<(String, Vec<String>)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut contigs: HashSet<String> = HashSet::new();
+    let file =
+        File::open(path).with_context(|| format!("Could not open file \"{}\"", path.display()))?;
+    let reader: Box<dyn BufRead> = if path.extension().is_some_and(|e| e == "gz") {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line.context("Failed to read line from cluster file")?;
+        if (lineno == 0 && line == "clustername\tcontigname") || line.is_empty() {
+            continue;
+        }
+        let (cluster, contig) = line
+            .split_once('\t')
+            .filter(|(_, contig)| !contig.contains('\t'))
+            .with_context(|| {
+                format!(
+                    "Expected two tab-separated fields on line {} of cluster file",
+                    lineno + 1
+                )
+            })?;
+        if !contigs.insert(contig.to_owned()) {
+            bail!(
+                "Contig \"{}\" is present multiple times in cluster file",
+                contig
+            );
+        }
+        let i = *index.entry(cluster.to_owned()).or_insert_with(|| {
+            clusters.push((cluster.to_owned(), Vec::new()));
+            clusters.len() - 1
+        });
+        clusters[i].1.push(contig.to_owned());
+    }
+    Ok(clusters)
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>> {
+    Ok(BufWriter::new(File::create(path).with_context(|| {
+        format!("Could not create output file \"{}\"", path.display())
+    })?))
+}
+
+const LONG_ABOUT: &str = "Split a Vamb cluster file into reproducible train and test sets,
+e.g. for benchmarking and cross-validation of binning post-processing.
+Either a fraction of whole clusters, a fraction of the contigs of every cluster, or all
+contigs from some samples are put in the test set, and the rest in the train set.
+Clusters and contigs are selected by a hash of their name and the seed, so the split does
+not depend on the order of the file, and the same seed always gives the same split.
+Usage: clustersample -c clusters.tsv --bin-fraction 0.2 --train train.tsv --test test.tsv";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+#[command(group(ArgGroup::new("split").required(true).args(["bin_fraction", "contig_fraction", "holdout_sample"])))]
+struct Cli {
+    /// Vamb cluster file (may be gzipped)
+    #[arg(short, long)]
+    clusters: PathBuf,
+
+    /// Output cluster file of the train set
+    #[arg(long)]
+    train: PathBuf,
+
+    /// Output cluster file of the test set
+    #[arg(long)]
+    test: PathBuf,
+
+    /// Fraction of clusters to put in the test set
+    #[arg(long)]
+    bin_fraction: Option<f64>,
+
+    /// Fraction of the contigs of each cluster to put in the test set
+    #[arg(long)]
+    contig_fraction: Option<f64>,
+
+    /// Put all contigs of this sample in the test set (can be repeated)
+    #[arg(long)]
+    holdout_sample: Vec<String>,
+
+    /// Separator between sample and contig name, for --holdout-sample
+    #[arg(long, default_value = "C")]
+    separator: String,
+
+    /// Seed for the split
+    #[arg(short, long, default_value_t = 0)]
+    seed: u64,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
@@ -0,0 +1,243 @@
+use anyhow::{self, bail, Context, Result};
+use clap::Parser;
+use flate2::read::MultiGzDecoder;
+use progress::{info, LogArgs};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{stdout, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    if !(0.0..=1.0).contains(&args.min_fraction) {
+        bail!("Minimum fraction must be in 0-1");
+    }
+    let (cluster_names, chunk_clusters) = read_clusters(&args.clusters)?;
+    let contigs = read_chunk_table(&args.table, &chunk_clusters)?;
+
+    let mut out: Box<dyn Write> = match &args.o {
+        None => Box::new(BufWriter::new(stdout().lock())),
+        Some(p) => Box::new(BufWriter::new(File::create(p).with_context(|| {
+            format!("Could not create output file \"{}\"", p.to_string_lossy())
+        })?)),
+    };
+    let mut ambiguous: Option<Box<dyn Write>> = match &args.ambiguous {
+        None => None,
+        Some(p) => {
+            let mut w =
+                BufWriter::new(File::create(p).with_context(|| {
+                    format!("Could not create file \"{}\"", p.to_string_lossy())
+                })?);
+            w.write_all(b"contigname\tbest_cluster\tfraction\tn_clusters\n")?;
+            Some(Box::new(w))
+        }
+    };
+
+    out.write_all(b"clustername\tcontigname\n")?;
+    let (mut n_assigned, mut n_ambiguous, mut n_unbinned) = (0usize, 0usize, 0usize);
+    for contig in contigs.iter() {
+        match assign(&contig.weights, args.min_fraction) {
+            Assignment::Unbinned => n_unbinned += 1,
+            Assignment::Bin(cluster) => {
+                n_assigned += 1;
+                writeln!(out, "{}\t{}", cluster_names[cluster], contig.name)?;
+            }
+            Assignment::Ambiguous(cluster, fraction) => {
+                n_ambiguous += 1;
+                if let Some(w) = ambiguous.as_mut() {
+                    writeln!(
+                        w,
+                        "{}\t{}\t{:.4}\t{}",
+                        contig.name,
+                        cluster_names[cluster],
+                        fraction,
+                        contig.weights.len()
+                    )?;
+                }
+            }
+        }
+    }
+    out.flush()?;
+    if let Some(mut w) = ambiguous {
+        w.flush()?;
+    }
+    info!(
+        "Assigned {} contigs, {} ambiguous, {} with no binned chunks",
+        n_assigned, n_ambiguous, n_unbinned
+    );
+    Ok(())
+}
+
+struct Contig {
+    name: String,
+    // Total length of the contig's chunks in each cluster
+    weights: Vec<(usize, u64)>,
+}
+
+enum Assignment {
+    Unbinned,
+    Bin(usize),
+    // Best cluster and its fraction of the binned length
+    Ambiguous(usize, f64),
+}
+
+// Assign to the cluster with the largest summed chunk length, if it has at least
+// `min_fraction` of the total binned length, and is not tied with another cluster.
+fn assign(weights: &[(usize, u64)], min_fraction: f64) -> Assignment {
+    let Some(&(best, best_weight)) = weights.iter().max_by_key(|(_, w)| *w) else {
+        return Assignment::Unbinned;
+    };
+    let total: u64 = weights.iter().map(|(_, w)| w).sum();
+    let fraction = best_weight as f64 / total as f64;
+    let is_tied = weights.iter().filter(|(_, w)| *w == best_weight).count() > 1;
+    if is_tied || fraction < min_fraction {
+        Assignment::Ambiguous(best, fraction)
+    } else {
+        Assignment::Bin(best)
+    }
+}
+
+#[cfg(test)]
+mod test_assign {
+    use crate::{assign, Assignment};
+
+    #[test]
+    fn test_assign() {
+        assert!(matches!(assign(&[], 0.5), Assignment::Unbinned));
+        assert!(matches!(assign(&[(3, 100)], 0.5), Assignment::Bin(3)));
+        assert!(matches!(
+            assign(&[(0, 100), (1, 300)], 0.5),
+            Assignment::Bin(1)
+        ));
+        assert!(matches!(
+            assign(&[(0, 100), (1, 100)], 0.5),
+            Assignment::Ambiguous(_, f) if f == 0.5
+        ));
+        assert!(matches!(
+            assign(&[(0, 100), (1, 150), (2, 100)], 0.5),
+            Assignment::Ambiguous(1, _)
+        ));
+    }
+}
+
+fn open_input(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path)
+        .with_context(|| format!("Could not open file \"{}\"", path.to_string_lossy()))?;
+    if path.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+// Returns the cluster names, and a map from chunk name to index into the names.
+fn read_clusters(path: &Path) -> Result<(Vec<String>, HashMap<String, usize>)> {
+    let mut names: Vec<String> = Vec::new();
+    let mut name_index: HashMap<String, usize> = HashMap::new();
+    let mut chunks: HashMap<String, usize> = HashMap::new();
+    for (lineno, line) in open_input(path)?.lines().enumerate() {
+        let line = line.context("Failed to read line from cluster file")?;
+        if (lineno == 0 && line == "clustername\tcontigname") || line.is_empty() {
+            continue;
+        }
+        let (cluster, chunk) = line
+            .split_once('\t')
+            .filter(|(_, chunk)| !chunk.contains('\t'))
+            .with_context(|| {
+                format!(
+                    "Expected two tab-separated fields on line {} of cluster file",
+                    lineno + 1
+                )
+            })?;
+        let index = *name_index.entry(cluster.to_owned()).or_insert_with(|| {
+            names.push(cluster.to_owned());
+            names.len() - 1
+        });
+        if chunks.insert(chunk.to_owned(), index).is_some() {
+            bail!(
+                "Chunk \"{}\" is present multiple times in cluster file",
+                chunk
+            );
+        }
+    }
+    Ok((names, chunks))
+}
+
+// Returns the contigs in the order they first appear in the chunk table
+fn read_chunk_table(path: &Path, chunk_clusters: &HashMap<String, usize>) -> Result<Vec<Contig>> {
+    let mut contigs: Vec<Contig> = Vec::new();
+    let mut contig_index: HashMap<String, usize> = HashMap::new();
+    for (lineno, line) in open_input(path)?.lines().enumerate() {
+        let line = line.context("Failed to read line from chunk table")?;
+        if lineno == 0 && line.starts_with("chunk\tcontig") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [chunk, contig, start, end] = fields[..] else {
+            bail!(
+                "Expected four tab-separated fields on line {} of chunk table",
+                lineno + 1
+            );
+        };
+        let parse = |s: &str| {
+            s.parse::<u64>().with_context(|| {
+                format!("Invalid coordinate on line {} of chunk table", lineno + 1)
+            })
+        };
+        let length = parse(end)?.saturating_sub(parse(start)?);
+        let index = *contig_index.entry(contig.to_owned()).or_insert_with(|| {
+            contigs.push(Contig {
+                name: contig.to_owned(),
+                weights: Vec::new(),
+            });
+            contigs.len() - 1
+        });
+        let Some(&cluster) = chunk_clusters.get(chunk) else {
+            continue;
+        };
+        let weights = &mut contigs[index].weights;
+        match weights.iter_mut().find(|(c, _)| *c == cluster) {
+            Some((_, w)) => *w += length,
+            None => weights.push((cluster, length)),
+        }
+    }
+    Ok(contigs)
+}
+
+const LONG_ABOUT: &str = "Assign contigs to bins from cluster assignments of their chunks.
+This is the step after binning chunks produced by `cchunk`. Each contig is assigned
+to the cluster containing the largest total length of its chunks. If that cluster has
+less than the minimum fraction of the binned length of the contig, or is tied with
+another cluster, the contig is ambiguous and is not assigned.
+Prints a Vamb-style cluster TSV file.
+Usage: cunchunk -c chunk_clusters.tsv -t chunks.tsv -a ambiguous.tsv > clusters.tsv";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Cluster file of chunks, with columns clustername and contigname (may be gzipped)
+    #[arg(short, long)]
+    clusters: PathBuf,
+
+    /// Chunk table written by cchunk (may be gzipped)
+    #[arg(short, long)]
+    table: PathBuf,
+
+    /// Output cluster file of contigs [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    /// Write ambiguous contigs to this TSV file
+    #[arg(short, long)]
+    ambiguous: Option<PathBuf>,
+
+    /// Minimum fraction of binned length in the best cluster
+    #[arg(short, long, default_value_t = 0.5)]
+    min_fraction: f64,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
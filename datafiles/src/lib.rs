@@ -0,0 +1,101 @@
+//! Selection of data files when walking directories, shared by the tools in this repository,
+//! so their --include and --exclude options behave the same.
+
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+pub const FASTA_EXTENSIONS: [&str; 4] = ["fna", "fasta", "fa", "faa"];
+pub const FASTQ_EXTENSIONS: [&str; 2] = ["fq", "fastq"];
+
+fn has_extension(p: &Path, extensions: &[&str]) -> bool {
+    p.extension()
+        .is_some_and(|e| e.to_str().is_some_and(|s| extensions.contains(&s)))
+}
+
+pub fn is_fasta(p: &Path) -> bool {
+    has_extension(p, &FASTA_EXTENSIONS)
+}
+
+pub fn is_fastq(p: &Path) -> bool {
+    has_extension(p, &FASTQ_EXTENSIONS)
+}
+
+/// Selects files by glob patterns. Patterns without a `/` match the file name in any
+/// directory, like in a .gitignore file. Patterns with a `/` match the whole path
+/// relative to the directory being walked.
+pub struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+fn build_globset(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+        let full = if pattern.contains('/') {
+            pattern.to_owned()
+        } else {
+            format!("**/{}", pattern)
+        };
+        // Like in a .gitignore file, * does not match across directories, but ** does
+        let glob = GlobBuilder::new(&full)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("Invalid pattern \"{}\"", pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(build_globset(include)?)
+            },
+            exclude: build_globset(exclude)?,
+        })
+    }
+
+    /// Whether the file at the relative path is selected. If no include patterns were given,
+    /// `default` decides whether the file is included. Exclude patterns take precedence.
+    pub fn is_selected(&self, relative_path: &Path, default: bool) -> bool {
+        let included = match &self.include {
+            None => default,
+            Some(set) => set.is_match(relative_path),
+        };
+        included && !self.exclude.is_match(relative_path)
+    }
+}
+
+#[cfg(test)]
+mod test_filter {
+    use crate::{is_fasta, PathFilter};
+    use std::path::Path;
+
+    #[test]
+    fn test_filter() {
+        let no_patterns = PathFilter::new(&[], &[]).unwrap();
+        assert!(no_patterns.is_selected(Path::new("a/b.fq"), true));
+        assert!(!no_patterns.is_selected(Path::new("a/b.fq"), false));
+
+        let filter = PathFilter::new(
+            &["*.fq.gz".to_owned(), "raw/*.bam".to_owned()],
+            &["tmp/**".to_owned()],
+        )
+        .unwrap();
+        assert!(filter.is_selected(Path::new("x/y/reads.fq.gz"), false));
+        assert!(!filter.is_selected(Path::new("x/y/reads.fq"), true));
+        assert!(filter.is_selected(Path::new("raw/aln.bam"), false));
+        assert!(!filter.is_selected(Path::new("x/raw/aln.bam"), false));
+        assert!(!filter.is_selected(Path::new("raw/x/aln.bam"), false));
+        assert!(!filter.is_selected(Path::new("tmp/reads.fq.gz"), false));
+
+        assert!(PathFilter::new(&["a[".to_owned()], &[]).is_err());
+        assert!(is_fasta(Path::new("a/b.fna")));
+        assert!(!is_fasta(Path::new("a/b.fna.gz")));
+    }
+}
@@ -0,0 +1,449 @@
+use anyhow::{self, bail, Context, Result};
+use bio::io::fastq::{Reader, Record, Records};
+use clap::Parser;
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use progress::{info, LogArgs, Progress};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{stdin, stdout, BufRead, BufReader, BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    thread,
+};
+
+// Number of reads (or pairs) read into memory before they are sent to the writers.
+const BATCH_SIZE: usize = 16_384;
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    if args.in2.is_some() && args.i.is_none() {
+        bail!("Paired mode requires -i to be a file, not stdin");
+    }
+    let samples = read_sample_sheet(&args.barcodes)?;
+    check_barcode_distances(&samples, args.mismatches)?;
+    std::fs::create_dir_all(&args.outdir).with_context(|| {
+        format!(
+            "Could not create output directory \"{}\"",
+            args.outdir.to_string_lossy()
+        )
+    })?;
+
+    let barcodes: Vec<&[u8]> = samples.iter().map(|s| s.barcode.as_slice()).collect();
+    let paired = args.in2.is_some();
+    let unit = if paired { "pairs" } else { "reads" };
+    // The last output is for undetermined reads
+    let mut names: Vec<&str> = samples.iter().map(|s| s.name.as_str()).collect();
+    names.push("undetermined");
+    let mut first = Reader::from_bufread(open_input(args.i.as_deref())?).records();
+    let mut second = match &args.in2 {
+        Some(p) => Some(Reader::from_bufread(open_input(Some(p))?).records()),
+        None => None,
+    };
+
+    let mut counts = vec![Count::default(); samples.len()];
+    let mut undetermined = 0usize;
+    let n_writers = args.threads.get().min(names.len());
+    // Each writer thread owns the output files of every n_writers'th sample, so the
+    // outputs are compressed in parallel.
+    thread::scope(|s| -> Result<()> {
+        let mut senders = Vec::with_capacity(n_writers);
+        let mut handles = Vec::with_capacity(n_writers);
+        for w in 0..n_writers {
+            let (sender, receiver) = crossbeam_channel::bounded::<(usize, Vec<u8>, Vec<u8>)>(8);
+            senders.push(sender);
+            let mut files = Vec::new();
+            for (i, name) in names.iter().enumerate().skip(w).step_by(n_writers) {
+                let path = |mate: &str| args.outdir.join(format!("{}{}.fq.gz", name, mate));
+                let outputs = if paired {
+                    (create(&path("_R1"))?, Some(create(&path("_R2"))?))
+                } else {
+                    (create(&path(""))?, None)
+                };
+                files.push((i, outputs));
+            }
+            handles.push(s.spawn(move || -> Result<()> {
+                for (i, data1, data2) in receiver.iter() {
+                    let (out1, out2) = &mut files.iter_mut().find(|(j, _)| *j == i).unwrap().1;
+                    out1.write_all(&data1)?;
+                    if let Some(out2) = out2 {
+                        out2.write_all(&data2)?;
+                    }
+                }
+                for (_, (out1, out2)) in files {
+                    out1.finish()?.flush()?;
+                    if let Some(out2) = out2 {
+                        out2.finish()?.flush()?;
+                    }
+                }
+                Ok(())
+            }));
+        }
+
+        let mut buffers = vec![(Vec::new(), Vec::new()); names.len()];
+        let progress = Progress::new("Demultiplexing", unit, None);
+        'read: loop {
+            let batch = next_batch(&mut first, second.as_mut())?;
+            if batch.is_empty() {
+                break;
+            }
+            progress.inc(batch.len() as u64);
+            for (fst, snd) in batch.iter() {
+                let (index, trim) = match classify(fst.seq(), &barcodes, args.mismatches) {
+                    Some((index, mismatches)) => {
+                        counts[index].add(mismatches);
+                        let trim = if args.keep_barcode {
+                            0
+                        } else {
+                            barcodes[index].len()
+                        };
+                        (index, trim)
+                    }
+                    None => {
+                        undetermined += 1;
+                        (names.len() - 1, 0)
+                    }
+                };
+                let (buf1, buf2) = &mut buffers[index];
+                write_fastq(buf1, fst, trim);
+                if let Some(snd) = snd {
+                    write_fastq(buf2, snd, 0);
+                }
+            }
+            for (i, (buf1, buf2)) in buffers.iter_mut().enumerate() {
+                if buf1.is_empty() {
+                    continue;
+                }
+                let data = (i, std::mem::take(buf1), std::mem::take(buf2));
+                // Only fails if the writer exited with an error, which we return below
+                if senders[i % n_writers].send(data).is_err() {
+                    break 'read;
+                }
+            }
+        }
+        drop(senders);
+        for handle in handles {
+            handle.join().unwrap().context("Failed to write output")?;
+        }
+        progress.finish();
+        Ok(())
+    })?;
+
+    let assigned: usize = counts.iter().map(|c| c.perfect + c.mismatched).sum();
+    let total = assigned + undetermined;
+    let fraction = |n: usize| n as f64 / total.max(1) as f64;
+    let mut out = BufWriter::new(stdout().lock());
+    out.write_all(b"sample\tbarcode\treads\tperfect\tmismatched\tfraction\n")?;
+    for (sample, count) in samples.iter().zip(counts.iter()) {
+        let n = count.perfect + count.mismatched;
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{:.4}",
+            sample.name,
+            String::from_utf8_lossy(&sample.barcode),
+            n,
+            count.perfect,
+            count.mismatched,
+            fraction(n)
+        )?;
+    }
+    writeln!(
+        out,
+        "undetermined\t-\t{}\t-\t-\t{:.4}",
+        undetermined,
+        fraction(undetermined)
+    )?;
+    out.flush()?;
+    info!(
+        "Assigned {} of {} {} to {} samples",
+        assigned,
+        total,
+        unit,
+        samples.len()
+    );
+    Ok(())
+}
+
+struct Sample {
+    name: String,
+    barcode: Vec<u8>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Count {
+    perfect: usize,
+    mismatched: usize,
+}
+
+impl Count {
+    fn add(&mut self, mismatches: usize) {
+        if mismatches == 0 {
+            self.perfect += 1;
+        } else {
+            self.mismatched += 1;
+        }
+    }
+}
+
+// The sample sheet has two tab-separated columns: sample name and barcode.
+fn read_sample_sheet(path: &Path) -> Result<Vec<Sample>> {
+    let mut samples: Vec<Sample> = Vec::new();
+    let mut names: HashSet<String> = HashSet::new();
+    for (lineno, line) in open_input(Some(path))?.lines().enumerate() {
+        let line = line.context("Failed to read line from sample sheet")?;
+        if (lineno == 0 && line == "sample\tbarcode") || line.is_empty() {
+            continue;
+        }
+        let Some((name, barcode)) = line.split_once('\t') else {
+            bail!(
+                "Expected two tab-separated fields on line {} of sample sheet",
+                lineno + 1
+            );
+        };
+        let barcode = barcode.trim().to_ascii_uppercase().into_bytes();
+        if barcode.is_empty() || !barcode.iter().all(|b| b"ACGT".contains(b)) {
+            bail!(
+                "Barcode on line {} of sample sheet must be only ACGT",
+                lineno + 1
+            );
+        }
+        if name.is_empty() || name.contains('/') || name == "undetermined" {
+            bail!(
+                "Invalid sample name \"{}\" on line {} of sample sheet",
+                name,
+                lineno + 1
+            );
+        }
+        if !names.insert(name.to_owned()) {
+            bail!(
+                "Sample \"{}\" is present multiple times in sample sheet",
+                name
+            );
+        }
+        samples.push(Sample {
+            name: name.to_owned(),
+            barcode,
+        });
+    }
+    if samples.is_empty() {
+        bail!("Sample sheet contains no samples");
+    }
+    Ok(samples)
+}
+
+fn hamming(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).filter(|(x, y)| x != y).count()
+}
+
+// With m mismatches allowed, a read could match two barcodes differing in 2m or fewer
+// positions, so such barcodes cannot be reliably told apart. Barcodes of different lengths
+// are compared over the length of the shortest.
+fn check_barcode_distances(samples: &[Sample], mismatches: usize) -> Result<()> {
+    for (i, a) in samples.iter().enumerate() {
+        for b in samples[i + 1..].iter() {
+            let distance = hamming(&a.barcode, &b.barcode);
+            if distance <= 2 * mismatches {
+                bail!(
+                    "Barcodes of samples \"{}\" and \"{}\" differ in only {} positions, \
+                    so they cannot be distinguished with {} mismatches",
+                    a.name,
+                    b.name,
+                    distance,
+                    mismatches
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// Find the barcode at the start of the read with the fewest mismatches. Returns its
+// index and number of mismatches, or None if there is no unique best match.
+fn classify(seq: &[u8], barcodes: &[&[u8]], max_mismatches: usize) -> Option<(usize, usize)> {
+    let mut best: Option<(usize, usize)> = None;
+    let mut is_tied = false;
+    for (i, barcode) in barcodes.iter().enumerate() {
+        if seq.len() < barcode.len() {
+            continue;
+        }
+        let mismatches = hamming(&seq[..barcode.len()], barcode);
+        if mismatches > max_mismatches {
+            continue;
+        }
+        match best {
+            Some((_, m)) if m < mismatches => (),
+            Some((_, m)) if m == mismatches => is_tied = true,
+            _ => {
+                best = Some((i, mismatches));
+                is_tied = false;
+            }
+        }
+    }
+    if is_tied {
+        None
+    } else {
+        best
+    }
+}
+
+#[cfg(test)]
+mod test_classify {
+    use crate::{check_barcode_distances, classify, Sample};
+
+    #[test]
+    fn test_classify() {
+        let barcodes: [&[u8]; 3] = [b"AAAA", b"CCCC", b"GGGGGG"];
+        assert_eq!(classify(b"AAAATTT", &barcodes, 1), Some((0, 0)));
+        assert_eq!(classify(b"CACCTTT", &barcodes, 1), Some((1, 1)));
+        assert_eq!(classify(b"CACCTTT", &barcodes, 0), None);
+        assert_eq!(classify(b"GGGGGTTT", &barcodes, 1), Some((2, 1)));
+        // Too short for the barcode
+        assert_eq!(classify(b"GGGGG", &barcodes, 1), None);
+        // Tied between two barcodes
+        assert_eq!(classify(b"AACCTTT", &barcodes, 2), None);
+    }
+
+    #[test]
+    fn test_distances() {
+        let samples: Vec<Sample> = ["AAAA", "AACC"]
+            .iter()
+            .enumerate()
+            .map(|(i, b)| Sample {
+                name: i.to_string(),
+                barcode: b.as_bytes().to_vec(),
+            })
+            .collect();
+        assert!(check_barcode_distances(&samples, 0).is_ok());
+        assert!(check_barcode_distances(&samples, 1).is_err());
+    }
+}
+
+// A record and its mate, if we run in paired mode
+type Pair = (Record, Option<Record>);
+
+// An empty batch means both inputs are exhausted.
+fn next_batch(
+    first: &mut Records<Box<dyn BufRead>>,
+    mut second: Option<&mut Records<Box<dyn BufRead>>>,
+) -> Result<Vec<Pair>> {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    while batch.len() < BATCH_SIZE {
+        let fst = match first.next() {
+            None => break,
+            Some(r) => r.context("Failed to parse FASTQ record")?,
+        };
+        let snd = match second.as_mut() {
+            None => None,
+            Some(records) => {
+                let snd = records
+                    .next()
+                    .context("Mate file has fewer records than first file")?
+                    .context("Failed to parse FASTQ record in mate file")?;
+                if mate_name(fst.id()) != mate_name(snd.id()) {
+                    bail!(
+                        "Mates out of sync: \"{}\" in first file, \"{}\" in mate file",
+                        fst.id(),
+                        snd.id()
+                    );
+                }
+                Some(snd)
+            }
+        };
+        batch.push((fst, snd));
+    }
+    if batch.is_empty() && second.is_some_and(|r| r.next().is_some()) {
+        bail!("Mate file has more records than first file")
+    }
+    Ok(batch)
+}
+
+// Illumina-style names may end with /1 and /2 to distinguish the mates
+fn mate_name(id: &str) -> &str {
+    id.strip_suffix("/1")
+        .or_else(|| id.strip_suffix("/2"))
+        .unwrap_or(id)
+}
+
+// Write the record with the first `trim` bases removed
+fn write_fastq(buffer: &mut Vec<u8>, record: &Record, trim: usize) {
+    buffer.push(b'@');
+    buffer.extend_from_slice(record.id().as_bytes());
+    if let Some(desc) = record.desc() {
+        buffer.push(b' ');
+        buffer.extend_from_slice(desc.as_bytes());
+    }
+    buffer.push(b'\n');
+    buffer.extend_from_slice(&record.seq()[trim..]);
+    buffer.extend_from_slice(b"\n+\n");
+    buffer.extend_from_slice(&record.qual()[trim..]);
+    buffer.push(b'\n');
+}
+
+// Read from stdin, from a file, or from a gzipped file if the name ends with .gz
+fn open_input(path: Option<&Path>) -> Result<Box<dyn BufRead>> {
+    let Some(p) = path else {
+        return Ok(Box::new(stdin().lock()));
+    };
+    let file = File::open(p)
+        .with_context(|| format!("Could not open input file \"{}\"", p.to_string_lossy()))?;
+    if p.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+fn create(path: &Path) -> Result<GzEncoder<BufWriter<File>>> {
+    let file = File::create(path).with_context(|| {
+        format!(
+            "Could not create output file \"{}\"",
+            path.to_string_lossy()
+        )
+    })?;
+    Ok(GzEncoder::new(BufWriter::new(file), Compression::default()))
+}
+
+const LONG_ABOUT: &str = "Demultiplex FASTQ files by inline barcodes at the start of the reads.
+The sample sheet is a TSV file with the columns sample and barcode. Each read is assigned to
+the sample whose barcode matches the start of the read with the fewest mismatches. Reads
+with no unique match within the allowed mismatches are written to the undetermined files.
+In paired mode, the barcode is read from the first mate.
+Outputs are written as gzipped files to the output directory, e.g. S1_R1.fq.gz and S1_R2.fq.gz
+in paired mode, and a summary table is printed to stdout.
+Usage: demux -i R1.fq.gz --in2 R2.fq.gz -b samples.tsv -o outdir -m 1 -t 4 > summary.tsv";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Input FASTQ file (may be gzipped) [stdin]
+    #[arg(short)]
+    i: Option<PathBuf>,
+
+    /// Input FASTQ file of second mates
+    #[arg(long)]
+    in2: Option<PathBuf>,
+
+    /// Sample sheet with sample names and barcodes
+    #[arg(short, long)]
+    barcodes: PathBuf,
+
+    /// Output directory
+    #[arg(short, long)]
+    outdir: PathBuf,
+
+    /// Maximum number of mismatches in barcodes
+    #[arg(short, long, default_value_t = 1)]
+    mismatches: usize,
+
+    /// Do not remove the barcode from the reads
+    #[arg(short, long)]
+    keep_barcode: bool,
+
+    /// Number of threads used for compressing output
+    #[arg(short, long, default_value = "1")]
+    threads: NonZeroUsize,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
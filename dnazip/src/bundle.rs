@@ -0,0 +1,300 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{bail, Context};
+use flate2::Crc;
+use htslite::bgzf::EOF_BLOCK;
+use progress::warn;
+
+use crate::{
+    compress_chunk, create_parent, is_unchanged, manifest::Entry, sync_file, validated,
+    verify_compressed, write_atomically, write_path, Codec, Existing, FileProgress, FileReport,
+    Options,
+};
+
+// The name of the bundle in each directory, before the extension of the codec
+const BUNDLE_NAME: &str = "bundle";
+
+// Where a file is in a bundle
+struct Member {
+    name: String,
+    offset: u64,
+    compressed_size: u64,
+    original_size: u64,
+}
+
+// Compress each file as a gzip member or zstd frame of its own, and write them one after
+// another to the bundle, which decompresses to all the files concatenated
+fn write_members(
+    files: &[PathBuf],
+    p: &OsStr,
+    options: &Options,
+    progress: &FileProgress,
+    reports: &mut Vec<FileReport>,
+) -> anyhow::Result<Vec<Member>> {
+    let mut dst = BufWriter::new(
+        File::create(p).with_context(|| format!("Could not create bundle {:?}", p))?,
+    );
+    let mut members = Vec::with_capacity(files.len());
+    let mut crc = Crc::new();
+    let mut offset = 0;
+    let mut length = 0;
+    for path in files {
+        let start = Instant::now();
+        let file = options.open(path)?;
+        let modified = file.get_ref().metadata()?.modified().ok();
+        let mut data = Vec::new();
+        validated(progress.wrap(file), path, options.validate)
+            .read_to_end(&mut data)
+            .with_context(|| format!("Could not read file {:?}", path))?;
+        crc.update(&data);
+        let mut file_crc = Crc::new();
+        file_crc.update(&data);
+        length += data.len() as u64;
+        let compressed = compress_chunk(&data, options.codec, options.level)
+            .with_context(|| format!("Could not compress file {:?}", path))?;
+        dst.write_all(&compressed)
+            .with_context(|| format!("Could not write bundle {:?}", p))?;
+        members.push(Member {
+            name: path.file_name().unwrap().to_string_lossy().into_owned(),
+            offset,
+            compressed_size: compressed.len() as u64,
+            original_size: data.len() as u64,
+        });
+        reports.push(FileReport {
+            path: path.to_owned(),
+            original_size: data.len() as u64,
+            compressed_size: compressed.len() as u64,
+            duration: start.elapsed(),
+            original: modified.map(|modified| Entry {
+                size: data.len() as u64,
+                modified,
+                crc: file_crc.sum(),
+            }),
+        });
+        offset += compressed.len() as u64;
+    }
+    // The members of a BGZF bundle are series of blocks, and the file must end with an empty one
+    if options.codec == Codec::Bgzip {
+        dst.write_all(&EOF_BLOCK)
+            .with_context(|| format!("Could not write bundle {:?}", p))?;
+    }
+    sync_file(dst, p)?;
+    if options.verify {
+        verify_compressed(p, options.codec, crc.sum(), length)?;
+    }
+    Ok(members)
+}
+
+// The manifest of a bundle is a TSV file with the name of each file, and the offset and size
+// of its compressed data in the bundle
+fn write_manifest(members: &[Member], p: &OsStr) -> anyhow::Result<()> {
+    let mut out = BufWriter::new(
+        File::create(p).with_context(|| format!("Could not create manifest {:?}", p))?,
+    );
+    let mut write_rows = || -> std::io::Result<()> {
+        writeln!(out, "name\toffset\tcompressed_size\toriginal_size")?;
+        for member in members {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}",
+                member.name, member.offset, member.compressed_size, member.original_size
+            )?;
+        }
+        Ok(())
+    };
+    write_rows().with_context(|| format!("Could not write manifest {:?}", p))?;
+    sync_file(out, p)?;
+    Ok(())
+}
+
+// The paths of the bundle and its manifest. The original files in an existing bundle have been
+// removed, so it is never replaced. Instead, the files are bundled into bundle.1.gz, and so on.
+fn bundle_paths(dir: &Path, options: &Options) -> anyhow::Result<(OsString, OsString)> {
+    let mut i = 0;
+    loop {
+        let name = match i {
+            0 => BUNDLE_NAME.to_owned(),
+            i => format!("{}.{}", BUNDLE_NAME, i),
+        };
+        let p = options.compressed_path(&dir.join(name));
+        let manifest = Path::new(&p).with_extension("tsv").into_os_string();
+        // A bundle of another codec may have the same manifest
+        let Some(existing) = [&p, &manifest].into_iter().find(|p| Path::new(p).exists()) else {
+            if i > 0 {
+                warn!(
+                    "A bundle already exists in {}, so the files are bundled into {}",
+                    dir.display(),
+                    Path::new(&p).display()
+                );
+            }
+            return Ok((p, manifest));
+        };
+        // --bundle conflicts with --existing overwrite and --resume
+        if options.existing == Existing::Error {
+            bail!("{:?} already exists", existing);
+        }
+        i += 1;
+    }
+}
+
+// Bundle the files of a directory into one compressed file in the directory, or in --dest,
+// and write its manifest next to it. The original files are removed once both are written.
+pub fn write_bundle(
+    dir: &Path,
+    files: &[PathBuf],
+    options: &Options,
+    progress: &FileProgress,
+) -> anyhow::Result<Vec<FileReport>> {
+    // If any file changed, the bundle is written again with all files
+    let mut unchanged = true;
+    for path in files {
+        if !is_unchanged(path, options)? {
+            unchanged = false;
+            break;
+        }
+    }
+    if unchanged {
+        if options.verbose {
+            write_path(dir, Some("Unchanged: "), None);
+        }
+        return Ok(Vec::new());
+    }
+    // Tabs and newlines separate the fields and lines of the manifest
+    if let Some(path) = files.iter().find(|path| {
+        let name = path.file_name().unwrap().as_encoded_bytes();
+        name.iter().any(|b| b"\t\n".contains(b))
+    }) {
+        bail!(
+            "File name has a tab or newline, so the files in {} are not bundled: {:?}",
+            dir.display(),
+            path
+        );
+    }
+    let (p, manifest) = bundle_paths(dir, options)?;
+    if options.dry_run {
+        let prefix = format!("Would bundle {} files into ", files.len());
+        write_path(Path::new(&p), Some(&prefix), None);
+        return Ok(Vec::new());
+    }
+    create_parent(&p)?;
+    let mut reports = Vec::with_capacity(files.len());
+    let members = write_atomically(&p, |tmp| {
+        write_members(files, tmp, options, progress, &mut reports)
+    })?;
+    if let Err(e) = write_atomically(&manifest, |tmp| write_manifest(&members, tmp)) {
+        // A bundle without its manifest is of little use, so the files are kept instead.
+        // Errors are ignored, since the first error is more useful
+        let _ = std::fs::remove_file(&p);
+        return Err(e);
+    }
+    for path in files {
+        options.remove_original(path, &p, None)?;
+    }
+    if options.verbose {
+        let prefix = format!("Bundled {} files into ", files.len());
+        write_path(Path::new(&p), Some(&prefix), None);
+    }
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod test_bundle {
+    use crate::{
+        bundle::write_bundle,
+        test_dnazip::{options, scratch_dir},
+        Codec, Existing, FileProgress, Options,
+    };
+    use flate2::read::MultiGzDecoder;
+    use htslite::bgzf::EOF_BLOCK;
+    use progress::Progress;
+    use std::{cell::Cell, io::Read, path::Path};
+
+    fn bundle(dir: &Path, names: &[&str], existing: Existing) -> anyhow::Result<()> {
+        let options = Options {
+            existing,
+            ..options(dir)
+        };
+        bundle_with(dir, names, &options)
+    }
+
+    fn bundle_with(dir: &Path, names: &[&str], options: &Options) -> anyhow::Result<()> {
+        let files: Vec<_> = names.iter().map(|name| dir.join(name)).collect();
+        for path in &files {
+            std::fs::write(path, format!(">{}\nACGT\n", path.display())).unwrap();
+        }
+        let bytes = Progress::new("Compressing", "bytes", None);
+        let progress = FileProgress {
+            bytes: &bytes,
+            n_read: Cell::new(0),
+        };
+        write_bundle(dir, &files, options, &progress).map(|_| ())
+    }
+
+    fn read_to_string(path: &Path, gzipped: bool) -> String {
+        let file = std::fs::File::open(path).unwrap();
+        let mut s = String::new();
+        if gzipped {
+            MultiGzDecoder::new(file).read_to_string(&mut s).unwrap();
+        } else {
+            std::io::BufReader::new(file)
+                .read_to_string(&mut s)
+                .unwrap();
+        }
+        s
+    }
+
+    #[test]
+    fn test_existing_bundle_is_kept() {
+        let dir = scratch_dir("bundle");
+        bundle(&dir, &["a.fa", "b.fa"], Existing::Skip).unwrap();
+        assert!(!dir.join("a.fa").exists() && !dir.join("b.fa").exists());
+        let first = read_to_string(&dir.join("bundle.gz"), true);
+        let manifest = read_to_string(&dir.join("bundle.tsv"), false);
+
+        // The originals in the first bundle are gone, so it must not be replaced
+        bundle(&dir, &["c.fa", "e.fa"], Existing::Skip).unwrap();
+        assert_eq!(read_to_string(&dir.join("bundle.gz"), true), first);
+        assert_eq!(read_to_string(&dir.join("bundle.tsv"), false), manifest);
+        assert!(first.contains("a.fa") && first.contains("b.fa"));
+        let second = read_to_string(&dir.join("bundle.1.gz"), true);
+        assert!(second.contains("c.fa") && second.contains("e.fa"));
+        let lines: Vec<_> = read_to_string(&dir.join("bundle.1.tsv"), false)
+            .lines()
+            .map(|line| line.split('\t').next().unwrap().to_owned())
+            .collect();
+        assert_eq!(lines, ["name", "c.fa", "e.fa"]);
+
+        assert!(bundle(&dir, &["f.fa", "g.fa"], Existing::Error).is_err());
+        assert!(dir.join("f.fa").exists() && !dir.join("bundle.2.gz").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bgzf_bundle() {
+        let dir = scratch_dir("bundle_bgzf");
+        let options = Options {
+            codec: Codec::Bgzip,
+            ..options(&dir)
+        };
+        bundle_with(&dir, &["a.fa", "b.fa"], &options).unwrap();
+        let bytes = std::fs::read(dir.join("bundle.gz")).unwrap();
+        assert!(bytes.ends_with(&EOF_BLOCK));
+        let s = read_to_string(&dir.join("bundle.gz"), true);
+        assert!(s.contains("a.fa") && s.contains("b.fa"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_name_with_tab() {
+        let dir = scratch_dir("bundle_tab");
+        assert!(bundle(&dir, &["a.fa", "b\t.fa"], Existing::Skip).is_err());
+        assert!(dir.join("a.fa").exists() && !dir.join("bundle.gz").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,29 +1,81 @@
-use anyhow::{self, Context};
-use clap::Parser;
+use anyhow::{self, bail, Context};
+use clap::{CommandFactory, Parser, ValueEnum};
 
-use crossbeam_channel::{self, Receiver, RecvError, TryRecvError};
-use flate2::{bufread::GzEncoder, Compression};
-use std::io::{stderr, ErrorKind, Write};
+use bundle::write_bundle;
+use crossbeam_channel::{self, Receiver, RecvError, Sender, TrySendError};
+use datafiles::{is_fasta, is_fastq, PathFilter};
+use flate2::{bufread::GzEncoder, read::MultiGzDecoder, Compression, CrcReader, CrcWriter};
+use htslite::{
+    bgzf::{compress_block, EOF_BLOCK, MAX_BLOCK_DATA},
+    BgzfWriter,
+};
+use manifest::{Entry, Manifest};
+use progress::{error, info, warn, LogArgs, Progress};
+use queue::{purge, DeletionQueue};
+use report::{write_report, FileReport};
+use std::io::{stderr, BufRead, ErrorKind, Write};
 use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashSet},
+    ffi::{OsStr, OsString},
     fs::File,
-    io::{BufReader, BufWriter},
+    io::{self, BufReader, BufWriter, Read},
+    num::NonZeroUsize,
+    ops::RangeInclusive,
+    os::unix::{
+        ffi::OsStringExt,
+        fs::{symlink, MetadataExt},
+    },
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use validate::{SeqFormat, Validator};
+use walkdir::{DirEntry, WalkDir};
+use xz2::{
+    stream::{Check, MtStreamBuilder},
+    write::XzEncoder,
 };
-use walkdir::WalkDir;
 
-/// Gzip compresses all FAST{Q,A} files found recursively in the given directory.
-/// Does not follow symlinks.
+mod bundle;
+mod manifest;
+mod queue;
+mod report;
+mod validate;
+
+/// Gzip compresses all FAST{Q,A} files found recursively in the given directory,
+/// or compresses them with zstd or xz with `--codec zstd` or `--codec xz`.
+/// Does not follow symlinks, unless --follow-symlinks is given.
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Directory to start from
+    /// Directory to start from. With --files-from, the directory the listed paths are
+    /// relative to [default: .]
+    #[arg(
+        required_unless_present_any = ["files_from", "purge"],
+        default_value = ".",
+        hide_default_value = true
+    )]
     start: PathBuf,
 
+    /// Compress the files listed in this file, one per line, instead of all files found in
+    /// the start directory. Use - to read the list from standard input
+    #[arg(long)]
+    files_from: Option<PathBuf>,
+
     /// Print the paths that would be compressed; do not compress
     #[arg(short, long)]
     dry_run: bool,
 
+    /// With --dry-run, compress the first 16 MiB of each file to estimate its compressed
+    /// size, and print the estimated sizes and space savings
+    #[arg(short, long, requires = "dry_run")]
+    estimate: bool,
+
     /// Print paths that are being compressed
     #[arg(short, long)]
     verbose: bool,
@@ -31,84 +83,1263 @@ struct Cli {
     /// Number of additional threads to use for compression
     #[arg(short, long, default_value_t = 0)]
     threads: u8,
+
+    /// Compression format of the output files
+    #[arg(long, value_enum, default_value_t = Codec::Gzip)]
+    codec: Codec,
+
+    /// Compression level, 0-9 for gzip and bgzip, 1-22 for zstd and the preset 0-9 for xz
+    /// [default: 6 for gzip, bgzip and xz, 3 for zstd]
+    #[arg(short, long)]
+    level: Option<u32>,
+
+    /// Also compress gzipped FASTA and FASTQ files again that were compressed with the
+    /// fastest gzip level, like gzip -1, or all of them with --codec zstd
+    #[arg(long)]
+    recompress: bool,
+
+    /// Number of threads compressing each file, in blocks like pigz. Useful for a few
+    /// large files. Each of the --threads workers uses this many threads
+    #[arg(short, long, default_value = "1")]
+    block_threads: NonZeroUsize,
+
+    /// Do not decompress each compressed file and compare it to the original before
+    /// removing the original
+    #[arg(long)]
+    no_verify: bool,
+
+    /// What to do with files whose compressed file already exists, e.g. from an
+    /// interrupted run
+    #[arg(long, value_enum, default_value_t = Existing::Skip)]
+    existing: Existing,
+
+    /// Continue an interrupted run: If a compressed file already exists and has the same
+    /// content as the original, remove the original, otherwise compress it again
+    #[arg(long, conflicts_with = "existing")]
+    resume: bool,
+
+    /// Write the compressed files to this directory, in the same subdirectories as the
+    /// original files, instead of next to the original files
+    #[arg(long, conflicts_with = "follow_symlinks")]
+    dest: Option<PathBuf>,
+
+    /// Do not remove the original files after compressing them
+    #[arg(long)]
+    keep_originals: bool,
+
+    /// Do not remove the original files now, but add them to --deletion-queue, to be removed
+    /// by `dnazip --purge` once this many days have passed
+    #[arg(long, requires = "deletion_queue", conflicts_with = "keep_originals")]
+    delete_after: Option<u64>,
+
+    /// The file listing the original files to be removed later, with --delete-after
+    /// and --purge
+    #[arg(long)]
+    deletion_queue: Option<PathBuf>,
+
+    /// Remove the original files in --deletion-queue that are due, and have not changed since
+    /// they were compressed, instead of compressing files
+    #[arg(long, requires = "deletion_queue")]
+    purge: bool,
+
+    /// Compress files that symbolic links point to, and replace the links by links to the
+    /// compressed files. Each file is compressed once, even if several links point to it
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Only compress files matching this glob pattern (can be repeated) [FASTA and FASTQ files]
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// Do not compress files matching this glob pattern (can be repeated)
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Only compress files of at least this size, e.g. 500M or 1G
+    #[arg(long, value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Only compress files of at most this size, e.g. 500M or 1G
+    #[arg(long, value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Only compress files last modified longer ago than this, e.g. 30days or 12h
+    #[arg(long, value_parser = humantime::parse_duration)]
+    older_than: Option<Duration>,
+
+    /// Check that FASTA files start with a header, and that FASTQ records have four lines
+    /// with qualities as long as the sequence. Invalid files are not compressed
+    #[arg(long)]
+    validate: bool,
+
+    /// Bundle the files of at most this size, e.g. 64K, in each directory into one compressed
+    /// file, bundle.gz, listing the files and where they are in it in bundle.tsv. An existing
+    /// bundle is never replaced: New files are bundled into bundle.1.gz, and so on
+    #[arg(long, value_parser = parse_size, conflicts_with_all = ["follow_symlinks", "resume"])]
+    bundle: Option<u64>,
+
+    /// Find pairs of FASTQ files named like reads_R1.fq and reads_R2.fq, or reads_1.fq and
+    /// reads_2.fq, and only remove their original files once both are compressed
+    #[arg(long)]
+    paired_check: bool,
+
+    /// Read the original files at most this many MB (1024 * 1024 bytes) per second in total,
+    /// shared evenly by the threads, to not overload shared file systems
+    #[arg(long, value_parser = parse_throughput)]
+    max_throughput: Option<f64>,
+
+    /// Record the size, modification time and checksum of each file compressed in this file,
+    /// and skip files that have not changed since they were compressed by an earlier run
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// Stop at the first file that could not be compressed, instead of compressing the
+    /// other files and exiting with an error at the end
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Write the sizes before and after compression and the time taken for each file,
+    /// and totals for each subdirectory, to this file. JSON if it ends with .json, else TSV
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    #[command(flatten)]
+    logging: LogArgs,
 }
 
-const FASTA_EXTENSIONS: [&str; 4] = ["fna", "fasta", "fa", "faa"];
-const FASTQ_EXTENSIONS: [&str; 2] = ["fq", "fastq"];
+// Parse a number of bytes, with an optional suffix K, M, G or T for powers of 1024
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (number, multiplier) = match s.trim_end_matches(['B', 'b']).char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let exponent = match c.to_ascii_uppercase() {
+                'K' => 1,
+                'M' => 2,
+                'G' => 3,
+                'T' => 4,
+                _ => return Err(format!("Unknown size suffix: {}", c)),
+            };
+            (&s[..i], 1024u64.pow(exponent))
+        }
+        _ => (s.trim_end_matches(['B', 'b']), 1),
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("Not a size: {}", s))?;
+    if number < 0.0 {
+        return Err(format!("Size is negative: {}", s));
+    }
+    Ok((number * multiplier as f64) as u64)
+}
 
-fn is_fasta(p: &Path) -> bool {
-    p.extension()
-        .is_some_and(|e| e.to_str().is_some_and(|s| FASTA_EXTENSIONS.contains(&s)))
+fn parse_throughput(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(n) if n > 0.0 && n.is_finite() => Ok(n),
+        _ => Err(format!("Not a positive number of MB/s: {}", s)),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Codec {
+    /// Gzip, written to .gz files
+    Gzip,
+    /// Blocked gzip as written by bgzip, to .gz files, which can be indexed by samtools faidx
+    Bgzip,
+    /// Zstandard, written to .zst files. Faster than gzip at a similar ratio
+    Zstd,
+    /// Xz, written to .xz files. Much slower than gzip, but smaller, for archiving
+    Xz,
 }
 
-fn is_fastq(p: &Path) -> bool {
-    p.extension()
-        .is_some_and(|e| e.to_str().is_some_and(|s| FASTQ_EXTENSIONS.contains(&s)))
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Existing {
+    /// Warn and keep both files
+    Skip,
+    /// Compress the file again, replacing the compressed file
+    Overwrite,
+    /// Stop with an error
+    Error,
+    // Set by --resume
+    #[value(skip)]
+    Resume,
 }
 
-fn write_path(path: &Path, prefix: Option<&str>) {
+impl Codec {
+    fn extension(&self) -> &'static str {
+        match self {
+            Codec::Gzip | Codec::Bgzip => ".gz",
+            Codec::Zstd => ".zst",
+            Codec::Xz => ".xz",
+        }
+    }
+
+    fn levels(&self) -> RangeInclusive<u32> {
+        match self {
+            Codec::Gzip | Codec::Bgzip | Codec::Xz => 0..=9,
+            Codec::Zstd => 1..=22,
+        }
+    }
+
+    fn default_level(&self) -> u32 {
+        match self {
+            Codec::Gzip | Codec::Bgzip | Codec::Xz => 6,
+            Codec::Zstd => 3,
+        }
+    }
+}
+
+// The files to compress in one go: A single file, a pair of files with --paired-check,
+// or the small files of a directory with --bundle
+enum Job {
+    Files(Vec<PathBuf>),
+    Bundle(PathBuf, Vec<PathBuf>),
+}
+
+// The original and compressed files of the pair compressed so far, with --paired-check
+type Deferred = Vec<(PathBuf, OsString)>;
+
+// How each file is compressed, shared by all workers
+#[derive(Clone)]
+struct Options {
+    start: PathBuf,
+    dest: Option<PathBuf>,
+    keep_originals: bool,
+    dry_run: bool,
+    estimate: bool,
+    verbose: bool,
+    codec: Codec,
+    level: u32,
+    recompress: bool,
+    block_threads: NonZeroUsize,
+    verify: bool,
+    existing: Existing,
+    validate: bool,
+    fail_fast: bool,
+    // Bytes per second that each thread may read, with --max-throughput
+    max_rate: Option<f64>,
+    manifest: Option<Arc<Manifest>>,
+    // With --delete-after, the original files to be removed later
+    deletion_queue: Option<Arc<DeletionQueue>>,
+}
+
+impl Options {
+    // The path of the compressed file of a file, given the path of the uncompressed file
+    fn compressed_path(&self, uncompressed: &Path) -> OsString {
+        let mut p = match &self.dest {
+            Some(dest) => dest
+                .join(uncompressed.strip_prefix(&self.start).unwrap())
+                .into_os_string(),
+            None => uncompressed.as_os_str().to_owned(),
+        };
+        p.push(self.codec.extension());
+        p
+    }
+
+    // Open an original file, to be read no faster than --max-throughput allows
+    fn open(&self, path: &Path) -> anyhow::Result<Throttled<File>> {
+        let file = File::open(path).with_context(|| format!("Could not open file: {:?}", path))?;
+        Ok(Throttled {
+            inner: file,
+            rate: self.max_rate,
+            start: Instant::now(),
+            n_bytes: 0,
+        })
+    }
+
+    // Remove the original file after it has been compressed to `p`, unless --keep-originals
+    // is given, or queue it to be removed later with --delete-after. For pairs, it is instead
+    // added to `deferred`, to be removed once both files are compressed.
+    fn remove_original(
+        &self,
+        path: &Path,
+        p: &OsStr,
+        deferred: Option<&mut Deferred>,
+    ) -> anyhow::Result<()> {
+        if self.keep_originals {
+            return Ok(());
+        }
+        match (deferred, &self.deletion_queue) {
+            (Some(deferred), _) => deferred.push((path.to_owned(), p.to_owned())),
+            (None, Some(queue)) => queue.push(path, p)?,
+            (None, None) => std::fs::remove_file(path)
+                .with_context(|| format!("Could not remove file {:?}", path))?,
+        }
+        Ok(())
+    }
+}
+
+// Number of paths queued for each worker thread. When the queue is full, the main thread
+// compresses files itself instead of queueing more, so the queue stays small in huge directories.
+const QUEUED_PER_THREAD: usize = 4;
+
+// Number of bytes compressed by each thread at a time with --block-threads.
+// This is a whole number of BGZF blocks, so all BGZF blocks but the last are full.
+const BLOCK_SIZE: usize = 16 * MAX_BLOCK_DATA;
+
+// Compress a block as a gzip member, zstd frame or xz stream of its own, or as a series of
+// BGZF blocks. Concatenated gzip members, zstd frames or xz streams are a valid compressed file.
+fn compress_chunk(data: &[u8], codec: Codec, level: u32) -> io::Result<Vec<u8>> {
+    match codec {
+        Codec::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Compression::new(level));
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Codec::Bgzip => {
+            let mut out = Vec::new();
+            for block in data.chunks(MAX_BLOCK_DATA) {
+                compress_block(block, Compression::new(level), &mut out)?;
+            }
+            Ok(out)
+        }
+        Codec::Zstd => zstd::encode_all(data, level as i32),
+        Codec::Xz => {
+            let mut encoder = xz2::write::XzEncoder::new(Vec::new(), level);
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+    }
+}
+
+// Compress `n_threads` blocks at a time in parallel, and write them in order
+fn compress_parallel(
+    mut src: impl Read,
+    dst: &mut impl Write,
+    codec: Codec,
+    level: u32,
+    n_threads: usize,
+) -> io::Result<()> {
+    let mut blocks: Vec<Vec<u8>> = vec![Vec::with_capacity(BLOCK_SIZE); n_threads];
+    let mut is_empty = true;
+    loop {
+        let mut n_blocks = 0;
+        for block in blocks.iter_mut() {
+            block.clear();
+            src.by_ref().take(BLOCK_SIZE as u64).read_to_end(block)?;
+            if block.is_empty() {
+                break;
+            }
+            n_blocks += 1;
+        }
+        if n_blocks == 0 {
+            break;
+        }
+        is_empty = false;
+        let compressed: Vec<io::Result<Vec<u8>>> = thread::scope(|s| {
+            let handles: Vec<_> = blocks[..n_blocks]
+                .iter()
+                .map(|block| s.spawn(move || compress_chunk(block, codec, level)))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for chunk in compressed {
+            dst.write_all(&chunk?)?;
+        }
+    }
+    match codec {
+        // An empty gzip file still has one member
+        Codec::Gzip if is_empty => dst.write_all(&compress_chunk(&[], codec, level)?),
+        Codec::Bgzip => dst.write_all(&EOF_BLOCK),
+        _ => Ok(()),
+    }
+}
+
+fn write_path(path: &Path, prefix: Option<&str>, suffix: Option<&str>) {
     let mut v: Vec<u8> = Vec::new();
     if let Some(s) = prefix {
         v.write_all(s.as_bytes()).unwrap();
     }
     v.write_all(path.as_os_str().as_encoded_bytes()).unwrap();
-    v.write_all(&[b'\n']).unwrap();
-    stderr().write_all(&v).unwrap()
+    if let Some(s) = suffix {
+        v.write_all(s.as_bytes()).unwrap();
+    }
+    v.write_all(b"\n").unwrap();
+    progress::suspend(|| stderr().write_all(&v).unwrap())
 }
 
-fn compress(path: &Path, dry_run: bool, verbose: bool) -> anyhow::Result<()> {
-    if dry_run {
-        write_path(path, Some("Would compress: "));
-        return Ok(());
+// Progress in files and bytes. The totals grow as files are found.
+#[derive(Clone)]
+struct Bars {
+    files: Progress,
+    bytes: Progress,
+}
+
+// Counts the bytes read from the file being compressed in the bar of bytes. When the file is
+// done, the rest of its size is counted, in case it was skipped, failed or changed.
+struct FileProgress<'a> {
+    bytes: &'a Progress,
+    n_read: Cell<u64>,
+}
+
+impl FileProgress<'_> {
+    fn wrap<R: Read>(&self, inner: R) -> ProgressReader<'_, R> {
+        ProgressReader {
+            inner,
+            progress: self,
+        }
+    }
+}
+
+struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a FileProgress<'a>,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.bytes.inc(n as u64);
+        self.progress
+            .n_read
+            .set(self.progress.n_read.get() + n as u64);
+        Ok(n)
+    }
+}
+
+// The CRC32 and length of everything read from the reader
+fn checksum(mut reader: impl Read) -> io::Result<(u32, u64)> {
+    let mut check = CrcWriter::new(io::sink());
+    let n_bytes = io::copy(&mut reader, &mut check)?;
+    Ok((check.crc().sum(), n_bytes))
+}
+
+// The CRC32 and length of the decompressed content of a compressed file
+fn decompressed_checksum(path: &OsStr, codec: Codec) -> anyhow::Result<(u32, u64)> {
+    let file = BufReader::new(
+        File::open(path).with_context(|| format!("Could not open compressed file {:?}", path))?,
+    );
+    let decoder: Box<dyn Read> = match codec {
+        Codec::Gzip | Codec::Bgzip => Box::new(MultiGzDecoder::new(file)),
+        Codec::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+        Codec::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(file)),
+    };
+    checksum(decoder).with_context(|| format!("Could not decompress file {:?}", path))
+}
+
+// Decompress the compressed file, and check that it has the same CRC32 and length as the original
+fn verify_compressed(path: &OsStr, codec: Codec, crc: u32, length: u64) -> anyhow::Result<()> {
+    if decompressed_checksum(path, codec)? != (crc, length) {
+        bail!(
+            "Decompressed file {:?} does not match the original; the original is kept",
+            path
+        );
+    }
+    Ok(())
+}
+
+// A reader that counts the bytes read through it
+struct Counter<R> {
+    inner: R,
+    n_bytes: u64,
+}
+
+impl<R: Read> Read for Counter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.n_bytes += n as u64;
+        Ok(n)
+    }
+}
+
+// A reader that sleeps as needed to read at most `rate` bytes per second on average
+struct Throttled<R> {
+    inner: R,
+    rate: Option<f64>,
+    start: Instant,
+    n_bytes: u64,
+}
+
+impl<R> Throttled<R> {
+    fn get_ref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(rate) = self.rate else {
+            return self.inner.read(buf);
+        };
+        // Read at most a tenth of a second's worth at a time, so reading is smooth at low rates
+        let max_len = ((rate / 10.0) as usize).max(1);
+        let len = buf.len().min(max_len);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.n_bytes += n as u64;
+        let due = Duration::from_secs_f64(self.n_bytes as f64 / rate);
+        if let Some(wait) = due.checked_sub(self.start.elapsed()) {
+            thread::sleep(wait);
+        }
+        Ok(n)
+    }
+}
+
+// With --validate, check the format of FASTA and FASTQ files, given the path of the
+// uncompressed file. Files selected with --include that are neither are not checked.
+fn validated<'a>(reader: impl Read + 'a, path: &Path, validate: bool) -> Box<dyn Read + 'a> {
+    if validate && is_fasta(path) {
+        Box::new(Validator::new(reader, SeqFormat::Fasta))
+    } else if validate && is_fastq(path) {
+        Box::new(Validator::new(reader, SeqFormat::Fastq))
+    } else {
+        Box::new(reader)
+    }
+}
+
+// Compress everything read from `src` to the file at `p`, and return the CRC32 of everything
+// read and the size of the compressed file
+fn write_compressed(src: impl Read, p: &OsStr, options: &Options) -> anyhow::Result<(u32, u64)> {
+    write_atomically(p, |tmp| write_verified(src, tmp, options))
+}
+
+// Write the file at `p` with `write`, which is given the path to write to, `p` with .tmp
+// appended. The file is only renamed to `p` if `write` succeeds, so a file at `p` is always
+// complete.
+fn write_atomically<T>(
+    p: &OsStr,
+    write: impl FnOnce(&OsStr) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut tmp = p.to_owned();
+    tmp.push(".tmp");
+    let written = write(&tmp).and_then(|value| {
+        std::fs::rename(&tmp, p)
+            .with_context(|| format!("Could not rename {:?} to {:?}", tmp, p))?;
+        Ok(value)
+    });
+    let value = match written {
+        Ok(value) => value,
+        Err(e) => {
+            // Errors are ignored, since the first error is more useful
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e);
+        }
+    };
+    // The rename is only on disk once the directory is
+    if let Some(parent) = Path::new(p).parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        File::open(parent)
+            .and_then(|d| d.sync_all())
+            .with_context(|| format!("Could not write directory {:?}", parent))?;
     }
-    let mut p = path.as_os_str().to_owned();
-    p.push(".gz");
+    Ok(value)
+}
+
+// Flush the file and make sure it is written to disk, and return its size
+fn sync_file(file: BufWriter<File>, p: &OsStr) -> anyhow::Result<u64> {
+    let size = file
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .and_then(|f| f.sync_all().and_then(|_| f.metadata()))
+        .with_context(|| format!("Could not write file {:?}", p))?
+        .len();
+    Ok(size)
+}
+
+// Write the compressed file, make sure it is on disk, and verify it unless --no-verify is given
+fn write_verified(src: impl Read, p: &OsStr, options: &Options) -> anyhow::Result<(u32, u64)> {
+    let Options {
+        codec,
+        level,
+        block_threads,
+        verify,
+        ..
+    } = *options;
     let mut dst = BufWriter::new(
-        File::create(&p).with_context(|| format!("Could not create gzipped file {:?}", p))?,
+        File::create(p).with_context(|| format!("Could not create compressed file {:?}", p))?,
     );
-    let mut new = GzEncoder::new(
-        BufReader::new(
-            File::open(path).with_context(|| format!("Could not open file: {:?}", path))?,
-        ),
-        Compression::default(),
+    // The checksum of the original is computed while it is read for compression
+    let mut src = CrcReader::new(BufReader::new(Counter {
+        inner: src,
+        n_bytes: 0,
+    }));
+    match codec {
+        Codec::Gzip | Codec::Bgzip if block_threads.get() > 1 => {
+            compress_parallel(&mut src, &mut dst, codec, level, block_threads.get())
+                .context("Error when compressing file in parallel")?;
+        }
+        Codec::Gzip => {
+            let mut new = GzEncoder::new(&mut src, Compression::new(level));
+            std::io::copy(&mut new, &mut dst).context("Error when copying file to gzip wrier")?;
+        }
+        Codec::Bgzip => {
+            let mut new = BgzfWriter::new(&mut dst, Compression::new(level));
+            std::io::copy(&mut src, &mut new).context("Error when copying file to bgzip writer")?;
+            new.finish()
+                .context("Error when writing end of file block of bgzip file")?;
+        }
+        Codec::Zstd => {
+            let mut new = zstd::Encoder::new(&mut dst, level as i32)?;
+            if block_threads.get() > 1 {
+                new.multithread(block_threads.get() as u32)?;
+            }
+            std::io::copy(&mut src, &mut new).context("Error when copying file to zstd writer")?;
+            new.finish()?;
+        }
+        Codec::Xz => {
+            let mut new = if block_threads.get() > 1 {
+                let stream = MtStreamBuilder::new()
+                    .threads(block_threads.get() as u32)
+                    .preset(level)
+                    .check(Check::Crc64)
+                    .encoder()?;
+                XzEncoder::new_stream(&mut dst, stream)
+            } else {
+                XzEncoder::new(&mut dst, level)
+            };
+            std::io::copy(&mut src, &mut new).context("Error when copying file to xz writer")?;
+            new.finish()?;
+        }
+    }
+    // Make sure the compressed file is written to disk before the original is removed
+    let compressed_size = sync_file(dst, p)?;
+    let length = src.get_ref().get_ref().n_bytes;
+    if verify {
+        verify_compressed(p, codec, src.crc().sum(), length)?;
+    }
+    Ok((src.crc().sum(), compressed_size))
+}
+
+// Create the directory of a compressed file in --dest, if it does not exist
+fn create_parent(p: &OsStr) -> anyhow::Result<()> {
+    if let Some(parent) = Path::new(p).parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Could not create directory {:?}", parent))?;
+    }
+    Ok(())
+}
+
+// Whether the gzip file was compressed with the fastest level, as recorded in its header
+fn is_fast_gzip(path: &Path) -> io::Result<bool> {
+    let mut header = [0u8; 10];
+    File::open(path)?.read_exact(&mut header)?;
+    Ok(header[..3] == [0x1f, 0x8b, 8] && header[8] == 4)
+}
+
+// Whether the file is a gzipped FASTA or FASTQ file, which is recompressed with --recompress
+fn is_gzipped_seq(path: &Path) -> bool {
+    let stem = path.with_extension("");
+    path.extension().is_some_and(|e| e == "gz") && (is_fasta(&stem) || is_fastq(&stem))
+}
+
+// Decompress a gzip file compressed with the fastest level, and compress it again.
+// The new file replaces the old file once it is complete.
+fn recompress(
+    path: &Path,
+    options: &Options,
+    progress: &FileProgress,
+    deferred: Option<&mut Deferred>,
+) -> anyhow::Result<Option<FileReport>> {
+    let Options {
+        dry_run,
+        verbose,
+        codec,
+        existing,
+        validate,
+        ..
+    } = *options;
+    // With zstd or xz, all files are recompressed, since they are converted
+    if matches!(codec, Codec::Gzip | Codec::Bgzip)
+        && !is_fast_gzip(path).with_context(|| format!("Could not read gzip file {:?}", path))?
+    {
+        return Ok(None);
+    }
+    let uncompressed = path.with_extension("");
+    let p = options.compressed_path(&uncompressed);
+    if p == path.as_os_str() && options.keep_originals {
+        bail!("Can not keep the original file when it is replaced by the recompressed file");
+    }
+    if p != path.as_os_str() && Path::new(&p).exists() {
+        match existing {
+            Existing::Skip => {
+                warn!(
+                    "Not recompressing {}, since {} already exists",
+                    path.display(),
+                    Path::new(&p).display()
+                );
+                return Ok(None);
+            }
+            Existing::Error => bail!("Compressed file {:?} already exists", p),
+            Existing::Overwrite | Existing::Resume => (),
+        }
+    }
+    if dry_run {
+        write_path(path, Some("Would recompress: "), None);
+        return Ok(None);
+    }
+    create_parent(&p)?;
+    let start = Instant::now();
+    let file = options.open(path)?;
+    let original_size = file.get_ref().metadata()?.len();
+    let src = validated(
+        MultiGzDecoder::new(BufReader::new(progress.wrap(file))),
+        &uncompressed,
+        validate,
     );
-    std::io::copy(&mut new, &mut dst).context("Error when copying file to gzip wrier")?;
-    std::fs::remove_file(path).with_context(|| format!("Could not remove file {:?}", path))?;
+    let (_, compressed_size) = write_compressed(src, &p, options)?;
+    if p != path.as_os_str() {
+        options.remove_original(path, &p, deferred)?;
+    }
     if verbose {
-        write_path(path, Some("Compressed: "))
+        write_path(path, Some("Recompressed: "), None)
     }
-    Ok(())
+    Ok(Some(FileReport {
+        path: path.to_owned(),
+        original_size,
+        compressed_size,
+        duration: start.elapsed(),
+        // The gzip file is replaced or removed, so is not recorded
+        original: None,
+    }))
+}
+
+// Number of bytes at the start of each file that are compressed by --estimate
+const SAMPLE_SIZE: u64 = 16 << 20;
+
+// Compress the start of the file, and extrapolate the size of the compressed file
+// and the time it takes to compress it from that
+fn estimate_compressed(path: &Path, codec: Codec, level: u32) -> anyhow::Result<FileReport> {
+    let file = File::open(path).with_context(|| format!("Could not open file: {:?}", path))?;
+    let original_size = file.metadata()?.len();
+    let mut sample = Vec::new();
+    file.take(SAMPLE_SIZE)
+        .read_to_end(&mut sample)
+        .with_context(|| format!("Could not read file {:?}", path))?;
+    let start = Instant::now();
+    let compressed = compress_chunk(&sample, codec, level)?;
+    // The file may have changed since its size was read
+    let scale = original_size as f64 / sample.len().max(1) as f64;
+    Ok(FileReport {
+        path: path.to_owned(),
+        original_size,
+        compressed_size: (compressed.len() as f64 * scale) as u64,
+        duration: start.elapsed().mul_f64(scale),
+        original: None,
+    })
 }
 
-fn read_channel(reciever: Receiver<PathBuf>, dry_run: bool, verbose: bool) {
+// With --manifest, whether the file has not changed since an earlier run compressed it
+fn is_unchanged(path: &Path, options: &Options) -> anyhow::Result<bool> {
+    let Some(manifest) = &options.manifest else {
+        return Ok(false);
+    };
+    let metadata = std::fs::metadata(path)
+        .with_context(|| format!("Could not read metadata of {:?}", path))?;
+    manifest.is_unchanged(path, &metadata, || {
+        let file = options.open(path)?;
+        let (crc, _) = checksum(BufReader::new(file))
+            .with_context(|| format!("Could not read file {:?}", path))?;
+        Ok(crc)
+    })
+}
+
+// Compress the file, and return its report if it was compressed
+fn compress(
+    path: &Path,
+    options: &Options,
+    progress: &FileProgress,
+    deferred: Option<&mut Deferred>,
+) -> anyhow::Result<Option<FileReport>> {
+    let Options {
+        dry_run,
+        estimate,
+        verbose,
+        codec,
+        level,
+        recompress: recompress_gzip,
+        existing,
+        validate,
+        ..
+    } = *options;
+    if is_unchanged(path, options)? {
+        if verbose {
+            write_path(path, Some("Unchanged: "), None)
+        }
+        return Ok(None);
+    }
+    if recompress_gzip && is_gzipped_seq(path) {
+        return recompress(path, options, progress, deferred);
+    }
+    let p = options.compressed_path(path);
+    if Path::new(&p).exists() {
+        match existing {
+            Existing::Skip => {
+                warn!(
+                    "Not compressing {}, since {} already exists",
+                    path.display(),
+                    Path::new(&p).display()
+                );
+                return Ok(None);
+            }
+            Existing::Error => bail!("Compressed file {:?} already exists", p),
+            Existing::Overwrite => (),
+            Existing::Resume if !dry_run => {
+                let file = options.open(path)?;
+                let original = checksum(BufReader::new(file))
+                    .with_context(|| format!("Could not read file {:?}", path))?;
+                // A compressed file that can't be decompressed was cut short, so is compressed again
+                if decompressed_checksum(&p, codec).is_ok_and(|c| c == original) {
+                    options.remove_original(path, &p, deferred)?;
+                    if verbose {
+                        write_path(path, Some("Already compressed: "), None)
+                    }
+                    return Ok(None);
+                }
+            }
+            Existing::Resume => (),
+        }
+    }
+    if dry_run && estimate {
+        let report = estimate_compressed(path, codec, level)?;
+        let suffix = format!(
+            ": {} to about {}",
+            size::Size::from_bytes(report.original_size),
+            size::Size::from_bytes(report.compressed_size)
+        );
+        write_path(path, Some("Would compress "), Some(&suffix));
+        return Ok(Some(report));
+    }
+    if dry_run {
+        write_path(path, Some("Would compress: "), None);
+        return Ok(None);
+    }
+    create_parent(&p)?;
+    let start = Instant::now();
+    let file = options.open(path)?;
+    let metadata = file.get_ref().metadata()?;
+    let original_size = metadata.len();
+    let (crc, compressed_size) =
+        write_compressed(validated(progress.wrap(file), path, validate), &p, options)?;
+    options.remove_original(path, &p, deferred)?;
+    if verbose {
+        write_path(path, Some("Compressed: "), None)
+    }
+    Ok(Some(FileReport {
+        path: path.to_owned(),
+        original_size,
+        compressed_size,
+        duration: start.elapsed(),
+        original: metadata.modified().ok().map(|modified| Entry {
+            size: original_size,
+            modified,
+            crc,
+        }),
+    }))
+}
+
+// Compress the files of a job, which is a single file, or a pair of files with --paired-check,
+// and add their reports. Errors are logged and counted, so the other files are still compressed.
+// The original files of a pair are only removed if both files were compressed. Otherwise
+// the compressed files are removed instead.
+fn compress_or_log(
+    job: &[PathBuf],
+    options: &Options,
+    bars: &Bars,
+    n_failed: &AtomicUsize,
+    reports: &mut Vec<FileReport>,
+) {
+    let mut deferred: Deferred = Vec::new();
+    let mut job_reports = Vec::new();
+    let mut failed = false;
+    for path in job {
+        let size = std::fs::metadata(path).map_or(0, |m| m.len());
+        let progress = FileProgress {
+            bytes: &bars.bytes,
+            n_read: Cell::new(0),
+        };
+        let defer = (job.len() > 1).then_some(&mut deferred);
+        match compress(path, options, &progress, defer) {
+            Ok(report) => job_reports.extend(report),
+            Err(e) => {
+                error!("Could not compress {}: {:#}", path.display(), e);
+                n_failed.fetch_add(1, Ordering::Relaxed);
+                failed = true;
+            }
+        }
+        bars.files.inc(1);
+        bars.bytes.inc(size.saturating_sub(progress.n_read.get()));
+    }
+    for (original, p) in deferred {
+        let result = if failed {
+            warn!(
+                "Not compressing {}, since its mate could not be compressed",
+                original.display()
+            );
+            std::fs::remove_file(&p).with_context(|| format!("Could not remove file {:?}", p))
+        } else {
+            options.remove_original(&original, &p, None)
+        };
+        if let Err(e) = result {
+            error!("{:#}", e);
+            n_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    if !failed {
+        reports.extend(job_reports);
+    }
+}
+
+// Bundle the small files of a directory, and add their reports. Errors are logged and counted.
+fn bundle_or_log(
+    dir: &Path,
+    files: &[PathBuf],
+    options: &Options,
+    bars: &Bars,
+    n_failed: &AtomicUsize,
+    reports: &mut Vec<FileReport>,
+) {
+    let size: u64 = files
+        .iter()
+        .map(|path| std::fs::metadata(path).map_or(0, |m| m.len()))
+        .sum();
+    let progress = FileProgress {
+        bytes: &bars.bytes,
+        n_read: Cell::new(0),
+    };
+    match write_bundle(dir, files, options, &progress) {
+        Ok(bundled) => reports.extend(bundled),
+        Err(e) => {
+            error!("Could not bundle the files in {}: {:#}", dir.display(), e);
+            n_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    bars.files.inc(files.len() as u64);
+    bars.bytes.inc(size.saturating_sub(progress.n_read.get()));
+}
+
+fn run_job(
+    job: &Job,
+    options: &Options,
+    bars: &Bars,
+    n_failed: &AtomicUsize,
+    reports: &mut Vec<FileReport>,
+) {
+    match job {
+        Job::Files(files) => compress_or_log(files, options, bars, n_failed, reports),
+        Job::Bundle(dir, files) => bundle_or_log(dir, files, options, bars, n_failed, reports),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum HardLinks {
+    Compress,
+    // The file has other hard links, and would be removed
+    Linked,
+    // The file was already selected under another name
+    Seen,
+}
+
+// Compressing a file with other hard links and removing it, also with --dest or later with
+// --delete-after, would not free any space, and would leave the file uncompressed under its
+// other names. So these are only compressed with --keep-originals, and then only once.
+fn check_hard_links(
+    metadata: &std::fs::Metadata,
+    keep_originals: bool,
+    inodes: &mut HashSet<(u64, u64)>,
+) -> HardLinks {
+    if metadata.nlink() <= 1 {
+        HardLinks::Compress
+    } else if !keep_originals {
+        HardLinks::Linked
+    } else if inodes.insert((metadata.dev(), metadata.ino())) {
+        HardLinks::Compress
+    } else {
+        HardLinks::Seen
+    }
+}
+
+// The path of the other file of a pair of FASTQ files, like reads_R2.fq for reads_R1.fq,
+// or reads_1.fq for reads_2.fq
+fn mate_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    for (from, to) in [("_R1", "_R2"), ("_R2", "_R1"), ("_1", "_2"), ("_2", "_1")] {
+        // The number must end the part of the name, like in reads_R1_001.fastq
+        let Some(i) = name
+            .match_indices(from)
+            .map(|(i, _)| i)
+            .filter(|&i| matches!(name.as_bytes().get(i + from.len()), Some(b'_' | b'.')))
+            .last()
+        else {
+            continue;
+        };
+        let mate = format!("{}{}{}", &name[..i], to, &name[i + from.len()..]);
+        return Some(path.with_file_name(mate));
+    }
+    None
+}
+
+// Queue the job for the worker threads. If the queue is full, or there are no worker threads,
+// the main thread compresses it. This way the main thread never waits for the workers.
+fn send_or_compress(
+    sender: &Sender<Job>,
+    job: Job,
+    options: &Options,
+    bars: &Bars,
+    n_failed: &AtomicUsize,
+    reports: &mut Vec<FileReport>,
+) {
+    match sender.try_send(job) {
+        Ok(()) => (),
+        Err(TrySendError::Full(job)) => run_job(&job, options, bars, n_failed, reports),
+        Err(TrySendError::Disconnected(_)) => unreachable!(),
+    }
+}
+
+// With --fail-fast, no more files are compressed after an error
+fn should_stop(options: &Options, n_failed: &AtomicUsize) -> bool {
+    options.fail_fast && n_failed.load(Ordering::Relaxed) > 0
+}
+
+fn read_channel(
+    reciever: Receiver<Job>,
+    options: Options,
+    bars: &Bars,
+    n_failed: &AtomicUsize,
+) -> Vec<FileReport> {
+    let mut reports = Vec::new();
     loop {
+        if should_stop(&options, n_failed) {
+            return reports;
+        }
         match reciever.recv() {
-            Err(RecvError) => return,
-            Ok(path) => compress(&path, dry_run, verbose).unwrap(),
+            Err(RecvError) => return reports,
+            Ok(job) => run_job(&job, &options, bars, n_failed, &mut reports),
         }
     }
 }
 
+// The file a symbolic link points to, through any number of links, even if the file no longer
+// exists because it was compressed
+fn link_target(link: &Path) -> Option<PathBuf> {
+    let mut path = link.to_owned();
+    // Like the limit of the Linux kernel
+    for _ in 0..40 {
+        let target = path.parent()?.join(std::fs::read_link(&path).ok()?);
+        let resolved = std::fs::canonicalize(target.parent()?)
+            .ok()?
+            .join(target.file_name()?);
+        if !resolved.is_symlink() {
+            return Some(resolved);
+        }
+        path = resolved;
+    }
+    None
+}
+
+// Replace a symbolic link to a file that was compressed by a link to the compressed file,
+// named like the compressed file. The new link is relative if the old link was.
+fn relink(link: &Path, compressed: &Path, extension: &str) -> anyhow::Result<()> {
+    let mut target = std::fs::read_link(link)?.into_os_string();
+    target.push(extension);
+    let dir = link.parent().unwrap_or(Path::new(""));
+    // If the old link points to another link, the new link points directly to the compressed file
+    if !std::fs::canonicalize(dir.join(&target)).is_ok_and(|p| p == compressed) {
+        target = compressed.as_os_str().to_owned();
+    }
+    let mut new_link = link.as_os_str().to_owned();
+    new_link.push(extension);
+    symlink(&target, &new_link)
+        .with_context(|| format!("Could not create symbolic link {:?}", new_link))?;
+    std::fs::remove_file(link).with_context(|| format!("Could not remove symbolic link {:?}", link))
+}
+
+// The paths listed in a file, one per line, or in standard input if the path is -.
+// Paths are read as they are needed, so dnazip can start before e.g. find is done.
+fn listed_paths(list: &Path) -> anyhow::Result<impl Iterator<Item = io::Result<PathBuf>>> {
+    let reader: Box<dyn BufRead> = if list == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        let file =
+            File::open(list).with_context(|| format!("Could not open file list {:?}", list))?;
+        Box::new(BufReader::new(file))
+    };
+    Ok(reader.split(b'\n').filter_map(|line| match line {
+        Ok(mut line) => {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            (!line.is_empty()).then(|| Ok(PathBuf::from(OsString::from_vec(line))))
+        }
+        Err(e) => Some(Err(e)),
+    }))
+}
+
 fn main() {
     let args = Cli::parse();
+    let level = args.level.unwrap_or(args.codec.default_level());
+    if !args.codec.levels().contains(&level) {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ValueValidation,
+                format!(
+                    "Level must be from {} to {} with this codec",
+                    args.codec.levels().start(),
+                    args.codec.levels().end()
+                ),
+            )
+            .exit()
+    }
+    // The original files of an existing bundle have been removed, so it must not be replaced
+    if args.bundle.is_some() && args.existing == Existing::Overwrite {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--bundle can not be used with --existing overwrite",
+            )
+            .exit()
+    }
+    progress::init(&args.logging).unwrap();
+    if args.purge {
+        let path = args.deletion_queue.as_deref().unwrap();
+        match purge(path, args.dry_run, args.verbose) {
+            Ok(0) => return,
+            Ok(n_failed) => {
+                error!("{} files could not be removed", n_failed);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                error!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let filter = match PathFilter::new(&args.include, &args.exclude) {
+        Ok(filter) => filter,
+        Err(e) => Cli::command()
+            .error(clap::error::ErrorKind::ValueValidation, format!("{:#}", e))
+            .exit(),
+    };
+    // Files modified after this are not compressed
+    let cutoff = args
+        .older_than
+        .map(|d| SystemTime::now().checked_sub(d).unwrap_or(UNIX_EPOCH));
     let mut n_files = 0;
     let mut n_bytes = 0;
-    let (sender, reciever) = crossbeam_channel::unbounded::<PathBuf>();
+    let mut reports: Vec<FileReport> = Vec::new();
+    // With --follow-symlinks, the real path of each file selected, and each symbolic link
+    // followed with the file it points to
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    let mut links: Vec<(PathBuf, PathBuf)> = Vec::new();
+    // With --keep-originals, the device and inode of each file selected with other hard links
+    let mut inodes: HashSet<(u64, u64)> = HashSet::new();
+    // With --paired-check, the files whose mate has not been found yet
+    let mut waiting: HashSet<PathBuf> = HashSet::new();
+    // With --bundle, the small files in each directory
+    let mut bundles: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    let manifest = match args.manifest.as_deref().map(Manifest::read).transpose() {
+        Ok(manifest) => manifest.map(Arc::new),
+        Err(e) => {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let deletion_queue = args
+        .delete_after
+        .map(|days| Arc::new(DeletionQueue::new(Duration::from_secs(days * 24 * 60 * 60))));
+    let options = Options {
+        start: args.start.clone(),
+        dest: args.dest.clone(),
+        keep_originals: args.keep_originals,
+        dry_run: args.dry_run,
+        estimate: args.estimate,
+        verbose: args.verbose,
+        codec: args.codec,
+        level,
+        recompress: args.recompress,
+        block_threads: args.block_threads,
+        verify: !args.no_verify,
+        existing: if args.resume {
+            Existing::Resume
+        } else {
+            args.existing
+        },
+        validate: args.validate,
+        fail_fast: args.fail_fast,
+        // The main thread compresses files too
+        max_rate: args
+            .max_throughput
+            .map(|mb| mb * (1 << 20) as f64 / (args.threads as f64 + 1.0)),
+        manifest: manifest.clone(),
+        deletion_queue: deletion_queue.clone(),
+    };
+    let n_failed = Arc::new(AtomicUsize::new(0));
+    let (sender, reciever) =
+        crossbeam_channel::bounded::<Job>(QUEUED_PER_THREAD * args.threads as usize);
+    let bars = Bars {
+        files: Progress::new("Compressing", "files", Some(0)),
+        bytes: Progress::bytes("Compressing", Some(0)),
+    };
     let handles: Vec<_> = (0..args.threads)
         .map(|_| {
             let rec = reciever.clone();
-            thread::spawn(move || read_channel(rec, args.dry_run, args.verbose))
+            let options = options.clone();
+            let bars = bars.clone();
+            let n_failed = n_failed.clone();
+            thread::spawn(move || read_channel(rec, options, &bars, &n_failed))
         })
         .collect();
-    for maybe_entry in WalkDir::new(args.start) {
+    let entries: Box<dyn Iterator<Item = walkdir::Result<DirEntry>>> = match &args.files_from {
+        None => Box::new(
+            WalkDir::new(&args.start)
+                .follow_links(args.follow_symlinks)
+                .into_iter(),
+        ),
+        Some(list) => {
+            let paths = match listed_paths(list) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    error!("{:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            let n_failed = n_failed.clone();
+            let paths = paths.map_while(move |path| match path {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    error!("Could not read the file list: {}", e);
+                    n_failed.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+            });
+            // Walking each path without descending gives the same entries as a walk would,
+            // so they are selected the same way
+            let start = args.start.clone();
+            let follow_links = args.follow_symlinks;
+            Box::new(paths.flat_map(move |path| {
+                WalkDir::new(start.join(path))
+                    .max_depth(0)
+                    .follow_links(follow_links)
+            }))
+        }
+    };
+    for maybe_entry in entries {
+        if should_stop(&options, &n_failed) {
+            break;
+        }
         let handled_entry = match maybe_entry {
             Ok(e) => Some(Ok(e)),
             Err(err) => {
                 let path = err.path().unwrap_or(Path::new("")).display();
-                if let Some(inner) = err.io_error() {
+                let link = err
+                    .path()
+                    .filter(|p| args.follow_symlinks && p.is_symlink());
+                if err.loop_ancestor().is_some() {
+                    warn!("Symbolic link loop: {}", path);
+                    None
+                } else if let Some(link) = link {
+                    // Files are compressed while the directory is walked, so the file may
+                    // have been compressed already through another path
+                    match link_target(link).filter(|t| seen.contains(t)) {
+                        Some(target) => links.push((link.to_owned(), target)),
+                        None => warn!("Broken symbolic link: {}", path),
+                    }
+                    None
+                } else if let Some(inner) = err.io_error() {
                     match inner.kind() {
                         ErrorKind::PermissionDenied => {
-                            eprintln!("Permission denied: {}", path);
+                            warn!("Permission denied: {}", path);
                             None
                         }
                         _ => Some(Err(err)),
@@ -118,55 +1349,339 @@ fn main() {
                 }
             }
         };
-        let entry = if let Some(res) = handled_entry {
-            res.unwrap()
-        } else {
-            continue;
+        let entry = match handled_entry {
+            Some(Ok(entry)) => entry,
+            Some(Err(err)) => {
+                if args.files_from.is_some() {
+                    error!("Could not read file: {}", err);
+                } else {
+                    error!("Could not read directory: {}", err);
+                }
+                n_failed.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            None => continue,
         };
-        if entry.file_type().is_file() && !entry.path_is_symlink() {
+        if entry.file_type().is_file() && (args.follow_symlinks || !entry.path_is_symlink()) {
             let path = entry.path();
-            if is_fasta(path) || is_fastq(path) {
-                sender.send(path.to_owned()).unwrap();
+            let relative = match path.strip_prefix(&args.start) {
+                Ok(relative) => relative,
+                // Listed files may be outside the start directory
+                Err(_) if args.dest.is_none() => path,
+                Err(_) => {
+                    error!(
+                        "Could not compress {}, since it is not in {} to be written to --dest",
+                        path.display(),
+                        args.start.display()
+                    );
+                    n_failed.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            };
+            let default =
+                is_fasta(path) || is_fastq(path) || args.recompress && is_gzipped_seq(path);
+            if filter.is_selected(relative, default) {
+                let Ok(metadata) = entry.metadata() else {
+                    error!("Could not read metadata of {}", path.display());
+                    n_failed.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                };
+                let size = metadata.len();
+                // Platforms without modification times are treated as old
+                if args.min_size.is_some_and(|n| size < n)
+                    || args.max_size.is_some_and(|n| size > n)
+                    || cutoff.is_some_and(|t| metadata.modified().is_ok_and(|m| m > t))
+                {
+                    continue;
+                }
+                match check_hard_links(&metadata, args.keep_originals, &mut inodes) {
+                    HardLinks::Compress => (),
+                    HardLinks::Linked => {
+                        warn!(
+                            "Not compressing {}, since it has other hard links",
+                            path.display()
+                        );
+                        continue;
+                    }
+                    HardLinks::Seen => continue,
+                }
+                let path = if !args.follow_symlinks {
+                    path.to_owned()
+                } else {
+                    let real_path = if entry.path_is_symlink() {
+                        link_target(path)
+                    } else {
+                        std::fs::canonicalize(path).ok()
+                    };
+                    let Some(real_path) = real_path else {
+                        error!("Could not find the real path of {}", path.display());
+                        n_failed.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    };
+                    if entry.path_is_symlink() {
+                        links.push((path.to_owned(), real_path.clone()));
+                    }
+                    // The same file can be found through several paths
+                    if !seen.insert(real_path.clone()) {
+                        continue;
+                    }
+                    real_path
+                };
                 n_files += 1;
-                n_bytes += entry.metadata().unwrap().len()
-            }
-        } else {
-            continue;
-        }
-        // If there are no dedicated readers, we use the main thread to compress an entry.
-        // This way the main thread never has to wait for the worker threads.
-        if args.threads == 0 {
-            match reciever.try_recv() {
-                Ok(p) => compress(&p, args.dry_run, args.verbose).unwrap(),
-                Err(TryRecvError::Disconnected) => unreachable!(),
-                // Below can also never happen, but no big deal if it does
-                Err(TryRecvError::Empty) => (),
+                n_bytes += size;
+                bars.files.inc_length(1);
+                bars.bytes.inc_length(size);
+                // Small files are bundled once the walk is done
+                if args.bundle.is_some_and(|max| size <= max) {
+                    let dir = path.parent().unwrap_or(Path::new("")).to_owned();
+                    bundles.entry(dir).or_default().push(path);
+                    continue;
+                }
+                // A file waits until its mate is found, or the walk is done
+                let job = match mate_path(&path).filter(|_| args.paired_check) {
+                    Some(mate) if waiting.remove(&mate) => vec![mate, path],
+                    Some(_) => {
+                        waiting.insert(path);
+                        continue;
+                    }
+                    None => vec![path],
+                };
+                send_or_compress(
+                    &sender,
+                    Job::Files(job),
+                    &options,
+                    &bars,
+                    &n_failed,
+                    &mut reports,
+                );
             }
         }
     }
+    // A single small file in a directory is compressed on its own
+    for (dir, mut files) in bundles {
+        files.sort_unstable();
+        let job = if files.len() > 1 {
+            Job::Bundle(dir, files)
+        } else {
+            Job::Files(files)
+        };
+        send_or_compress(&sender, job, &options, &bars, &n_failed, &mut reports);
+    }
+    // Files whose mate was not found are compressed on their own
+    for path in waiting {
+        send_or_compress(
+            &sender,
+            Job::Files(vec![path]),
+            &options,
+            &bars,
+            &n_failed,
+            &mut reports,
+        );
+    }
 
     // This signals to the worker threads that they should exit,
     // once they run out of paths to process
     drop(sender);
 
     // Turn the main thread into a worker thread to help with the last paths.
-    read_channel(reciever, args.dry_run, args.verbose);
+    reports.extend(read_channel(reciever, options, &bars, &n_failed));
 
     // Make sure all the workers exited
     for handle in handles {
-        handle.join().unwrap()
+        reports.extend(handle.join().unwrap())
     }
-    if args.dry_run {
-        eprintln!(
+    bars.files.finish();
+    bars.bytes.finish();
+    if !args.dry_run {
+        for (link, target) in links {
+            let mut compressed = target.as_os_str().to_owned();
+            compressed.push(args.codec.extension());
+            let compressed = PathBuf::from(compressed);
+            // Links to files that were not compressed are left alone
+            if target.exists() || !compressed.exists() {
+                continue;
+            }
+            if let Err(e) = relink(&link, &compressed, args.codec.extension()) {
+                error!("Could not update {}: {:#}", link.display(), e);
+                n_failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    // Files written at the end, which are all attempted before exiting if one fails
+    let mut is_written = true;
+    if let (Some(manifest), Some(path)) = (&manifest, &args.manifest) {
+        if !args.dry_run {
+            if let Err(e) = manifest.write(path, &reports) {
+                error!("Could not write manifest to {:?}: {:#}", path, e);
+                is_written = false;
+            }
+        }
+    }
+    if let (Some(queue), Some(path)) = (&deletion_queue, &args.deletion_queue) {
+        // The original files are kept, but will not be removed by --purge
+        if let Err(e) = queue.write(path) {
+            error!(
+                "Could not add the original files to {:?}, so they were kept: {:#}",
+                path, e
+            );
+            is_written = false;
+        }
+    }
+    if let Some(path) = &args.report {
+        if let Err(e) = write_report(path, &args.start, &mut reports) {
+            error!("Could not write report to {:?}: {}", path, e);
+            is_written = false;
+        }
+    }
+    if args.estimate {
+        let original_size: u64 = reports.iter().map(|r| r.original_size).sum();
+        let compressed_size: u64 = reports.iter().map(|r| r.compressed_size).sum();
+        info!(
+            "Would compress {} files, {} to about {}, saving about {}",
+            reports.len(),
+            size::Size::from_bytes(original_size),
+            size::Size::from_bytes(compressed_size),
+            size::Size::from_bytes(original_size.saturating_sub(compressed_size))
+        );
+    } else if args.dry_run {
+        info!(
             "Would compress {} files, {}",
             n_files,
             size::Size::from_bytes(n_bytes)
         );
     } else {
-        eprintln!(
+        info!(
             "Compressed {} files, {}",
-            n_files,
-            size::Size::from_bytes(n_bytes)
+            reports.len(),
+            size::Size::from_bytes(reports.iter().map(|r| r.original_size).sum::<u64>())
         );
     }
+    let n_failed = n_failed.load(Ordering::Relaxed);
+    if n_failed > 0 {
+        error!("{} files or directories could not be compressed", n_failed);
+    }
+    if n_failed > 0 || !is_written {
+        std::process::exit(1)
+    }
+}
+
+#[cfg(test)]
+mod test_dnazip {
+    use crate::{
+        check_hard_links, compress_or_log, mate_path, Bars, Codec, Existing, HardLinks, Options,
+    };
+    use progress::Progress;
+    use std::{
+        collections::HashSet,
+        num::NonZeroUsize,
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    // The options used when no flags are given, compressing the files under `start`
+    pub(crate) fn options(start: &Path) -> Options {
+        Options {
+            start: start.to_owned(),
+            dest: None,
+            keep_originals: false,
+            dry_run: false,
+            estimate: false,
+            verbose: false,
+            codec: Codec::Gzip,
+            level: Codec::Gzip.default_level(),
+            recompress: false,
+            block_threads: NonZeroUsize::MIN,
+            verify: true,
+            existing: Existing::Skip,
+            validate: false,
+            fail_fast: false,
+            max_rate: None,
+            manifest: None,
+            deletion_queue: None,
+        }
+    }
+
+    // An empty directory for a test, named after it
+    pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dnazip_test_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_mate_path() {
+        let mate = |name: &str| mate_path(Path::new(name)).map(|p| p.display().to_string());
+        assert_eq!(mate("dir/x_R1.fq").as_deref(), Some("dir/x_R2.fq"));
+        assert_eq!(mate("x_R2.fq.gz").as_deref(), Some("x_R1.fq.gz"));
+        assert_eq!(mate("x_R1_001.fastq").as_deref(), Some("x_R2_001.fastq"));
+        assert_eq!(mate("x_1.fq").as_deref(), Some("x_2.fq"));
+        assert_eq!(mate("x_2.fq").as_deref(), Some("x_1.fq"));
+        assert_eq!(mate("a_1_R1.fq").as_deref(), Some("a_1_R2.fq"));
+        assert_eq!(mate("x_10.fq"), None);
+        assert_eq!(mate("sample_R1x.fq"), None);
+        assert_eq!(mate("x.fq"), None);
+    }
+
+    #[test]
+    fn test_failed_mate() {
+        let dir = scratch_dir("mate");
+        let r1 = dir.join("x_R1.fq");
+        let r2 = dir.join("x_R2.fq");
+        std::fs::write(&r1, "@r1\nACGT\n+\nIIII\n").unwrap();
+        // The quality is too short, so the file is invalid
+        std::fs::write(&r2, "@r1\nACGT\n+\nIII\n").unwrap();
+        let options = Options {
+            validate: true,
+            ..options(&dir)
+        };
+        let bars = Bars {
+            files: Progress::new("Compressing", "files", None),
+            bytes: Progress::new("Compressing", "bytes", None),
+        };
+        let n_failed = AtomicUsize::new(0);
+        let mut reports = Vec::new();
+        compress_or_log(
+            &[r1.clone(), r2.clone()],
+            &options,
+            &bars,
+            &n_failed,
+            &mut reports,
+        );
+        assert_eq!(n_failed.load(Ordering::Relaxed), 1);
+        assert!(reports.is_empty());
+        // Both originals are kept, and the compressed mate is removed
+        assert!(r1.exists() && r2.exists());
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(names, ["x_R1.fq", "x_R2.fq"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hard_links() {
+        let dir = scratch_dir("hard_links");
+        let (single, a, b) = (dir.join("single.fa"), dir.join("a.fa"), dir.join("b.fa"));
+        std::fs::write(&single, ">s\nACGT\n").unwrap();
+        std::fs::write(&a, ">a\nACGT\n").unwrap();
+        std::fs::hard_link(&a, &b).unwrap();
+        let check = |path: &Path, keep_originals: bool, inodes: &mut HashSet<(u64, u64)>| {
+            let metadata = std::fs::metadata(path).unwrap();
+            check_hard_links(&metadata, keep_originals, inodes)
+        };
+        // The originals would be removed, also with --dest and --delete-after
+        let mut inodes = HashSet::new();
+        assert_eq!(check(&single, false, &mut inodes), HardLinks::Compress);
+        assert_eq!(check(&a, false, &mut inodes), HardLinks::Linked);
+        assert_eq!(check(&b, false, &mut inodes), HardLinks::Linked);
+        // With --keep-originals, the file is compressed under its first name only
+        assert_eq!(check(&single, true, &mut inodes), HardLinks::Compress);
+        assert_eq!(check(&a, true, &mut inodes), HardLinks::Compress);
+        assert_eq!(check(&b, true, &mut inodes), HardLinks::Seen);
+        assert_eq!(check(&a, true, &mut inodes), HardLinks::Seen);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
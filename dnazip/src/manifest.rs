@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs::{File, Metadata},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    os::unix::ffi::OsStringExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{sync_file, write_atomically, FileReport};
+
+// The size, modification time and CRC32 of an original file when it was compressed
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub size: u64,
+    pub modified: SystemTime,
+    pub crc: u32,
+}
+
+// The files compressed by an earlier run with --manifest, and the files found unchanged
+// since by this run. Files that have not changed are not compressed again, which saves
+// work when dnazip is run over the same directory with --keep-originals or --dest.
+pub struct Manifest {
+    previous: HashMap<PathBuf, Entry>,
+    unchanged: Mutex<Vec<(PathBuf, Entry)>>,
+}
+
+// Modification times are written as seconds since the Unix epoch, with nanoseconds
+pub fn format_time(time: SystemTime) -> Option<String> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "{}.{:09}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    ))
+}
+
+pub fn parse_time(s: &str) -> Option<SystemTime> {
+    let (secs, nanos) = s.split_once('.')?;
+    let since_epoch = Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+    UNIX_EPOCH.checked_add(since_epoch)
+}
+
+// Parse a line of the manifest: The path, size, modification time and CRC32 in hex, separated
+// by tabs. The fields are split from the end, since the path may contain tabs.
+fn parse_line(line: Vec<u8>) -> Option<(PathBuf, Entry)> {
+    let mut fields = line.rsplitn(4, |&b| b == b'\t');
+    let crc = std::str::from_utf8(fields.next()?).ok()?;
+    let modified = std::str::from_utf8(fields.next()?).ok()?;
+    let size = std::str::from_utf8(fields.next()?).ok()?;
+    let path = PathBuf::from(OsString::from_vec(fields.next()?.to_vec()));
+    let entry = Entry {
+        size: size.parse().ok()?,
+        modified: parse_time(modified)?,
+        crc: u32::from_str_radix(crc, 16).ok()?,
+    };
+    Some((path, entry))
+}
+
+impl Manifest {
+    // Read the manifest of an earlier run. If there is none, all files are compressed.
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let mut previous = HashMap::new();
+        match File::open(path) {
+            Ok(file) => {
+                // The first line is the header
+                for (i, line) in BufReader::new(file).split(b'\n').enumerate().skip(1) {
+                    let line =
+                        line.with_context(|| format!("Could not read manifest {:?}", path))?;
+                    let Some((file_path, entry)) = parse_line(line) else {
+                        bail!("Invalid line {} in manifest {:?}", i + 1, path);
+                    };
+                    previous.insert(file_path, entry);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e).with_context(|| format!("Could not open manifest {:?}", path)),
+        }
+        Ok(Self {
+            previous,
+            unchanged: Mutex::new(Vec::new()),
+        })
+    }
+
+    // Whether the file is unchanged since it was compressed by an earlier run. Files with the
+    // same size and modification time are unchanged. Files with the same size, but another
+    // modification time, are read to compare their CRC32, given by `checksum`.
+    pub fn is_unchanged(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        checksum: impl FnOnce() -> anyhow::Result<u32>,
+    ) -> anyhow::Result<bool> {
+        let Some(&entry) = self.previous.get(path) else {
+            return Ok(false);
+        };
+        let Ok(modified) = metadata.modified() else {
+            return Ok(false);
+        };
+        if metadata.len() != entry.size || modified != entry.modified && checksum()? != entry.crc {
+            return Ok(false);
+        }
+        self.unchanged
+            .lock()
+            .unwrap()
+            .push((path.to_owned(), Entry { modified, ..entry }));
+        Ok(true)
+    }
+
+    // Write the entries of the files that were unchanged, and of the files compressed in full
+    // by this run. Files that were not found by this run are left out.
+    pub fn write(&self, path: &Path, reports: &[FileReport]) -> anyhow::Result<()> {
+        let unchanged = self.unchanged.lock().unwrap();
+        let compressed = reports
+            .iter()
+            .filter_map(|r| r.original.map(|entry| (&r.path, entry)));
+        let entries: HashMap<&PathBuf, Entry> = unchanged
+            .iter()
+            .map(|(path, entry)| (path, *entry))
+            .chain(compressed)
+            .collect();
+        let mut entries: Vec<_> = entries.into_iter().collect();
+        entries.sort_unstable_by_key(|(path, _)| *path);
+        write_atomically(path.as_os_str(), |tmp: &OsStr| {
+            let mut out = BufWriter::new(
+                File::create(tmp)
+                    .with_context(|| format!("Could not create manifest {:?}", tmp))?,
+            );
+            let write_rows = || -> std::io::Result<()> {
+                writeln!(out, "path\tsize\tmodified\tcrc32")?;
+                for (file_path, entry) in entries {
+                    let bytes = file_path.as_os_str().as_encoded_bytes();
+                    // Files with newlines in their path, or modified before 1970, are left out,
+                    // so they are compressed again
+                    let Some(modified) = format_time(entry.modified) else {
+                        continue;
+                    };
+                    if bytes.contains(&b'\n') {
+                        continue;
+                    }
+                    out.write_all(bytes)?;
+                    writeln!(out, "\t{}\t{}\t{:08x}", entry.size, modified, entry.crc)?;
+                }
+                Ok(())
+            };
+            write_rows().with_context(|| format!("Could not write manifest {:?}", tmp))?;
+            sync_file(out, tmp)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_manifest {
+    use crate::{
+        manifest::{format_time, parse_line, Manifest},
+        test_dnazip::scratch_dir,
+    };
+    use std::{
+        path::Path,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    #[test]
+    fn test_parse_line() {
+        let (path, entry) =
+            parse_line(b"dir/a\tb.fa\t12\t1700000000.000000005\t0000abcd".to_vec()).unwrap();
+        assert_eq!(path, Path::new("dir/a\tb.fa"));
+        assert_eq!(entry.size, 12);
+        assert_eq!(entry.modified, UNIX_EPOCH + Duration::new(1_700_000_000, 5));
+        assert_eq!(entry.crc, 0xabcd);
+        assert!(parse_line(b"a.fa\t12\t1700000000.0".to_vec()).is_none());
+        assert!(parse_line(b"a.fa\tx\t1700000000.0\t0000abcd".to_vec()).is_none());
+        assert!(parse_line(b"a.fa\t12\t1700000000\t0000abcd".to_vec()).is_none());
+    }
+
+    #[test]
+    fn test_is_unchanged() {
+        let dir = scratch_dir("manifest");
+        let file = dir.join("a.fa");
+        std::fs::write(&file, "ACGT").unwrap();
+        let metadata = std::fs::metadata(&file).unwrap();
+        let modified = format_time(metadata.modified().unwrap()).unwrap();
+        let earlier = format_time(metadata.modified().unwrap() - Duration::from_secs(60)).unwrap();
+        let manifest_path = dir.join("manifest.tsv");
+        let read = |size: u64, modified: &str| {
+            let line = format!("{}\t{}\t{}\t0000abcd\n", file.display(), size, modified);
+            std::fs::write(
+                &manifest_path,
+                format!("path\tsize\tmodified\tcrc32\n{}", line),
+            )
+            .unwrap();
+            Manifest::read(&manifest_path).unwrap()
+        };
+
+        // Same size and modification time: The file is not read
+        let manifest = read(4, &modified);
+        let unchanged = manifest.is_unchanged(&file, &metadata, || panic!("File was read"));
+        assert!(unchanged.unwrap());
+
+        // Same size, other modification time: The CRC32 decides
+        let manifest = read(4, &earlier);
+        assert!(manifest
+            .is_unchanged(&file, &metadata, || Ok(0xabcd))
+            .unwrap());
+        assert!(!manifest
+            .is_unchanged(&file, &metadata, || Ok(0x1234))
+            .unwrap());
+        assert!(manifest
+            .is_unchanged(&file, &metadata, || anyhow::bail!("Read error"))
+            .is_err());
+
+        // Other size
+        let manifest = read(5, &modified);
+        assert!(!manifest
+            .is_unchanged(&file, &metadata, || Ok(0xabcd))
+            .unwrap());
+
+        // Not in the manifest
+        let other = dir.join("b.fa");
+        assert!(!manifest
+            .is_unchanged(&other, &metadata, || Ok(0xabcd))
+            .unwrap());
+
+        std::fs::write(&manifest_path, "path\tsize\tmodified\tcrc32\nbad line\n").unwrap();
+        assert!(Manifest::read(&manifest_path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    os::unix::ffi::OsStringExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use progress::{error, info, warn};
+
+use crate::{
+    manifest::{format_time, parse_time},
+    sync_file, write_atomically, write_path,
+};
+
+// An original file to be removed once it is due, if it has not changed since it was compressed
+struct Queued {
+    original: PathBuf,
+    compressed: PathBuf,
+    size: u64,
+    modified: SystemTime,
+    due: SystemTime,
+}
+
+// Parse a line of the queue: The original and compressed paths, the size and modification time
+// of the original, and when it is due to be removed, separated by tabs
+fn parse_line(line: Vec<u8>) -> Option<Queued> {
+    let fields: Vec<&[u8]> = line.split(|&b| b == b'\t').collect();
+    let [original, compressed, size, modified, due] = fields[..] else {
+        return None;
+    };
+    let text = |field| std::str::from_utf8(field).ok();
+    Some(Queued {
+        original: PathBuf::from(OsString::from_vec(original.to_vec())),
+        compressed: PathBuf::from(OsString::from_vec(compressed.to_vec())),
+        size: text(size)?.parse().ok()?,
+        modified: parse_time(text(modified)?)?,
+        due: parse_time(text(due)?)?,
+    })
+}
+
+fn read_queue(path: &Path) -> anyhow::Result<Vec<Queued>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Could not open deletion queue {:?}", path))
+        }
+    };
+    let mut queued = Vec::new();
+    // The first line is the header
+    for (i, line) in BufReader::new(file).split(b'\n').enumerate().skip(1) {
+        let line = line.with_context(|| format!("Could not read deletion queue {:?}", path))?;
+        let Some(entry) = parse_line(line) else {
+            bail!("Invalid line {} in deletion queue {:?}", i + 1, path);
+        };
+        queued.push(entry);
+    }
+    Ok(queued)
+}
+
+fn write_queue(path: &Path, queued: &[Queued]) -> anyhow::Result<()> {
+    write_atomically(path.as_os_str(), |tmp: &OsStr| {
+        let mut out = BufWriter::new(
+            File::create(tmp)
+                .with_context(|| format!("Could not create deletion queue {:?}", tmp))?,
+        );
+        let mut write_rows = || -> std::io::Result<()> {
+            writeln!(out, "original\tcompressed\tsize\tmodified\tdue")?;
+            for entry in queued {
+                out.write_all(entry.original.as_os_str().as_encoded_bytes())?;
+                out.write_all(b"\t")?;
+                out.write_all(entry.compressed.as_os_str().as_encoded_bytes())?;
+                // Files modified before 1970 are not queued
+                writeln!(
+                    out,
+                    "\t{}\t{}\t{}",
+                    entry.size,
+                    format_time(entry.modified).unwrap(),
+                    format_time(entry.due).unwrap()
+                )?;
+            }
+            Ok(())
+        };
+        write_rows().with_context(|| format!("Could not write deletion queue {:?}", tmp))?;
+        sync_file(out, tmp)?;
+        Ok(())
+    })
+}
+
+// With --delete-after, the original files compressed by this run, which are added to the
+// deletion queue at the end of the run instead of being removed
+pub struct DeletionQueue {
+    delay: Duration,
+    queued: Mutex<Vec<Queued>>,
+}
+
+impl DeletionQueue {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            queued: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Queue the original file, which has been compressed to `compressed`, to be removed later
+    pub fn push(&self, original: &Path, compressed: &OsStr) -> anyhow::Result<()> {
+        let metadata = std::fs::metadata(original)
+            .with_context(|| format!("Could not read metadata of {:?}", original))?;
+        let modified = metadata.modified()?;
+        // Tabs and newlines separate the fields and lines of the queue
+        let has_separator = |p: &OsStr| p.as_encoded_bytes().iter().any(|b| b"\t\n".contains(b));
+        if has_separator(original.as_os_str()) || has_separator(compressed) {
+            bail!("Path has a tab or newline, so {:?} is kept", original);
+        }
+        if format_time(modified).is_none() {
+            bail!("File was modified before 1970, so {:?} is kept", original);
+        }
+        self.queued.lock().unwrap().push(Queued {
+            original: original.to_owned(),
+            compressed: PathBuf::from(compressed),
+            size: metadata.len(),
+            modified,
+            due: SystemTime::now() + self.delay,
+        });
+        Ok(())
+    }
+
+    // Add the original files compressed by this run to the deletion queue at `path`
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut queued = read_queue(path)?;
+        queued.append(&mut self.queued.lock().unwrap());
+        write_queue(path, &queued)
+    }
+}
+
+// Whether the queued original file can be removed: It must not have changed since it was
+// compressed, and its compressed file must still exist. If not, it is no longer queued.
+fn can_remove(entry: &Queued) -> bool {
+    let metadata = match std::fs::metadata(&entry.original) {
+        Ok(metadata) => metadata,
+        // Already removed
+        Err(e) if e.kind() == ErrorKind::NotFound => return false,
+        Err(e) => {
+            warn!(
+                "Not removing {}, since its metadata could not be read: {}",
+                entry.original.display(),
+                e
+            );
+            return false;
+        }
+    };
+    if metadata.len() != entry.size || metadata.modified().ok() != Some(entry.modified) {
+        warn!(
+            "Not removing {}, since it changed after it was compressed",
+            entry.original.display()
+        );
+        false
+    } else if !entry.compressed.is_file() {
+        warn!(
+            "Not removing {}, since its compressed file {} no longer exists",
+            entry.original.display(),
+            entry.compressed.display()
+        );
+        false
+    } else {
+        true
+    }
+}
+
+// Remove the original files in the deletion queue that are due, and keep the others in the
+// queue. Files that could not be removed are kept too, to be tried again by the next purge.
+// Return the number of files that could not be removed.
+pub fn purge(path: &Path, dry_run: bool, verbose: bool) -> anyhow::Result<usize> {
+    let now = SystemTime::now();
+    let (due, mut waiting): (Vec<_>, Vec<_>) = read_queue(path)?
+        .into_iter()
+        .partition(|entry| entry.due <= now);
+    let n_waiting = waiting.len();
+    let removable: Vec<Queued> = due.into_iter().filter(can_remove).collect();
+    if dry_run {
+        for entry in &removable {
+            write_path(&entry.original, Some("Would remove: "), None);
+        }
+        info!(
+            "Would remove {} original files, {} files are not yet due",
+            removable.len(),
+            n_waiting
+        );
+        return Ok(0);
+    }
+    let mut n_removed = 0;
+    let mut n_failed = 0;
+    for entry in removable {
+        match std::fs::remove_file(&entry.original) {
+            Ok(()) => {
+                n_removed += 1;
+                if verbose {
+                    write_path(&entry.original, Some("Removed: "), None);
+                }
+            }
+            Err(e) => {
+                error!("Could not remove {}: {}", entry.original.display(), e);
+                n_failed += 1;
+                waiting.push(entry);
+            }
+        }
+    }
+    write_queue(path, &waiting)?;
+    info!(
+        "Removed {} original files, {} files are not yet due",
+        n_removed, n_waiting
+    );
+    Ok(n_failed)
+}
+
+#[cfg(test)]
+mod test_queue {
+    use crate::{
+        queue::{purge, read_queue, write_queue, Queued},
+        test_dnazip::scratch_dir,
+    };
+    use std::{
+        fs::File,
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    };
+
+    // Write an original file and its compressed file, and queue the original
+    fn queued(dir: &Path, name: &str, due: SystemTime) -> Queued {
+        let original = dir.join(name);
+        let compressed = dir.join(format!("{}.gz", name));
+        std::fs::write(&original, "ACGT").unwrap();
+        std::fs::write(&compressed, "").unwrap();
+        let metadata = std::fs::metadata(&original).unwrap();
+        Queued {
+            original,
+            compressed,
+            size: metadata.len(),
+            modified: metadata.modified().unwrap(),
+            due,
+        }
+    }
+
+    fn originals(path: &Path) -> Vec<PathBuf> {
+        read_queue(path)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.original)
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = scratch_dir("queue_round_trip");
+        let path = dir.join("queue.tsv");
+        assert!(read_queue(&path).unwrap().is_empty());
+        let entries = [
+            queued(&dir, "a b.fa", SystemTime::now()),
+            queued(&dir, "c.fq", SystemTime::now() + Duration::from_secs(86400)),
+        ];
+        write_queue(&path, &entries).unwrap();
+        let read = read_queue(&path).unwrap();
+        assert_eq!(read.len(), 2);
+        for (a, b) in entries.iter().zip(&read) {
+            assert_eq!(a.original, b.original);
+            assert_eq!(a.compressed, b.compressed);
+            assert_eq!(a.size, b.size);
+            assert_eq!(a.modified, b.modified);
+            assert_eq!(a.due, b.due);
+        }
+        std::fs::write(
+            &path,
+            "original\tcompressed\tsize\tmodified\tdue\na\tb\t1\t2\n",
+        )
+        .unwrap();
+        assert!(read_queue(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_purge() {
+        let dir = scratch_dir("queue_purge");
+        let path = dir.join("queue.tsv");
+        let past = SystemTime::now() - Duration::from_secs(1);
+        let future = SystemTime::now() + Duration::from_secs(86400);
+        let entries = [
+            queued(&dir, "due.fa", past),
+            queued(&dir, "waiting.fa", future),
+            queued(&dir, "resized.fa", past),
+            queued(&dir, "touched.fa", past),
+            queued(&dir, "no_compressed.fa", past),
+        ];
+        write_queue(&path, &entries).unwrap();
+        std::fs::write(dir.join("resized.fa"), "ACGTACGT").unwrap();
+        File::options()
+            .write(true)
+            .open(dir.join("touched.fa"))
+            .unwrap()
+            .set_modified(past - Duration::from_secs(60))
+            .unwrap();
+        std::fs::remove_file(dir.join("no_compressed.fa.gz")).unwrap();
+
+        // A dry run removes nothing
+        assert_eq!(purge(&path, true, false).unwrap(), 0);
+        assert!(entries.iter().all(|entry| entry.original.exists()));
+        assert_eq!(originals(&path).len(), 5);
+
+        assert_eq!(purge(&path, false, false).unwrap(), 0);
+        assert!(!dir.join("due.fa").exists());
+        for name in ["waiting.fa", "resized.fa", "touched.fa", "no_compressed.fa"] {
+            assert!(dir.join(name).exists(), "{} was removed", name);
+        }
+        // Only the files that are not yet due are still queued
+        assert_eq!(originals(&path), [dir.join("waiting.fa")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use crate::manifest::Entry;
+
+// The sizes of a file before and after compression, and how long it took
+pub struct FileReport {
+    pub path: PathBuf,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub duration: Duration,
+    // The original file, if it was compressed in full, for --manifest
+    pub original: Option<Entry>,
+}
+
+// The sum over a group of files
+#[derive(Default)]
+struct Totals {
+    n_files: u64,
+    original_size: u64,
+    compressed_size: u64,
+    duration: Duration,
+}
+
+impl Totals {
+    fn add(&mut self, file: &FileReport) {
+        self.n_files += 1;
+        self.original_size += file.original_size;
+        self.compressed_size += file.compressed_size;
+        self.duration += file.duration;
+    }
+}
+
+// How many times smaller the compressed file is
+fn ratio(original_size: u64, compressed_size: u64) -> f64 {
+    if compressed_size == 0 {
+        0.0
+    } else {
+        original_size as f64 / compressed_size as f64
+    }
+}
+
+// The subdirectory of `start` the file is in, or "." for files directly in `start`
+fn top_directory(path: &Path, start: &Path) -> String {
+    let relative = path.strip_prefix(start).unwrap_or(path);
+    let mut components = relative.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(dir)), Some(_)) => dir.to_string_lossy().into_owned(),
+        _ => ".".to_owned(),
+    }
+}
+
+// A path as a JSON string. File names may contain any character but / and NUL, so
+// tabs, newlines and other control characters are escaped. Paths are read lossily, as
+// JSON strings can't hold bytes that are not UTF-8.
+fn json_path(path: &str) -> String {
+    let mut json = String::with_capacity(path.len() + 2);
+    json.push('"');
+    for c in path.chars() {
+        match c {
+            '"' | '\\' => {
+                json.push('\\');
+                json.push(c);
+            }
+            '\t' => json.push_str("\\t"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// Write the report of each file, and the totals of each subdirectory of `start` and of all files.
+// The report is JSON if the path ends with .json, and TSV otherwise, where the kind of
+// each row is in the first column.
+pub fn write_report(path: &Path, start: &Path, files: &mut [FileReport]) -> io::Result<()> {
+    files.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    let mut directories: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut total = Totals::default();
+    for file in files.iter() {
+        directories
+            .entry(top_directory(&file.path, start))
+            .or_default()
+            .add(file);
+        total.add(file);
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    if path.extension().is_some_and(|e| e == "json") {
+        write_json(&mut out, files, &directories, &total)?;
+    } else {
+        write_tsv(&mut out, start, files, &directories, &total)?;
+    }
+    out.flush()
+}
+
+fn write_tsv(
+    out: &mut impl Write,
+    start: &Path,
+    files: &[FileReport],
+    directories: &BTreeMap<String, Totals>,
+    total: &Totals,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "kind\tpath\tfiles\toriginal_size\tcompressed_size\tratio\tseconds"
+    )?;
+    let mut write_row = |kind: &str, path: &str, totals: &Totals| {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{:.3}\t{:.3}",
+            kind,
+            path,
+            totals.n_files,
+            totals.original_size,
+            totals.compressed_size,
+            ratio(totals.original_size, totals.compressed_size),
+            totals.duration.as_secs_f64()
+        )
+    };
+    for file in files {
+        let mut totals = Totals::default();
+        totals.add(file);
+        write_row("file", &file.path.to_string_lossy(), &totals)?;
+    }
+    for (directory, totals) in directories {
+        write_row("directory", directory, totals)?;
+    }
+    write_row("total", &start.to_string_lossy(), total)
+}
+
+// The fields of a file or group of files, without braces
+fn json_fields(original_size: u64, compressed_size: u64, duration: Duration) -> String {
+    format!(
+        "\"original_size\": {}, \"compressed_size\": {}, \"ratio\": {:.3}, \"seconds\": {:.3}",
+        original_size,
+        compressed_size,
+        ratio(original_size, compressed_size),
+        duration.as_secs_f64()
+    )
+}
+
+fn write_json(
+    out: &mut impl Write,
+    files: &[FileReport],
+    directories: &BTreeMap<String, Totals>,
+    total: &Totals,
+) -> io::Result<()> {
+    out.write_all(b"{\"files\": [")?;
+    for (i, file) in files.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write!(
+            out,
+            "\n{{\"path\": {}, {}}}",
+            json_path(&file.path.to_string_lossy()),
+            json_fields(file.original_size, file.compressed_size, file.duration)
+        )?;
+    }
+    out.write_all(b"\n],\n\"directories\": [")?;
+    for (i, (directory, totals)) in directories.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write!(
+            out,
+            "\n{{\"path\": {}, \"files\": {}, {}}}",
+            json_path(directory),
+            totals.n_files,
+            json_fields(
+                totals.original_size,
+                totals.compressed_size,
+                totals.duration
+            )
+        )?;
+    }
+    writeln!(
+        out,
+        "\n],\n\"total\": {{\"files\": {}, {}}}}}",
+        total.n_files,
+        json_fields(total.original_size, total.compressed_size, total.duration)
+    )
+}
+
+#[cfg(test)]
+mod test_report {
+    use crate::report::json_path;
+
+    #[test]
+    fn test_json_path() {
+        assert_eq!(json_path("run1/reads.fq"), "\"run1/reads.fq\"");
+        assert_eq!(json_path("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_path("a\tb\nc"), "\"a\\tb\\nc\"");
+        assert_eq!(json_path("a\rb\u{7f}"), "\"a\\u000db\\u007f\"");
+        assert_eq!(json_path("å"), "\"å\"");
+    }
+}
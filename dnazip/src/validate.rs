@@ -0,0 +1,225 @@
+use std::io::{self, Read};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SeqFormat {
+    Fasta,
+    Fastq,
+}
+
+// A reader that checks the basic format of a FASTA or FASTQ file as it is read, and fails with
+// an error of kind InvalidData at the first problem. FASTA files must start with a header.
+// FASTQ records must have four lines, a header, sequence, "+" line and qualities, and
+// the qualities must be as long as the sequence. Empty lines are allowed at the end.
+pub struct Validator<R> {
+    inner: R,
+    format: SeqFormat,
+    // Number of lines finished, and number of lines checked, which does not count empty lines
+    // before the first FASTA header or at the end of FASTQ files
+    line_number: u64,
+    n_lines: u64,
+    // The first byte and length of the current line, without line endings
+    first: Option<u8>,
+    length: u64,
+    last: Option<u8>,
+    seq_length: u64,
+    // Set at the first empty line in a FASTQ file, after which all lines must be empty
+    blank_seen: bool,
+}
+
+impl<R: Read> Validator<R> {
+    pub fn new(inner: R, format: SeqFormat) -> Self {
+        Self {
+            inner,
+            format,
+            line_number: 0,
+            n_lines: 0,
+            first: None,
+            length: 0,
+            last: None,
+            seq_length: 0,
+            blank_seen: false,
+        }
+    }
+
+    fn error(&self, message: &str) -> io::Error {
+        let format = match self.format {
+            SeqFormat::Fasta => "FASTA",
+            SeqFormat::Fastq => "FASTQ",
+        };
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Invalid {} file at line {}: {}",
+                format, self.line_number, message
+            ),
+        )
+    }
+
+    fn end_line(&mut self) -> io::Result<()> {
+        let length = self.length - (self.last == Some(b'\r')) as u64;
+        let first = self.first;
+        self.first = None;
+        self.length = 0;
+        self.last = None;
+        self.line_number += 1;
+        if length == 0 {
+            // Empty lines are allowed before the first FASTA header, and at the end of FASTQ files
+            match self.format {
+                SeqFormat::Fasta if self.n_lines > 0 => (),
+                SeqFormat::Fasta => return Ok(()),
+                SeqFormat::Fastq if self.n_lines.is_multiple_of(4) => {
+                    self.blank_seen = true;
+                    return Ok(());
+                }
+                SeqFormat::Fastq => (),
+            }
+        }
+        match self.format {
+            SeqFormat::Fasta if self.n_lines == 0 && first != Some(b'>') => {
+                return Err(self.error("file does not start with a '>' header"))
+            }
+            SeqFormat::Fasta => (),
+            SeqFormat::Fastq if self.blank_seen => {
+                return Err(self.error("empty line before the end of the file"))
+            }
+            SeqFormat::Fastq => match self.n_lines % 4 {
+                0 if first != Some(b'@') => {
+                    return Err(self.error("header does not start with '@'"))
+                }
+                1 => self.seq_length = length,
+                2 if first != Some(b'+') => {
+                    return Err(self.error("separator line does not start with '+'"))
+                }
+                3 if length != self.seq_length => {
+                    return Err(self.error(&format!(
+                        "quality has length {}, but sequence has length {}",
+                        length, self.seq_length
+                    )))
+                }
+                _ => (),
+            },
+        }
+        self.n_lines += 1;
+        Ok(())
+    }
+
+    fn check(&mut self, data: &[u8]) -> io::Result<()> {
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            let ends_line = line.last() == Some(&b'\n');
+            let content = if ends_line {
+                &line[..line.len() - 1]
+            } else {
+                line
+            };
+            if self.first.is_none() {
+                self.first = content.first().copied();
+            }
+            self.length += content.len() as u64;
+            if let Some(&b) = content.last() {
+                self.last = Some(b);
+            }
+            if ends_line {
+                self.end_line()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // The last line may not end with a newline
+        if self.length > 0 {
+            self.end_line()?;
+        }
+        if self.format == SeqFormat::Fastq && !self.n_lines.is_multiple_of(4) {
+            return Err(self.error("file ends in the middle of a record"));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Validator<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.finish()?;
+        } else {
+            self.check(&buf[..n])?;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test_validate {
+    use crate::validate::{SeqFormat, Validator};
+    use std::io::{self, Read};
+
+    // Yields the data in chunks of at most `chunk` bytes, to split lines across reads
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.data.len().min(self.chunk).min(buf.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn validate(data: &str, format: SeqFormat) -> io::Result<()> {
+        for chunk in [1, 3, 7, data.len().max(1)] {
+            let reader = Chunked {
+                data: data.as_bytes(),
+                chunk,
+            };
+            let mut out = Vec::new();
+            Validator::new(reader, format).read_to_end(&mut out)?;
+            assert_eq!(out, data.as_bytes());
+        }
+        Ok(())
+    }
+
+    fn is_invalid(data: &str, format: SeqFormat) -> bool {
+        validate(data, format).is_err_and(|e| e.kind() == io::ErrorKind::InvalidData)
+    }
+
+    #[test]
+    fn test_valid() {
+        validate("@r1\nACGT\n+\nIIII\n@r2\nAC\n+r2\nII\n", SeqFormat::Fastq).unwrap();
+        validate("@r1\r\nACGT\r\n+\r\nIIII\r\n", SeqFormat::Fastq).unwrap();
+        validate("@r1\nACGT\n+\nIIII", SeqFormat::Fastq).unwrap();
+        validate("@r1\nACGT\n+\nIIII\n\n\n", SeqFormat::Fastq).unwrap();
+        validate("", SeqFormat::Fastq).unwrap();
+        validate(">c1\nACGT\nAC\n>c2\n\nGG\n", SeqFormat::Fasta).unwrap();
+        validate("\n>c1\r\nACGT\r\n", SeqFormat::Fasta).unwrap();
+    }
+
+    #[test]
+    fn test_invalid() {
+        // Quality and sequence of different lengths, also with CRLF
+        assert!(is_invalid("@r1\nACGT\n+\nIII\n", SeqFormat::Fastq));
+        assert!(is_invalid(
+            "@r1\r\nACGT\r\n+\r\nIIIII\r\n",
+            SeqFormat::Fastq
+        ));
+        // Truncated last record
+        assert!(is_invalid(
+            "@r1\nACGT\n+\nIIII\n@r2\nAC\n",
+            SeqFormat::Fastq
+        ));
+        // Empty lines in the middle
+        assert!(is_invalid(
+            "@r1\nACGT\n+\nIIII\n\n@r2\nAC\n+\nII\n",
+            SeqFormat::Fastq
+        ));
+        assert!(is_invalid("@r1\n\n+\nIIII\n", SeqFormat::Fastq));
+        assert!(is_invalid("r1\nACGT\n+\nIIII\n", SeqFormat::Fastq));
+        assert!(is_invalid("@r1\nACGT\n-\nIIII\n", SeqFormat::Fastq));
+        // FASTA without a leading header
+        assert!(is_invalid("ACGT\n>c1\nACGT\n", SeqFormat::Fasta));
+        assert!(is_invalid("\nACGT\n", SeqFormat::Fasta));
+    }
+}
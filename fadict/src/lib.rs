@@ -0,0 +1,48 @@
+//! The MD5 checksums of sequence dictionaries. This is the library behind the fadict binary,
+//! so other tools, like refcheck, compute the same checksums as the dictionaries they check.
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use md5::{Digest, Md5};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+/// The MD5 of the sequence in uppercase, as in the M5 tag specified by the SAM format.
+/// The sequence must not contain line breaks, as returned by a FASTA parser.
+pub fn sequence_md5(seq: &[u8]) -> String {
+    let mut hasher = Md5::new();
+    for chunk in seq.chunks(8192) {
+        let upper: Vec<u8> = chunk.iter().map(|b| b.to_ascii_uppercase()).collect();
+        hasher.update(&upper);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Open a file, which is decompressed if its name ends with .gz
+pub fn open_input(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path)
+        .with_context(|| format!("Could not open input file \"{}\"", path.to_string_lossy()))?;
+    if path.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+#[cfg(test)]
+mod test_md5 {
+    use crate::sequence_md5;
+
+    #[test]
+    fn test_md5() {
+        assert_eq!(sequence_md5(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(sequence_md5(b"ACGT"), sequence_md5(b"acgt"));
+        assert_eq!(sequence_md5(b"ACGT"), "f1f8f4bf413b16ad135722aa4591043e");
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{self, bail, Context, Result};
+use bio::io::fasta::Reader;
+use clap::Parser;
+use fadict::{open_input, sequence_md5};
+use progress::{info, LogArgs, Progress};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let reader = Reader::from_bufread(open_input(&args.fasta)?);
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| default_output(&args.fasta));
+    if output.exists() && !args.force {
+        bail!(
+            "Output file \"{}\" already exists. Use --force to overwrite it",
+            output.to_string_lossy()
+        );
+    }
+    let uri = match &args.uri {
+        Some(uri) => uri.clone(),
+        None => {
+            let path = std::fs::canonicalize(&args.fasta).with_context(|| {
+                format!(
+                    "Could not get absolute path of \"{}\"",
+                    args.fasta.to_string_lossy()
+                )
+            })?;
+            format!("file:{}", path.to_string_lossy())
+        }
+    };
+
+    let mut out = BufWriter::new(File::create(&output).with_context(|| {
+        format!(
+            "Could not create output file \"{}\"",
+            output.to_string_lossy()
+        )
+    })?);
+    out.write_all(b"@HD\tVN:1.6\n")?;
+    let mut names: HashSet<String> = HashSet::new();
+    let progress = Progress::new("Hashing", "sequences", None);
+    for record in reader.records() {
+        let record = record.context("Failed to parse record from FASTA file")?;
+        progress.inc(1);
+        if !names.insert(record.id().to_owned()) {
+            bail!(
+                "Sequence name \"{}\" is present multiple times",
+                record.id()
+            );
+        }
+        write!(
+            out,
+            "@SQ\tSN:{}\tLN:{}\tM5:{}\tUR:{}",
+            record.id(),
+            record.seq().len(),
+            sequence_md5(record.seq()),
+            uri
+        )?;
+        if let Some(assembly) = &args.assembly {
+            write!(out, "\tAS:{}", assembly)?;
+        }
+        if let Some(species) = &args.species {
+            write!(out, "\tSP:{}", species)?;
+        }
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    progress.finish();
+    info!("Wrote dictionary of {} sequences", names.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_output {
+    use crate::default_output;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_default_output() {
+        assert_eq!(
+            default_output(Path::new("dir/ref.fa.gz")),
+            PathBuf::from("dir/ref.dict")
+        );
+        assert_eq!(
+            default_output(Path::new("ref.fasta")),
+            PathBuf::from("ref.dict")
+        );
+        assert_eq!(
+            default_output(Path::new("ref.txt")),
+            PathBuf::from("ref.txt.dict")
+        );
+    }
+}
+
+// Like Picard, replace the FASTA extension with .dict, so ref.fa.gz becomes ref.dict
+fn default_output(path: &Path) -> PathBuf {
+    const EXTENSIONS: [&str; 6] = [".fasta", ".fa", ".fna", ".fas", ".ffn", ".faa"];
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stripped = name.strip_suffix(".gz").unwrap_or(&name);
+    let stem = EXTENSIONS
+        .iter()
+        .find_map(|e| stripped.strip_suffix(e))
+        .unwrap_or(&name);
+    path.with_file_name(format!("{}.dict", stem))
+}
+
+const LONG_ABOUT: &str = "Write a sequence dictionary of a FASTA file, like Picard does.
+The dictionary is a SAM header with an @SQ line for each sequence, giving its name, length,
+MD5 of the uppercased sequence, and the URI of the FASTA file. GATK-based tools require
+this file next to the FASTA file.
+Usage: fadict contigs.fna.gz";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// FASTA file (may be gzipped)
+    fasta: PathBuf,
+
+    /// Output dictionary [FASTA file with extension replaced by .dict]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Overwrite output file if it exists
+    #[arg(short, long)]
+    force: bool,
+
+    /// URI of the FASTA file (UR tag) [file: followed by its absolute path]
+    #[arg(short, long)]
+    uri: Option<String>,
+
+    /// Genome assembly identifier (AS tag)
+    #[arg(short, long)]
+    assembly: Option<String>,
+
+    /// Species (SP tag)
+    #[arg(short, long)]
+    species: Option<String>,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
@@ -0,0 +1,273 @@
+use anyhow::{self, bail, Context, Result};
+use clap::Parser;
+use htslite::BgzfReader;
+use progress::{info, LogArgs, Progress};
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let file = File::open(&args.fasta).with_context(|| {
+        format!(
+            "Could not open FASTA file \"{}\"",
+            args.fasta.to_string_lossy()
+        )
+    })?;
+    let progress = Progress::bytes("Indexing", file.metadata().ok().map(|m| m.len()));
+    let mut file = BufReader::new(progress.wrap_read(file));
+
+    let (entries, gzi) = match compression(file.fill_buf()?) {
+        Compression::None => (index_fasta(&mut file)?, None),
+        Compression::Gzip => bail!(
+            "File is gzipped, but not with BGZF, so it cannot be indexed. Recompress it with `bgzip`."
+        ),
+        Compression::Bgzf => {
+            let mut reader = BgzfReader::new(file).with_index();
+            let entries = index_fasta(&mut reader)?;
+            if !reader.ends_with_eof_block() {
+                bail!("BGZF file is missing the EOF block, and may be truncated");
+            }
+            // Like htslib, the first block (which always begins at offset 0,0) is not
+            // listed, nor is the empty EOF block.
+            let index = reader.index().unwrap();
+            let total = index.last().map_or(0, |&(_, u)| u);
+            let blocks: Vec<(u64, u64)> = index
+                .iter()
+                .skip(1)
+                .filter(|&&(_, u)| u < total)
+                .copied()
+                .collect();
+            (entries, Some(blocks))
+        }
+    };
+    progress.finish();
+
+    let fai_path = args
+        .output
+        .clone()
+        .unwrap_or_else(|| with_suffix(&args.fasta, ".fai"));
+    let mut fai = create(&fai_path)?;
+    for entry in entries.iter() {
+        writeln!(
+            fai,
+            "{}\t{}\t{}\t{}\t{}",
+            entry.name, entry.length, entry.offset, entry.line_bases, entry.line_width
+        )?;
+    }
+    fai.flush()?;
+
+    if let Some(blocks) = gzi {
+        let gzi_path = args
+            .gzi
+            .clone()
+            .unwrap_or_else(|| with_suffix(&args.fasta, ".gzi"));
+        let mut gzi = create(&gzi_path)?;
+        gzi.write_all(&(blocks.len() as u64).to_le_bytes())?;
+        for (compressed, uncompressed) in blocks.iter() {
+            gzi.write_all(&compressed.to_le_bytes())?;
+            gzi.write_all(&uncompressed.to_le_bytes())?;
+        }
+        gzi.flush()?;
+    }
+    info!("Indexed {} sequences", entries.len());
+    Ok(())
+}
+
+enum Compression {
+    None,
+    Gzip,
+    Bgzf,
+}
+
+// BGZF files are gzip files whose first header has the FEXTRA flag and a BC subfield
+fn compression(start: &[u8]) -> Compression {
+    if !start.starts_with(&[0x1f, 0x8b]) {
+        Compression::None
+    } else if start.len() >= 16 && start[3] & 4 != 0 && start[12..14] == *b"BC" {
+        Compression::Bgzf
+    } else {
+        Compression::Gzip
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut s: OsString = path.as_os_str().to_owned();
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>> {
+    Ok(BufWriter::new(File::create(path).with_context(|| {
+        format!("Could not create file \"{}\"", path.to_string_lossy())
+    })?))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct FaiEntry {
+    name: String,
+    length: u64,
+    // Offset of the first base in the uncompressed file
+    offset: u64,
+    line_bases: u64,
+    // Including the line terminator
+    line_width: u64,
+}
+
+// Index the uncompressed FASTA data. Like samtools, all lines of a sequence except the
+// last must have the same length, and the last may not be longer than the others.
+fn index_fasta<R: BufRead>(reader: &mut R) -> Result<Vec<FaiEntry>> {
+    let mut entries: Vec<FaiEntry> = Vec::new();
+    let mut names: HashSet<String> = HashSet::new();
+    let mut line: Vec<u8> = Vec::new();
+    let mut position: u64 = 0;
+    let mut lineno: usize = 0;
+    // Set when a line shorter than the first line of the sequence has been seen,
+    // after which only empty lines may follow until the next header
+    let mut seen_short_line = false;
+    loop {
+        line.clear();
+        let n = reader
+            .read_until(b'\n', &mut line)
+            .context("Failed to read from FASTA file")? as u64;
+        if n == 0 {
+            break;
+        }
+        lineno += 1;
+        position += n;
+        let width = n;
+        let content = line
+            .strip_suffix(b"\n")
+            .map(|l| l.strip_suffix(b"\r").unwrap_or(l))
+            .unwrap_or(&line);
+
+        if let Some(header) = content.strip_prefix(b">") {
+            let header = std::str::from_utf8(header)
+                .with_context(|| format!("Header on line {} is not valid UTF-8", lineno))?;
+            let name = header.split_ascii_whitespace().next().unwrap_or_default();
+            if name.is_empty() {
+                bail!("Header on line {} has an empty name", lineno);
+            }
+            if !names.insert(name.to_owned()) {
+                bail!("Sequence name \"{}\" is present multiple times", name);
+            }
+            entries.push(FaiEntry {
+                name: name.to_owned(),
+                length: 0,
+                offset: position,
+                line_bases: 0,
+                line_width: 0,
+            });
+            seen_short_line = false;
+            continue;
+        }
+
+        let Some(entry) = entries.last_mut() else {
+            if content.is_empty() {
+                continue;
+            }
+            bail!("FASTA file does not begin with a header");
+        };
+        let bases = content.len() as u64;
+        if bases == 0 {
+            seen_short_line = true;
+            continue;
+        }
+        if seen_short_line {
+            bail!(
+                "Sequence \"{}\" has lines of different lengths, or a blank line, on line {}",
+                entry.name,
+                lineno
+            );
+        }
+        if entry.line_bases == 0 {
+            entry.line_bases = bases;
+            entry.line_width = width;
+        } else if bases > entry.line_bases
+            // The last line of the file may have no newline
+            || (line.ends_with(b"\n") && width - bases != entry.line_width - entry.line_bases)
+        {
+            bail!(
+                "Sequence \"{}\" has lines of different lengths on line {}",
+                entry.name,
+                lineno
+            );
+        } else if bases < entry.line_bases {
+            seen_short_line = true;
+        }
+        entry.length += bases;
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test_index {
+    use crate::{index_fasta, FaiEntry};
+
+    fn entry(name: &str, length: u64, offset: u64, bases: u64, width: u64) -> FaiEntry {
+        FaiEntry {
+            name: name.to_owned(),
+            length,
+            offset,
+            line_bases: bases,
+            line_width: width,
+        }
+    }
+
+    #[test]
+    fn test_index() {
+        let data = b">a desc\nACGT\nACGT\nAC\n\n>b\r\nAAA\r\nA\r\n>c\n";
+        let entries = index_fasta(&mut &data[..]).unwrap();
+        assert_eq!(
+            entries,
+            [
+                entry("a", 10, 8, 4, 5),
+                entry("b", 4, 26, 3, 5),
+                entry("c", 0, 37, 0, 0)
+            ]
+        );
+
+        // Last line may not be longer, and no lines may follow a short one
+        assert!(index_fasta(&mut &b">a\nACG\nACGT\n"[..]).is_err());
+        assert!(index_fasta(&mut &b">a\nACG\nAC\nACG\n"[..]).is_err());
+        assert!(index_fasta(&mut &b">a\nACG\n\nACG\n"[..]).is_err());
+        // The last line may have no newline
+        let entries = index_fasta(&mut &b">a\nACGT\nAC"[..]).unwrap();
+        assert_eq!(entries, [entry("a", 6, 3, 4, 5)]);
+        let entries = index_fasta(&mut &b">a\r\nACGT\r\nACGT"[..]).unwrap();
+        assert_eq!(entries, [entry("a", 8, 4, 4, 6)]);
+        // But the other lines must end the same way
+        assert!(index_fasta(&mut &b">a\nACGT\r\nACGT\nAC"[..]).is_err());
+        // Duplicate names
+        assert!(index_fasta(&mut &b">a\nA\n>a\nA\n"[..]).is_err());
+    }
+}
+
+const LONG_ABOUT: &str = "Write a samtools-compatible .fai index of a FASTA file.
+The FASTA file may be uncompressed or compressed with bgzip. For BGZF-compressed files,
+a .gzi index of the compressed blocks is also written, as done by `samtools faidx`.
+Plain gzip files cannot be indexed, since they do not support random access.
+Usage: faindex contigs.fna.gz";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// FASTA file, uncompressed or BGZF-compressed
+    fasta: PathBuf,
+
+    /// Output .fai file [<fasta>.fai]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Output .gzi file, if the FASTA is BGZF-compressed [<fasta>.gzi]
+    #[arg(long)]
+    gzi: Option<PathBuf>,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
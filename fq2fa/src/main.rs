@@ -0,0 +1,231 @@
+use anyhow::{self, bail, Context, Result};
+use bio::io::fastq::{Reader, Record};
+use clap::Parser;
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use progress::{info, LogArgs, Progress};
+use std::{
+    fs::File,
+    io::{stdin, stdout, BufRead, BufReader, BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    thread,
+};
+
+// Number of reads read into memory before they are converted in parallel.
+const BATCH_SIZE: usize = 16_384;
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    if args.max_length.is_some_and(|m| m < args.min_length) {
+        bail!("Maximum length cannot be smaller than minimum length");
+    }
+    let filter = Filter {
+        min_quality: args.min_quality,
+        offset: args.phred_offset,
+        min_length: args.min_length,
+        max_length: args.max_length.unwrap_or(usize::MAX),
+    };
+    let compression = args
+        .o
+        .as_ref()
+        .is_some_and(|p| p.extension().is_some_and(|e| e == "gz"))
+        .then(|| Compression::new(args.compression_level));
+    let mut records = Reader::from_bufread(open_input(args.i.as_deref())?).records();
+    let mut out: Box<dyn Write> = match &args.o {
+        None => Box::new(BufWriter::new(stdout().lock())),
+        Some(p) => Box::new(BufWriter::new(File::create(p).with_context(|| {
+            format!("Could not create output file \"{}\"", p.to_string_lossy())
+        })?)),
+    };
+
+    let mut n_out = 0usize;
+    let progress = Progress::new("Converting", "reads", None);
+    let mut batch: Vec<Record> = Vec::with_capacity(BATCH_SIZE);
+    loop {
+        batch.clear();
+        for record in records.by_ref().take(BATCH_SIZE) {
+            batch.push(record.context("Failed to parse FASTQ record")?);
+        }
+        if batch.is_empty() {
+            break;
+        }
+        progress.inc(batch.len() as u64);
+        let chunk_size = batch.len().div_ceil(args.threads.get());
+        // Each thread formats its chunk of records. If the output is gzipped, each chunk
+        // is compressed as a separate gzip member, so compression is also parallel.
+        let results: Vec<Result<(Vec<u8>, usize)>> = thread::scope(|s| {
+            let handles: Vec<_> = batch
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let filter = &filter;
+                    s.spawn(move || {
+                        let mut buffer: Vec<u8> = Vec::new();
+                        let mut n = 0;
+                        for record in chunk.iter().filter(|r| filter.keep(r)) {
+                            write_fasta(&mut buffer, record, args.line_width);
+                            n += 1;
+                        }
+                        match compression {
+                            None => Ok((buffer, n)),
+                            Some(level) => {
+                                let mut encoder = GzEncoder::new(Vec::new(), level);
+                                encoder.write_all(&buffer)?;
+                                Ok((encoder.finish()?, n))
+                            }
+                        }
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for result in results {
+            let (buffer, n) = result?;
+            out.write_all(&buffer)
+                .context("Failed to write to output file")?;
+            n_out += n;
+        }
+    }
+    out.flush()?;
+    progress.finish();
+    info!("Kept {} of {} reads", n_out, progress.position());
+    Ok(())
+}
+
+struct Filter {
+    // In Phred scale, i.e. with the offset subtracted
+    min_quality: f64,
+    offset: u8,
+    min_length: usize,
+    max_length: usize,
+}
+
+impl Filter {
+    fn keep(&self, record: &Record) -> bool {
+        let len = record.seq().len();
+        len >= self.min_length
+            && len <= self.max_length
+            && (self.min_quality <= 0.0
+                || mean_quality(record.qual(), self.offset) >= self.min_quality)
+    }
+}
+
+// The arithmetic mean of the Phred scores. Empty reads have mean quality 0.
+fn mean_quality(qual: &[u8], offset: u8) -> f64 {
+    if qual.is_empty() {
+        return 0.0;
+    }
+    let sum: usize = qual
+        .iter()
+        .map(|&q| q.saturating_sub(offset) as usize)
+        .sum();
+    sum as f64 / qual.len() as f64
+}
+
+// With a line width of 0, the sequence is written on a single line.
+fn write_fasta(buffer: &mut Vec<u8>, record: &Record, line_width: usize) {
+    buffer.push(b'>');
+    buffer.extend_from_slice(record.id().as_bytes());
+    if let Some(desc) = record.desc() {
+        buffer.push(b' ');
+        buffer.extend_from_slice(desc.as_bytes());
+    }
+    buffer.push(b'\n');
+    let width = if line_width == 0 {
+        usize::MAX
+    } else {
+        line_width
+    };
+    for line in record.seq().chunks(width) {
+        buffer.extend_from_slice(line);
+        buffer.push(b'\n');
+    }
+}
+
+#[cfg(test)]
+mod test_convert {
+    use crate::{mean_quality, write_fasta};
+    use bio::io::fastq::Record;
+
+    #[test]
+    fn test_mean_quality() {
+        assert_eq!(mean_quality(b"", 33), 0.0);
+        // '+' is Q10, 'I' is Q40
+        assert_eq!(mean_quality(b"+I", 33), 25.0);
+    }
+
+    #[test]
+    fn test_write_fasta() {
+        let record = Record::with_attrs("r1", Some("x y"), b"ACGTACG", b"IIIIIII");
+        let mut buffer = Vec::new();
+        write_fasta(&mut buffer, &record, 0);
+        assert_eq!(buffer, b">r1 x y\nACGTACG\n");
+        buffer.clear();
+        let record = Record::with_attrs("r2", None, b"ACGTACG", b"IIIIIII");
+        write_fasta(&mut buffer, &record, 3);
+        assert_eq!(buffer, b">r2\nACG\nTAC\nG\n");
+    }
+}
+
+// Read from stdin, from a file, or from a gzipped file if the name ends with .gz
+fn open_input(path: Option<&Path>) -> Result<Box<dyn BufRead>> {
+    let Some(p) = path else {
+        return Ok(Box::new(stdin().lock()));
+    };
+    let file = File::open(p)
+        .with_context(|| format!("Could not open input file \"{}\"", p.to_string_lossy()))?;
+    if p.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+const LONG_ABOUT: &str = "Convert FASTQ to FASTA, optionally filtering by mean quality and length.
+Reads are converted in parallel batches. Gzipped output is compressed in parallel as
+a multi-member gzip file, which can be read by all common tools.
+Input and output files are gzip (de)compressed if the file name ends with .gz.
+Usage: fq2fa -i reads.fq.gz -o reads.fa.gz -q 20 -l 50 -t 4";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Input FASTQ file (may be gzipped) [stdin]
+    #[arg(short)]
+    i: Option<PathBuf>,
+
+    /// Output FASTA file (gzipped if ending in .gz) [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    /// Minimum mean Phred quality of reads
+    #[arg(short = 'q', long, default_value_t = 0.0)]
+    min_quality: f64,
+
+    /// Offset of the quality encoding
+    #[arg(long, default_value_t = 33)]
+    phred_offset: u8,
+
+    /// Minimum length of reads
+    #[arg(short = 'l', long, default_value_t = 0)]
+    min_length: usize,
+
+    /// Maximum length of reads
+    #[arg(short = 'L', long)]
+    max_length: Option<usize>,
+
+    /// Line width of sequences. 0 writes each sequence on one line
+    #[arg(short = 'w', long, default_value_t = 0)]
+    line_width: usize,
+
+    /// Gzip compression level, if output is gzipped
+    #[arg(short = 'c', long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(0..=9))]
+    compression_level: u32,
+
+    /// Number of threads
+    #[arg(short, long, default_value = "1")]
+    threads: NonZeroUsize,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
@@ -0,0 +1,287 @@
+use anyhow::{self, bail, Context, Result};
+use bio::io::fastq::{Reader, Record, Writer};
+use clap::{Parser, ValueEnum};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use progress::{info, LogArgs, Progress};
+use std::{
+    fs::File,
+    io::{stdin, stdout, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let mut records = Reader::from_bufread(open_input(args.i.as_deref())?).records();
+    let mut range = QualityRange::default();
+
+    if args.detect {
+        // Only scan as much as needed, unless asked to scan the whole file
+        for record in records.by_ref() {
+            let record = record.context("Failed to parse FASTQ record")?;
+            range.add(record.qual())?;
+            if !args.all && range.n_records >= args.n_records {
+                break;
+            }
+        }
+        let encoding = range.detect();
+        println!("{}", encoding.map_or("ambiguous", |e| e.name()));
+        info!(
+            "Scanned {} records, quality characters range from {} to {}",
+            range.n_records, range.min as char, range.max as char
+        );
+        return Ok(());
+    }
+
+    // Buffer the first records to detect the encoding, then stream the rest
+    let mut buffer: Vec<Record> = Vec::new();
+    if args.from == Encoding::Auto {
+        for record in records.by_ref() {
+            let record = record.context("Failed to parse FASTQ record")?;
+            range.add(record.qual())?;
+            buffer.push(record);
+            if range.n_records >= args.n_records {
+                break;
+            }
+        }
+    }
+    let from = match args.from {
+        // An empty file needs no conversion
+        Encoding::Auto if range.n_records == 0 => Encoding::Phred33,
+        Encoding::Auto => range.detect().with_context(|| {
+            format!(
+                "Could not detect quality encoding from the first {} records, \
+                since the quality characters fit both high-quality Phred+33 data and \
+                Phred+64 or Solexa data. Use --from to set the encoding",
+                range.n_records
+            )
+        })?,
+        e => e,
+    };
+    let table = conversion_table(from);
+    info!("Converting from {} to phred33", from.name());
+
+    let mut writer = Writer::new(open_output(args.o.as_deref())?);
+    let mut qual: Vec<u8> = Vec::new();
+    let progress = Progress::new("Converting", "records", None);
+    let rest = records.map(|r| r.context("Failed to parse FASTQ record"));
+    for record in buffer.into_iter().map(Ok).chain(rest) {
+        let record = record?;
+        qual.clear();
+        for &q in record.qual() {
+            match table[q as usize] {
+                0 => bail!(
+                    "Quality character '{}' of record \"{}\" is invalid for {}",
+                    q as char,
+                    record.id(),
+                    from.name()
+                ),
+                c => qual.push(c),
+            }
+        }
+        writer
+            .write(record.id(), record.desc(), record.seq(), &qual)
+            .context("Failed to write record to output")?;
+        progress.inc(1);
+    }
+    writer.flush()?;
+    progress.finish();
+    Ok(())
+}
+
+#[derive(Clone, Copy, ValueEnum, PartialEq, Eq, Debug)]
+enum Encoding {
+    /// Detect from the first records
+    Auto,
+    /// Sanger and Illumina 1.8+
+    Phred33,
+    /// Illumina 1.3 to 1.7
+    Phred64,
+    /// Solexa and Illumina 1.0 to 1.2, with Solexa scores offset by 64
+    Solexa,
+}
+
+impl Encoding {
+    fn name(&self) -> &'static str {
+        match self {
+            Encoding::Auto => "auto",
+            Encoding::Phred33 => "phred33",
+            Encoding::Phred64 => "phred64",
+            Encoding::Solexa => "solexa",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct QualityRange {
+    min: u8,
+    max: u8,
+    n_records: usize,
+}
+
+impl Default for QualityRange {
+    fn default() -> Self {
+        Self {
+            min: u8::MAX,
+            max: 0,
+            n_records: 0,
+        }
+    }
+}
+
+impl QualityRange {
+    fn add(&mut self, qual: &[u8]) -> Result<()> {
+        for &q in qual {
+            if !(b'!'..=b'~').contains(&q) {
+                bail!("Invalid quality character with byte value {}", q);
+            }
+            self.min = self.min.min(q);
+            self.max = self.max.max(q);
+        }
+        self.n_records += 1;
+        Ok(())
+    }
+
+    // Characters below ';' (59) are only valid in Phred+33, and characters from ';' to
+    // '?' only in Phred+33 and Solexa. Phred+64 and Solexa data from old Illumina machines
+    // have scores up to 40 or 41, so characters above 'j' (Q42) mean Phred+33, e.g. from
+    // long reads. High-quality Phred+33 data may have no characters below '@', so Phred+64
+    // or Solexa is only reported if the scores reach at least 36 ('d') in those encodings,
+    // which would be 67 in Phred+33. Otherwise, we cannot tell.
+    fn detect(&self) -> Option<Encoding> {
+        if self.min < b';' || self.max > b'j' {
+            Some(Encoding::Phred33)
+        } else if self.max < b'd' {
+            None
+        } else if self.min < b'@' {
+            Some(Encoding::Solexa)
+        } else {
+            Some(Encoding::Phred64)
+        }
+    }
+}
+
+// Maps each input character to the Phred+33 character, or to 0 if it's invalid.
+fn conversion_table(from: Encoding) -> [u8; 256] {
+    let mut table = [0u8; 256];
+    for c in b'!'..=b'~' {
+        let score: i32 = match from {
+            Encoding::Auto | Encoding::Phred33 => c as i32 - 33,
+            Encoding::Phred64 => c as i32 - 64,
+            // Solexa scores are 10 log10(p / (1 - p)), phred scores are -10 log10(p)
+            Encoding::Solexa => {
+                let s = c as f64 - 64.0;
+                (10.0 * (10f64.powf(s / 10.0) + 1.0).log10()).round() as i32
+            }
+        };
+        if (0..=93).contains(&score) {
+            table[c as usize] = score as u8 + 33;
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod test_encoding {
+    use crate::{conversion_table, Encoding, QualityRange};
+
+    #[test]
+    fn test_detect() {
+        let detect = |qual: &[u8]| {
+            let mut range = QualityRange::default();
+            range.add(qual).unwrap();
+            range.detect()
+        };
+        assert_eq!(detect(b"#IIJJ"), Some(Encoding::Phred33));
+        assert_eq!(detect(b";@hhh"), Some(Encoding::Solexa));
+        assert_eq!(detect(b"BBhhh"), Some(Encoding::Phred64));
+        assert_eq!(detect(b"@IIJ"), None);
+        // High-quality Phred+33 reads, like from modern Illumina or long-read machines
+        assert_eq!(detect(b"@@FJKZ"), None);
+        assert_eq!(detect(b"<<FJ"), None);
+        assert_eq!(detect(b"@IJ~~"), Some(Encoding::Phred33));
+        assert_eq!(detect(b"BBhhj"), Some(Encoding::Phred64));
+        assert!(QualityRange::default().add(b"II\x7f").is_err());
+    }
+
+    #[test]
+    fn test_conversion() {
+        let phred64 = conversion_table(Encoding::Phred64);
+        assert_eq!(phred64[b'@' as usize], b'!');
+        assert_eq!(phred64[b'h' as usize], b'I');
+        assert_eq!(phred64[b'#' as usize], 0);
+        let solexa = conversion_table(Encoding::Solexa);
+        // Solexa -5 is phred 1, and high scores are nearly the same
+        assert_eq!(solexa[b';' as usize], b'"');
+        assert_eq!(solexa[b'h' as usize], b'I');
+    }
+}
+
+// Read from stdin, from a file, or from a gzipped file if the name ends with .gz
+fn open_input(path: Option<&Path>) -> Result<Box<dyn BufRead>> {
+    let Some(p) = path else {
+        return Ok(Box::new(stdin().lock()));
+    };
+    let file = File::open(p)
+        .with_context(|| format!("Could not open input file \"{}\"", p.to_string_lossy()))?;
+    if p.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+// Write to stdout, to a file, or to a gzipped file if the name ends with .gz
+fn open_output(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    let Some(p) = path else {
+        return Ok(Box::new(stdout().lock()));
+    };
+    let file = File::create(p)
+        .with_context(|| format!("Could not create output file \"{}\"", p.to_string_lossy()))?;
+    if p.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(GzEncoder::new(
+            BufWriter::new(file),
+            Compression::default(),
+        )))
+    } else {
+        Ok(Box::new(BufWriter::new(file)))
+    }
+}
+
+const LONG_ABOUT: &str = "Detect the quality encoding of a FASTQ file, and convert it to Phred+33.
+Old Illumina data may use Phred+64 or Solexa encoding, which modern tools misinterpret.
+The encoding is detected from the range of quality characters in the first records.
+Files already in Phred+33 are copied unchanged.
+Usage: fqphred -i old_reads.fq.gz -o reads.fq.gz
+       fqphred --detect -i reads.fq.gz";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Input FASTQ file (may be gzipped) [stdin]
+    #[arg(short)]
+    i: Option<PathBuf>,
+
+    /// Output FASTQ file (gzipped if ending in .gz) [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    /// Only print the detected encoding
+    #[arg(short, long)]
+    detect: bool,
+
+    /// With --detect, scan the whole file
+    #[arg(short, long, requires = "detect")]
+    all: bool,
+
+    /// Number of records used to detect the encoding
+    #[arg(short, long, default_value_t = 10_000)]
+    n_records: usize,
+
+    /// Encoding of the input
+    #[arg(short, long, value_enum, default_value_t = Encoding::Auto)]
+    from: Encoding,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
@@ -0,0 +1,453 @@
+use anyhow::{self, bail, Context, Result};
+use bio::io::fastq::{Reader, Record, Records, Writer};
+use clap::{Parser, ValueEnum};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use progress::{info, LogArgs, Progress};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{stdin, stdout, BufRead, BufReader, BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    thread,
+};
+
+// Number of reads (or pairs) in each batch trimmed by a worker thread.
+const BATCH_SIZE: usize = 16_384;
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let opts = TrimOptions::from_cli(&args)?;
+
+    if args.in2.is_some() != args.out2.is_some() {
+        bail!("--in2 and --out2 must be given together");
+    }
+    if args.in2.is_some() && args.i.is_none() {
+        bail!("Paired mode requires -i to be a file, not stdin");
+    }
+    let mut batches = Batches {
+        first: Reader::from_bufread(open_input(args.i.as_deref())?).records(),
+        second: match &args.in2 {
+            Some(p) => Some(Reader::from_bufread(open_input(Some(p))?).records()),
+            None => None,
+        },
+    };
+    let mut writer1 = Writer::new(open_output(args.o.as_deref())?);
+    let mut writer2 = match &args.out2 {
+        Some(p) => Some(Writer::new(open_output(Some(p))?)),
+        None => None,
+    };
+
+    let mut stats = Stats::default();
+    let unit = if args.in2.is_some() { "pairs" } else { "reads" };
+    let progress = Progress::new("Trimming", unit, None);
+    // A reader thread parses the input into numbered batches, which are trimmed by the
+    // workers. The main thread writes the trimmed batches in their original order.
+    thread::scope(|s| -> Result<()> {
+        // The channels are created inside the scope, so they are dropped if we return early
+        // with an error, which makes the other threads exit.
+        let (batch_sender, batch_receiver) =
+            crossbeam_channel::bounded::<(usize, Vec<Pair>)>(args.threads.get());
+        let (result_sender, result_receiver) =
+            crossbeam_channel::bounded::<(usize, Vec<Trimmed>)>(args.threads.get());
+        let reader = s.spawn(move || -> Result<()> {
+            let mut index = 0;
+            while let Some(batch) = batches.next_batch()? {
+                // Only fails if the main thread returned with an error
+                if batch_sender.send((index, batch)).is_err() {
+                    break;
+                }
+                index += 1;
+            }
+            Ok(())
+        });
+        for _ in 0..args.threads.get() {
+            let (receiver, sender) = (batch_receiver.clone(), result_sender.clone());
+            let opts = &opts;
+            s.spawn(move || {
+                for (index, batch) in receiver.iter() {
+                    let trimmed = batch
+                        .into_iter()
+                        .map(|pair| {
+                            let kept = opts.trim_pair(&pair);
+                            (pair, kept)
+                        })
+                        .collect();
+                    if sender.send((index, trimmed)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop((batch_receiver, result_sender));
+
+        // Batches trimmed before an earlier batch
+        let mut waiting: HashMap<usize, Vec<Trimmed>> = HashMap::new();
+        let mut next = 0;
+        for (index, trimmed) in result_receiver.iter() {
+            waiting.insert(index, trimmed);
+            while let Some(trimmed) = waiting.remove(&next) {
+                next += 1;
+                progress.inc(trimmed.len() as u64);
+                for (pair, kept) in trimmed {
+                    stats.add(&pair, kept.as_ref());
+                    let Some((fst, snd)) = kept else { continue };
+                    writer1
+                        .write_record(&fst)
+                        .context("Failed to write record to output")?;
+                    if let (Some(snd), Some(w)) = (snd, writer2.as_mut()) {
+                        w.write_record(&snd)
+                            .context("Failed to write record to mate output")?;
+                    }
+                }
+            }
+        }
+        // The workers are done once the reader is, so this only fails on a parse error
+        reader.join().unwrap()
+    })?;
+    writer1.flush()?;
+    if let Some(mut w) = writer2 {
+        w.flush()?;
+    }
+    progress.finish();
+    stats.print(unit);
+    Ok(())
+}
+
+// A record and its mate, if we run in paired mode
+type Pair = (Record, Option<Record>);
+
+// A pair, and the trimmed pair unless it was removed
+type Trimmed = (Pair, Option<Pair>);
+
+struct Batches {
+    first: Records<Box<dyn BufRead + Send>>,
+    second: Option<Records<Box<dyn BufRead + Send>>>,
+}
+
+impl Batches {
+    // None when both inputs are exhausted.
+    fn next_batch(&mut self) -> Result<Option<Vec<Pair>>> {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while batch.len() < BATCH_SIZE {
+            let fst = match self.first.next() {
+                None => break,
+                Some(r) => r.context("Failed to parse FASTQ record")?,
+            };
+            let snd = match self.second.as_mut() {
+                None => None,
+                Some(records) => {
+                    let snd = records
+                        .next()
+                        .context("Mate file has fewer records than first file")?
+                        .context("Failed to parse FASTQ record in mate file")?;
+                    if mate_name(fst.id()) != mate_name(snd.id()) {
+                        bail!(
+                            "Mates out of sync: \"{}\" in first file, \"{}\" in mate file",
+                            fst.id(),
+                            snd.id()
+                        );
+                    }
+                    Some(snd)
+                }
+            };
+            batch.push((fst, snd));
+        }
+        if batch.is_empty() {
+            if self.second.as_mut().is_some_and(|r| r.next().is_some()) {
+                bail!("Mate file has more records than first file")
+            }
+            Ok(None)
+        } else {
+            Ok(Some(batch))
+        }
+    }
+}
+
+// Illumina-style names may end with /1 and /2 to distinguish the mates
+fn mate_name(id: &str) -> &str {
+    id.strip_suffix("/1")
+        .or_else(|| id.strip_suffix("/2"))
+        .unwrap_or(id)
+}
+
+struct TrimOptions {
+    mode: Option<TrimMode>,
+    // Quality threshold in Phred scale (i.e. with offset subtracted)
+    quality: u8,
+    offset: u8,
+    window: usize,
+    crop_head: usize,
+    crop_tail: usize,
+    min_length: usize,
+}
+
+impl TrimOptions {
+    fn from_cli(args: &Cli) -> Result<Self> {
+        if args.window == 0 {
+            bail!("Window size must be at least 1");
+        }
+        // The threshold is compared to the encoded qualities, which are bytes
+        if args.quality as u16 + args.phred_offset as u16 > u8::MAX as u16 {
+            bail!(
+                "Quality threshold {} plus Phred offset {} must be at most 255",
+                args.quality,
+                args.phred_offset
+            );
+        }
+        Ok(Self {
+            mode: (args.quality > 0).then_some(args.mode),
+            quality: args.quality,
+            offset: args.phred_offset,
+            window: args.window,
+            crop_head: args.crop_head,
+            crop_tail: args.crop_tail,
+            min_length: args.min_length,
+        })
+    }
+
+    // Returns None if the read (or any mate) became shorter than the minimum length.
+    fn trim_pair(&self, (fst, snd): &Pair) -> Option<Pair> {
+        let fst = self.trim(fst)?;
+        let snd = match snd {
+            Some(r) => Some(self.trim(r)?),
+            None => None,
+        };
+        Some((fst, snd))
+    }
+
+    fn trim(&self, record: &Record) -> Option<Record> {
+        let qual = record.qual();
+        let start = self.crop_head.min(qual.len());
+        let stop = qual.len().saturating_sub(self.crop_tail).max(start);
+        let cropped = &qual[start..stop];
+        let keep = match self.mode {
+            None => cropped.len(),
+            Some(TrimMode::Window) => window_trim(cropped, self.quality + self.offset, self.window),
+            Some(TrimMode::Bwa) => bwa_trim(cropped, self.quality, self.offset),
+        };
+        if keep < self.min_length {
+            return None;
+        }
+        let range = start..start + keep;
+        Some(Record::with_attrs(
+            record.id(),
+            record.desc(),
+            &record.seq()[range.clone()],
+            &qual[range],
+        ))
+    }
+}
+
+// Scan from the 5' end and cut at the start of the first window whose mean quality
+// is below the threshold. Returns the number of bases to keep.
+// Threshold includes the Phred offset.
+fn window_trim(qual: &[u8], threshold: u8, window: usize) -> usize {
+    if qual.len() < window {
+        let sum: usize = qual.iter().map(|&q| q as usize).sum();
+        return if sum < threshold as usize * qual.len() {
+            0
+        } else {
+            qual.len()
+        };
+    }
+    let min_sum = threshold as usize * window;
+    let mut sum: usize = qual[..window].iter().map(|&q| q as usize).sum();
+    for start in 0..=qual.len() - window {
+        if start > 0 {
+            sum = sum + qual[start + window - 1] as usize - qual[start - 1] as usize;
+        }
+        if sum < min_sum {
+            return start;
+        }
+    }
+    qual.len()
+}
+
+// The algorithm used by BWA's -q option: Find the 3' cut position that maximizes
+// the sum of (threshold - quality) over the removed bases.
+// Returns the number of bases to keep.
+fn bwa_trim(qual: &[u8], threshold: u8, offset: u8) -> usize {
+    let mut sum: isize = 0;
+    let mut max: isize = 0;
+    let mut keep = qual.len();
+    for (i, &q) in qual.iter().enumerate().rev() {
+        sum += threshold as isize - (q as isize - offset as isize);
+        if sum < 0 {
+            break;
+        }
+        if sum > max {
+            max = sum;
+            keep = i;
+        }
+    }
+    keep
+}
+
+#[derive(Default)]
+struct Stats {
+    reads_in: usize,
+    reads_out: usize,
+    bases_in: usize,
+    bases_out: usize,
+}
+
+impl Stats {
+    fn add(&mut self, input: &Pair, output: Option<&Pair>) {
+        let len = |(a, b): &Pair| a.seq().len() + b.as_ref().map_or(0, |r| r.seq().len());
+        self.reads_in += 1;
+        self.bases_in += len(input);
+        if let Some(p) = output {
+            self.reads_out += 1;
+            self.bases_out += len(p);
+        }
+    }
+
+    fn print(&self, unit: &str) {
+        info!(
+            "Kept {} of {} {}, {} of {} bases",
+            self.reads_out, self.reads_in, unit, self.bases_out, self.bases_in
+        );
+    }
+}
+
+// Read from stdin, from a file, or from a gzipped file if the name ends with .gz
+fn open_input(path: Option<&Path>) -> Result<Box<dyn BufRead + Send>> {
+    let Some(p) = path else {
+        // Not locked, since the input is read by another thread
+        return Ok(Box::new(BufReader::new(stdin())));
+    };
+    let file = File::open(p)
+        .with_context(|| format!("Could not open input file \"{}\"", p.to_string_lossy()))?;
+    if p.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+// Write to stdout, to a file, or to a gzipped file if the name ends with .gz
+fn open_output(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    let Some(p) = path else {
+        return Ok(Box::new(stdout().lock()));
+    };
+    let file = File::create(p)
+        .with_context(|| format!("Could not create output file \"{}\"", p.to_string_lossy()))?;
+    if p.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(GzEncoder::new(
+            BufWriter::new(file),
+            Compression::default(),
+        )))
+    } else {
+        Ok(Box::new(BufWriter::new(file)))
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TrimMode {
+    /// Cut at the first window with a mean quality below the threshold
+    Window,
+    /// BWA's -q algorithm: Trim the 3' end maximizing the summed quality deficit
+    Bwa,
+}
+
+const LONG_ABOUT: &str = "Crop, quality trim and length filter single or paired FASTQ files.
+Bases are first cropped from the head and tail, then the read is quality trimmed,
+then reads shorter than the minimum length are removed. In paired mode, both mates
+are removed if either is too short.
+Input and output files are gzip (de)compressed if the file name ends with .gz.
+Usage: fqtrim -i R1.fq.gz --in2 R2.fq.gz -o out_R1.fq.gz --out2 out_R2.fq.gz -q 20";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Input FASTQ file (may be gzipped) [stdin]
+    #[arg(short)]
+    i: Option<PathBuf>,
+
+    /// Output FASTQ file (gzipped if ending in .gz) [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    /// Input FASTQ file of second mates
+    #[arg(long)]
+    in2: Option<PathBuf>,
+
+    /// Output FASTQ file of second mates
+    #[arg(long)]
+    out2: Option<PathBuf>,
+
+    /// Quality trimming algorithm
+    #[arg(short, long, value_enum, default_value_t = TrimMode::Window)]
+    mode: TrimMode,
+
+    /// Quality threshold for trimming. 0 disables quality trimming
+    #[arg(short, long, default_value_t = 20)]
+    quality: u8,
+
+    /// Window size for window trimming
+    #[arg(short, long, default_value_t = 4)]
+    window: usize,
+
+    /// Offset of the quality encoding
+    #[arg(long, default_value_t = 33)]
+    phred_offset: u8,
+
+    /// Number of bases to remove from the start of reads
+    #[arg(long, default_value_t = 0)]
+    crop_head: usize,
+
+    /// Number of bases to remove from the end of reads
+    #[arg(long, default_value_t = 0)]
+    crop_tail: usize,
+
+    /// Minimum length of reads after trimming
+    #[arg(short = 'l', long, default_value_t = 15)]
+    min_length: usize,
+
+    /// Number of threads used for trimming
+    #[arg(short, long, default_value = "1")]
+    threads: NonZeroUsize,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
+
+#[cfg(test)]
+mod test_trim {
+    use crate::{bwa_trim, window_trim, Cli, TrimOptions};
+    use clap::Parser;
+
+    #[test]
+    fn test_window_trim() {
+        // Phred+33: '5' is Q20, '+' is Q10, 'I' is Q40
+        assert_eq!(window_trim(b"IIIIIIII", b'5', 4), 8);
+        assert_eq!(window_trim(b"IIIIII++++", b'5', 4), 5);
+        assert_eq!(window_trim(b"++++IIII", b'5', 4), 0);
+        assert_eq!(window_trim(b"II", b'5', 4), 2);
+        assert_eq!(window_trim(b"++", b'5', 4), 0);
+        assert_eq!(window_trim(b"", b'5', 4), 0);
+    }
+
+    #[test]
+    fn test_bwa_trim() {
+        assert_eq!(bwa_trim(b"IIIIIIII", 20, 33), 8);
+        assert_eq!(bwa_trim(b"IIIIII++", 20, 33), 6);
+        // A single good base among bad ones at the end does not stop trimming
+        assert_eq!(bwa_trim(b"IIIIII+++I+++", 20, 33), 6);
+        assert_eq!(bwa_trim(b"++++", 20, 33), 0);
+    }
+
+    #[test]
+    fn test_options() {
+        let options = |args: &[&str]| {
+            let cli = Cli::parse_from(["fqtrim"].iter().chain(args));
+            TrimOptions::from_cli(&cli)
+        };
+        assert!(options(&["-q", "222"]).is_ok());
+        assert!(options(&["-q", "223"]).is_err());
+        assert!(options(&["-q", "230", "--phred-offset", "0"]).is_ok());
+        assert!(options(&["-w", "0"]).is_err());
+    }
+}
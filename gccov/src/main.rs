@@ -0,0 +1,304 @@
+use anyhow::{self, bail, Context, Result};
+use bio::io::fasta::Reader;
+use clap::{ArgGroup, Parser};
+use flate2::read::MultiGzDecoder;
+use htslite::{AlignmentReader, Record, FLAG_DUPLICATE, FLAG_QCFAIL};
+use progress::{info, LogArgs, Progress};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{stdout, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let depths = match (&args.depth, &args.bam) {
+        (Some(p), None) => read_depth_table(p, &args.depth_column)?,
+        (None, Some(p)) => depths_from_alignments(p, args.min_mapq)?,
+        // Clap makes sure exactly one is passed
+        _ => unreachable!(),
+    };
+    let clusters = match &args.clusters {
+        Some(p) => Some(read_clusters(p)?),
+        None => None,
+    };
+
+    let mut out: Box<dyn Write> = match &args.o {
+        None => Box::new(BufWriter::new(stdout().lock())),
+        Some(p) => Box::new(BufWriter::new(File::create(p).with_context(|| {
+            format!("Could not create output file \"{}\"", p.display())
+        })?)),
+    };
+    out.write_all(b"contig\tlength\tgc\tdepth")?;
+    if clusters.is_some() {
+        out.write_all(b"\tcluster")?;
+    }
+    out.write_all(b"\n")?;
+
+    let (mut n_written, mut n_binned) = (0usize, 0usize);
+    let progress = Progress::new("Reading contigs", "contigs", None);
+    for record in Reader::from_bufread(open_input(&args.fasta)?).records() {
+        let record = record.context("Failed to parse record from FASTA file")?;
+        progress.inc(1);
+        if record.seq().len() < args.min_length {
+            continue;
+        }
+        let depth = depths
+            .get(record.id())
+            .with_context(|| format!("Contig \"{}\" not found in depth input", record.id()))?;
+        match gc_content(record.seq()) {
+            Some(gc) => write!(
+                out,
+                "{}\t{}\t{:.4}\t{:.4}",
+                record.id(),
+                record.seq().len(),
+                gc,
+                depth
+            )?,
+            None => write!(
+                out,
+                "{}\t{}\tNA\t{:.4}",
+                record.id(),
+                record.seq().len(),
+                depth
+            )?,
+        }
+        if let Some(clusters) = &clusters {
+            match clusters.get(record.id()) {
+                Some(cluster) => {
+                    n_binned += 1;
+                    write!(out, "\t{}", cluster)?;
+                }
+                None => out.write_all(b"\tNA")?,
+            }
+        }
+        out.write_all(b"\n")?;
+        n_written += 1;
+    }
+    out.flush()?;
+    progress.finish();
+    if clusters.is_some() {
+        info!("Wrote {} contigs, {} in clusters", n_written, n_binned);
+    } else {
+        info!("Wrote {} contigs", n_written);
+    }
+    Ok(())
+}
+
+// Fraction of G and C among the unambiguous bases, or None if there are none
+fn gc_content(seq: &[u8]) -> Option<f64> {
+    let (mut gc, mut at) = (0usize, 0usize);
+    for &b in seq {
+        match b {
+            b'G' | b'C' | b'g' | b'c' => gc += 1,
+            b'A' | b'T' | b'a' | b't' => at += 1,
+            _ => (),
+        }
+    }
+    (gc + at > 0).then(|| gc as f64 / (gc + at) as f64)
+}
+
+// Resolve a column given by the user as a 1-based index or a header name
+// to a 0-based index.
+fn resolve_column(column: &str, header: &[&str]) -> Result<usize> {
+    if let Ok(n) = column.parse::<usize>() {
+        if n == 0 {
+            bail!("Column indices are 1-based, got 0");
+        }
+        return Ok(n - 1);
+    }
+    header
+        .iter()
+        .position(|&h| h == column)
+        .with_context(|| format!("Column \"{}\" not found in header of depth file", column))
+}
+
+// A TSV file with a header, with the contig names in the first column,
+// e.g. the output of jgi_summarize_bam_contig_depths.
+fn read_depth_table(path: &Path, column: &str) -> Result<HashMap<String, f64>> {
+    let mut lines = open_input(path)?.lines().enumerate();
+    let header = match lines.next() {
+        None => bail!("Depth file is empty"),
+        Some((_, line)) => line.context("Failed to read line from depth file")?,
+    };
+    let index = resolve_column(column, &header.split('\t').collect::<Vec<_>>())?;
+    let mut depths: HashMap<String, f64> = HashMap::new();
+    for (lineno, line) in lines {
+        let line = line.context("Failed to read line from depth file")?;
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let depth = fields
+            .get(index)
+            .and_then(|s| s.parse::<f64>().ok())
+            .with_context(|| format!("Invalid depth on line {} of depth file", lineno + 1))?;
+        if depths.insert(fields[0].to_owned(), depth).is_some() {
+            bail!(
+                "Contig \"{}\" is present multiple times in depth file",
+                fields[0]
+            );
+        }
+    }
+    Ok(depths)
+}
+
+// The mean depth of each reference, as the number of aligned bases divided by its length.
+// The alignments need not be sorted.
+fn depths_from_alignments(path: &Path, min_mapq: u8) -> Result<HashMap<String, f64>> {
+    let mut reader = AlignmentReader::open(Some(path))?;
+    let mut bases = vec![0u64; reader.header().references.len()];
+    let mut record = Record::default();
+    let progress = Progress::new("Reading alignments", "records", None);
+    while reader.read_record(&mut record)? {
+        progress.inc(1);
+        if record.is_unmapped()
+            || record.is_non_primary()
+            || record.has_flag(FLAG_DUPLICATE | FLAG_QCFAIL)
+            || record.mapq < min_mapq
+        {
+            continue;
+        }
+        // Checked by is_unmapped
+        bases[record.ref_id.unwrap()] += record
+            .aligned_blocks()
+            .map(|(start, stop)| (stop - start) as u64)
+            .sum::<u64>();
+    }
+    progress.finish();
+    Ok(reader
+        .header()
+        .references
+        .iter()
+        .zip(bases)
+        .map(|(r, n)| (r.name.clone(), n as f64 / r.length.max(1) as f64))
+        .collect())
+}
+
+fn read_clusters(path: &Path) -> Result<HashMap<String, String>> {
+    let mut clusters: HashMap<String, String> = HashMap::new();
+    for (lineno, line) in open_input(path)?.lines().enumerate() {
+        let line = line.context("Failed to read line from cluster file")?;
+        if (lineno == 0 && line == "clustername\tcontigname") || line.is_empty() {
+            continue;
+        }
+        let (cluster, contig) = line
+            .split_once('\t')
+            .filter(|(_, contig)| !contig.contains('\t'))
+            .with_context(|| {
+                format!(
+                    "Expected two tab-separated fields on line {} of cluster file",
+                    lineno + 1
+                )
+            })?;
+        if clusters
+            .insert(contig.to_owned(), cluster.to_owned())
+            .is_some()
+        {
+            bail!(
+                "Contig \"{}\" is present multiple times in cluster file",
+                contig
+            );
+        }
+    }
+    Ok(clusters)
+}
+
+#[cfg(test)]
+mod test_gc {
+    use crate::{gc_content, resolve_column, Cli};
+    use clap::Parser;
+
+    #[test]
+    fn test_gc_content() {
+        assert_eq!(gc_content(b""), None);
+        assert_eq!(gc_content(b"NNNN"), None);
+        assert_eq!(gc_content(b"ACgtNN"), Some(0.5));
+        assert_eq!(gc_content(b"GGGA"), Some(0.75));
+    }
+
+    #[test]
+    fn test_resolve_column() {
+        let header = ["contigName", "contigLen", "totalAvgDepth"];
+        assert_eq!(resolve_column("2", &header).unwrap(), 1);
+        assert_eq!(resolve_column("totalAvgDepth", &header).unwrap(), 2);
+        assert!(resolve_column("0", &header).is_err());
+        assert!(resolve_column("depth", &header).is_err());
+    }
+
+    #[test]
+    fn test_default_depth_column() {
+        // Header of jgi_summarize_bam_contig_depths output
+        let header = [
+            "contigName",
+            "contigLen",
+            "totalAvgDepth",
+            "aln.bam",
+            "aln.bam-var",
+        ];
+        let args = Cli::try_parse_from(["gccov", "-f", "x.fna", "-d", "x.tsv", "-c", "c.tsv"]);
+        let column = args.unwrap().depth_column;
+        assert_eq!(resolve_column(&column, &header).unwrap(), 2);
+    }
+}
+
+// Read from a file, or from a gzipped file if the name ends with .gz
+fn open_input(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file =
+        File::open(path).with_context(|| format!("Could not open file \"{}\"", path.display()))?;
+    if path.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+const LONG_ABOUT: &str = "Combine the GC content and depth of each contig into a TSV table,
+e.g. to make GC-coverage plots for quality control of binning.
+The depth is read from a TSV file with a header and contig names in the first column, or
+computed from a SAM/BAM file as the number of aligned bases divided by the contig length.
+With a cluster file, the cluster of each contig is added as the last column.
+Usage: gccov -f contigs.fna.gz -d depths.tsv -c clusters.tsv";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+#[command(group(ArgGroup::new("depths").required(true).args(["depth", "bam"])))]
+struct Cli {
+    /// Contigs FASTA file (may be gzipped)
+    #[arg(short)]
+    fasta: PathBuf,
+
+    /// Depth TSV file (may be gzipped)
+    #[arg(short)]
+    depth: Option<PathBuf>,
+
+    /// Column of the depth file with the depth, as 1-based index or header name.
+    /// The default is the depth column of jgi_summarize_bam_contig_depths output
+    #[arg(long, default_value = "totalAvgDepth")]
+    depth_column: String,
+
+    /// SAM or BAM file to compute depths from
+    #[arg(short)]
+    bam: Option<PathBuf>,
+
+    /// Minimum mapping quality of alignments, when computing depths
+    #[arg(short = 'Q', long, default_value_t = 0)]
+    min_mapq: u8,
+
+    /// Vamb cluster file to annotate contigs with (may be gzipped)
+    #[arg(short)]
+    clusters: Option<PathBuf>,
+
+    /// Skip contigs shorter than this
+    #[arg(short = 'm', long, default_value_t = 0)]
+    min_length: usize,
+
+    /// Output file [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
@@ -0,0 +1,203 @@
+use anyhow::{self, bail, Context, Result};
+use clap::Parser;
+use flate2::read::MultiGzDecoder;
+use htslite::BgzfReader;
+use progress::{info, warn, LogArgs, Progress};
+use std::{
+    fs::File,
+    io::{self, stdout, BufRead, BufReader, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    thread,
+};
+use walkdir::WalkDir;
+
+const EXTENSIONS: [&str; 3] = ["gz", "bgz", "bam"];
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let mut out = stdout().lock();
+    let (mut n_files, mut n_bytes, mut n_corrupt) = (0usize, 0u64, 0usize);
+    let progress = Progress::new("Checking", "files", None);
+    thread::scope(|s| -> Result<()> {
+        // The channels are created inside the scope, so they are dropped if we return early
+        // with an error, which makes the other threads exit.
+        let (path_sender, path_receiver) = crossbeam_channel::bounded::<PathBuf>(1024);
+        let (result_sender, result_receiver) =
+            crossbeam_channel::unbounded::<(PathBuf, Result<Format>)>();
+        for _ in 0..args.threads.get() {
+            let (receiver, sender) = (path_receiver.clone(), result_sender.clone());
+            s.spawn(move || {
+                for path in receiver.iter() {
+                    let result = check_file(&path);
+                    if sender.send((path, result)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(result_sender);
+
+        // Walk the directories in a separate thread, so results are printed as they come
+        let paths = &args.paths;
+        let walker = s.spawn(move || -> (usize, u64) {
+            let (mut n_files, mut n_bytes) = (0usize, 0u64);
+            for start in paths.iter() {
+                for entry in WalkDir::new(start) {
+                    let entry = match entry {
+                        Ok(e) => e,
+                        Err(e) => {
+                            let path = e.path().unwrap_or(start).display().to_string();
+                            warn!("Could not read \"{}\": {}", path, e);
+                            continue;
+                        }
+                    };
+                    // Like dnazip, symlinks are not followed
+                    if !entry.file_type().is_file() || !has_extension(entry.path()) {
+                        continue;
+                    }
+                    n_files += 1;
+                    n_bytes += entry.metadata().map_or(0, |m| m.len());
+                    if path_sender.send(entry.into_path()).is_err() {
+                        break;
+                    }
+                }
+            }
+            (n_files, n_bytes)
+        });
+
+        for (path, result) in result_receiver.iter() {
+            progress.inc(1);
+            match result {
+                Ok(format) => {
+                    if args.verbose {
+                        writeln!(out, "{}\tok\t{}", path.display(), format.name())?;
+                    }
+                }
+                Err(e) => {
+                    n_corrupt += 1;
+                    writeln!(out, "{}\tcorrupt\t{:#}", path.display(), e)?;
+                }
+            }
+        }
+        (n_files, n_bytes) = walker.join().unwrap();
+        Ok(())
+    })?;
+    out.flush()?;
+    progress.finish();
+    info!(
+        "Checked {} files, {}. {} corrupt.",
+        n_files,
+        size::Size::from_bytes(n_bytes),
+        n_corrupt
+    );
+    if n_corrupt > 0 {
+        bail!("Found {} corrupt files", n_corrupt);
+    }
+    Ok(())
+}
+
+fn has_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.to_str().is_some_and(|s| EXTENSIONS.contains(&s)))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Format {
+    Gzip,
+    Bgzf,
+}
+
+impl Format {
+    fn name(&self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Bgzf => "bgzf",
+        }
+    }
+}
+
+fn check_file(path: &Path) -> Result<Format> {
+    let file = File::open(path).context("Could not open file")?;
+    check(BufReader::with_capacity(1 << 16, file))
+}
+
+// Decompress everything, which verifies the CRC32 and size of every gzip member.
+// BGZF files must also end with the EOF marker block, which is missing if the file
+// was truncated at a block boundary.
+fn check<R: BufRead>(mut reader: R) -> Result<Format> {
+    let start = reader.fill_buf()?;
+    if start.is_empty() {
+        bail!("File is empty");
+    }
+    if !start.starts_with(&[0x1f, 0x8b]) {
+        bail!("Not a gzip file");
+    }
+    let is_bgzf = start.len() >= 16 && start[3] & 4 != 0 && start[12..14] == *b"BC";
+    if is_bgzf {
+        let mut reader = BgzfReader::new(reader);
+        while reader.read_block()? {}
+        if !reader.ends_with_eof_block() {
+            bail!("BGZF file has no EOF block, and may be truncated");
+        }
+        Ok(Format::Bgzf)
+    } else {
+        io::copy(&mut MultiGzDecoder::new(reader), &mut io::sink())?;
+        Ok(Format::Gzip)
+    }
+}
+
+#[cfg(test)]
+mod test_check {
+    use crate::{check, Format};
+    use flate2::{write::GzEncoder, Compression};
+    use htslite::BgzfWriter;
+    use std::io::Write;
+
+    #[test]
+    fn test_check() {
+        let data = b"ACGT".repeat(10_000);
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&data).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let mut bgzf = BgzfWriter::new(Vec::new(), Compression::default());
+        bgzf.write_all(&data).unwrap();
+        let bgzf = bgzf.finish().unwrap();
+
+        assert_eq!(check(&gzip[..]).unwrap(), Format::Gzip);
+        assert_eq!(check(&bgzf[..]).unwrap(), Format::Bgzf);
+        assert!(check(&b""[..]).is_err());
+        assert!(check(&data[..]).is_err());
+        // Truncated
+        assert!(check(&gzip[..gzip.len() - 1]).is_err());
+        assert!(check(&bgzf[..bgzf.len() - 28]).is_err());
+        // Corrupted CRC
+        let mut corrupt = gzip.clone();
+        let n = corrupt.len();
+        corrupt[n - 6] ^= 1;
+        assert!(check(&corrupt[..]).is_err());
+    }
+}
+
+/// Check the integrity of all gzip and BGZF files found recursively in the given
+/// directories, by decompressing them. Files ending in .gz, .bgz and .bam are checked.
+/// Corrupt files are printed to stdout. Does not follow symlinks.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Directories or files to check
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Also print files that are not corrupt
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Number of threads used for checking
+    #[arg(short, long, default_value = "1")]
+    threads: NonZeroUsize,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
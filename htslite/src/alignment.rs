@@ -0,0 +1,599 @@
+use crate::bgzf::BgzfWriter;
+use anyhow::{bail, Context, Result};
+use flate2::{bufread::MultiGzDecoder, Compression};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, stdin, stdout, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Sam,
+    /// Gzipped SAM. It is written as BGZF, like `bgzip` would.
+    SamGz,
+    Bam,
+}
+
+#[derive(Clone, Debug)]
+pub struct Reference {
+    pub name: String,
+    pub length: u64,
+}
+
+/// The header of a SAM or BAM file: The raw header text, and the reference sequences.
+#[derive(Clone, Debug, Default)]
+pub struct Header {
+    pub text: String,
+    pub references: Vec<Reference>,
+    index: HashMap<String, usize>,
+}
+
+impl Header {
+    pub fn new(text: String, references: Vec<Reference>) -> Result<Self> {
+        let mut index = HashMap::with_capacity(references.len());
+        for (i, reference) in references.iter().enumerate() {
+            if index.insert(reference.name.clone(), i).is_some() {
+                bail!("Duplicate reference name in header: {}", reference.name);
+            }
+        }
+        Ok(Self {
+            text,
+            references,
+            index,
+        })
+    }
+
+    /// Parse the references from the @SQ lines of a SAM header text.
+    pub fn from_text(text: String) -> Result<Self> {
+        let mut references = Vec::new();
+        for line in text.lines().filter(|l| l.starts_with("@SQ\t")) {
+            let (mut name, mut length) = (None, None);
+            for field in line.split('\t').skip(1) {
+                if let Some(s) = field.strip_prefix("SN:") {
+                    name = Some(s.to_owned());
+                } else if let Some(s) = field.strip_prefix("LN:") {
+                    length =
+                        Some(s.parse::<u64>().with_context(|| {
+                            format!("Invalid LN field in header line: {}", line)
+                        })?);
+                }
+            }
+            match (name, length) {
+                (Some(name), Some(length)) => references.push(Reference { name, length }),
+                _ => bail!("@SQ header line is missing SN or LN field: {}", line),
+            }
+        }
+        Self::new(text, references)
+    }
+
+    pub fn reference_index(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cigar {
+    /// The operation, as its SAM character, e.g. b'M'
+    pub op: u8,
+    pub len: u32,
+}
+
+impl Cigar {
+    /// Whether the operation consumes bases of the reference
+    pub fn consumes_reference(&self) -> bool {
+        matches!(self.op, b'M' | b'D' | b'N' | b'=' | b'X')
+    }
+
+    /// Whether the operation consumes bases of the read
+    pub fn consumes_query(&self) -> bool {
+        matches!(self.op, b'M' | b'I' | b'S' | b'=' | b'X')
+    }
+}
+
+const BAM_CIGAR_OPS: &[u8; 9] = b"MIDNSHP=X";
+
+pub const FLAG_PAIRED: u16 = 0x1;
+pub const FLAG_PROPER_PAIR: u16 = 0x2;
+pub const FLAG_UNMAPPED: u16 = 0x4;
+pub const FLAG_MATE_UNMAPPED: u16 = 0x8;
+pub const FLAG_REVERSE: u16 = 0x10;
+pub const FLAG_MATE_REVERSE: u16 = 0x20;
+pub const FLAG_FIRST: u16 = 0x40;
+pub const FLAG_LAST: u16 = 0x80;
+pub const FLAG_SECONDARY: u16 = 0x100;
+pub const FLAG_QCFAIL: u16 = 0x200;
+pub const FLAG_DUPLICATE: u16 = 0x400;
+pub const FLAG_SUPPLEMENTARY: u16 = 0x800;
+
+/// A SAM or BAM record. Only the fields up to TLEN are parsed, the rest of the
+/// record is available in its raw form.
+/// Records are meant to be reused with `AlignmentReader::read_record` to avoid allocations.
+#[derive(Clone, Debug, Default)]
+pub struct Record {
+    pub qname: Vec<u8>,
+    pub flag: u16,
+    pub ref_id: Option<usize>,
+    /// 0-based leftmost position
+    pub pos: Option<u32>,
+    pub mapq: u8,
+    pub cigar: Vec<Cigar>,
+    pub next_ref_id: Option<usize>,
+    /// 0-based leftmost position of the mate
+    pub next_pos: Option<u32>,
+    pub tlen: i32,
+    // The line without newline for SAM, the bytes after block_size for BAM
+    raw: Vec<u8>,
+}
+
+impl Record {
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn has_flag(&self, flag: u16) -> bool {
+        self.flag & flag != 0
+    }
+
+    pub fn is_unmapped(&self) -> bool {
+        self.has_flag(FLAG_UNMAPPED) || self.ref_id.is_none() || self.pos.is_none()
+    }
+
+    /// Secondary or supplementary
+    pub fn is_non_primary(&self) -> bool {
+        self.has_flag(FLAG_SECONDARY | FLAG_SUPPLEMENTARY)
+    }
+
+    /// Number of reference bases covered by the alignment
+    pub fn reference_length(&self) -> u32 {
+        self.cigar
+            .iter()
+            .filter(|c| c.consumes_reference())
+            .map(|c| c.len)
+            .sum()
+    }
+
+    /// 0-based exclusive end position of the alignment on the reference
+    pub fn end(&self) -> Option<u32> {
+        self.pos.map(|p| p + self.reference_length())
+    }
+
+    /// Iterate over the 0-based half-open reference intervals covered by aligned bases,
+    /// i.e. M, = and X operations.
+    pub fn aligned_blocks(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let mut refpos = self.pos.unwrap_or(0);
+        self.cigar.iter().filter_map(move |c| {
+            let start = refpos;
+            if c.consumes_reference() {
+                refpos += c.len;
+            }
+            matches!(c.op, b'M' | b'=' | b'X').then_some((start, refpos))
+        })
+    }
+
+    fn parse_sam(&mut self, header: &Header) -> Result<()> {
+        let mut fields = self.raw.split(|&b| b == b'\t');
+        let mut next = |name: &str| {
+            fields
+                .next()
+                .with_context(|| format!("SAM line is missing the {} field", name))
+        };
+        let qname = next("QNAME")?;
+        self.qname.clear();
+        self.qname.extend_from_slice(qname);
+        self.flag = parse_int(next("FLAG")?, "FLAG")?;
+        let rname = next("RNAME")?;
+        self.ref_id = parse_reference(rname, header)?;
+        self.pos = parse_int::<u32>(next("POS")?, "POS")?.checked_sub(1);
+        self.mapq = parse_int(next("MAPQ")?, "MAPQ")?;
+        let cigar = next("CIGAR")?;
+        self.cigar.clear();
+        if cigar != b"*" {
+            let mut len: u32 = 0;
+            for &b in cigar {
+                if b.is_ascii_digit() {
+                    len = len
+                        .checked_mul(10)
+                        .and_then(|n| n.checked_add((b - b'0') as u32))
+                        .context("CIGAR operation length overflows")?;
+                } else if BAM_CIGAR_OPS.contains(&b) {
+                    self.cigar.push(Cigar { op: b, len });
+                    len = 0;
+                } else {
+                    bail!("Invalid CIGAR operation: {}", b as char);
+                }
+            }
+        }
+        let rnext = next("RNEXT")?;
+        self.next_ref_id = if rnext == b"=" {
+            self.ref_id
+        } else {
+            parse_reference(rnext, header)?
+        };
+        self.next_pos = parse_int::<u32>(next("PNEXT")?, "PNEXT")?.checked_sub(1);
+        self.tlen = parse_int(next("TLEN")?, "TLEN")?;
+        next("SEQ")?;
+        next("QUAL")?;
+        Ok(())
+    }
+
+    fn parse_bam(&mut self, n_references: usize) -> Result<()> {
+        let raw = &self.raw;
+        if raw.len() < 32 {
+            bail!("BAM record is truncated");
+        }
+        let i32_at = |i: usize| i32::from_le_bytes(raw[i..i + 4].try_into().unwrap());
+        let u16_at = |i: usize| u16::from_le_bytes(raw[i..i + 2].try_into().unwrap());
+        let ref_id = |i: i32| -> Result<Option<usize>> {
+            match usize::try_from(i) {
+                Err(_) => Ok(None),
+                Ok(u) if u < n_references => Ok(Some(u)),
+                Ok(u) => bail!("BAM record refers to reference {}, not in header", u),
+            }
+        };
+        self.ref_id = ref_id(i32_at(0))?;
+        self.pos = u32::try_from(i32_at(4)).ok();
+        let l_read_name = raw[8] as usize;
+        self.mapq = raw[9];
+        let n_cigar_op = u16_at(12) as usize;
+        self.flag = u16_at(14);
+        self.next_ref_id = ref_id(i32_at(20))?;
+        self.next_pos = u32::try_from(i32_at(24)).ok();
+        self.tlen = i32_at(28);
+        let cigar_start = 32 + l_read_name;
+        if raw.len() < cigar_start + 4 * n_cigar_op || l_read_name == 0 {
+            bail!("BAM record is truncated");
+        }
+        self.qname.clear();
+        self.qname.extend_from_slice(&raw[32..cigar_start - 1]);
+        self.cigar.clear();
+        for chunk in raw[cigar_start..cigar_start + 4 * n_cigar_op].chunks_exact(4) {
+            let v = u32::from_le_bytes(chunk.try_into().unwrap());
+            let op = *BAM_CIGAR_OPS
+                .get((v & 0xf) as usize)
+                .context("Invalid CIGAR operation in BAM record")?;
+            self.cigar.push(Cigar { op, len: v >> 4 });
+        }
+        Ok(())
+    }
+}
+
+fn parse_int<T: std::str::FromStr>(field: &[u8], name: &str) -> Result<T> {
+    std::str::from_utf8(field)
+        .ok()
+        .and_then(|s| s.parse::<T>().ok())
+        .with_context(|| {
+            format!(
+                "Could not parse {} field: \"{}\"",
+                name,
+                String::from_utf8_lossy(field)
+            )
+        })
+}
+
+fn parse_reference(field: &[u8], header: &Header) -> Result<Option<usize>> {
+    if field == b"*" {
+        return Ok(None);
+    }
+    let name = std::str::from_utf8(field).context("Reference name is not UTF-8")?;
+    header
+        .reference_index(name)
+        .map(Some)
+        .with_context(|| format!("Reference \"{}\" is not in the header", name))
+}
+
+/// Reads SAM, gzipped SAM or BAM. The format is detected from the content.
+pub struct AlignmentReader {
+    reader: Box<dyn BufRead>,
+    format: Format,
+    header: Header,
+}
+
+impl AlignmentReader {
+    /// Open the file at the path, or stdin if None.
+    pub fn open(path: Option<&Path>) -> Result<Self> {
+        let reader: Box<dyn BufRead> = match path {
+            None => Box::new(stdin().lock()),
+            Some(p) => Box::new(BufReader::new(File::open(p).with_context(|| {
+                format!("Could not open input file \"{}\"", p.to_string_lossy())
+            })?)),
+        };
+        Self::new(reader)
+    }
+
+    pub fn new(mut reader: Box<dyn BufRead>) -> Result<Self> {
+        let gzipped = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+        if gzipped {
+            reader = Box::new(BufReader::new(MultiGzDecoder::new(reader)));
+        }
+        if reader.fill_buf()?.starts_with(b"BAM\x01") {
+            let header = read_bam_header(&mut reader)?;
+            Ok(Self {
+                reader,
+                format: Format::Bam,
+                header,
+            })
+        } else {
+            let mut text = Vec::new();
+            while reader.fill_buf()?.first() == Some(&b'@') {
+                reader.read_until(b'\n', &mut text)?;
+            }
+            let text = String::from_utf8(text).context("SAM header is not UTF-8")?;
+            Ok(Self {
+                reader,
+                format: if gzipped { Format::SamGz } else { Format::Sam },
+                header: Header::from_text(text)?,
+            })
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Read the next record into `record`. Returns false at end of file.
+    pub fn read_record(&mut self, record: &mut Record) -> Result<bool> {
+        record.raw.clear();
+        match self.format {
+            Format::Sam | Format::SamGz => loop {
+                if self.reader.read_until(b'\n', &mut record.raw)? == 0 {
+                    return Ok(false);
+                }
+                while matches!(record.raw.last(), Some(b'\n' | b'\r')) {
+                    record.raw.pop();
+                }
+                if !record.raw.is_empty() {
+                    record.parse_sam(&self.header).with_context(|| {
+                        format!("Invalid SAM line: {}", String::from_utf8_lossy(&record.raw))
+                    })?;
+                    return Ok(true);
+                }
+            },
+            Format::Bam => {
+                if self.reader.fill_buf()?.is_empty() {
+                    return Ok(false);
+                }
+                let mut size = [0u8; 4];
+                self.reader
+                    .read_exact(&mut size)
+                    .context("BAM file is truncated")?;
+                record.raw.resize(u32::from_le_bytes(size) as usize, 0);
+                self.reader
+                    .read_exact(&mut record.raw)
+                    .context("BAM file is truncated")?;
+                record.parse_bam(self.header.references.len())?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+fn read_bam_header(reader: &mut impl Read) -> Result<Header> {
+    fn read_u32(reader: &mut impl Read) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        reader
+            .read_exact(&mut buf)
+            .context("BAM header is truncated")?;
+        Ok(u32::from_le_bytes(buf))
+    }
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    let mut text = vec![0u8; read_u32(reader)? as usize];
+    reader
+        .read_exact(&mut text)
+        .context("BAM header is truncated")?;
+    // The text may be padded with NUL bytes
+    while text.last() == Some(&0) {
+        text.pop();
+    }
+    let text = String::from_utf8(text).context("BAM header text is not UTF-8")?;
+    let n_references = read_u32(reader)?;
+    let mut references = Vec::with_capacity(n_references as usize);
+    for _ in 0..n_references {
+        let mut name = vec![0u8; read_u32(reader)? as usize];
+        reader
+            .read_exact(&mut name)
+            .context("BAM header is truncated")?;
+        if name.pop() != Some(0) {
+            bail!("Reference name in BAM header is not NUL-terminated");
+        }
+        let name = String::from_utf8(name).context("Reference name is not UTF-8")?;
+        let length = read_u32(reader)? as u64;
+        references.push(Reference { name, length });
+    }
+    Header::new(text, references)
+}
+
+/// Writes records in the same format as they were read.
+pub struct AlignmentWriter {
+    writer: Writer,
+}
+
+enum Writer {
+    Sam(Box<dyn Write>),
+    SamGz(BgzfWriter<Box<dyn Write>>),
+    Bam(BgzfWriter<Box<dyn Write>>),
+}
+
+impl AlignmentWriter {
+    /// Create a writer to the path, or stdout if None, and write the header.
+    pub fn create(path: Option<&Path>, format: Format, header: &Header) -> Result<Self> {
+        let out: Box<dyn Write> = match path {
+            None => Box::new(BufWriter::new(stdout().lock())),
+            Some(p) => Box::new(BufWriter::new(File::create(p).with_context(|| {
+                format!("Could not create output file \"{}\"", p.to_string_lossy())
+            })?)),
+        };
+        let mut writer = match format {
+            Format::Sam => Writer::Sam(out),
+            Format::SamGz => Writer::SamGz(BgzfWriter::new(out, Compression::default())),
+            Format::Bam => Writer::Bam(BgzfWriter::new(out, Compression::default())),
+        };
+        match &mut writer {
+            Writer::Sam(w) => write_sam_header(w, header)?,
+            Writer::SamGz(w) => write_sam_header(w, header)?,
+            Writer::Bam(w) => {
+                w.write_all(b"BAM\x01")?;
+                w.write_all(&(header.text.len() as u32).to_le_bytes())?;
+                w.write_all(header.text.as_bytes())?;
+                w.write_all(&(header.references.len() as u32).to_le_bytes())?;
+                for reference in header.references.iter() {
+                    w.write_all(&(reference.name.len() as u32 + 1).to_le_bytes())?;
+                    w.write_all(reference.name.as_bytes())?;
+                    w.write_all(&[0])?;
+                    w.write_all(&(reference.length as u32).to_le_bytes())?;
+                }
+            }
+        }
+        Ok(Self { writer })
+    }
+
+    /// Write the record. It must have been read from a file of the same format
+    /// as this writer, with the same header.
+    pub fn write_record(&mut self, record: &Record) -> Result<()> {
+        match &mut self.writer {
+            Writer::Sam(w) => write_sam_line(w, record)?,
+            Writer::SamGz(w) => write_sam_line(w, record)?,
+            Writer::Bam(w) => {
+                w.write_all(&(record.raw.len() as u32).to_le_bytes())?;
+                w.write_all(&record.raw)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self.writer {
+            Writer::Sam(mut w) => w.flush()?,
+            Writer::SamGz(w) => w.finish()?.flush()?,
+            Writer::Bam(w) => w.finish()?.flush()?,
+        }
+        Ok(())
+    }
+}
+
+fn write_sam_header(w: &mut impl Write, header: &Header) -> io::Result<()> {
+    w.write_all(header.text.as_bytes())?;
+    if !(header.text.is_empty() || header.text.ends_with('\n')) {
+        w.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn write_sam_line(w: &mut impl Write, record: &Record) -> io::Result<()> {
+    w.write_all(&record.raw)?;
+    w.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod test_alignment {
+    use crate::{AlignmentReader, AlignmentWriter, Cigar, Format, Record};
+    use std::io::BufReader;
+
+    const SAM: &str = "@HD\tVN:1.6\n@SQ\tSN:c1\tLN:1000\n@SQ\tSN:c2\tLN:500\n\
+        r1\t99\tc1\t11\t60\t5S10M2D10M\t=\t101\t120\tACGT\t*\tNM:i:2\n\
+        r2\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t*\n";
+
+    fn read_all(reader: &mut AlignmentReader) -> Vec<Record> {
+        let mut records = Vec::new();
+        let mut record = Record::default();
+        while reader.read_record(&mut record).unwrap() {
+            records.push(record.clone());
+        }
+        records
+    }
+
+    #[test]
+    fn test_sam_parsing() {
+        let mut reader = AlignmentReader::new(Box::new(SAM.as_bytes())).unwrap();
+        assert_eq!(reader.format(), Format::Sam);
+        assert_eq!(reader.header().references.len(), 2);
+        let records = read_all(&mut reader);
+        assert_eq!(records.len(), 2);
+        let r = &records[0];
+        assert_eq!(r.qname, b"r1");
+        assert_eq!(
+            (r.flag, r.ref_id, r.pos, r.mapq),
+            (99, Some(0), Some(10), 60)
+        );
+        assert_eq!(r.cigar[0], Cigar { op: b'S', len: 5 });
+        assert_eq!(r.reference_length(), 22);
+        assert_eq!(r.aligned_blocks().collect::<Vec<_>>(), [(10, 20), (22, 32)]);
+        assert_eq!(
+            (r.next_ref_id, r.next_pos, r.tlen),
+            (Some(0), Some(100), 120)
+        );
+        assert!(records[1].is_unmapped());
+    }
+
+    #[test]
+    fn test_gzipped_sam_roundtrip() {
+        let mut sam = AlignmentReader::new(Box::new(SAM.as_bytes())).unwrap();
+        let records = read_all(&mut sam);
+        let path = std::env::temp_dir().join("htslite_test_roundtrip.sam.gz");
+        let mut writer = AlignmentWriter::create(Some(&path), Format::SamGz, sam.header()).unwrap();
+        for record in records.iter() {
+            writer.write_record(record).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let compressed = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(compressed.starts_with(&[0x1f, 0x8b]));
+        let mut reader = AlignmentReader::new(Box::new(std::io::Cursor::new(compressed))).unwrap();
+        assert_eq!(reader.format(), Format::SamGz);
+        assert_eq!(reader.header().text, sam.header().text);
+        let roundtripped = read_all(&mut reader);
+        assert_eq!(roundtripped.len(), 2);
+        assert_eq!(roundtripped[0].raw(), records[0].raw());
+        assert_eq!(roundtripped[1].raw(), records[1].raw());
+    }
+
+    #[test]
+    fn test_bam_roundtrip() {
+        // We can't produce BAM records from SAM, so write a minimal BAM record by hand
+        let mut raw: Vec<u8> = Vec::new();
+        for v in [1i32, 99] {
+            raw.extend_from_slice(&v.to_le_bytes());
+        }
+        raw.extend_from_slice(&[3, 42, 0, 0, 1, 0, 0x10, 0]);
+        for v in [0i32, -1, -1, 0] {
+            raw.extend_from_slice(&v.to_le_bytes());
+        }
+        raw.extend_from_slice(b"ab\0");
+        raw.extend_from_slice(&(7u32 << 4).to_le_bytes());
+
+        let sam = AlignmentReader::new(Box::new(SAM.as_bytes())).unwrap();
+        let record = Record {
+            raw,
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join("htslite_test_roundtrip.bam");
+        let mut writer = AlignmentWriter::create(Some(&path), Format::Bam, sam.header()).unwrap();
+        for _ in 0..3 {
+            writer.write_record(&record).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let mut reader = AlignmentReader::new(Box::new(BufReader::new(file))).unwrap();
+        assert_eq!(reader.format(), Format::Bam);
+        assert_eq!(reader.header().references[1].name, "c2");
+        let records = read_all(&mut reader);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 3);
+        let r = &records[2];
+        assert_eq!(r.qname, b"ab");
+        assert_eq!(
+            (r.flag, r.ref_id, r.pos, r.mapq),
+            (16, Some(1), Some(99), 42)
+        );
+        assert_eq!(r.cigar, [Cigar { op: b'M', len: 7 }]);
+        assert_eq!(r.next_ref_id, None);
+    }
+}
@@ -0,0 +1,306 @@
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression, Crc};
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+
+// Maximum number of uncompressed bytes put in one block. This is the same as htslib uses,
+// and ensures the compressed block stays below the 64 KiB limit of the format even
+// when the data is incompressible.
+pub const MAX_BLOCK_DATA: usize = 0xff00;
+
+/// The empty block that marks the end of a BGZF file.
+pub const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Compress at most `MAX_BLOCK_DATA` bytes into one BGZF block, which is appended to `out`.
+/// A BGZF file is a series of blocks followed by `EOF_BLOCK`, so blocks can be compressed
+/// in parallel and concatenated.
+pub fn compress_block(data: &[u8], level: Compression, out: &mut Vec<u8>) -> io::Result<()> {
+    assert!(data.len() <= MAX_BLOCK_DATA);
+    let start = out.len();
+    // The last two bytes of the header are BSIZE, which is set below
+    out.extend_from_slice(&[
+        0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, b'B', b'C', 0x02,
+        0x00, 0x00, 0x00,
+    ]);
+    let mut encoder = DeflateEncoder::new(&mut *out, level);
+    encoder.write_all(data)?;
+    encoder.finish()?;
+    let mut crc = Crc::new();
+    crc.update(data);
+    out.extend_from_slice(&crc.sum().to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    // BSIZE is the total block size minus one
+    let bsize = (out.len() - start - 1) as u16;
+    out[start + 16..start + 18].copy_from_slice(&bsize.to_le_bytes());
+    Ok(())
+}
+
+/// A writer which compresses its input into BGZF blocks, as produced by `bgzip`.
+/// Call `finish` to write the EOF marker block. If the writer is dropped without
+/// calling `finish`, it will try to finish, but errors are ignored.
+pub struct BgzfWriter<W: Write> {
+    // Only None after finish has been called
+    inner: Option<W>,
+    buffer: Vec<u8>,
+    compressed: Vec<u8>,
+    level: Compression,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W, level: Compression) -> Self {
+        Self {
+            inner: Some(inner),
+            buffer: Vec::with_capacity(MAX_BLOCK_DATA),
+            compressed: Vec::new(),
+            level,
+        }
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        let inner = self.inner.as_mut().expect("Wrote to finished BgzfWriter");
+        self.compressed.clear();
+        compress_block(&self.buffer, self.level, &mut self.compressed)?;
+        inner.write_all(&self.compressed)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Write any buffered data and the EOF block, and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        if self.inner.is_none() {
+            return Ok(());
+        }
+        if !self.buffer.is_empty() {
+            self.write_block()?;
+        }
+        let inner = self.inner.as_mut().unwrap();
+        inner.write_all(&EOF_BLOCK)?;
+        inner.flush()
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() == MAX_BLOCK_DATA {
+            self.write_block()?;
+        }
+        let n = buf.len().min(MAX_BLOCK_DATA - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    // Flushing ends the current block early. This is needed by e.g. BAM indexing, which
+    // needs some records to begin at block boundaries, but makes the file a little larger.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_block()?;
+        }
+        self.inner
+            .as_mut()
+            .expect("Flushed finished BgzfWriter")
+            .flush()
+    }
+}
+
+impl<W: Write> Drop for BgzfWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.try_finish();
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// A reader of BGZF files, which decompresses one block at a time and verifies the
+/// CRC32 and size of every block. Unlike a generic gzip decoder, it knows the
+/// compressed offset of every block, which is needed for indexing.
+pub struct BgzfReader<R: Read> {
+    inner: R,
+    data: Vec<u8>,
+    // Position in `data` of the next byte to read with Read/BufRead
+    pos: usize,
+    compressed: Vec<u8>,
+    block_offset: u64,
+    next_offset: u64,
+    // Uncompressed offset of the start of the current block
+    uncompressed_offset: u64,
+    last_block_empty: bool,
+    // (compressed, uncompressed) offset of the start of every block read, if enabled
+    index: Option<Vec<(u64, u64)>>,
+}
+
+impl<R: Read> BgzfReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            data: Vec::new(),
+            pos: 0,
+            compressed: Vec::new(),
+            block_offset: 0,
+            next_offset: 0,
+            uncompressed_offset: 0,
+            last_block_empty: false,
+            index: None,
+        }
+    }
+
+    /// Record the offsets of every block read, available from `index`.
+    pub fn with_index(mut self) -> Self {
+        self.index = Some(Vec::new());
+        self
+    }
+
+    /// The (compressed, uncompressed) offsets of the start of every block read so far,
+    /// if `with_index` was used.
+    pub fn index(&self) -> Option<&[(u64, u64)]> {
+        self.index.as_deref()
+    }
+
+    /// Compressed offset of the current block
+    pub fn block_offset(&self) -> u64 {
+        self.block_offset
+    }
+
+    /// The decompressed data of the current block
+    pub fn block_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Whether the last block read was empty, as the EOF marker block is.
+    /// After reading the whole file, this tells whether the file was truncated.
+    pub fn ends_with_eof_block(&self) -> bool {
+        self.last_block_empty
+    }
+
+    /// Read and decompress the next block. Returns false at end of file.
+    pub fn read_block(&mut self) -> io::Result<bool> {
+        let mut header = [0u8; 12];
+        // Distinguish clean end of file from a truncated header
+        let mut n = 0;
+        while n < header.len() {
+            match self.inner.read(&mut header[n..])? {
+                0 if n == 0 => return Ok(false),
+                0 => return Err(invalid_data("Truncated BGZF block header")),
+                i => n += i,
+            }
+        }
+        if header[..4] != [0x1f, 0x8b, 0x08, 0x04] {
+            return Err(invalid_data("Not a BGZF block"));
+        }
+        let xlen = u16::from_le_bytes([header[10], header[11]]) as usize;
+        let mut extra = vec![0u8; xlen];
+        self.inner.read_exact(&mut extra)?;
+        // Find the BC subfield, which contains the block size
+        let mut bsize: Option<usize> = None;
+        let mut i = 0;
+        while i + 4 <= extra.len() {
+            let slen = u16::from_le_bytes([extra[i + 2], extra[i + 3]]) as usize;
+            if extra[i..i + 2] == *b"BC" && slen == 2 && i + 6 <= extra.len() {
+                bsize = Some(u16::from_le_bytes([extra[i + 4], extra[i + 5]]) as usize);
+            }
+            i += 4 + slen;
+        }
+        let bsize = bsize.ok_or_else(|| invalid_data("Gzip block has no BGZF size field"))?;
+        let cdata_len = (bsize + 1)
+            .checked_sub(xlen + 20)
+            .ok_or_else(|| invalid_data("Invalid BGZF block size"))?;
+        self.compressed.resize(cdata_len, 0);
+        self.inner.read_exact(&mut self.compressed)?;
+        let mut footer = [0u8; 8];
+        self.inner.read_exact(&mut footer)?;
+        let expected_crc = u32::from_le_bytes(footer[..4].try_into().unwrap());
+        let isize = u32::from_le_bytes(footer[4..].try_into().unwrap()) as usize;
+
+        self.uncompressed_offset += self.data.len() as u64;
+        self.data.clear();
+        self.data.reserve(isize);
+        DeflateDecoder::new(&self.compressed[..]).read_to_end(&mut self.data)?;
+        if self.data.len() != isize {
+            return Err(invalid_data("BGZF block has wrong uncompressed size"));
+        }
+        let mut crc = Crc::new();
+        crc.update(&self.data);
+        if crc.sum() != expected_crc {
+            return Err(invalid_data("BGZF block has wrong CRC32 checksum"));
+        }
+        self.pos = 0;
+        self.block_offset = self.next_offset;
+        self.next_offset += (bsize + 1) as u64;
+        self.last_block_empty = isize == 0;
+        if let Some(index) = self.index.as_mut() {
+            index.push((self.block_offset, self.uncompressed_offset));
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for BgzfReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Loop, since blocks may be empty
+        while self.pos == self.data.len() {
+            if !self.read_block()? {
+                break;
+            }
+        }
+        Ok(&self.data[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.data.len());
+    }
+}
+
+#[cfg(test)]
+mod test_bgzf {
+    use crate::{BgzfReader, BgzfWriter};
+    use flate2::Compression;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_roundtrip() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let mut writer = BgzfWriter::new(Vec::new(), Compression::fast());
+        writer.write_all(&data).unwrap();
+        let compressed = writer.finish().unwrap();
+
+        let mut reader = BgzfReader::new(&compressed[..]).with_index();
+        let mut decompressed = Vec::new();
+        reader.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(data, decompressed);
+        assert!(reader.ends_with_eof_block());
+        // Three full blocks, one partial, and the EOF block
+        let index = reader.index().unwrap();
+        assert_eq!(index.len(), 5);
+        assert_eq!(index[1].1, super::MAX_BLOCK_DATA as u64);
+        assert_eq!(index[4].1, data.len() as u64);
+
+        // Flip a bit in the compressed data of the first block
+        let mut corrupt = compressed.clone();
+        corrupt[30] ^= 1;
+        let mut reader = BgzfReader::new(&corrupt[..]);
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+
+        // Truncate the EOF block
+        let truncated = &compressed[..compressed.len() - 28];
+        let mut reader = BgzfReader::new(truncated);
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert!(!reader.ends_with_eof_block());
+    }
+}
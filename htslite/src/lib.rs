@@ -0,0 +1,8 @@
+//! Minimal reading and writing of SAM, BAM and BGZF files for the tools in this repository.
+//! This avoids depending on htslib, which requires a C toolchain and libclang to build.
+
+mod alignment;
+pub mod bgzf;
+
+pub use alignment::*;
+pub use bgzf::{BgzfReader, BgzfWriter};
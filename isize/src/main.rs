@@ -0,0 +1,226 @@
+use anyhow::{self, bail, Context, Result};
+use clap::Parser;
+use htslite::{
+    AlignmentReader, Record, FLAG_DUPLICATE, FLAG_FIRST, FLAG_MATE_UNMAPPED, FLAG_PAIRED,
+    FLAG_PROPER_PAIR, FLAG_QCFAIL,
+};
+use progress::{info, LogArgs, Progress};
+use std::{
+    fs::File,
+    io::{stdout, BufWriter, Write},
+    path::PathBuf,
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let mut reader = AlignmentReader::open(args.i.as_deref())?;
+    let mut record = Record::default();
+    // Number of pairs with each insert size
+    let mut histogram: Vec<u64> = Vec::new();
+    let (mut n_pairs, mut n_too_large) = (0u64, 0u64);
+    let progress = Progress::new("Reading pairs", "pairs", Some(args.max_pairs));
+    while n_pairs < args.max_pairs && reader.read_record(&mut record)? {
+        let Some(size) = insert_size(&record, args.min_mapq, args.all_pairs) else {
+            continue;
+        };
+        n_pairs += 1;
+        progress.inc(1);
+        if size > args.max_insert {
+            n_too_large += 1;
+            continue;
+        }
+        if histogram.len() <= size {
+            histogram.resize(size + 1, 0);
+        }
+        histogram[size] += 1;
+    }
+    progress.finish();
+    let Some(stats) = Stats::from_histogram(&histogram) else {
+        bail!("Found no pairs to estimate the insert size from");
+    };
+    if n_too_large > 0 {
+        info!(
+            "Ignored {} pairs with insert size above {}",
+            n_too_large, args.max_insert
+        );
+    }
+
+    let mut out: Box<dyn Write> = match &args.o {
+        None => Box::new(BufWriter::new(stdout().lock())),
+        Some(p) => Box::new(BufWriter::new(File::create(p).with_context(|| {
+            format!("Could not create output file \"{}\"", p.display())
+        })?)),
+    };
+    out.write_all(b"pairs\tmean\tmedian\tsd\tmad\tmin\tmax\n")?;
+    writeln!(
+        out,
+        "{}\t{:.2}\t{}\t{:.2}\t{}\t{}\t{}",
+        stats.n, stats.mean, stats.median, stats.sd, stats.mad, stats.min, stats.max
+    )?;
+    out.flush()?;
+
+    if let Some(p) = &args.histogram {
+        let mut w = BufWriter::new(
+            File::create(p)
+                .with_context(|| format!("Could not create histogram file \"{}\"", p.display()))?,
+        );
+        w.write_all(b"insert_size\tpairs\n")?;
+        for (size, &n) in histogram.iter().enumerate().filter(|(_, &n)| n > 0) {
+            writeln!(w, "{}\t{}", size, n)?;
+        }
+        w.flush()?;
+    }
+    Ok(())
+}
+
+// The insert size of the pair, counted only for the first mate, so each pair is counted once.
+fn insert_size(record: &Record, min_mapq: u8, all_pairs: bool) -> Option<usize> {
+    let required = if all_pairs {
+        FLAG_PAIRED | FLAG_FIRST
+    } else {
+        FLAG_PAIRED | FLAG_PROPER_PAIR | FLAG_FIRST
+    };
+    let is_usable = record.flag & required == required
+        && !record.is_unmapped()
+        && !record.is_non_primary()
+        && !record.has_flag(FLAG_MATE_UNMAPPED | FLAG_DUPLICATE | FLAG_QCFAIL)
+        && record.mapq >= min_mapq
+        && record.next_ref_id == record.ref_id
+        && record.tlen != 0;
+    is_usable.then(|| record.tlen.unsigned_abs() as usize)
+}
+
+#[derive(Debug, PartialEq)]
+struct Stats {
+    n: u64,
+    mean: f64,
+    median: f64,
+    sd: f64,
+    // Median absolute deviation from the median, not scaled to estimate the SD
+    mad: f64,
+    min: usize,
+    max: usize,
+}
+
+// The value at the given index, if all values of the histogram were sorted
+fn nth_value(histogram: &[u64], index: u64) -> usize {
+    let mut seen = 0u64;
+    for (value, &n) in histogram.iter().enumerate() {
+        seen += n;
+        if seen > index {
+            return value;
+        }
+    }
+    unreachable!()
+}
+
+fn histogram_median(histogram: &[u64], n: u64) -> f64 {
+    (nth_value(histogram, (n - 1) / 2) + nth_value(histogram, n / 2)) as f64 / 2.0
+}
+
+impl Stats {
+    fn from_histogram(histogram: &[u64]) -> Option<Self> {
+        let n: u64 = histogram.iter().sum();
+        if n == 0 {
+            return None;
+        }
+        let (mut sum, mut sum_squares) = (0.0, 0.0);
+        for (value, &count) in histogram.iter().enumerate() {
+            sum += (value as f64) * count as f64;
+            sum_squares += (value as f64).powi(2) * count as f64;
+        }
+        let mean = sum / n as f64;
+        let variance = if n > 1 {
+            (sum_squares - n as f64 * mean * mean) / (n - 1) as f64
+        } else {
+            0.0
+        };
+        let median = histogram_median(histogram, n);
+        // The median may be a half integer, so deviations are counted in halves
+        let mut deviations: Vec<u64> = Vec::new();
+        for (value, &count) in histogram.iter().enumerate().filter(|(_, &c)| c > 0) {
+            let d = (2.0 * (value as f64 - median)).abs() as usize;
+            if deviations.len() <= d {
+                deviations.resize(d + 1, 0);
+            }
+            deviations[d] += count;
+        }
+        Some(Self {
+            n,
+            mean,
+            median,
+            sd: variance.max(0.0).sqrt(),
+            mad: histogram_median(&deviations, n) / 2.0,
+            min: histogram.iter().position(|&c| c > 0).unwrap(),
+            max: histogram.iter().rposition(|&c| c > 0).unwrap(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_stats {
+    use crate::Stats;
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(Stats::from_histogram(&[0, 0]), None);
+        // Values 2, 2, 3, 7
+        let mut histogram = vec![0; 8];
+        histogram[2] = 2;
+        histogram[3] = 1;
+        histogram[7] = 1;
+        let stats = Stats::from_histogram(&histogram).unwrap();
+        assert_eq!(stats.n, 4);
+        assert_eq!(stats.mean, 3.5);
+        assert_eq!(stats.median, 2.5);
+        assert!((stats.sd - 5.6666f64.sqrt()).abs() < 1e-3);
+        // Deviations 0.5, 0.5, 0.5, 4.5
+        assert_eq!(stats.mad, 0.5);
+        assert_eq!((stats.min, stats.max), (2, 7));
+    }
+}
+
+const LONG_ABOUT: &str =
+    "Estimate the insert size distribution of paired reads from a SAM or BAM file.
+Reads the first pairs of the file, and prints the number of pairs, and the mean, median,
+standard deviation, median absolute deviation, minimum and maximum insert size as a TSV file.
+Only primary alignments of properly paired reads, with both mates on the same reference,
+are used, and duplicates and QC-failed reads are skipped. The insert size is the absolute
+value of the TLEN field. The input need not be sorted.
+Usage: isize -i aln.bam -n 1000000 --histogram histogram.tsv";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Input SAM or BAM file [stdin]
+    #[arg(short)]
+    i: Option<PathBuf>,
+
+    /// Output statistics file [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    /// Write a histogram of insert sizes as a TSV file
+    #[arg(long)]
+    histogram: Option<PathBuf>,
+
+    /// Stop after this many pairs
+    #[arg(short = 'n', long, default_value_t = 10_000_000)]
+    max_pairs: u64,
+
+    /// Minimum mapping quality of the first mate
+    #[arg(short = 'Q', long, default_value_t = 0)]
+    min_mapq: u8,
+
+    /// Ignore pairs with a larger insert size, e.g. chimeric pairs
+    #[arg(long, default_value_t = 100_000)]
+    max_insert: usize,
+
+    /// Also use pairs not flagged as properly paired
+    #[arg(long)]
+    all_pairs: bool,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
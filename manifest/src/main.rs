@@ -0,0 +1,309 @@
+use anyhow::{self, bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use datafiles::PathFilter;
+use progress::{info, LogArgs, Progress};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{stdout, BufRead, BufReader, BufWriter, Read, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    thread,
+};
+use walkdir::WalkDir;
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    progress::init(&cli.logging)?;
+    match cli.command {
+        Command::Create(args) => create(&args),
+        Command::Verify(args) => verify(&args),
+    }
+}
+
+// Files found in the directory, relative to it, with '/' as separator.
+// The manifest file itself is skipped, if it's in the directory.
+fn find_files(dir: &Path, filter: &PathFilter, skip: Option<&Path>) -> Result<Vec<String>> {
+    let skip = skip.and_then(|p| std::fs::canonicalize(p).ok());
+    let mut files: Vec<String> = Vec::new();
+    for entry in WalkDir::new(dir) {
+        let entry = entry.context("Error when walking directory")?;
+        // Like dnazip, symlinks are not followed
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(dir).unwrap();
+        if !filter.is_selected(relative, true) {
+            continue;
+        }
+        if skip.is_some() && std::fs::canonicalize(entry.path()).ok() == skip {
+            continue;
+        }
+        let Some(s) = relative.to_str() else {
+            bail!("Path \"{}\" is not valid UTF-8", entry.path().display());
+        };
+        files.push(s.replace(std::path::MAIN_SEPARATOR, "/"));
+    }
+    files.sort_unstable();
+    Ok(files)
+}
+
+fn sha256_file(path: &Path, progress: &Progress) -> Result<String> {
+    let file =
+        File::open(path).with_context(|| format!("Could not open file \"{}\"", path.display()))?;
+    let mut file = progress.wrap_read(file);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let n = file
+            .read(&mut buffer)
+            .with_context(|| format!("Could not read file \"{}\"", path.display()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Hash the files in parallel. Returns the hashes in the same order as the paths.
+fn hash_files(dir: &Path, paths: &[String], threads: NonZeroUsize) -> Vec<Result<String>> {
+    let n_bytes: u64 = paths
+        .iter()
+        .map(|f| std::fs::metadata(dir.join(f)).map_or(0, |m| m.len()))
+        .sum();
+    let progress = Progress::bytes("Hashing", Some(n_bytes));
+    let mut results: Vec<Option<Result<String>>> = (0..paths.len()).map(|_| None).collect();
+    thread::scope(|s| {
+        let (index_sender, index_receiver) = crossbeam_channel::unbounded::<usize>();
+        let (result_sender, result_receiver) = crossbeam_channel::unbounded();
+        for _ in 0..threads.get() {
+            let (receiver, sender) = (index_receiver.clone(), result_sender.clone());
+            let progress = &progress;
+            s.spawn(move || {
+                for i in receiver.iter() {
+                    let result = sha256_file(&dir.join(&paths[i]), progress);
+                    if sender.send((i, result)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(result_sender);
+        for i in 0..paths.len() {
+            index_sender.send(i).unwrap();
+        }
+        drop(index_sender);
+        for (i, result) in result_receiver.iter() {
+            results[i] = Some(result);
+        }
+    });
+    progress.finish();
+    results.into_iter().map(|r| r.unwrap()).collect()
+}
+
+fn create(args: &CreateArgs) -> Result<()> {
+    let filter = PathFilter::new(&args.filter.include, &args.filter.exclude)?;
+    let files = find_files(&args.dir, &filter, args.o.as_deref())?;
+    let hashes = hash_files(&args.dir, &files, args.threads);
+
+    let mut out: Box<dyn Write> = match &args.o {
+        None => Box::new(BufWriter::new(stdout().lock())),
+        Some(p) => Box::new(BufWriter::new(File::create(p).with_context(|| {
+            format!("Could not create manifest file \"{}\"", p.display())
+        })?)),
+    };
+    for (file, hash) in files.iter().zip(hashes) {
+        // Same format as sha256sum
+        writeln!(out, "{}  {}", hash?, file)?;
+    }
+    out.flush()?;
+    info!("Wrote checksums of {} files", files.len());
+    Ok(())
+}
+
+// Parse a line of sha256sum output into the hash and the path
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let (hash, path) = line.split_once(' ')?;
+    // sha256sum marks files read in binary mode with '*'
+    let path = path.strip_prefix(' ').or_else(|| path.strip_prefix('*'))?;
+    let is_valid = hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+    (is_valid && !path.is_empty()).then_some((hash, path))
+}
+
+#[cfg(test)]
+mod test_manifest {
+    use crate::{hex, parse_line};
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_parse_line() {
+        let hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(hex(&Sha256::digest(b"")), hash);
+        let line = format!("{}  a/b c.fq.gz", hash);
+        assert_eq!(parse_line(&line), Some((hash, "a/b c.fq.gz")));
+        let line = format!("{} *a.fq", hash);
+        assert_eq!(parse_line(&line), Some((hash, "a.fq")));
+        assert_eq!(parse_line("abc  a.fq"), None);
+        assert_eq!(parse_line(hash), None);
+    }
+}
+
+fn verify(args: &VerifyArgs) -> Result<()> {
+    let dir = match &args.dir {
+        Some(d) => d.clone(),
+        None => args
+            .manifest
+            .parent()
+            .map_or(PathBuf::from("."), |p| p.to_path_buf()),
+    };
+    let reader = BufReader::new(File::open(&args.manifest).with_context(|| {
+        format!(
+            "Could not open manifest file \"{}\"",
+            args.manifest.display()
+        )
+    })?);
+    let (mut expected, mut paths): (Vec<String>, Vec<String>) = (Vec::new(), Vec::new());
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line.context("Failed to read line from manifest")?;
+        if line.is_empty() {
+            continue;
+        }
+        let (hash, path) = parse_line(&line)
+            .with_context(|| format!("Invalid line {} in manifest", lineno + 1))?;
+        expected.push(hash.to_ascii_lowercase());
+        paths.push(path.to_owned());
+    }
+
+    let (existing, missing): (Vec<usize>, Vec<usize>) =
+        (0..paths.len()).partition(|&i| dir.join(&paths[i]).is_file());
+    let existing_paths: Vec<String> = existing.iter().map(|&i| paths[i].clone()).collect();
+    let hashes = hash_files(&dir, &existing_paths, args.threads);
+
+    let mut out = BufWriter::new(stdout().lock());
+    let mut n_problems = missing.len();
+    for &i in missing.iter() {
+        writeln!(out, "{}\tMISSING", paths[i])?;
+    }
+    for (&i, hash) in existing.iter().zip(hashes) {
+        match hash {
+            Err(e) => {
+                n_problems += 1;
+                writeln!(out, "{}\tUNREADABLE\t{:#}", paths[i], e)?;
+            }
+            Ok(h) if h != expected[i] => {
+                n_problems += 1;
+                writeln!(out, "{}\tFAILED", paths[i])?;
+            }
+            Ok(_) => {
+                if args.verbose {
+                    writeln!(out, "{}\tOK", paths[i])?;
+                }
+            }
+        }
+    }
+    // Files in the directory which are not in the manifest
+    if args.strict {
+        let filter = PathFilter::new(&args.filter.include, &args.filter.exclude)?;
+        let listed: HashSet<&str> = paths.iter().map(|p| p.as_str()).collect();
+        for file in find_files(&dir, &filter, Some(&args.manifest))? {
+            if !listed.contains(file.as_str()) {
+                n_problems += 1;
+                writeln!(out, "{}\tNEW", file)?;
+            }
+        }
+    }
+    out.flush()?;
+    if n_problems > 0 {
+        bail!(
+            "Found {} problems when verifying {} files",
+            n_problems,
+            paths.len()
+        );
+    }
+    info!("Verified {} files", paths.len());
+    Ok(())
+}
+
+const LONG_ABOUT: &str = "Create and verify SHA-256 manifests of data directories.
+The manifest lists every file in the directory with its checksum, in the format of sha256sum,
+so it can also be checked with `sha256sum -c`. Paths are relative to the directory.
+Run e.g. `manifest create -h` for help on each command.
+Usage: manifest create -t 8 project_dir -o project_dir/MANIFEST.sha256
+       manifest verify project_dir/MANIFEST.sha256";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compute checksums of the files in a directory
+    Create(CreateArgs),
+    /// Check the files in a directory against a manifest
+    Verify(VerifyArgs),
+}
+
+#[derive(Args)]
+struct FilterArgs {
+    /// Only include files matching this glob pattern (can be repeated) [all files]
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// Exclude files matching this glob pattern (can be repeated)
+    #[arg(long)]
+    exclude: Vec<String>,
+}
+
+#[derive(Args)]
+struct CreateArgs {
+    /// Directory to create manifest of
+    dir: PathBuf,
+
+    /// Output manifest file [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    /// Number of threads used for hashing
+    #[arg(short, long, default_value = "1")]
+    threads: NonZeroUsize,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    /// Manifest file
+    manifest: PathBuf,
+
+    /// Directory the paths in the manifest are relative to [directory of manifest]
+    #[arg(short, long)]
+    dir: Option<PathBuf>,
+
+    /// Also report files in the directory which are not in the manifest
+    #[arg(short, long)]
+    strict: bool,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    /// Also print files that are OK
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Number of threads used for hashing
+    #[arg(short, long, default_value = "1")]
+    threads: NonZeroUsize,
+}
@@ -0,0 +1,622 @@
+use crate::{kmers::Spill, Edge, Kmers, Skip};
+use anyhow::{bail, Context, Result};
+use bio::{alphabets::dna::revcomp, io::fasta};
+use clap::ValueEnum;
+use progress::Progress;
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    num::{NonZeroU16, NonZeroUsize},
+    path::Path,
+    thread,
+};
+
+/// A contig in the graph. The sequence is only kept if it is written in S lines.
+pub struct Contig {
+    pub id: String,
+    pub k: NonZeroU16,
+    pub length: usize,
+    /// The multi= field of MEGAHIT headers, which is the average kmer multiplicity
+    pub depth: Option<f32>,
+    pub seq: Option<Vec<u8>>,
+    /// The first and the last kmer, concatenated. Only kept to check the edges.
+    pub ends: Option<Vec<u8>>,
+}
+
+impl Contig {
+    /// The first n bases of the contig in the given orientation, with n at most k
+    pub fn start(&self, n: usize, rc: bool) -> Vec<u8> {
+        let ends = self.ends.as_ref().unwrap();
+        if rc {
+            revcomp(&ends[ends.len() - n..])
+        } else {
+            ends[..n].to_vec()
+        }
+    }
+
+    /// The last n bases of the contig in the given orientation, with n at most k
+    pub fn end(&self, n: usize, rc: bool) -> Vec<u8> {
+        let ends = self.ends.as_ref().unwrap();
+        if rc {
+            revcomp(&ends[..n])
+        } else {
+            ends[ends.len() - n..].to_vec()
+        }
+    }
+
+    /// The first kmer of the contig in the given orientation
+    pub fn start_kmer(&self, rc: bool) -> Vec<u8> {
+        self.start(self.k.get() as usize, rc)
+    }
+
+    /// The last kmer of the contig in the given orientation
+    pub fn end_kmer(&self, rc: bool) -> Vec<u8> {
+        self.end(self.k.get() as usize, rc)
+    }
+}
+
+fn check_identifier(id: &str) -> Result<()> {
+    if !is_acceptable_identifier(id.as_bytes()) {
+        bail!("Invalid record identifier: {}.\nIdentifier names are restricted by the GFA format to regex [!-)+-<>-~][!-~]*. Pass --rename-invalid to rename them.", id);
+    }
+    Ok(())
+}
+
+// Replace the characters not allowed in GFA identifiers by underscores
+fn sanitize_identifier(id: &str) -> String {
+    let sanitized: String = id
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if c.is_ascii_graphic() && !(i == 0 && (c == '*' || c == '=')) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if sanitized.is_empty() {
+        "_".to_owned()
+    } else {
+        sanitized
+    }
+}
+
+// Check the identifier. With --rename-invalid, an invalid identifier is instead sanitized,
+// and the old and new identifier are added to `renamed`.
+fn valid_identifier(
+    id: String,
+    rename_invalid: bool,
+    renamed: &mut Vec<(String, String)>,
+) -> Result<String> {
+    if !rename_invalid || is_acceptable_identifier(id.as_bytes()) {
+        check_identifier(&id)?;
+        return Ok(id);
+    }
+    let new = sanitize_identifier(&id);
+    renamed.push((id, new.clone()));
+    Ok(new)
+}
+
+/// According to the GFA specs, FASTA identifiers must conform to this pattern.
+/// Too bad if we have identifiers which don't - we must end the program.
+pub fn is_acceptable_identifier(s: &[u8]) -> bool {
+    s.split_first().is_some_and(|(first, rest)| {
+        ((b'!'..=b')').contains(first)
+            | (b'+'..=b'<').contains(first)
+            | (b'>'..=b'~').contains(first))
+            & rest
+                .iter()
+                .fold(true, |acc, b| acc & (b'!'..=b'~').contains(b))
+    })
+}
+
+#[cfg(test)]
+mod test_identifiers {
+    use crate::{contigs::sanitize_identifier, is_acceptable_identifier};
+
+    #[test]
+    fn test_sanitize_identifier() {
+        for (id, expected) in [
+            ("k141_1", "k141_1"),
+            ("*k141", "_k141"),
+            ("=a=b", "_a=b"),
+            ("contig\u{e9}1", "contig_1"),
+            ("a\x01b", "a_b"),
+            ("", "_"),
+        ] {
+            assert_eq!(sanitize_identifier(id), expected);
+            assert!(is_acceptable_identifier(expected.as_bytes()));
+        }
+    }
+}
+
+/// MEGAHIT identifiers begin with the k they were assembled with, e.g. k141_12
+pub fn k_from_identifier(id: &str) -> Option<NonZeroU16> {
+    let (k, _) = id.strip_prefix('k')?.split_once('_')?;
+    k.parse::<NonZeroU16>().ok()
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Assembler {
+    Megahit,
+    Spades,
+}
+
+/// MEGAHIT headers look like "k141_0 flag=1 multi=2.0000 len=312", and
+/// SPAdes headers like "NODE_1_length_312_cov_2.000000"
+pub fn depth_from_header(assembler: Assembler, id: &str, desc: Option<&str>) -> Option<f32> {
+    match assembler {
+        Assembler::Megahit => desc?
+            .split_ascii_whitespace()
+            .find_map(|field| field.strip_prefix("multi="))?
+            .parse::<f32>()
+            .ok(),
+        Assembler::Spades => id.rsplit_once("_cov_")?.1.parse::<f32>().ok(),
+    }
+}
+
+/// How the k of each contig is found
+#[derive(Clone, Copy)]
+pub enum KMode {
+    // Passed with -k
+    Fixed(NonZeroU16),
+    // From the identifiers, which must all have the same k
+    Detect,
+    // From the identifier of each contig. Contigs are only linked to contigs with the same k.
+    Multi,
+}
+
+/// How the contigs of a file are read
+pub struct ReadOptions<'a> {
+    pub k_mode: KMode,
+    pub assembler: Assembler,
+    pub min_contig_length: usize,
+    /// Only keep the length of each record, and build the contigs of the linked records
+    /// in a second pass with `read_linked_contigs`
+    pub low_memory: bool,
+    /// Rename invalid identifiers instead of failing
+    pub rename_invalid: bool,
+    /// Keep the identifiers of skipped records
+    pub keep_skipped: bool,
+    pub keep_seqs: bool,
+    /// Keep the first and last kmer of each contig
+    pub keep_ends: bool,
+    /// Prepended to the identifiers
+    pub prefix: &'a str,
+    /// Write the kmers to this directory, in partitions, instead of keeping them in memory
+    pub temp_dir: Option<&'a Path>,
+    pub n_partitions: NonZeroUsize,
+    /// Do not link kmers at the ends of more contigs than this
+    pub max_degree: Option<usize>,
+    /// Expand ambiguous kmers to at most this many kmers. 1 to not expand them.
+    pub max_expansions: usize,
+}
+
+/// The records read by `find_edges`: A contig for each record that is in the graph, or with
+/// --low-memory only the length of each record, which takes 8 bytes instead of a `Contig`.
+pub enum Records {
+    Contigs(Vec<Option<Contig>>),
+    Lengths(Vec<u64>),
+}
+
+impl Records {
+    pub fn new(low_memory: bool) -> Self {
+        if low_memory {
+            Records::Lengths(Vec::new())
+        } else {
+            Records::Contigs(Vec::new())
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Records::Contigs(contigs) => contigs.len(),
+            Records::Lengths(lengths) => lengths.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn extend(&mut self, other: Records) {
+        match (self, other) {
+            (Records::Contigs(contigs), Records::Contigs(other)) => contigs.extend(other),
+            (Records::Lengths(lengths), Records::Lengths(other)) => lengths.extend(other),
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn record_k(record: &fasta::Record, k_mode: KMode) -> Result<NonZeroU16> {
+    match k_mode {
+        KMode::Fixed(k) => Ok(k),
+        KMode::Detect | KMode::Multi => k_from_identifier(record.id()).with_context(|| {
+            format!(
+                "Could not get k from identifier \"{}\", expected e.g. k141_12. Pass k with -k",
+                record.id()
+            )
+        }),
+    }
+}
+
+fn make_contig(record: &fasta::Record, id: String, k: NonZeroU16, options: &ReadOptions) -> Contig {
+    let seq = record.seq();
+    Contig {
+        id,
+        k,
+        length: seq.len(),
+        depth: depth_from_header(options.assembler, record.id(), record.desc()),
+        seq: options.keep_seqs.then(|| seq.to_vec()),
+        ends: options.keep_ends.then(|| {
+            let k = (k.get() as usize).min(seq.len());
+            [&seq[..k], &seq[seq.len() - k..]].concat()
+        }),
+    }
+}
+
+const BATCH_SIZE: usize = 4096;
+
+// The contigs and kmers of a batch of records.
+// The kmers are grouped by k, which is only more than one group with --multi-k.
+struct Batch {
+    // Index of the first record
+    index: usize,
+    records: Records,
+    groups: HashMap<NonZeroU16, Kmers>,
+    // The old and new identifiers of renamed contigs
+    renamed: Vec<(String, String)>,
+    // The identifiers of the records that are not in the graph, and why
+    skipped: Vec<(String, Skip)>,
+}
+
+fn read_batch(records: &[fasta::Record], index: usize, options: &ReadOptions) -> Result<Batch> {
+    let mut groups: HashMap<NonZeroU16, Kmers> = HashMap::new();
+    // None if the record is skipped due to being too short
+    let mut contigs: Vec<Option<Contig>> = Vec::new();
+    let mut lengths: Vec<u64> = Vec::new();
+    let mut renamed: Vec<(String, String)> = Vec::new();
+    let mut skipped: Vec<(String, Skip)> = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        let k = record_k(record, options.k_mode)?;
+        let kmers = groups
+            .entry(k)
+            .or_insert_with(|| Kmers::new(k, 2 * records.len()));
+        let seq = record.seq();
+        // Indices are unique across files
+        let added = if seq.len() < options.min_contig_length {
+            Err(Skip::TooShort)
+        } else {
+            kmers.add(seq, index + i, options.max_expansions)
+        };
+        if let Err(reason) = &added {
+            if options.keep_skipped {
+                skipped.push((format!("{}{}", options.prefix, record.id()), *reason));
+            }
+        }
+        if options.low_memory {
+            lengths.push(seq.len() as u64);
+        } else if added.is_err() {
+            contigs.push(None);
+        } else {
+            let id = format!("{}{}", options.prefix, record.id());
+            let id = valid_identifier(id, options.rename_invalid, &mut renamed)?;
+            contigs.push(Some(make_contig(record, id, k, options)));
+        }
+    }
+    Ok(Batch {
+        index,
+        records: if options.low_memory {
+            Records::Lengths(lengths)
+        } else {
+            Records::Contigs(contigs)
+        },
+        groups,
+        renamed,
+        skipped,
+    })
+}
+
+/// Find the edges between the contigs of one file, and add the records to `records`,
+/// the old and new identifiers of renamed contigs to `renamed`, and the skipped records
+/// to `skipped`.
+pub fn find_edges(
+    input: impl BufRead,
+    options: &ReadOptions,
+    threads: NonZeroUsize,
+    records: &mut Records,
+    renamed: &mut Vec<(String, String)>,
+    skipped: &mut Vec<(String, Skip)>,
+) -> Result<Vec<Edge>> {
+    // Approach: We store the starting kmers (forward and reverse-complement)
+    // in a HashMap, with keys being kmers and values being KmerOrigin to show
+    // where the kmer is from.
+    // We can then look up in the hash map to match KmerOrigins with shared kmers
+    // and create edges between them.
+    // The records are parsed in this thread, and sent in batches to the worker threads,
+    // which encode the kmers. The batches may finish out of order, so they are sorted after.
+    // With --temp-dir, the kmers of each batch are written to disk as soon as it is finished.
+    let first_index = records.len();
+    let mut batches: Vec<Batch> = Vec::new();
+    let mut spill = match options.temp_dir {
+        Some(dir) => Some(Spill::new(dir, options.n_partitions)?),
+        None => None,
+    };
+    let mut first_k: Option<NonZeroU16> = None;
+    let progress = Progress::new("Reading contigs", "contigs", None);
+    let mut collect = |result: Result<Batch>| -> Result<()> {
+        let mut batch = result?;
+        progress.inc(batch.records.len() as u64);
+        if let KMode::Detect = options.k_mode {
+            for &k in batch.groups.keys() {
+                let first_k = *first_k.get_or_insert(k);
+                if k != first_k {
+                    bail!(
+                        "Identifiers have both k = {} and k = {}. Use --multi-k to link contigs with different k",
+                        first_k,
+                        k
+                    );
+                }
+            }
+        }
+        if let Some(spill) = spill.as_mut() {
+            for (_, kmers) in batch.groups.drain() {
+                spill.write(&kmers)?;
+            }
+        }
+        batches.push(batch);
+        Ok(())
+    };
+    thread::scope(|s| -> Result<()> {
+        // The channels are created inside the scope, so they are dropped if we return early
+        // with an error, which makes the other threads exit.
+        let (batch_sender, batch_receiver) =
+            crossbeam_channel::bounded::<(usize, Vec<fasta::Record>)>(2 * threads.get());
+        let (result_sender, result_receiver) = crossbeam_channel::unbounded();
+        for _ in 0..threads.get() {
+            let (receiver, sender) = (batch_receiver.clone(), result_sender.clone());
+            s.spawn(move || {
+                for (index, records) in receiver.iter() {
+                    let result = read_batch(&records, index, options);
+                    if sender.send(result).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(result_sender);
+
+        let mut batch: Vec<fasta::Record> = Vec::with_capacity(BATCH_SIZE);
+        let mut index = first_index;
+        for record in fasta::Reader::new(input).records() {
+            batch.push(record.context("Failed to parse record from FASTA file")?);
+            if batch.len() == BATCH_SIZE {
+                index += batch.len();
+                let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                batch_sender.send((index - full.len(), full))?;
+                // Collect the finished batches, to fail early on errors
+                for result in result_receiver.try_iter() {
+                    collect(result)?;
+                }
+            }
+        }
+        if !batch.is_empty() {
+            batch_sender.send((index, batch))?;
+        }
+        drop(batch_sender);
+        for result in result_receiver.iter() {
+            collect(result)?;
+        }
+        Ok(())
+    })?;
+    progress.finish();
+    if let Some(spill) = spill {
+        batches.sort_unstable_by_key(|batch| batch.index);
+        for batch in batches {
+            records.extend(batch.records);
+            renamed.extend(batch.renamed);
+            skipped.extend(batch.skipped);
+        }
+        return spill.edges(threads, options.max_degree);
+    }
+
+    batches.sort_unstable_by_key(|batch| batch.index);
+    let mut groups: HashMap<NonZeroU16, Kmers> = HashMap::new();
+    for batch in batches {
+        records.extend(batch.records);
+        renamed.extend(batch.renamed);
+        skipped.extend(batch.skipped);
+        for (k, kmers) in batch.groups {
+            groups
+                .entry(k)
+                .or_insert_with(|| Kmers::new(k, 0))
+                .extend(&kmers);
+        }
+    }
+    Ok(groups
+        .values()
+        .flat_map(|kmers| kmers.edges(threads, options.max_degree))
+        .collect())
+}
+
+/// With --low-memory, read the records of a file again, and add a contig for each record that
+/// is linked, and None for the others. `lengths` are the lengths of the records found by
+/// `find_edges`, to check that the file did not change.
+pub fn read_linked_contigs(
+    input: impl BufRead,
+    options: &ReadOptions,
+    lengths: &[u64],
+    is_linked: &[bool],
+    contigs: &mut Vec<Option<Contig>>,
+    renamed: &mut Vec<(String, String)>,
+) -> Result<()> {
+    let progress = Progress::new(
+        "Reading linked contigs",
+        "contigs",
+        Some(lengths.len() as u64),
+    );
+    let mut n_records = 0usize;
+    let expected = lengths.iter().zip(is_linked);
+    for (record, (&length, &linked)) in fasta::Reader::new(input).records().zip(expected) {
+        let record = record.context("Failed to parse record from FASTA file")?;
+        progress.inc(1);
+        n_records += 1;
+        let id = format!("{}{}", options.prefix, record.id());
+        if record.seq().len() as u64 != length {
+            bail!("Input file changed between reads, at identifier \"{}\"", id);
+        }
+        if !linked {
+            contigs.push(None);
+            continue;
+        }
+        let k = record_k(&record, options.k_mode)?;
+        let id = valid_identifier(id, options.rename_invalid, renamed)?;
+        contigs.push(Some(make_contig(&record, id, k, options)));
+    }
+    progress.finish();
+    if n_records != lengths.len() {
+        bail!("Input file changed between reads, and now has fewer records");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_low_memory {
+    use crate::{find_edges, read_linked_contigs, Assembler, Edge, KMode, ReadOptions, Records};
+    use std::num::{NonZeroU16, NonZeroUsize};
+
+    #[test]
+    fn test_read_linked_contigs() {
+        // k4_0 ends with the kmer k4_1 starts with, and k4_2 is not linked
+        let fasta = ">k4_0 multi=2.0\nACCTGAGT\n>k4_1 multi=3.0\nGAGTTCAA\n>k4_2\nCCCAGGAT\n";
+        let options = |low_memory| ReadOptions {
+            k_mode: KMode::Fixed(NonZeroU16::new(4).unwrap()),
+            assembler: Assembler::Megahit,
+            min_contig_length: 0,
+            low_memory,
+            rename_invalid: false,
+            keep_skipped: false,
+            keep_seqs: false,
+            keep_ends: true,
+            prefix: "S1C",
+            temp_dir: None,
+            n_partitions: NonZeroUsize::MIN,
+            max_degree: None,
+            max_expansions: 1,
+        };
+        let read = |low_memory| {
+            let mut records = Records::new(low_memory);
+            let edges = find_edges(
+                fasta.as_bytes(),
+                &options(low_memory),
+                NonZeroUsize::MIN,
+                &mut records,
+                &mut Vec::new(),
+                &mut Vec::new(),
+            )
+            .unwrap();
+            (records, edges)
+        };
+        let (Records::Contigs(contigs), edges) = read(false) else {
+            panic!("Expected contigs");
+        };
+        let (Records::Lengths(lengths), low_memory_edges) = read(true) else {
+            panic!("Expected lengths");
+        };
+        assert_eq!(lengths, [8, 8, 8]);
+        // Identical edges are removed later, which also sorts them
+        let keys = |edges: Vec<Edge>| {
+            let mut keys: Vec<_> = edges.iter().map(|e| e.key()).collect();
+            keys.sort_unstable();
+            keys.dedup();
+            keys
+        };
+        let keys_in_memory = keys(edges);
+        assert_eq!(keys_in_memory.len(), 2);
+        assert_eq!(keys_in_memory, keys(low_memory_edges));
+
+        let is_linked = [true, true, false];
+        let mut linked = Vec::new();
+        read_linked_contigs(
+            fasta.as_bytes(),
+            &options(true),
+            &lengths,
+            &is_linked,
+            &mut linked,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(linked.len(), 3);
+        assert!(linked[2].is_none());
+        for (a, b) in contigs.iter().zip(&linked).take(2) {
+            let (a, b) = (a.as_ref().unwrap(), b.as_ref().unwrap());
+            assert_eq!(a.id, b.id);
+            assert_eq!(a.length, b.length);
+            assert_eq!(a.depth, b.depth);
+            assert_eq!(a.ends, b.ends);
+        }
+        assert_eq!(linked[1].as_ref().unwrap().id, "S1Ck4_1");
+
+        // The file changed between the reads
+        let changed = ">k4_0\nACCTGAGT\n>k4_1\nGAGTTCA\n>k4_2\nCCCAGGAT\n";
+        let result = read_linked_contigs(
+            changed.as_bytes(),
+            &options(true),
+            &lengths,
+            &is_linked,
+            &mut Vec::new(),
+            &mut Vec::new(),
+        );
+        assert!(result.is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_verify {
+    use crate::{depth_from_header, k_from_identifier, Assembler, Contig};
+    use std::num::NonZeroU16;
+
+    #[test]
+    fn test_contig_kmers() {
+        // Sequence AACGTTTTGCA with k = 4
+        let contig = Contig {
+            id: "k4_1".to_owned(),
+            k: NonZeroU16::new(4).unwrap(),
+            length: 11,
+            depth: None,
+            seq: None,
+            ends: Some(b"AACGTGCA".to_vec()),
+        };
+        assert_eq!(contig.start_kmer(false), b"AACG");
+        assert_eq!(contig.start_kmer(true), b"TGCA");
+        assert_eq!(contig.end_kmer(false), b"TGCA");
+        assert_eq!(contig.end_kmer(true), b"CGTT");
+        assert_eq!(contig.start(2, true), b"TG");
+        assert_eq!(contig.end(3, false), b"GCA");
+    }
+
+    #[test]
+    fn test_k_from_identifier() {
+        assert_eq!(k_from_identifier("k141_12"), NonZeroU16::new(141));
+        assert_eq!(k_from_identifier("k99_0"), NonZeroU16::new(99));
+        assert_eq!(k_from_identifier("k0_1"), None);
+        assert_eq!(k_from_identifier("k141"), None);
+        assert_eq!(k_from_identifier("contig_1"), None);
+    }
+
+    #[test]
+    fn test_depth_from_header() {
+        let depth = |id, desc| depth_from_header(Assembler::Megahit, id, desc);
+        assert_eq!(
+            depth("k141_0", Some("flag=1 multi=2.5000 len=312")),
+            Some(2.5)
+        );
+        assert_eq!(depth("k141_0", Some("flag=1 len=312")), None);
+        assert_eq!(depth("k141_0", Some("multi=x")), None);
+        assert_eq!(depth("k141_0", None), None);
+        let depth = |id| depth_from_header(Assembler::Spades, id, None);
+        assert_eq!(depth("NODE_1_length_312_cov_2.500000"), Some(2.5));
+        assert_eq!(depth("NODE_1_length_312"), None);
+    }
+}
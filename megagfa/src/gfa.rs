@@ -0,0 +1,447 @@
+use crate::{Contig, Edge};
+use anyhow::Result;
+use clap::ValueEnum;
+use std::io::Write;
+
+/// Which S (segment) lines to write
+#[derive(Clone, Copy, PartialEq)]
+pub enum Segments {
+    Omit,
+    // S lines without sequence, but with a LN tag
+    Lengths,
+    Sequences,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    Gfa,
+    // Node-link JSON, as read by networkx
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum GfaVersion {
+    // GFA 1.2, with L lines
+    #[value(name = "1")]
+    V1,
+    // GFA 2.0, with E lines
+    #[value(name = "2")]
+    V2,
+}
+
+pub fn rc_byte(rc: bool) -> &'static [u8] {
+    if rc {
+        b"-"
+    } else {
+        b"+"
+    }
+}
+
+// A GFA2 position on a segment of the given length. The end of a segment is marked with a $.
+fn gfa2_position(pos: usize, length: usize) -> String {
+    if pos == length {
+        format!("{}$", pos)
+    } else {
+        pos.to_string()
+    }
+}
+
+// The interval on the forward strand of the contig spanned by the shared kmer,
+// which is either its first or its last kmer.
+fn kmer_interval(contig: &Contig, at_end: bool) -> (String, String) {
+    let k = contig.k.get() as usize;
+    let begin = if at_end { contig.length - k } else { 0 };
+    (
+        gfa2_position(begin, contig.length),
+        gfa2_position(begin + k, contig.length),
+    )
+}
+
+// The empty interval at the start or end of the contig
+fn end_interval(contig: &Contig, at_end: bool) -> (String, String) {
+    let pos = if at_end { contig.length } else { 0 };
+    (
+        gfa2_position(pos, contig.length),
+        gfa2_position(pos, contig.length),
+    )
+}
+
+#[cfg(test)]
+mod test_gfa2 {
+    use crate::{gfa::kmer_interval, Contig};
+    use std::num::NonZeroU16;
+
+    #[test]
+    fn test_kmer_interval() {
+        let mut contig = Contig {
+            id: "k5_1".to_owned(),
+            k: NonZeroU16::new(5).unwrap(),
+            length: 12,
+            depth: None,
+            seq: None,
+            ends: None,
+        };
+        let s = |(a, b): (String, String)| format!("{} {}", a, b);
+        assert_eq!(s(kmer_interval(&contig, false)), "0 5");
+        assert_eq!(s(kmer_interval(&contig, true)), "7 12$");
+        contig.k = NonZeroU16::new(12).unwrap();
+        assert_eq!(s(kmer_interval(&contig, false)), "0 12$");
+    }
+}
+
+// Write a minimal GFA
+fn print_gfa<'a>(
+    out: &mut impl Write,
+    nodes: impl Iterator<Item = &'a Contig>,
+    contigs: &[Option<Contig>],
+    edges: &[Edge],
+    read_links: &[(Edge, u32)],
+    paths: &[Vec<(usize, bool)>],
+    writer: &GfaWriter,
+) -> Result<()> {
+    let GfaWriter {
+        segments,
+        gfa_version: version,
+        cigar_overlap,
+        length_tags,
+        ..
+    } = *writer;
+    // Write header - this is GFA version 1.2 or 2.0
+    match version {
+        GfaVersion::V1 => out.write_all(b"H\tVN:Z:1.2\n")?,
+        GfaVersion::V2 => out.write_all(b"H\tVN:Z:2.0\n")?,
+    }
+    if segments != Segments::Omit {
+        // Write S lines. GFA1: S, name, sequence or * if omitted, and the length as a tag.
+        // GFA2: S, name, length, and sequence or * if omitted.
+        for contig in nodes {
+            out.write_all(b"S\t")?;
+            out.write_all(contig.id.as_bytes())?;
+            out.write_all(b"\t")?;
+            if version == GfaVersion::V2 {
+                write!(out, "{}\t", contig.length)?;
+            }
+            out.write_all(contig.seq.as_deref().unwrap_or(b"*"))?;
+            if version == GfaVersion::V1 {
+                write!(out, "\tLN:i:{}", contig.length)?;
+            }
+            // The depth, and the total count of kmers in the contig, used by e.g. Bandage.
+            if let Some(depth) = contig.depth {
+                let n_kmers = (contig.length + 1).saturating_sub(contig.k.get() as usize);
+                write!(
+                    out,
+                    "\tDP:f:{}\tKC:i:{}",
+                    depth,
+                    (depth as f64 * n_kmers as f64).round() as u64
+                )?;
+            }
+            out.write_all(b"\n")?;
+        }
+    }
+    // The links from shared kmers, then the links from read pairs with their number of pairs
+    let links = edges.iter().map(|edge| (edge, None)).chain(
+        read_links
+            .iter()
+            .map(|(edge, n_reads)| (edge, Some(*n_reads))),
+    );
+    for (edge, n_reads) in links {
+        let from = contigs[edge.from_end.index()].as_ref().unwrap();
+        let to = contigs[edge.to_start.index()].as_ref().unwrap();
+        // Contigs linked by kmers always overlap by exactly one kmer. The overlap of
+        // contigs linked by read pairs is not known, so they are written as not overlapping.
+        let overlap = match (cigar_overlap, n_reads) {
+            (false, _) => "*".to_owned(),
+            (true, None) => format!("{}M", from.k),
+            (true, Some(_)) => "0M".to_owned(),
+        };
+        match version {
+            GfaVersion::V1 => {
+                // Write L lines: L
+                out.write_all(b"L\t")?;
+                // Name of sequende with end kmer (from)
+                out.write_all(from.id.as_bytes())?;
+                out.write_all(b"\t")?;
+                // Whether the from sequence is forward or reverse
+                out.write_all(rc_byte(edge.from_end.is_rc()))?;
+                out.write_all(b"\t")?;
+                // Same for the to edge
+                out.write_all(to.id.as_bytes())?;
+                out.write_all(b"\t")?;
+                out.write_all(rc_byte(edge.to_start.is_rc()))?;
+                // A star for the missing overlap (which carries no information, the user should know
+                // it's always just one kmer's overlap), unless a CIGAR is requested
+                write!(out, "\t{}", overlap)?;
+            }
+            GfaVersion::V2 => {
+                // Write E lines: E, no edge ID, the two segments with orientation, then
+                // the overlapping intervals of the two segments, which must be explicit in GFA2.
+                // The end kmer of a reverse-complemented contig is the start of its forward strand.
+                // Contigs linked by read pairs overlap by an empty interval at their ends.
+                let ((from_begin, from_end), (to_begin, to_end)) = if n_reads.is_none() {
+                    (
+                        kmer_interval(from, !edge.from_end.is_rc()),
+                        kmer_interval(to, edge.to_start.is_rc()),
+                    )
+                } else {
+                    (
+                        end_interval(from, !edge.from_end.is_rc()),
+                        end_interval(to, edge.to_start.is_rc()),
+                    )
+                };
+                out.write_all(b"E\t*\t")?;
+                out.write_all(from.id.as_bytes())?;
+                out.write_all(rc_byte(edge.from_end.is_rc()))?;
+                out.write_all(b"\t")?;
+                out.write_all(to.id.as_bytes())?;
+                out.write_all(rc_byte(edge.to_start.is_rc()))?;
+                write!(
+                    out,
+                    "\t{}\t{}\t{}\t{}\t{}",
+                    from_begin, from_end, to_begin, to_end, overlap
+                )?;
+            }
+        }
+        // The lengths of the two contigs, for tools that weight links by them
+        if length_tags {
+            write!(out, "\tL1:i:{}\tL2:i:{}", from.length, to.length)?;
+        }
+        // The number of read pairs supporting the link
+        if let Some(n_reads) = n_reads {
+            write!(out, "\tRC:i:{}", n_reads)?;
+        }
+        out.write_all(b"\n")?;
+    }
+    for (path_index, path) in paths.iter().enumerate() {
+        // Write P lines: P, name, the comma-separated contigs with orientation, then the
+        // overlaps between them. In GFA2, these are O (ordered group) lines without overlaps.
+        match version {
+            GfaVersion::V1 => write!(out, "P\tpath{}\t", path_index + 1)?,
+            GfaVersion::V2 => write!(out, "O\tpath{}\t", path_index + 1)?,
+        }
+        for (i, &(index, is_rc)) in path.iter().enumerate() {
+            if i > 0 {
+                match version {
+                    GfaVersion::V1 => out.write_all(b",")?,
+                    GfaVersion::V2 => out.write_all(b" ")?,
+                }
+            }
+            out.write_all(contigs[index].as_ref().unwrap().id.as_bytes())?;
+            out.write_all(rc_byte(is_rc))?;
+        }
+        if version == GfaVersion::V1 {
+            out.write_all(b"\t")?;
+            if cigar_overlap {
+                let overlaps: Vec<String> = path[1..]
+                    .iter()
+                    .map(|&(index, _)| format!("{}M", contigs[index].as_ref().unwrap().k))
+                    .collect();
+                out.write_all(overlaps.join(",").as_bytes())?;
+            } else {
+                out.write_all(b"*")?;
+            }
+        }
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Writes a graph of contigs and the edges between them, as GFA or as JSON
+#[derive(Clone, Copy)]
+pub struct GfaWriter {
+    pub format: Format,
+    pub segments: Segments,
+    pub gfa_version: GfaVersion,
+    /// Write the overlap of links as a CIGAR string of k matches instead of *
+    pub cigar_overlap: bool,
+    /// Write the lengths of the two contigs of each link in L1 and L2 tags. Not used for JSON,
+    /// where the lengths are attributes of the nodes.
+    pub length_tags: bool,
+}
+
+impl GfaWriter {
+    /// Write the graph, with S lines (or JSON nodes) for the given contigs.
+    /// The edges, read links and paths refer to the contigs by their index in `contigs`.
+    /// The read links are written after the edges, with their number of read pairs in
+    /// an RC tag, and the paths are written as P lines (O lines in GFA2).
+    pub fn write<'a>(
+        &self,
+        out: &mut impl Write,
+        nodes: impl Iterator<Item = &'a Contig>,
+        contigs: &[Option<Contig>],
+        edges: &[Edge],
+        read_links: &[(Edge, u32)],
+        paths: &[Vec<(usize, bool)>],
+    ) -> Result<()> {
+        match self.format {
+            Format::Gfa => print_gfa(out, nodes, contigs, edges, read_links, paths, self),
+            Format::Json => {
+                print_json(out, nodes, contigs, edges, read_links, paths, self.segments)
+            }
+        }
+    }
+}
+
+// A JSON string, with quotes and the characters escaped that must be
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// Write the graph as JSON in the node-link format of networkx, which can be read with
+// networkx.node_link_graph(data, edges="links"), or converted to Cytoscape.js elements.
+// All contigs are nodes, even without --with-segments, since the links refer to them.
+// The graph is a directed multigraph, as a pair of contigs can be linked in several
+// orientations, which are attributes of the links.
+fn print_json<'a>(
+    out: &mut impl Write,
+    nodes: impl Iterator<Item = &'a Contig>,
+    contigs: &[Option<Contig>],
+    edges: &[Edge],
+    read_links: &[(Edge, u32)],
+    paths: &[Vec<(usize, bool)>],
+    segments: Segments,
+) -> Result<()> {
+    out.write_all(b"{\"directed\": true, \"multigraph\": true, \"graph\": {")?;
+    if !paths.is_empty() {
+        // Each path as a list of contigs with orientation, like "k141_7+"
+        out.write_all(b"\"paths\": [")?;
+        for (path_index, path) in paths.iter().enumerate() {
+            if path_index > 0 {
+                out.write_all(b", ")?;
+            }
+            let members: Vec<String> = path
+                .iter()
+                .map(|&(index, is_rc)| {
+                    let mut member = contigs[index].as_ref().unwrap().id.clone();
+                    member.push(if is_rc { '-' } else { '+' });
+                    json_string(&member)
+                })
+                .collect();
+            write!(out, "[{}]", members.join(", "))?;
+        }
+        out.write_all(b"]")?;
+    }
+    out.write_all(b"},\n\"nodes\": [")?;
+    for (i, contig) in nodes.enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write!(
+            out,
+            "\n{{\"id\": {}, \"length\": {}",
+            json_string(&contig.id),
+            contig.length
+        )?;
+        // JSON has no NaN or infinity
+        if let Some(depth) = contig.depth.filter(|d| d.is_finite()) {
+            write!(out, ", \"depth\": {}", depth)?;
+        }
+        if segments == Segments::Sequences {
+            out.write_all(b", \"sequence\": \"")?;
+            out.write_all(contig.seq.as_deref().unwrap())?;
+            out.write_all(b"\"")?;
+        }
+        out.write_all(b"}")?;
+    }
+    out.write_all(b"\n],\n\"links\": [")?;
+    // Links from read pairs do not overlap, and have the number of read pairs as "reads"
+    let links = edges.iter().map(|edge| (edge, None)).chain(
+        read_links
+            .iter()
+            .map(|(edge, n_reads)| (edge, Some(*n_reads))),
+    );
+    for (i, (edge, n_reads)) in links.enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        let from = contigs[edge.from_end.index()].as_ref().unwrap();
+        let to = contigs[edge.to_start.index()].as_ref().unwrap();
+        write!(
+            out,
+            "\n{{\"source\": {}, \"target\": {}, \"source_orientation\": \"{}\", \"target_orientation\": \"{}\", ",
+            json_string(&from.id),
+            json_string(&to.id),
+            String::from_utf8_lossy(rc_byte(edge.from_end.is_rc())),
+            String::from_utf8_lossy(rc_byte(edge.to_start.is_rc())),
+        )?;
+        match n_reads {
+            None => write!(out, "\"overlap\": {}}}", from.k)?,
+            Some(n_reads) => write!(out, "\"overlap\": 0, \"reads\": {}}}", n_reads)?,
+        }
+    }
+    out.write_all(b"\n]}\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_json {
+    use crate::gfa::{json_string, print_json};
+    use crate::{Contig, Edge, KmerOrigin, Segments};
+    use std::num::NonZeroU16;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("k141_1"), "\"k141_1\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("a\tb"), "\"a\\u0009b\"");
+    }
+
+    #[test]
+    fn test_print_json() {
+        let contig = |id: &str, depth| {
+            Some(Contig {
+                id: id.to_owned(),
+                k: NonZeroU16::new(5).unwrap(),
+                length: 12,
+                depth,
+                seq: None,
+                ends: None,
+            })
+        };
+        let contigs = vec![contig("k5_1", Some(2.5)), None, contig("k5_3", None)];
+        let edges = vec![Edge {
+            from_end: KmerOrigin(0),
+            to_start: KmerOrigin(2 | 0x80000000),
+        }];
+        let mut out = Vec::new();
+        print_json(
+            &mut out,
+            contigs.iter().flatten(),
+            &contigs,
+            &edges,
+            &[(
+                Edge {
+                    from_end: KmerOrigin(2),
+                    to_start: KmerOrigin(0),
+                },
+                3,
+            )],
+            &[vec![(0, false), (2, true)]],
+            Segments::Omit,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"directed\": true, \"multigraph\": true, \"graph\": {\"paths\": [[\"k5_1+\", \"k5_3-\"]]},
+\"nodes\": [
+{\"id\": \"k5_1\", \"length\": 12, \"depth\": 2.5},
+{\"id\": \"k5_3\", \"length\": 12}
+],
+\"links\": [
+{\"source\": \"k5_1\", \"target\": \"k5_3\", \"source_orientation\": \"+\", \"target_orientation\": \"-\", \"overlap\": 5},
+{\"source\": \"k5_3\", \"target\": \"k5_1\", \"source_orientation\": \"+\", \"target_orientation\": \"+\", \"overlap\": 0, \"reads\": 3}
+]}
+"
+        );
+    }
+}
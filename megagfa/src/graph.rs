@@ -0,0 +1,316 @@
+use crate::{kmers::iupac_bases, rc_byte, Contig, Edge, KmerOrigin};
+use anyhow::{bail, Result};
+use progress::{info, warn};
+
+/// Maximal chains of at least two contigs, where each link is the only link out of the first
+/// contig and the only link into the second, in their orientations, so the contigs could be merged.
+/// The contigs are given as index and whether they are reverse-complemented.
+/// Each chain is found once, and not also as its reverse complement. Circular chains are skipped.
+pub fn find_paths(n_contigs: usize, edges: &[Edge]) -> Vec<Vec<(usize, bool)>> {
+    // The nodes of the graph are the contigs in either orientation
+    let node = |origin: KmerOrigin| 2 * origin.index() + origin.is_rc() as usize;
+    let mut next: Vec<Option<usize>> = vec![None; 2 * n_contigs];
+    let mut n_out = vec![0u32; 2 * n_contigs];
+    let mut n_in = vec![0u32; 2 * n_contigs];
+    for edge in edges.iter() {
+        let (from, to) = (node(edge.from_end), node(edge.to_start));
+        n_out[from] += 1;
+        n_in[to] += 1;
+        next[from] = Some(to);
+    }
+    // The next node, if the link to it can be part of a chain
+    let chain_next = |u: usize| -> Option<usize> {
+        let v = next[u]?;
+        (n_out[u] == 1 && n_in[v] == 1 && u / 2 != v / 2).then_some(v)
+    };
+    let mut is_chain_start = vec![true; 2 * n_contigs];
+    for u in 0..2 * n_contigs {
+        if let Some(v) = chain_next(u) {
+            is_chain_start[v] = false;
+        }
+    }
+    let mut is_used = vec![false; n_contigs];
+    let mut paths: Vec<Vec<(usize, bool)>> = Vec::new();
+    for start in 0..2 * n_contigs {
+        if !is_chain_start[start] || is_used[start / 2] {
+            continue;
+        }
+        let mut path = vec![start];
+        let mut u = start;
+        // Stop if a contig is visited twice, e.g. A+ -> B+ -> A-
+        while let Some(v) = chain_next(u).filter(|&v| !path.iter().any(|&w| w / 2 == v / 2)) {
+            path.push(v);
+            u = v;
+        }
+        if path.len() > 1 {
+            for &w in path.iter() {
+                is_used[w / 2] = true;
+            }
+            paths.push(path.iter().map(|&w| (w / 2, w % 2 == 1)).collect());
+        }
+    }
+    paths
+}
+
+#[cfg(test)]
+mod test_paths {
+    use crate::{find_paths, kmers::KmerOriginPair, Edge};
+
+    fn edge(from: usize, from_rc: bool, to: usize, to_rc: bool) -> Edge {
+        let pick = |i: usize, rc: bool| {
+            let pair = KmerOriginPair::try_new(i).unwrap();
+            if rc {
+                pair.rc
+            } else {
+                pair.fw
+            }
+        };
+        Edge {
+            from_end: pick(from, from_rc),
+            to_start: pick(to, to_rc),
+        }
+    }
+
+    #[test]
+    fn test_find_paths() {
+        // 0+ -> 1- -> 2+, and 2+ -> 3+ and 2+ -> 4+ branches, with the reverse complements
+        let mut edges = Vec::new();
+        for (a, ar, b, br) in [
+            (0, false, 1, true),
+            (1, true, 2, false),
+            (2, false, 3, false),
+            (2, false, 4, false),
+        ] {
+            edges.push(edge(a, ar, b, br));
+            edges.push(edge(b, !br, a, !ar));
+        }
+        assert_eq!(
+            find_paths(5, &edges),
+            vec![vec![(0, false), (1, true), (2, false)]]
+        );
+        // A circular chain 0+ -> 1+ -> 0+ is skipped
+        let circle = vec![edge(0, false, 1, false), edge(1, false, 0, false)];
+        assert!(find_paths(2, &circle).is_empty());
+    }
+}
+
+/// Check that the last kmer of the from contig is the first kmer of the to contig of every edge,
+/// in the orientations of the edge. This catches bugs in the kmer encoding and orientations.
+pub fn verify_edges(contigs: &[Option<Contig>], edges: &[Edge]) -> Result<()> {
+    let mut n_mismatches = 0usize;
+    for edge in edges.iter() {
+        let from = contigs[edge.from_end.index()].as_ref().unwrap();
+        let to = contigs[edge.to_start.index()].as_ref().unwrap();
+        let end = from.end_kmer(edge.from_end.is_rc());
+        let start = to.start_kmer(edge.to_start.is_rc());
+        // Ambiguous bases match the bases they stand for, as they are expanded by --expand-ambiguous
+        let is_match = end.iter().zip(start.iter()).all(|(&a, &b)| {
+            a.eq_ignore_ascii_case(&b)
+                || iupac_bases(a)
+                    .zip(iupac_bases(b))
+                    .is_some_and(|(a, b)| a.iter().any(|x| b.contains(x)))
+        });
+        if !is_match {
+            n_mismatches += 1;
+            warn!(
+                "Edge {}{} -> {}{} does not match: {} vs {}",
+                from.id,
+                String::from_utf8_lossy(rc_byte(edge.from_end.is_rc())),
+                to.id,
+                String::from_utf8_lossy(rc_byte(edge.to_start.is_rc())),
+                String::from_utf8_lossy(&end),
+                String::from_utf8_lossy(&start),
+            );
+        }
+    }
+    if n_mismatches > 0 {
+        bail!("{} of {} edges do not match", n_mismatches, edges.len());
+    }
+    info!("Verified all {} edges", edges.len());
+    Ok(())
+}
+
+/// The complexity of the kmer shared by the linked contigs, see `kmer_complexity`.
+/// The contigs overlap by exactly this kmer, so no other bases of them can be compared.
+pub fn overlap_complexity(contigs: &[Option<Contig>], edge: &Edge) -> f64 {
+    let from = contigs[edge.from_end.index()].as_ref().unwrap();
+    kmer_complexity(&from.end_kmer(edge.from_end.is_rc()))
+}
+
+/// The number of distinct trinucleotides in the kmer, divided by the number it could have,
+/// which is the number of trinucleotides in the kmer, but at most 64.
+/// A homopolymer has a complexity near 0, while random kmers have about 0.9.
+/// Trinucleotides with other bases than ACGT are not counted.
+pub fn kmer_complexity(kmer: &[u8]) -> f64 {
+    if kmer.len() < 3 {
+        return 1.0;
+    }
+    let code = |b: u8| match b.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    };
+    let mut seen = 0u64;
+    for w in kmer.windows(3) {
+        if let (Some(a), Some(b), Some(c)) = (code(w[0]), code(w[1]), code(w[2])) {
+            seen |= 1 << (16 * a + 4 * b + c);
+        }
+    }
+    seen.count_ones() as f64 / (kmer.len() - 2).min(64) as f64
+}
+
+/// The connected component of each contig, ignoring the orientation of the links.
+/// Components are numbered from 0 by decreasing number of contigs, and then by their first
+/// contig, so the numbering does not depend on the order of the links.
+pub fn find_components(contigs: &[Option<Contig>], edges: &[Edge]) -> Vec<Option<usize>> {
+    // Union-find with path halving and union by size
+    let mut parent: Vec<usize> = (0..contigs.len()).collect();
+    let mut size = vec![1usize; contigs.len()];
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for edge in edges.iter() {
+        let a = root(&mut parent, edge.from_end.index());
+        let b = root(&mut parent, edge.to_start.index());
+        if a != b {
+            let (small, large) = if size[a] < size[b] { (a, b) } else { (b, a) };
+            parent[small] = large;
+            size[large] += size[small];
+        }
+    }
+    // Roots in order of the first contig of their component, then stably sorted by size
+    let mut roots: Vec<(usize, usize)> = Vec::new();
+    let mut is_seen = vec![false; contigs.len()];
+    for (i, contig) in contigs.iter().enumerate() {
+        let r = root(&mut parent, i);
+        if contig.is_some() && !is_seen[r] {
+            is_seen[r] = true;
+            roots.push((size[r], r));
+        }
+    }
+    roots.sort_by_key(|&(n, _)| std::cmp::Reverse(n));
+    let mut number = vec![usize::MAX; contigs.len()];
+    for (component, &(_, r)) in roots.iter().enumerate() {
+        number[r] = component;
+    }
+    contigs
+        .iter()
+        .enumerate()
+        .map(|(i, contig)| contig.is_some().then(|| number[root(&mut parent, i)]))
+        .collect()
+}
+
+#[cfg(test)]
+mod test_components {
+    use crate::{find_components, Contig, Edge, KmerOrigin};
+    use std::num::NonZeroU16;
+
+    #[test]
+    fn test_find_components() {
+        // Contig 2 is filtered out
+        let contigs: Vec<Option<Contig>> = (0..6)
+            .map(|i| {
+                (i != 2).then(|| Contig {
+                    id: format!("k5_{}", i),
+                    k: NonZeroU16::new(5).unwrap(),
+                    length: 10,
+                    depth: None,
+                    seq: None,
+                    ends: None,
+                })
+            })
+            .collect();
+        // 0 - 5, and 3 - 1 - 4 with links in both orientations
+        let edges: Vec<Edge> = [(0, 5), (3, 1 | 0x80000000), (1, 4), (4 | 0x80000000, 1)]
+            .into_iter()
+            .map(|(a, b)| Edge {
+                from_end: KmerOrigin(a),
+                to_start: KmerOrigin(b),
+            })
+            .collect();
+        assert_eq!(
+            find_components(&contigs, &edges),
+            vec![Some(1), Some(0), None, Some(0), Some(0), Some(1)]
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_overlap {
+    use crate::{
+        find_edges, kmer_complexity, overlap_complexity, Assembler, KMode, ReadOptions, Records,
+    };
+    use std::num::{NonZeroU16, NonZeroUsize};
+
+    #[test]
+    fn test_kmer_complexity() {
+        assert_eq!(kmer_complexity(b"AAAAAAAAAAAAAAAAAAAAA"), 1.0 / 19.0);
+        assert_eq!(kmer_complexity(b"ATATATATATATATATATATA"), 2.0 / 19.0);
+        assert_eq!(kmer_complexity(b"acgtacgt"), 4.0 / 6.0);
+        assert_eq!(kmer_complexity(b"ACGNNTT"), 1.0 / 5.0);
+        assert_eq!(kmer_complexity(b"AC"), 1.0);
+        assert!(kmer_complexity(b"GGATCACAGTCTACACTGCTC") > 0.8);
+    }
+
+    #[test]
+    fn test_overlap_complexity() {
+        // k21_0 and k21_1 overlap by a random 21-mer, like neighbours in a MEGAHIT assembly,
+        // while k21_2 and k21_3 only share a homopolymer
+        let fasta = format!(
+            ">k21_0\nACTCCAACCCCGGCCCCTGGGATCACAGTCTACACTGCTC\n\
+             >k21_1\nGGATCACAGTCTACACTGCTCAGTCCGAGGAGAGGGTGCT\n\
+             >k21_2\nTCAGAGTATGTATACCACT{}\n>k21_3\n{}GGGTAGGATACGGCGGAGG\n",
+            "A".repeat(21),
+            "A".repeat(21)
+        );
+        let options = ReadOptions {
+            k_mode: KMode::Fixed(NonZeroU16::new(21).unwrap()),
+            assembler: Assembler::Megahit,
+            min_contig_length: 0,
+            low_memory: false,
+            rename_invalid: false,
+            keep_skipped: false,
+            keep_seqs: false,
+            keep_ends: true,
+            prefix: "",
+            temp_dir: None,
+            n_partitions: NonZeroUsize::MIN,
+            max_degree: None,
+            max_expansions: 1,
+        };
+        let mut records = Records::new(false);
+        let edges = find_edges(
+            fasta.as_bytes(),
+            &options,
+            NonZeroUsize::MIN,
+            &mut records,
+            &mut Vec::new(),
+            &mut Vec::new(),
+        )
+        .unwrap();
+        let Records::Contigs(contigs) = records else {
+            panic!("Expected contigs");
+        };
+        let linked = |edges: &[crate::Edge]| {
+            let mut linked: Vec<_> = edges
+                .iter()
+                .map(|e| (e.from_end.index(), e.to_start.index()))
+                .collect();
+            linked.sort_unstable();
+            linked.dedup();
+            linked
+        };
+        assert_eq!(linked(&edges), [(0, 1), (1, 0), (2, 3), (3, 2)]);
+        let kept: Vec<_> = edges
+            .into_iter()
+            .filter(|edge| overlap_complexity(&contigs, edge) >= 0.5)
+            .collect();
+        assert_eq!(linked(&kept), [(0, 1), (1, 0)]);
+    }
+}
@@ -0,0 +1,682 @@
+use anyhow::{Context, Result};
+use progress::Progress;
+use smallvec::SmallVec;
+use std::{
+    collections::HashMap,
+    fs::File,
+    hash::{BuildHasher, RandomState},
+    io::{BufWriter, Write},
+    num::{NonZeroU16, NonZeroUsize},
+    path::{Path, PathBuf},
+    thread,
+};
+
+/// Contains the index of the contig the kmer came from, and whether it's reverse-complement
+/// or not.
+/// The last bit of information needed to identify a kmer is whether it's the ending or the starting kmer,
+/// but this is stored implicitly as the end/start kmers are stored in two different
+/// data structures in this program.
+/// In `Kmers`, the second highest bit is also set if the kmer is stored as its reverse
+/// complement, see `Kmers::add`.
+/// The information is packed into 32 bits in order to save memory, and to make the data structures
+/// that store KmerOrigin smaller and therefore faster.
+#[derive(Clone, Copy, Debug)]
+pub struct KmerOrigin(pub(crate) u32);
+
+impl KmerOrigin {
+    pub fn is_rc(&self) -> bool {
+        self.0 > 0x7fffffff
+    }
+
+    pub fn index(&self) -> usize {
+        (self.0 & 0x3fffffff) as usize
+    }
+
+    pub fn reverse_complement(&self) -> Self {
+        Self(self.0 ^ 0x80000000)
+    }
+
+    // Whether the kmer is stored as its reverse complement
+    fn is_flipped(&self) -> bool {
+        self.0 & 0x40000000 != 0
+    }
+
+    fn flipped(&self) -> Self {
+        Self(self.0 | 0x40000000)
+    }
+
+    fn unflipped(&self) -> Self {
+        Self(self.0 & !0x40000000)
+    }
+}
+
+// Just a convenience struct so we can construct a fw and an rc KmerOrigin in one go
+pub(crate) struct KmerOriginPair {
+    pub(crate) fw: KmerOrigin,
+    pub(crate) rc: KmerOrigin,
+}
+
+impl KmerOriginPair {
+    pub(crate) fn try_new(index: usize) -> Result<Self> {
+        // It's unlikely we get more than 1 billion records in a file, but let's check it anyway
+        let x: u32 = index
+            .try_into()
+            .ok()
+            .and_then(|u| if u > 0x3fffffff { None } else { Some(u) })
+            .context("Can only hande 1073741823 FASTA records in total")?;
+        Ok(Self {
+            fw: KmerOrigin(x),
+            rc: KmerOrigin(x | 0x80000000),
+        })
+    }
+}
+
+/// From: The ending kmer. To: The starting kmer of the next contig.
+#[derive(Clone, Copy)]
+pub struct Edge {
+    pub from_end: KmerOrigin,
+    pub to_start: KmerOrigin,
+}
+
+impl Edge {
+    /// Identical edges have the same key
+    pub fn key(&self) -> (u32, u32) {
+        (self.from_end.0, self.to_start.0)
+    }
+
+    /// From a contig to itself, because it ends with the kmer it starts with (e.g. circular),
+    /// or because its first or last kmer is its own reverse complement (palindrome).
+    pub fn is_self_loop(&self) -> bool {
+        self.from_end.index() == self.to_start.index()
+    }
+
+    /// The same edge, from the reverse complement of the second contig to the reverse
+    /// complement of the first
+    pub fn reverse_complement(&self) -> Self {
+        Self {
+            from_end: self.to_start.reverse_complement(),
+            to_start: self.from_end.reverse_complement(),
+        }
+    }
+
+    /// A self-loop from a contig to its own reverse complement, due to a palindromic kmer
+    pub fn is_palindrome(&self) -> bool {
+        self.is_self_loop() && self.from_end.is_rc() != self.to_start.is_rc()
+    }
+}
+
+// We use this LUT (lookup table) to encode arbitrary DNA/RNA nucleotides into two bits.
+// This is to make the Kmer struct smaller - both for memory reasons, but also to
+// make hashing it faster.
+// This will mean contigs with ambiguous nucleotides in the start/ending kmers will be skipped,
+// but I'm not sure MEGAHIT can even process ambiguous kmers in its graph anyway, so no loss.
+// Other assemblers may write e.g. an N at the end, so with --expand-ambiguous, ambiguous
+// kmers are instead expanded to the unambiguous kmers they stand for.
+const fn make_lut() -> [u8; 256] {
+    let mut lut = [0xff; 256];
+    let mut i: u8 = 0;
+    while i < 127 {
+        i += 1;
+        lut[i as usize] = match i {
+            b'A' | b'a' => 0,
+            b'C' | b'c' => 1,
+            b'G' | b'g' => 2,
+            b'T' | b't' | b'U' | b'u' => 3,
+            _ => 0xff,
+        }
+    }
+    lut
+}
+
+const LUT: [u8; 256] = make_lut();
+
+// We encode the kmers in two bits, so this is identical to ceiling dividing by 4.
+fn encoding_size(k: NonZeroU16) -> NonZeroU16 {
+    // Safety: k is nonzero, so the ceiling division is nonzero
+    unsafe { NonZeroU16::new_unchecked(k.get().div_ceil(4)) }
+}
+
+type KmerRef<'a> = (&'a KmerOrigin, &'a [u8]);
+
+/// Dense representation of all observed kmers, packed into a single vector.
+pub struct Kmers {
+    mers: Vec<u8>, // The DNA kmers themselves, packed together. Length: k * data.len()
+    data: Vec<KmerOrigin>,
+    encoding_buffer: Vec<u8>, // length encoding_size(k). Ephemeral.
+    k: NonZeroU16,
+}
+
+impl Kmers {
+    /// Preallocate to avoid unnecessary reallocations.
+    pub fn new(k: NonZeroU16, assumed_kmers: usize) -> Self {
+        Self {
+            mers: Vec::with_capacity(encoding_size(k).get() as usize * assumed_kmers),
+            data: Vec::with_capacity(assumed_kmers),
+            encoding_buffer: vec![0; encoding_size(k).get() as usize],
+            k,
+        }
+    }
+
+    /// How to get the kmers and KmerOrigin out of this struct.
+    pub fn iter_kmers(&self) -> impl Iterator<Item = (&KmerOrigin, &[u8])> {
+        let chunk_size = encoding_size(self.k).get() as usize;
+        self.data.iter().zip(self.mers.chunks_exact(chunk_size))
+    }
+
+    /// Add the kmers of another Kmers with the same k
+    pub fn extend(&mut self, other: &Kmers) {
+        self.mers.extend_from_slice(&other.mers);
+        self.data.extend_from_slice(&other.data);
+    }
+
+    /// Now, for every end kmer, we see if there are any matching starting kmers, then
+    /// we create an edge from end kmer to start kmer.
+    /// Why not from start to end? Remember, if contig B follows contig A, then we
+    /// go from the last contig of A to the first contig of B.
+    /// The kmers are canonical, so a starting kmer and the reverse complement of a starting
+    /// kmer, i.e. an ending kmer, are in the same entry of the map, and are told apart
+    /// by whether they are flipped.
+    /// To use multiple threads, the kmers are split into one shard per thread by their hash,
+    /// and each thread builds the map of its shard and links its kmers.
+    /// If more than `max_degree` contigs start or end with a kmer, it links none of them.
+    pub fn edges(&self, threads: NonZeroUsize, max_degree: Option<usize>) -> Vec<Edge> {
+        // With one thread, the kmers are linked straight from this struct, so no more memory
+        // is used than for the map
+        if threads.get() == 1 {
+            return self.link(self.iter_kmers(), max_degree);
+        }
+        let n_shards = threads.get();
+        let size = encoding_size(self.k).get() as usize;
+        let kmer_at = |i: usize| (&self.data[i], &self.mers[i * size..(i + 1) * size]);
+        let state = RandomState::new();
+        let shard_of = |kmer: &[u8]| state.hash_one(kmer) as usize % n_shards;
+        let n_kmers = self.data.len();
+        let chunk_size = n_kmers.div_ceil(n_shards).max(1);
+        // For each chunk of kmers, the indices of the kmers of each shard
+        let chunks: Vec<Vec<Vec<usize>>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..n_kmers)
+                .step_by(chunk_size)
+                .map(|start| {
+                    s.spawn(move || {
+                        let mut shards = vec![Vec::new(); n_shards];
+                        for i in start..(start + chunk_size).min(n_kmers) {
+                            shards[shard_of(kmer_at(i).1)].push(i);
+                        }
+                        shards
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        thread::scope(|s| {
+            let handles: Vec<_> = (0..n_shards)
+                .map(|shard| {
+                    let chunks = &chunks;
+                    s.spawn(move || {
+                        let kmers = chunks.iter().flat_map(|c| c[shard].iter());
+                        self.link(kmers.map(|&i| kmer_at(i)), max_degree)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        })
+    }
+
+    // Build the map of the kmers, and link the kmers in it, see `edges`
+    fn link<'a>(
+        &self,
+        kmers: impl Iterator<Item = KmerRef<'a>>,
+        max_degree: Option<usize>,
+    ) -> Vec<Edge> {
+        let mut map: HashMap<&[u8], SmallVec<[KmerOrigin; 2]>> = HashMap::new();
+        for (data, kmer) in kmers {
+            map.entry(kmer).or_default().push(*data);
+        }
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut rc_buffer: Vec<u8> = vec![0; encoding_size(self.k).get() as usize];
+        for (kmer, datas) in map.iter() {
+            // A repeat shared by many contigs
+            if max_degree.is_some_and(|n| datas.len() > n) {
+                continue;
+            }
+            // A palindromic kmer is its own reverse complement, so all its
+            // starting kmers match all of them, and they are never flipped.
+            rc_buffer.copy_from_slice(kmer);
+            let is_palindrome = reverse_complement(self.k, &mut rc_buffer) == *kmer;
+            // The reverse complement of a starting kmer is an ending kmer,
+            // which matches the starting kmers with the other orientation.
+            for rc_end_data in datas.iter() {
+                for start_data in datas.iter() {
+                    if is_palindrome || rc_end_data.is_flipped() != start_data.is_flipped() {
+                        edges.push(Edge {
+                            from_end: rc_end_data.unflipped().reverse_complement(),
+                            to_start: start_data.unflipped(),
+                        })
+                    }
+                }
+            }
+        }
+        edges
+    }
+
+    /// Add the kmers and kmer data from a sequence to this struct.
+    /// The starting kmer of the contig and of its reverse complement are added, each stored as
+    /// the smaller of the kmer and its reverse complement, so they only take up one entry
+    /// each in the map of `edges`. Their KmerOrigin is flipped if stored as the reverse complement.
+    /// Fails if seq too small, or its ending kmer contains non-DNA, in which case nothing is added.
+    /// Ambiguous kmers are expanded to at most `max_expansions` kmers, see `encode`.
+    pub fn add(&mut self, seq: &[u8], index: usize, max_expansions: usize) -> Result<(), Skip> {
+        let KmerOriginPair {
+            fw: fwdata,
+            rc: rvdata,
+        } = KmerOriginPair::try_new(index).map_err(|_| Skip::IndexOverflow)?;
+        let k = self.k.get() as usize;
+        if seq.len() < k {
+            return Err(Skip::ShorterThanK);
+        }
+        let (start, end) = (&seq[..k], &seq[seq.len() - k..]);
+
+        // Add reverse starting kmers. This is done first, since the contig is skipped without them.
+        let n_mers = self.mers.len();
+        let n_ends = encode(
+            end,
+            max_expansions,
+            &mut self.encoding_buffer,
+            &mut self.mers,
+        );
+        if n_ends == 0 {
+            return Err(Skip::AmbiguousKmer);
+        }
+        let size = encoding_size(self.k).get() as usize;
+        for kmer in self.mers[n_mers..].chunks_exact_mut(size) {
+            reverse_complement(self.k, kmer);
+        }
+        self.add_canonical(n_mers, rvdata);
+
+        // Add forward starting kmers
+        let n_mers = self.mers.len();
+        encode(
+            start,
+            max_expansions,
+            &mut self.encoding_buffer,
+            &mut self.mers,
+        );
+        self.add_canonical(n_mers, fwdata);
+        Ok(())
+    }
+
+    // Replace the kmers after the first `n_mers` bytes by the smaller of the kmer and its
+    // reverse complement, and add their data, flipped if the reverse complement is stored.
+    fn add_canonical(&mut self, n_mers: usize, data: KmerOrigin) {
+        let size = encoding_size(self.k).get() as usize;
+        for kmer in self.mers[n_mers..].chunks_exact_mut(size) {
+            self.encoding_buffer.copy_from_slice(kmer);
+            let rc = reverse_complement(self.k, &mut self.encoding_buffer);
+            if *rc < *kmer {
+                kmer.copy_from_slice(rc);
+                self.data.push(data.flipped());
+            } else {
+                self.data.push(data);
+            }
+        }
+    }
+}
+
+/// Why a record has no kmers, and is not in the graph
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Skip {
+    // Shorter than -m
+    TooShort,
+    ShorterThanK,
+    // The ending kmer has other characters than ACGT, and could not be expanded
+    AmbiguousKmer,
+    // More records than a KmerOrigin can index
+    IndexOverflow,
+}
+
+impl Skip {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Skip::TooShort => "too_short",
+            Skip::ShorterThanK => "shorter_than_k",
+            Skip::AmbiguousKmer => "ambiguous_kmer",
+            Skip::IndexOverflow => "index_overflow",
+        }
+    }
+}
+
+// The bases an IUPAC nucleotide code stands for, or None if it is not a nucleotide code
+pub(crate) fn iupac_bases(b: u8) -> Option<&'static [u8]> {
+    Some(match b.to_ascii_uppercase() {
+        b'A' => b"A",
+        b'C' => b"C",
+        b'G' => b"G",
+        b'T' | b'U' => b"T",
+        b'R' => b"AG",
+        b'Y' => b"CT",
+        b'S' => b"CG",
+        b'W' => b"AT",
+        b'K' => b"GT",
+        b'M' => b"AC",
+        b'B' => b"CGT",
+        b'D' => b"AGT",
+        b'H' => b"ACT",
+        b'V' => b"ACG",
+        b'N' => b"ACGT",
+        _ => return None,
+    })
+}
+
+// All unambiguous kmers an ambiguous kmer stands for, or None if there are more than `max`,
+// or it contains bytes that are not IUPAC nucleotide codes.
+fn expand_ambiguous(kmer: &[u8], max: usize) -> Option<Vec<Vec<u8>>> {
+    let choices: Vec<&[u8]> = kmer
+        .iter()
+        .map(|&b| iupac_bases(b))
+        .collect::<Option<_>>()?;
+    let mut n = 1usize;
+    for bases in choices.iter() {
+        n = n.saturating_mul(bases.len());
+        if n > max {
+            return None;
+        }
+    }
+    let mut expanded: Vec<Vec<u8>> = vec![Vec::with_capacity(kmer.len())];
+    for bases in choices {
+        expanded = expanded
+            .into_iter()
+            .flat_map(|prefix| {
+                bases.iter().map(move |&b| {
+                    let mut kmer = prefix.clone();
+                    kmer.push(b);
+                    kmer
+                })
+            })
+            .collect();
+    }
+    Some(expanded)
+}
+
+// Append the encoded kmer to `out`, or if it is ambiguous, the unambiguous kmers it stands for,
+// if there are at most `max_expansions` of them. Returns the number of kmers appended.
+fn encode(kmer: &[u8], max_expansions: usize, buffer: &mut [u8], out: &mut Vec<u8>) -> usize {
+    if translate(kmer, buffer).is_some() {
+        out.extend_from_slice(buffer);
+        return 1;
+    }
+    if max_expansions < 2 {
+        return 0;
+    }
+    let Some(expanded) = expand_ambiguous(kmer, max_expansions) else {
+        return 0;
+    };
+    for concrete in expanded.iter() {
+        // Only contains ACGT
+        translate(concrete, buffer).unwrap();
+        out.extend_from_slice(buffer);
+    }
+    expanded.len()
+}
+
+// Kmers written to temporary files, to link assemblies whose kmers do not fit in memory.
+// The kmers are canonical, so a starting kmer and the reverse complement it is matched with
+// are the same, and in the same partition, and each partition can be linked on its own. Only one partition is in memory at a time.
+// The files are records of the KmerOrigin as 4 little-endian bytes, then the encoded kmer.
+pub(crate) struct Spill {
+    dir: PathBuf,
+    state: RandomState,
+    // The files of each partition, for each k
+    files: HashMap<NonZeroU16, Vec<BufWriter<File>>>,
+    n_partitions: NonZeroUsize,
+}
+
+impl Spill {
+    // Create a directory for the files inside `parent`
+    pub(crate) fn new(parent: &Path, n_partitions: NonZeroUsize) -> Result<Self> {
+        let dir = parent.join(format!("megagfa-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).with_context(|| {
+            format!("Could not create temporary directory \"{}\"", dir.display())
+        })?;
+        Ok(Self {
+            dir,
+            state: RandomState::new(),
+            files: HashMap::new(),
+            n_partitions,
+        })
+    }
+
+    fn path(&self, k: NonZeroU16, partition: usize) -> PathBuf {
+        self.dir.join(format!("k{}_{}.bin", k, partition))
+    }
+
+    pub(crate) fn write(&mut self, kmers: &Kmers) -> Result<()> {
+        let k = kmers.k;
+        if !self.files.contains_key(&k) {
+            let files = (0..self.n_partitions.get())
+                .map(|partition| {
+                    let path = self.path(k, partition);
+                    File::create(&path)
+                        .map(BufWriter::new)
+                        .with_context(|| format!("Could not create file \"{}\"", path.display()))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            self.files.insert(k, files);
+        }
+        let files = self.files.get_mut(&k).unwrap();
+        for (data, kmer) in kmers.iter_kmers() {
+            let partition = self.state.hash_one(kmer) as usize % self.n_partitions.get();
+            let file = &mut files[partition];
+            file.write_all(&data.0.to_le_bytes())?;
+            file.write_all(kmer)?;
+        }
+        Ok(())
+    }
+
+    // Link the kmers of each partition, and remove the files
+    pub(crate) fn edges(
+        mut self,
+        threads: NonZeroUsize,
+        max_degree: Option<usize>,
+    ) -> Result<Vec<Edge>> {
+        let mut edges: Vec<Edge> = Vec::new();
+        let files = std::mem::take(&mut self.files);
+        let progress = Progress::new(
+            "Linking partitions",
+            "partitions",
+            Some((files.len() * self.n_partitions.get()) as u64),
+        );
+        for (k, files) in files {
+            for (partition, file) in files.into_iter().enumerate() {
+                file.into_inner()?;
+                let path = self.path(k, partition);
+                let bytes = std::fs::read(&path)
+                    .with_context(|| format!("Could not read file \"{}\"", path.display()))?;
+                std::fs::remove_file(&path)?;
+                let record_size = 4 + encoding_size(k).get() as usize;
+                let mut kmers = Kmers::new(k, bytes.len() / record_size);
+                for record in bytes.chunks_exact(record_size) {
+                    let (data, kmer) = record.split_at(4);
+                    kmers
+                        .data
+                        .push(KmerOrigin(u32::from_le_bytes(data.try_into().unwrap())));
+                    kmers.mers.extend_from_slice(kmer);
+                }
+                drop(bytes);
+                edges.extend(kmers.edges(threads, max_degree));
+                progress.inc(1);
+            }
+        }
+        progress.finish();
+        Ok(edges)
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        // Also clean up after errors. The directory may be gone already.
+        self.files.clear();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// None if the sequence contains a byte which are not ACGTUacgtu.
+fn translate(seq: &[u8], into: &mut [u8]) -> Option<()> {
+    // Handle first chunks of 4, which each are translated to a single byte.
+    let chunks = seq.chunks_exact(4);
+    let (last_encoding, mut is_error) = translate_chunk(chunks.remainder().iter());
+    for (e, (b, err)) in into
+        .iter_mut()
+        .zip(chunks.map(|chunk| translate_chunk(chunk.iter())))
+    {
+        *e = b;
+        is_error |= err;
+    }
+    // Handle last element. We could handle all elements in a single loop using
+    // chunk instead of chunks_exact, but that would cause worse code to be emitted.
+    // If k is divisible by 4, there is no remainder, and the last chunk is already written.
+    if !seq.len().is_multiple_of(4) {
+        if let Some(e) = into.last_mut() {
+            *e = last_encoding
+        };
+    }
+    if is_error {
+        None
+    } else {
+        Some(())
+    }
+}
+
+fn translate_chunk<'a, T: Iterator<Item = &'a u8>>(x: T) -> (u8, bool) {
+    // Statically and locally verify it has 256 elements for safety
+    let lut: [u8; 256] = LUT;
+    x.fold((0, false), |(kmer, is_error), byte| unsafe {
+        let b = lut.get_unchecked(*byte as usize);
+        ((kmer << 2) | b, is_error | (*b == 0xff))
+    })
+}
+
+fn reverse_complement(k: NonZeroU16, kmer: &mut [u8]) -> &[u8] {
+    // First we reverse. We need to reverse each byte (chunk of 4 2-bit symbols)
+    // then we bitreverse each byte.
+    // So e.g. a byte like ABCDEFGH becomes HGFEDCBA, when it should be
+    // GHEFCDAB. So, we also do a bit of bitshuffling to get correctly reversed.
+    // It should optimise well.
+    kmer.reverse();
+    for i in kmer.iter_mut() {
+        *i = {
+            let r = i.reverse_bits();
+            let bitreversed = ((r & 0b10101010) >> 1) | ((r & 0b01010101) << 1);
+            // Also complement by bitwise not on the bits. This works due to how
+            // the nucleotides are stored
+            !bitreversed
+        }
+    }
+
+    // The reversing operation have also reversed where the unused padding bits
+    // are. E.g. for a 5-mer it's encoded as AABBCCDD xxxxxxEE, then when reversed
+    // its EExxxxxx DDCCBBAA, when the correct result is EEDDCCBB xxxxxxAA.
+    // We solve this by shifting the bits. If k is divisible by 4, there is no padding.
+    let used_bits = 2 * (k.get() % 4);
+    if used_bits == 0 {
+        return kmer;
+    }
+    let unused_bits = 8 - used_bits;
+    let fst = kmer.first_mut().unwrap();
+    // First, shift the first byte. In the example above, it's the EExxxxxx shifted by 6.
+    *fst >>= unused_bits;
+    // It's now xxxxxxEE DDCCBBAA
+
+    // We now need to shift all bytes leftward.
+    for i in 0..kmer.len() - 1 {
+        // Each byte is its own content shifted leftward, OR'd with the next byte,
+        // shifted rightwards.
+        unsafe {
+            let v =
+                (kmer.get_unchecked(i) << unused_bits) | (kmer.get_unchecked(i + 1) >> used_bits);
+            *(kmer.get_unchecked_mut(i)) = v;
+        }
+    }
+    // It's now EEDDCCBB DDCCBBAA
+
+    // The last byte just needs its upper bits masked
+    unsafe { *kmer.last_mut().unwrap_unchecked() &= (1u8 << used_bits).wrapping_sub(1) };
+
+    // We now have EEDDCCBB xxxxxxAA, the correct answer.
+    kmer
+}
+
+#[cfg(test)]
+mod test_rc {
+    use crate::kmers::{encoding_size, expand_ambiguous, reverse_complement, translate};
+    use crate::{Kmers, Skip};
+    use std::num::NonZeroU16;
+
+    #[test]
+    fn test_expand_ambiguous() {
+        assert_eq!(expand_ambiguous(b"ACG", 1), Some(vec![b"ACG".to_vec()]));
+        assert_eq!(
+            expand_ambiguous(b"ARn", 8),
+            Some(
+                [b"AAA", b"AAC", b"AAG", b"AAT", b"AGA", b"AGC", b"AGG", b"AGT"]
+                    .map(|s| s.to_vec())
+                    .to_vec()
+            )
+        );
+        assert_eq!(expand_ambiguous(b"ARN", 7), None);
+        assert_eq!(expand_ambiguous(b"A-C", 100), None);
+    }
+
+    #[test]
+    fn test_add_ambiguous() {
+        let k = NonZeroU16::new(4).unwrap();
+        let mut kmers = Kmers::new(k, 0);
+        // Ending kmer ambiguous: Nothing is added
+        assert_eq!(kmers.add(b"ACGTAANA", 0, 1), Err(Skip::AmbiguousKmer));
+        assert!(kmers.data.is_empty() && kmers.mers.is_empty());
+        // Starting kmer ambiguous: Only the ending kmer is added
+        assert!(kmers.add(b"ACGYAAAA", 0, 1).is_ok());
+        assert_eq!(kmers.data.len(), 1);
+        // Expanded to 2 starting and 4 ending kmers
+        let mut kmers = Kmers::new(k, 0);
+        assert!(kmers.add(b"ACGYAAAN", 0, 4).is_ok());
+        assert_eq!(kmers.add(b"ACG", 1, 4), Err(Skip::ShorterThanK));
+        assert_eq!(kmers.data.len(), 6);
+        assert_eq!(kmers.mers.len(), 6);
+    }
+
+    #[test]
+    fn test_rc_fn() {
+        let (i, j) = (b"atcgactacG", b"cGTAGTCGAT");
+        let n: NonZeroU16 = i.len().try_into().ok().and_then(NonZeroU16::new).unwrap();
+        assert_eq!(n.get() as usize, j.len());
+        let mut a = vec![0u8; encoding_size(n).get() as usize];
+        let mut b = a.clone();
+        translate(i, &mut a).unwrap();
+        reverse_complement(NonZeroU16::new(10).unwrap(), &mut a);
+        translate(j, &mut b);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_rc_any_k() {
+        for k in (1..=12).chain([255, 256, 301]) {
+            let seq: Vec<u8> = (0..k).map(|i| b"ACGT"[(i * 7 + i / 3) % 4]).collect();
+            let n = NonZeroU16::new(k as u16).unwrap();
+            let mut a = vec![0u8; encoding_size(n).get() as usize];
+            let mut b = a.clone();
+            translate(&seq, &mut a).unwrap();
+            reverse_complement(n, &mut a);
+            translate(&bio::alphabets::dna::revcomp(&seq), &mut b).unwrap();
+            assert_eq!(a, b);
+            // All bases are encoded, also if k is divisible by 4
+            let mut other = seq.clone();
+            other[k - 1] = if seq[k - 1] == b'A' { b'C' } else { b'A' };
+            translate(&other, &mut b).unwrap();
+            translate(&seq, &mut a).unwrap();
+            assert_ne!(a, b);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! Find the links between MEGAHIT contigs that share their first or last kmer, and write
+//! them as a GFA file. This is the library behind the megagfa binary, so the links can
+//! also be found from other programs.
+mod contigs;
+mod gfa;
+mod graph;
+mod kmers;
+mod reads;
+
+pub use contigs::*;
+pub use gfa::*;
+pub use graph::*;
+pub use kmers::*;
+pub use reads::*;
@@ -1,12 +1,19 @@
 use anyhow::{self, bail, Context, Result};
-use bio::io::fasta::Reader;
 use clap::Parser;
-use smallvec::SmallVec;
+use flate2::{write::GzEncoder, Compression};
+use htslite::AlignmentReader;
+use megagfa::{
+    find_components, find_edges, find_paths, overlap_complexity, rc_byte, read_linked_contigs,
+    read_links, verify_edges, Assembler, Contig, Edge, Format, GfaVersion, GfaWriter, KMode,
+    ReadOptions, Records, Segments, Skip,
+};
+use progress::{info, LogArgs};
 use std::{
-    collections::HashMap,
+    collections::HashSet,
+    fs::File,
     io::{stdin, stdout, BufRead, BufReader, BufWriter, Write},
-    num::NonZeroU8,
-    path::PathBuf,
+    num::{NonZeroU16, NonZeroUsize},
+    path::{Path, PathBuf},
 };
 
 fn exitwith(s: &str) -> ! {
@@ -16,10 +23,220 @@ fn exitwith(s: &str) -> ! {
 
 fn main() -> Result<()> {
     let args = Cli::parse();
+    progress::init(&args.logging)?;
+
+    let segments = match (args.with_segments, args.segments_no_seq) {
+        (false, _) => Segments::Omit,
+        (true, true) => Segments::Lengths,
+        (true, false) => Segments::Sequences,
+    };
+    // With multiple files, e.g. from different samples, contigs are only linked to contigs
+    // from the same file, and the identifiers are prefixed by the file, like S1C, S2C etc.
+    // to make them unique. With a single file, the identifiers are unchanged.
+    let mut records = Records::new(args.low_memory);
+    let mut edges: Vec<Edge> = Vec::new();
+    // The old and new identifiers of contigs renamed with --rename-invalid
+    let mut renamed: Vec<(String, String)> = Vec::new();
+    // The records not in the graph, for --skipped
+    let mut skipped: Vec<(String, Skip)> = Vec::new();
+    let paths: Vec<Option<&Path>> = if args.i.is_empty() {
+        vec![None]
+    } else {
+        args.i.iter().map(|p| Some(p.as_path())).collect()
+    };
+    if let Some(complexity) = args.min_overlap_complexity {
+        if !(0.0..=1.0).contains(&complexity) {
+            bail!("Overlap complexity must be in 0-1, got {}", complexity);
+        }
+    }
+    if args.assembler == Assembler::Spades && args.k.is_none() {
+        bail!("SPAdes identifiers do not contain k, so it must be passed with -k");
+    }
+    let k_mode = match args.k {
+        Some(k) => KMode::Fixed(k),
+        None if args.multi_k => KMode::Multi,
+        None => KMode::Detect,
+    };
+    let prefixes: Vec<String> = (0..paths.len())
+        .map(|file_index| {
+            if paths.len() > 1 {
+                format!("S{}C", file_index + 1)
+            } else {
+                String::new()
+            }
+        })
+        .collect();
+    let read_options = |file_index: usize| ReadOptions {
+        k_mode,
+        assembler: args.assembler,
+        min_contig_length: args.min_contig_length as usize,
+        low_memory: args.low_memory,
+        rename_invalid: args.rename_invalid.is_some(),
+        keep_skipped: args.skipped.is_some(),
+        keep_seqs: segments == Segments::Sequences,
+        keep_ends: args.verify || args.min_overlap_complexity.is_some(),
+        prefix: &prefixes[file_index],
+        temp_dir: args.temp_dir.as_deref(),
+        n_partitions: args.partitions,
+        max_degree: args.max_degree,
+        max_expansions: args.expand_ambiguous.map_or(1, |n| n.get()),
+    };
+    // Index of the first contig of each file
+    let mut first_indices: Vec<usize> = Vec::with_capacity(paths.len());
+    for (file_index, path) in paths.iter().enumerate() {
+        first_indices.push(records.len());
+        edges.extend(find_edges(
+            open_input(*path, args.k, args.assembler)?,
+            &read_options(file_index),
+            args.threads,
+            &mut records,
+            &mut renamed,
+            &mut skipped,
+        )?);
+    }
+    // With --low-memory, only the lengths of the records were kept, so the files are read
+    // again to add the contigs with edges. The other contigs are None.
+    let contigs: Vec<Option<Contig>> = match records {
+        Records::Contigs(contigs) => contigs,
+        Records::Lengths(lengths) => {
+            let mut is_linked = vec![false; lengths.len()];
+            for edge in edges.iter() {
+                is_linked[edge.from_end.index()] = true;
+                is_linked[edge.to_start.index()] = true;
+            }
+            let mut contigs = Vec::with_capacity(lengths.len());
+            for (file_index, path) in paths.iter().enumerate() {
+                let range = first_indices[file_index]
+                    ..first_indices
+                        .get(file_index + 1)
+                        .copied()
+                        .unwrap_or(lengths.len());
+                read_linked_contigs(
+                    open_input(*path, args.k, args.assembler)?,
+                    &read_options(file_index),
+                    &lengths[range.clone()],
+                    &is_linked[range],
+                    &mut contigs,
+                    &mut renamed,
+                )?;
+            }
+            contigs
+        }
+    };
+    if let Some(path) = &args.skipped {
+        write_skipped(path, &skipped)?;
+        info!("Skipped {} records", skipped.len());
+    }
+    if let Some(path) = &args.rename_invalid {
+        write_renamed(path, &renamed)?;
+        info!("Renamed {} invalid identifiers", renamed.len());
+    }
+    if !args.allow_duplicates {
+        // Sorting also makes the order of the output deterministic
+        let n_edges = edges.len();
+        edges.sort_unstable_by_key(|edge| edge.key());
+        edges.dedup_by_key(|edge| edge.key());
+        info!("Removed {} duplicate edges", n_edges - edges.len());
+    }
+    if let Some(path) = &args.report_palindromes {
+        report_self_edges(path, &contigs, &edges)?;
+    }
+    if args.no_self_loops {
+        let n_edges = edges.len();
+        edges.retain(|edge| !edge.is_self_loop());
+        info!("Removed {} self-loops", n_edges - edges.len());
+    }
+    if let Some(complexity) = args.min_overlap_complexity {
+        let n_edges = edges.len();
+        edges.retain(|edge| overlap_complexity(&contigs, edge) >= complexity);
+        info!(
+            "Removed {} edges with overlap complexity below {}",
+            n_edges - edges.len(),
+            complexity
+        );
+    }
+    if args.verify {
+        verify_edges(&contigs, &edges)?;
+    }
+    let paths = if args.paths {
+        let paths = find_paths(contigs.len(), &edges);
+        info!("Found {} unbranched paths", paths.len());
+        paths
+    } else {
+        Vec::new()
+    };
+    // Links from read pairs are only added if the contigs are not already linked by a kmer,
+    // in either direction
+    let read_links = if let Some(path) = &args.link_bam {
+        let mut reader = AlignmentReader::open(Some(path))?;
+        let mut links = read_links(
+            &mut reader,
+            &contigs,
+            args.link_distance,
+            args.link_min_mapq,
+            args.link_min_reads,
+        )?;
+        let linked: HashSet<(u32, u32)> = edges.iter().map(|edge| edge.key()).collect();
+        let n_links = links.len();
+        links.retain(|(edge, _)| {
+            !linked.contains(&edge.key()) && !linked.contains(&edge.reverse_complement().key())
+        });
+        info!(
+            "Found {} links from read pairs, of which {} were already linked by kmers",
+            n_links,
+            n_links - links.len()
+        );
+        links
+    } else {
+        Vec::new()
+    };
+    let writer = GfaWriter {
+        format: args.format,
+        segments,
+        gfa_version: args.gfa_version,
+        cigar_overlap: args.cigar_overlap,
+        length_tags: args.length_tags,
+    };
+    if args.components.is_some() || args.component_dir.is_some() {
+        let components = find_components(&contigs, &edges);
+        let n_components = components.iter().flatten().max().map_or(0, |&c| c + 1);
+        info!("Found {} connected components", n_components);
+        if let Some(path) = &args.components {
+            write_components(path, &contigs, &components)?;
+        }
+        if let Some(dir) = &args.component_dir {
+            write_component_graphs(
+                dir,
+                &contigs,
+                &components,
+                &edges,
+                &paths,
+                args.min_component_size,
+                &writer,
+            )?;
+        }
+    }
+    let mut out = open_output(args.output.as_deref())?;
+    writer.write(
+        &mut out,
+        contigs.iter().flatten(),
+        &contigs,
+        &edges,
+        &read_links,
+        &paths,
+    )?;
+    out.flush()?;
+    Ok(())
+}
 
+fn open_input(
+    path: Option<&Path>,
+    k: Option<NonZeroU16>,
+    assembler: Assembler,
+) -> Result<Box<dyn BufRead>> {
     // We can read from stdin, from a file, or from a gzipped file. In any case, we wrap the result
     // in a BufRead so we can guarantee the input type implements BufRead.
-    let input: Box<dyn BufRead> = if let Some(p) = args.i {
+    Ok(if let Some(p) = path {
         if !p.is_file() {
             exitwith(&format!(
                 "Not an existing file: \"{}\"",
@@ -27,19 +244,27 @@ fn main() -> Result<()> {
             ));
         }
         // Check if the user passes e.g. a file k79.contigs.fa, but passes -k 75, where the values
-        // of k differ. This will raise an error.
-        p.file_name().and_then(|f| f.to_str()).and_then(|s| {
-            s.strip_prefix('k').and_then(|s| s.strip_suffix(".contigs.fa")).and_then(|s| {
-                s.parse::<u8>().ok().map(|k| {
-                    if k != args.k.get() {
-                        exitwith(&format!("ERROR: K value passed with -k is {}, but given file is {} with different K value.", args.k, k))
-                    };
-                })
-            })
-        });
+        // of k differ. This will raise an error. For SPAdes, check the directory of e.g.
+        // K55/final_contigs.fasta instead.
+        let file_k = match assembler {
+            Assembler::Megahit => p
+                .file_name()
+                .and_then(|f| f.to_str())
+                .and_then(|s| s.strip_prefix('k')?.strip_suffix(".contigs.fa")),
+            Assembler::Spades => p
+                .parent()
+                .and_then(|d| d.file_name())
+                .and_then(|f| f.to_str())
+                .and_then(|s| s.strip_prefix('K')),
+        };
+        if let Some(file_k) = file_k.and_then(|s| s.parse::<u16>().ok()) {
+            if let Some(k) = k.filter(|k| k.get() != file_k) {
+                exitwith(&format!("ERROR: K value passed with -k is {}, but given file is {} with different K value.", k, file_k))
+            };
+        }
         // Return a BufReader wrapping either the opened file directly, or a gzip reader if the file name
         // ends with .gz.
-        let file = std::fs::File::open(p.clone())
+        let file = File::open(p)
             .with_context(|| format!("Could not open input file \"{}\"", p.to_string_lossy()))?;
         if p.extension().is_some_and(|e| e == "gz") {
             Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file)))
@@ -50,392 +275,312 @@ fn main() -> Result<()> {
         // A locked stdin implements BufRead, so we can use that directly.
         // Also, no other part of this program reads stdin, so there is no downside.
         Box::new(stdin().lock())
-    };
-    let (identifiers, edges) = find_edges(input, args.k, args.min_contig_length as usize)?;
-    print_gfa(&identifiers, &edges)?;
-    Ok(())
-}
-
-// Contains the index of the contig the kmer came from, and whether it's reverse-complement
-// or not.
-// The last bit of information needed to identify a kmer is whether it's the ending or the starting kmer,
-// but this is stored implicitly as the end/start kmers are stored in two different
-// data structures in this program.
-// The information is packed into 32 bits in order to save memory, and to make the data structures
-// that store KmerOrigin smaller and therefore faster.
-#[derive(Clone, Copy, Debug)]
-struct KmerOrigin(u32);
-
-impl KmerOrigin {
-    fn is_rc(&self) -> bool {
-        self.0 > 0x7fffffff
-    }
-
-    fn index(&self) -> usize {
-        (self.0 & 0x7fffffff) as usize
-    }
-
-    fn reverse_complement(&self) -> Self {
-        Self(self.0 ^ 0x80000000)
-    }
-}
-
-// Just a convenience struct so we can construct a fw and an rc KmerOrigin in one go
-struct KmerOriginPair {
-    fw: KmerOrigin,
-    rc: KmerOrigin,
-}
-
-impl KmerOriginPair {
-    fn try_new(index: usize) -> Result<Self> {
-        // It's unlikely we get more than 2 billion records in a file, but let's check it anyway
-        let x: u32 = index
-            .try_into()
-            .ok()
-            .and_then(|u| if u > 0x7fffffff { None } else { Some(u) })
-            .context("Can only hande 2147483647 FASTA records in one file")?;
-        Ok(Self {
-            fw: KmerOrigin(x),
-            rc: KmerOrigin(x | 0x80000000),
-        })
-    }
-}
-
-// From: The ending kmer. To: The starting kmer of the next contig.
-struct Edge {
-    from_end: KmerOrigin,
-    to_start: KmerOrigin,
+    })
 }
 
-// We use this LUT (lookup table) to encode arbitrary DNA/RNA nucleotides into two bits.
-// This is to make the Kmer struct smaller - both for memory reasons, but also to
-// make hashing it faster.
-// This will mean contigs with ambiguous nucleotides in the start/ending kmers will be skipped,
-// but I'm not sure MEGAHIT can even process ambiguous kmers in its graph anyway, so no loss.
-const fn make_lut() -> [u8; 256] {
-    let mut lut = [0xff; 256];
-    let mut i: u8 = 0;
-    while i < 127 {
-        i += 1;
-        lut[i as usize] = match i {
-            b'A' | b'a' => 0,
-            b'C' | b'c' => 1,
-            b'G' | b'g' => 2,
-            b'T' | b't' | b'U' | b'u' => 3,
-            _ => 0xff,
+// Write the self-loops as a TSV file, with whether they are due to palindromic kmers
+fn report_self_edges(path: &Path, contigs: &[Option<Contig>], edges: &[Edge]) -> Result<()> {
+    let mut out = BufWriter::new(
+        File::create(path)
+            .with_context(|| format!("Could not create file \"{}\"", path.to_string_lossy()))?,
+    );
+    out.write_all(b"contig\tfrom\tto\ttype\n")?;
+    let mut n_palindromes = 0usize;
+    for edge in edges.iter().filter(|e| e.is_self_loop()) {
+        let contig = contigs[edge.from_end.index()].as_ref().unwrap();
+        out.write_all(contig.id.as_bytes())?;
+        out.write_all(b"\t")?;
+        out.write_all(rc_byte(edge.from_end.is_rc()))?;
+        out.write_all(b"\t")?;
+        out.write_all(rc_byte(edge.to_start.is_rc()))?;
+        if edge.is_palindrome() {
+            n_palindromes += 1;
+            out.write_all(b"\tpalindrome\n")?;
+        } else {
+            out.write_all(b"\tloop\n")?;
         }
     }
-    lut
+    out.flush()?;
+    info!("Found {} edges due to palindromic kmers", n_palindromes);
+    Ok(())
 }
 
-const LUT: [u8; 256] = make_lut();
-
-// We encode the kmers in two bits, so this is identical to ceiling dividing by 4.
-fn encoding_size(k: NonZeroU8) -> NonZeroU8 {
-    // Safety: If k > 3, the first term is > 0 and < 254.
-    // If k is 1, 2, or 3, the second term is > 0.
-    // Hence the sum will always be nonzero and cannot overflow.
-    unsafe {
-        (k.get() / 4 + ((k.get() % 4) > 0) as u8)
-            .try_into()
-            .unwrap_unchecked()
+// Write the component of each contig as a Vamb cluster file, with components named
+// like component1, so it can be used with the tools that read cluster files.
+fn write_components(
+    path: &Path,
+    contigs: &[Option<Contig>],
+    components: &[Option<usize>],
+) -> Result<()> {
+    let mut out = open_output(Some(path))?;
+    out.write_all(b"clustername\tcontigname\n")?;
+    for (contig, component) in contigs.iter().zip(components.iter()) {
+        if let (Some(contig), Some(component)) = (contig, component) {
+            writeln!(out, "component{}\t{}", component + 1, contig.id)?;
+        }
     }
+    out.flush()?;
+    Ok(())
 }
 
-// Dense representation of all observed kmers, packed into a single vector.
-struct Kmers {
-    mers: Vec<u8>, // The DNA kmers themselves, packed together. Length: k * data.len()
-    data: Vec<KmerOrigin>,
-    encoding_buffer: Vec<u8>, // length encoding_size(k). Ephemeral.
-    k: NonZeroU8,
-}
-
-impl Kmers {
-    fn new(k: NonZeroU8) -> Self {
-        // Preallocate to avoid unnecessary reallocations. This costs about 3 MB up front.
-        let assumed_kmers = 200_000;
-        Self {
-            mers: Vec::with_capacity(encoding_size(k).get() as usize * assumed_kmers),
-            data: Vec::with_capacity(assumed_kmers),
-            encoding_buffer: vec![0; encoding_size(k).get() as usize],
-            k,
+// Write a graph for each component with at least `min_size` contigs, named like
+// component1.gfa in the directory.
+fn write_component_graphs(
+    dir: &Path,
+    contigs: &[Option<Contig>],
+    components: &[Option<usize>],
+    edges: &[Edge],
+    paths: &[Vec<(usize, bool)>],
+    min_size: usize,
+    writer: &GfaWriter,
+) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Could not create directory \"{}\"", dir.display()))?;
+    let mut members: Vec<Vec<usize>> = Vec::new();
+    for (i, component) in components.iter().enumerate() {
+        if let Some(c) = *component {
+            if members.len() <= c {
+                members.resize(c + 1, Vec::new());
+            }
+            members[c].push(i);
         }
     }
-
-    // How to get the kmers and KmerOrigin out of this struct.
-    fn iter_kmers(&self) -> impl Iterator<Item = (&KmerOrigin, &[u8])> {
-        let chunk_size = self.mers.len() / self.data.len();
-        self.data.iter().zip(self.mers.chunks_exact(chunk_size))
-    }
-
-    // Add the kmers and kmer data from a sequence to this struct.
-    // None if seq too small, or contains non-DNA
-    fn add(&mut self, seq: &[u8], index: usize) -> Option<()> {
-        let KmerOriginPair {
-            fw: fwdata,
-            rc: rvdata,
-        } = KmerOriginPair::try_new(index).ok()?;
-
-        // Add forward starting kmer
-        if translate(
-            seq.get(0..self.k.get() as usize)?,
-            &mut self.encoding_buffer,
-        )
-        .is_some()
-        {
-            self.mers.extend_from_slice(&self.encoding_buffer);
-            self.data.push(fwdata);
+    // The components are numbered by size, so the large ones come first
+    let n_large = members.iter().take_while(|m| m.len() >= min_size).count();
+    let mut component_edges: Vec<Vec<Edge>> = vec![Vec::new(); n_large];
+    for edge in edges.iter() {
+        let c = components[edge.from_end.index()].unwrap();
+        if c < n_large {
+            component_edges[c].push(*edge);
         }
-
-        // Add reverse starting kmer
-        translate(
-            seq.get(seq.len() - self.k.get() as usize..)?,
-            &mut self.encoding_buffer,
-        )?;
-        reverse_complement(self.k, &mut self.encoding_buffer);
-        self.mers.extend_from_slice(&self.encoding_buffer);
-        self.data.push(rvdata);
-        Some(())
     }
-}
-
-// None if the sequence contains a byte which are not ACGTUacgtu.
-fn translate(seq: &[u8], into: &mut [u8]) -> Option<()> {
-    // Handle first chunks of 4, which each are translated to a single byte.
-    let chunks = seq.chunks_exact(4);
-    let (last_encoding, mut is_error) = translate_chunk(chunks.remainder().iter());
-    for (e, (b, err)) in into
-        .iter_mut()
-        .zip(chunks.map(|chunk| translate_chunk(chunk.iter())))
-    {
-        *e = b;
-        is_error |= err;
-    }
-    // Handle last element. We could handle all elements in a single loop using
-    // chunk instead of chunks_exact, but that would cause worse code to be emitted.
-    if let Some(e) = into.last_mut() {
-        *e = last_encoding
-    };
-    if is_error {
-        None
-    } else {
-        Some(())
-    }
-}
-
-fn translate_chunk<'a, T: Iterator<Item = &'a u8>>(x: T) -> (u8, bool) {
-    // Statically and locally verify it has 256 elements for safety
-    let lut: [u8; 256] = LUT;
-    x.fold((0, false), |(kmer, is_error), byte| unsafe {
-        let b = lut.get_unchecked(*byte as usize);
-        ((kmer << 2) | b, is_error | (*b == 0xff))
-    })
-}
-
-fn reverse_complement(k: NonZeroU8, kmer: &mut [u8]) -> &[u8] {
-    // First we reverse. We need to reverse each byte (chunk of 4 2-bit symbols)
-    // then we bitreverse each byte.
-    // So e.g. a byte like ABCDEFGH becomes HGFEDCBA, when it should be
-    // GHEFCDAB. So, we also do a bit of bitshuffling to get correctly reversed.
-    // It should optimise well.
-    kmer.reverse();
-    for i in kmer.iter_mut() {
-        *i = {
-            let r = i.reverse_bits();
-            let bitreversed = ((r & 0b10101010) >> 1) | ((r & 0b01010101) << 1);
-            // Also complement by bitwise not on the bits. This works due to how
-            // the nucleotides are stored
-            !bitreversed
+    let mut component_paths: Vec<Vec<Vec<(usize, bool)>>> = vec![Vec::new(); n_large];
+    for path in paths.iter() {
+        let c = components[path[0].0].unwrap();
+        if c < n_large {
+            component_paths[c].push(path.clone());
         }
     }
-
-    // The reversing operation have also reversed where the unused padding bits
-    // are. E.g. for a 5-mer it's encoded as AABBCCDD xxxxxxEE, then when reversed
-    // its EExxxxxx DDCCBBAA, when the correct result is EEDDCCBB xxxxxxAA.
-    // We solve this by shifting the bits.
-    let used_bits = 2 * (k.get() % 4);
-    let unused_bits = 8 - used_bits;
-    let fst = kmer.first_mut().unwrap();
-    // First, shift the first byte. In the example above, it's the EExxxxxx shifted by 6.
-    *fst >>= unused_bits;
-    // It's now xxxxxxEE DDCCBBAA
-
-    // We now need to shift all bytes leftward.
-    for i in 0..kmer.len() - 1 {
-        // Each byte is its own content shifted leftward, OR'd with the next byte,
-        // shifted rightwards.
-        unsafe {
-            let v =
-                (kmer.get_unchecked(i) << unused_bits) | (kmer.get_unchecked(i + 1) >> used_bits);
-            *(kmer.get_unchecked_mut(i)) = v;
-        }
+    let extension = match writer.format {
+        Format::Gfa => "gfa",
+        Format::Json => "json",
+    };
+    for c in 0..n_large {
+        let path = dir.join(format!("component{}.{}", c + 1, extension));
+        let mut out = open_output(Some(&path))?;
+        writer.write(
+            &mut out,
+            members[c].iter().map(|&i| contigs[i].as_ref().unwrap()),
+            contigs,
+            &component_edges[c],
+            &[],
+            &component_paths[c],
+        )?;
+        out.flush()?;
     }
-    // It's now EEDDCCBB DDCCBBAA
-
-    // The last byte just needs its upper bits masked
-    unsafe { *kmer.last_mut().unwrap_unchecked() &= (1u8 << used_bits).wrapping_sub(1) };
-
-    // We now have EEDDCCBB xxxxxxAA, the correct answer.
-    kmer
+    info!(
+        "Wrote {} components with at least {} contigs to \"{}\"",
+        n_large,
+        min_size,
+        dir.display()
+    );
+    Ok(())
 }
 
-#[cfg(test)]
-mod test_rc {
-    use crate::{encoding_size, reverse_complement, translate};
-    use std::num::NonZeroU8;
-
-    #[test]
-    fn test_rc_fn() {
-        for (i, j) in [(b"atcgactacG", b"cGTAGTCGAT")] {
-            let n: NonZeroU8 = i
-                .len()
-                .try_into()
-                .ok()
-                .and_then(|b| NonZeroU8::new(b))
-                .unwrap();
-            assert_eq!(n.get() as usize, j.len());
-            let mut a = vec![0u8; encoding_size(n).get() as usize];
-            let mut b = a.clone();
-            translate(i, &mut a).unwrap();
-            reverse_complement(NonZeroU8::new(10).unwrap(), &mut a);
-            translate(j, &mut b);
-            assert_eq!(a, b);
-        }
+// Write the identifiers of the skipped records, and why they were skipped, as a TSV file
+fn write_skipped(path: &Path, skipped: &[(String, Skip)]) -> Result<()> {
+    let mut out = open_output(Some(path))?;
+    out.write_all(b"contig\treason\n")?;
+    for (id, reason) in skipped.iter() {
+        writeln!(out, "{}\t{}", id, reason.as_str())?;
     }
+    out.flush()?;
+    Ok(())
 }
 
-// According to the GFA specs, FASTA identifiers must conform to this pattern.
-// Too bad if we have identifiers which don't - we must end the program.
-fn is_acceptable_identifier(s: &[u8]) -> bool {
-    s.split_first().is_some_and(|(first, rest)| {
-        ((b'!'..=b')').contains(first)
-            | (b'+'..=b'<').contains(first)
-            | (b'>'..=b'~').contains(first))
-            & rest
-                .iter()
-                .fold(true, |acc, b| acc & (b'!'..=b'~').contains(b))
-    })
-}
-
-fn find_edges(
-    input: impl BufRead,
-    k: NonZeroU8,
-    min_contig_length: usize,
-) -> Result<(Vec<Option<String>>, Vec<Edge>)> {
-    // Approach: We store the starting kmers (forward and reverse-complement)
-    // in a HashMap, with keys being kmers and values being KmerOrigin to show
-    // where the kmer is from.
-    // We can then look up in the hash map to match KmerOrigins with shared kmers
-    // and create edges between them
-    let mut kmers = Kmers::new(k);
-    let reader = Reader::new(input);
-    // None if the record is skipped due to being too short
-    let mut identifiers: Vec<Option<String>> = Vec::new();
-    for (record_index, record) in reader.records().enumerate() {
-        let record = record.context("Failed to parse record from FASTA file")?;
-        let seq = record.seq();
-        if seq.len() >= min_contig_length && kmers.add(record.seq(), record_index).is_some() {
-            let id = record.id();
-            if !is_acceptable_identifier(id.as_bytes()) {
-                bail!("Invalid record identifier: {}.\nIdentifier names are restricted by the GFA format to regex [!-)+-<>-~][!-~]*.", id);
-            }
-            identifiers.push(Some(id.to_owned()))
-        } else {
-            identifiers.push(None);
-        }
-    }
-    // Now, for every end kmer, we see if there are any matching starting kmers, then
-    // we create an edge from end kmer to start kmer.
-    // Why not from start to end? Remember, if contig B follows contig A, then we
-    // go from the last contig of A to the first contig of B.
-    let mut map: HashMap<&[u8], SmallVec<[KmerOrigin; 2]>> = HashMap::new();
-    for (start_data, kmer) in kmers.iter_kmers() {
-        map.entry(kmer).or_default().push(*start_data);
-    }
-
-    let mut edges: Vec<Edge> = Vec::new();
-    // Since `map` borrows from `kmers`, we can't mutate the buffer inside `kmers`
-    // and must allocate a new one. No worries.
-    let mut rc_buffer: Vec<u8> = vec![0; encoding_size(k).get() as usize];
-    for (rc_end_kmer, rc_end_datas) in map.iter() {
-        // The map contains starting kmers. By reverse-complementing them, we get
-        // ending kmers, which we then use to look up into the map.
-        rc_buffer.copy_from_slice(rc_end_kmer);
-        let end_kmer = reverse_complement(k, &mut rc_buffer);
-        if let Some(start_datas) = map.get(end_kmer) {
-            for start_data in start_datas.iter() {
-                for rc_end_data in rc_end_datas.iter() {
-                    edges.push(Edge {
-                        from_end: rc_end_data.reverse_complement(),
-                        to_start: *start_data,
-                    })
-                }
-            }
-        }
+// Write the old and new identifiers of the renamed contigs as a TSV file
+fn write_renamed(path: &Path, renamed: &[(String, String)]) -> Result<()> {
+    let mut out = open_output(Some(path))?;
+    out.write_all(b"old\tnew\n")?;
+    for (old, new) in renamed.iter() {
+        writeln!(out, "{}\t{}", old, new)?;
     }
-    Ok((identifiers, edges))
+    out.flush()?;
+    Ok(())
 }
 
-fn rc_byte(rc: bool) -> &'static [u8] {
-    if rc {
-        b"-"
+// Write to stdout, to a file, or to a gzipped file if the name ends with .gz
+fn open_output(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    let Some(p) = path else {
+        return Ok(Box::new(BufWriter::new(stdout().lock())));
+    };
+    let file = File::create(p)
+        .with_context(|| format!("Could not create output file \"{}\"", p.to_string_lossy()))?;
+    if p.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(GzEncoder::new(
+            BufWriter::new(file),
+            Compression::default(),
+        )))
     } else {
-        b"+"
+        Ok(Box::new(BufWriter::new(file)))
     }
 }
 
-// Write a minimal GFA
-fn print_gfa(identifiers: &[Option<String>], edges: &[Edge]) -> Result<()> {
-    let mut out = BufWriter::new(stdout().lock());
-    // Write header - this is GFA version 1.2
-    out.write_all(b"H\tVN:Z:1.2\n")?;
-    for edge in edges.iter() {
-        // Write L lines: L
-        out.write_all(b"L\t")?;
-        // Name of sequende with end kmer (from)
-        out.write_all(
-            identifiers[edge.from_end.index()]
-                .as_ref()
-                .unwrap()
-                .as_bytes(),
-        )?;
-        out.write_all(b"\t")?;
-        // Whether the from sequence is forward or reverse
-        out.write_all(rc_byte(edge.from_end.is_rc()))?;
-        out.write_all(b"\t")?;
-        // Same for the to edge
-        out.write_all(
-            identifiers[edge.to_start.index()]
-                .as_ref()
-                .unwrap()
-                .as_bytes(),
-        )?;
-        out.write_all(b"\t")?;
-        out.write_all(rc_byte(edge.to_start.is_rc()))?;
-        // A star for the missing overlap (which carries no information, the user should know
-        // it's always just one kmer's overlap)
-        out.write_all(b"\t*\n")?;
-    }
-    Ok(())
-}
-
-const LONG_ABOUT: &str = "Print a minimal GFA v1.2 file to stdout from a MEGAHIT contig file.
-Output file only contains the H (header) line and minimal L (link) lines.
+const LONG_ABOUT: &str =
+    "Print a minimal GFA v1.2 file from a MEGAHIT contig file, to stdout or to a file.
+Output file only contains the H (header) line and minimal L (link) lines, and optionally
+S (segment) lines with the contigs, so the graph can be used without the FASTA file.
+With --gfa-version 2, a GFA 2.0 file is written instead, with E (edge) lines.
+SPAdes contigs can be used with --assembler spades.
 See more information in the README.md.
 Usage: megagfa -i final.contigs.fa -k 141 > links.gfa";
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = LONG_ABOUT)]
 struct Cli {
-    /// Value of --k-max used in assembly
+    /// Value of --k-max used in assembly [taken from identifiers like k141_12]
     #[arg(short)]
-    k: NonZeroU8,
+    k: Option<NonZeroU16>,
+
+    /// Assembler that made the contigs. SPAdes requires -k
+    #[arg(long, value_enum, default_value_t = Assembler::Megahit)]
+    assembler: Assembler,
 
-    /// Input file (may be gzipped) [stdin]
+    /// Take k from each identifier, and only link contigs with the same k
+    #[arg(long, conflicts_with = "k")]
+    multi_k: bool,
+
+    /// Input file (may be gzipped), can be repeated to merge assemblies [stdin]
     #[arg(short)]
-    i: Option<PathBuf>,
+    i: Vec<PathBuf>,
+
+    /// Output file (gzipped if name ends with .gz) [stdout]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
 
     /// Minimum contig length
     #[arg(short, default_value_t = 200)]
     min_contig_length: u32,
+
+    /// Write S lines with the contig sequences (kept in memory)
+    #[arg(long)]
+    with_segments: bool,
+
+    /// With --with-segments, write only the contig lengths, not the sequences
+    #[arg(long, requires = "with_segments")]
+    segments_no_seq: bool,
+
+    /// Number of threads used for encoding and linking kmers
+    #[arg(short, long, default_value = "1")]
+    threads: NonZeroUsize,
+
+    /// Use less memory, by reading the input files twice, and only keeping the identifiers
+    /// of linked contigs
+    #[arg(long, requires = "i", conflicts_with = "with_segments")]
+    low_memory: bool,
+
+    /// Instead of failing on identifiers not allowed in GFA, replace the invalid characters
+    /// by underscores, and write the old and new identifiers to this TSV file
+    #[arg(long)]
+    rename_invalid: Option<PathBuf>,
+
+    /// Write the kmers to temporary files in this directory, and link them one partition at
+    /// a time, for assemblies whose kmers do not fit in memory
+    #[arg(long)]
+    temp_dir: Option<PathBuf>,
+
+    /// Number of partitions of the kmers with --temp-dir. More partitions use less memory
+    #[arg(long, default_value = "64", requires = "temp_dir")]
+    partitions: NonZeroUsize,
+
+    /// Remove all links of a kmer that more than this many contigs start or end with,
+    /// e.g. repeats that make the graph hard to view
+    #[arg(long)]
+    max_degree: Option<usize>,
+
+    /// Link contigs whose first or last kmer contains IUPAC codes like N or R, by expanding
+    /// the kmer to all the kmers it stands for, if there are at most this many
+    #[arg(long)]
+    expand_ambiguous: Option<NonZeroUsize>,
+
+    /// Write the identifiers of the records that are not in the graph to this TSV file,
+    /// with the reason: too_short, shorter_than_k, ambiguous_kmer or index_overflow
+    #[arg(long)]
+    skipped: Option<PathBuf>,
+
+    /// Check that the linked contigs share a kmer, and exit with an error if not
+    #[arg(long)]
+    verify: bool,
+
+    /// Only keep links where the shared kmer has at least this complexity, the fraction
+    /// of the possible trinucleotides it contains, to remove links through low-complexity
+    /// kmers like homopolymers [0.5 if given without a value]
+    #[arg(long, num_args = 0..=1, default_missing_value = "0.5")]
+    min_overlap_complexity: Option<f64>,
+
+    /// Do not sort the links and remove identical links
+    #[arg(long)]
+    allow_duplicates: bool,
+
+    /// Remove links from a contig to itself
+    #[arg(long)]
+    no_self_loops: bool,
+
+    /// Write links from a contig to itself to this TSV file, with whether
+    /// they are due to a palindromic kmer or a loop
+    #[arg(long)]
+    report_palindromes: Option<PathBuf>,
+
+    /// Add links between contigs from paired reads in this SAM/BAM file, which are aligned
+    /// to two contigs near their ends, with the number of pairs in an RC tag
+    #[arg(long, conflicts_with = "low_memory")]
+    link_bam: Option<PathBuf>,
+
+    /// Maximum distance of the reads from the contig ends, for --link-bam
+    #[arg(long, default_value_t = 500, requires = "link_bam")]
+    link_distance: u32,
+
+    /// Minimum number of read pairs supporting a link, for --link-bam
+    #[arg(long, default_value_t = 2, requires = "link_bam")]
+    link_min_reads: u32,
+
+    /// Minimum mapping quality of the first read of a pair, for --link-bam
+    #[arg(long, default_value_t = 0, requires = "link_bam")]
+    link_min_mapq: u8,
+
+    /// Write unbranched chains of linked contigs as P lines (O lines in GFA2)
+    #[arg(long)]
+    paths: bool,
+
+    /// Write the overlap of links as a CIGAR string of k matches instead of *
+    #[arg(long)]
+    cigar_overlap: bool,
+
+    /// Add the lengths of the two contigs of each link as L1:i and L2:i tags
+    #[arg(long)]
+    length_tags: bool,
+
+    /// Write the connected component of each contig to this file, in the format of
+    /// a Vamb cluster file
+    #[arg(long)]
+    components: Option<PathBuf>,
+
+    /// Write a graph for each connected component to this directory
+    #[arg(long)]
+    component_dir: Option<PathBuf>,
+
+    /// Minimum number of contigs of the components written with --component-dir
+    #[arg(long, default_value_t = 2, requires = "component_dir")]
+    min_component_size: usize,
+
+    /// Format of the output. JSON is a node-link graph, e.g. for networkx
+    #[arg(long, value_enum, default_value_t = Format::Gfa)]
+    format: Format,
+
+    /// GFA version of the output
+    #[arg(long, value_enum, default_value_t = GfaVersion::V1)]
+    gfa_version: GfaVersion,
+
+    #[command(flatten)]
+    logging: LogArgs,
 }
@@ -0,0 +1,155 @@
+use crate::{Contig, Edge, KmerOrigin};
+use anyhow::{bail, Result};
+use htslite::{
+    AlignmentReader, Record, FLAG_DUPLICATE, FLAG_FIRST, FLAG_MATE_REVERSE, FLAG_MATE_UNMAPPED,
+    FLAG_PAIRED, FLAG_QCFAIL, FLAG_REVERSE,
+};
+use progress::Progress;
+use std::collections::HashMap;
+
+/// Links between contigs from read pairs whose reads are aligned to two different contigs,
+/// each within `max_distance` bases of the end of the contig it points towards.
+/// Each link is found once, not also as its reverse complement, with the number of read pairs
+/// supporting it, and only links supported by at least `min_reads` pairs are returned.
+/// The references of the alignments are matched to the contigs by their identifiers.
+pub fn read_links(
+    reader: &mut AlignmentReader,
+    contigs: &[Option<Contig>],
+    max_distance: u32,
+    min_mapq: u8,
+    min_reads: u32,
+) -> Result<Vec<(Edge, u32)>> {
+    let indices: HashMap<&str, usize> = contigs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| c.as_ref().map(|c| (c.id.as_str(), i)))
+        .collect();
+    // The contig index and length of each reference, if it is in the graph
+    let mut references: Vec<Option<(usize, u32)>> = Vec::new();
+    for reference in reader.header().references.iter() {
+        let Some(&index) = indices.get(reference.name.as_str()) else {
+            references.push(None);
+            continue;
+        };
+        let length = contigs[index].as_ref().unwrap().length;
+        if reference.length != length as u64 {
+            bail!(
+                "Contig \"{}\" has length {}, but length {} in the alignment file",
+                reference.name,
+                length,
+                reference.length
+            );
+        }
+        references.push(Some((index, length as u32)));
+    }
+    let mut counts: HashMap<(u32, u32), (Edge, u32)> = HashMap::new();
+    let mut record = Record::default();
+    let progress = Progress::new("Reading alignments", "records", None);
+    while reader.read_record(&mut record)? {
+        progress.inc(1);
+        // Each pair is counted once, from its first read
+        if !record.has_flag(FLAG_PAIRED)
+            || !record.has_flag(FLAG_FIRST)
+            || record.is_unmapped()
+            || record.is_non_primary()
+            || record.has_flag(FLAG_MATE_UNMAPPED | FLAG_DUPLICATE | FLAG_QCFAIL)
+            || record.mapq < min_mapq
+        {
+            continue;
+        }
+        if let Some(edge) = pair_edge(&record, &references, max_distance) {
+            let reverse = edge.reverse_complement();
+            let edge = if reverse.key() < edge.key() {
+                reverse
+            } else {
+                edge
+            };
+            counts.entry(edge.key()).or_insert((edge, 0)).1 += 1;
+        }
+    }
+    progress.finish();
+    let mut links: Vec<(Edge, u32)> = counts
+        .into_values()
+        .filter(|&(_, n)| n >= min_reads)
+        .collect();
+    links.sort_unstable_by_key(|(edge, _)| edge.key());
+    Ok(links)
+}
+
+// The link between the contigs of a read and its mate, if they are aligned to different contigs
+// near the ends the reads point towards. With the usual forward-reverse orientation of paired
+// reads, a forward read points towards the end of its contig, and a reverse read towards
+// the start. The mate's end is not in the record, so its length is taken to be that of the read.
+fn pair_edge(
+    record: &Record,
+    references: &[Option<(usize, u32)>],
+    max_distance: u32,
+) -> Option<Edge> {
+    let (from, from_length) = references[record.ref_id?]?;
+    let (to, to_length) = references[record.next_ref_id?]?;
+    if from == to {
+        return None;
+    }
+    let pos = record.pos?;
+    let mate_pos = record.next_pos?;
+    let length = record.reference_length();
+    // Leaving the first contig at its end is the + strand, and entering the second contig
+    // at its start is also the + strand
+    let from_rc = record.has_flag(FLAG_REVERSE);
+    let to_rc = !record.has_flag(FLAG_MATE_REVERSE);
+    let from_distance = if from_rc {
+        pos + length
+    } else {
+        from_length.saturating_sub(pos)
+    };
+    let to_distance = if to_rc {
+        to_length.saturating_sub(mate_pos)
+    } else {
+        mate_pos + length
+    };
+    if from_distance > max_distance || to_distance > max_distance {
+        return None;
+    }
+    Some(Edge {
+        from_end: KmerOrigin(from as u32 | (from_rc as u32) << 31),
+        to_start: KmerOrigin(to as u32 | (to_rc as u32) << 31),
+    })
+}
+
+#[cfg(test)]
+mod test_reads {
+    use crate::{read_links, Contig};
+    use htslite::AlignmentReader;
+    use std::num::NonZeroU16;
+
+    #[test]
+    fn test_read_links() {
+        let contig = |id: &str| {
+            Some(Contig {
+                id: id.to_owned(),
+                k: NonZeroU16::new(5).unwrap(),
+                length: 1000,
+                depth: None,
+                seq: None,
+                ends: None,
+            })
+        };
+        let contigs = vec![contig("a"), None, contig("b"), contig("c")];
+        // a+ to b+, supported by two pairs, once seen from each contig. a- to c-, too far
+        // from the end of c. The second reads and the read to d are not used.
+        let sam =
+            "@SQ\tSN:a\tLN:1000\n@SQ\tSN:b\tLN:1000\n@SQ\tSN:c\tLN:1000\n@SQ\tSN:d\tLN:1000\n\
+            r1\t97\ta\t901\t60\t100M\tb\t51\t0\t*\t*\n\
+            r1\t145\tb\t51\t60\t100M\ta\t901\t0\t*\t*\n\
+            r2\t81\tb\t11\t60\t100M\ta\t951\t0\t*\t*\n\
+            r3\t81\ta\t11\t60\t100M\tc\t501\t0\t*\t*\n\
+            r4\t97\ta\t901\t60\t100M\td\t11\t0\t*\t*\n";
+        let mut reader = AlignmentReader::new(Box::new(sam.as_bytes())).unwrap();
+        let links = read_links(&mut reader, &contigs, 300, 0, 1).unwrap();
+        assert_eq!(links.len(), 1);
+        let (edge, n) = links[0];
+        assert_eq!(n, 2);
+        assert_eq!((edge.from_end.index(), edge.from_end.is_rc()), (0, false));
+        assert_eq!((edge.to_start.index(), edge.to_start.is_rc()), (2, false));
+    }
+}
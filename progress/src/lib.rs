@@ -0,0 +1,297 @@
+//! Progress bars and logging shared by the tools in this repository, so they all give
+//! the same feedback on long runs. A tool flattens [`LogArgs`] into its command line
+//! arguments, calls [`init`] first thing in `main`, and then logs with the re-exported
+//! macros, e.g. `progress::info!`.
+//! Progress bars are opt-in with `--progress`, and are only drawn if stderr is a terminal,
+//! so they never end up in log files. Log messages are printed above the bars.
+
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use indicatif::{
+    BinaryBytes, HumanCount, HumanDuration, MultiProgress, ProgressBar, ProgressBarIter,
+    ProgressStyle,
+};
+use log::{LevelFilter, Metadata, Record};
+use std::{
+    fs::File,
+    io::{stderr, IsTerminal, LineWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+pub use log::{debug, error, info, trace, warn};
+
+// Set if progress bars are drawn. Bars not added to it are hidden.
+static BARS: OnceLock<MultiProgress> = OnceLock::new();
+static START: OnceLock<Instant> = OnceLock::new();
+
+#[derive(Args, Debug)]
+pub struct LogArgs {
+    /// Show progress bars, if stderr is a terminal
+    #[arg(long, global = true)]
+    pub progress: bool,
+
+    /// Minimum level of log messages
+    #[arg(long, global = true, value_enum, default_value_t = Level::Info)]
+    pub log_level: Level,
+
+    /// Write log messages to this file instead of stderr
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<Level> for LevelFilter {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Off => LevelFilter::Off,
+            Level::Error => LevelFilter::Error,
+            Level::Warn => LevelFilter::Warn,
+            Level::Info => LevelFilter::Info,
+            Level::Debug => LevelFilter::Debug,
+            Level::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// Set up logging and progress bars. Must be called once, before anything is logged.
+pub fn init(args: &LogArgs) -> Result<()> {
+    START.get_or_init(Instant::now);
+    let file = match &args.log_file {
+        None => None,
+        Some(p) => Some(Mutex::new(LineWriter::new(File::create(p).with_context(
+            || format!("Could not create log file \"{}\"", p.display()),
+        )?))),
+    };
+    if args.progress && stderr().is_terminal() {
+        BARS.get_or_init(MultiProgress::new);
+    }
+    let level = LevelFilter::from(args.log_level);
+    let program = std::env::args()
+        .next()
+        .and_then(|a| Some(Path::new(&a).file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    log::set_boxed_logger(Box::new(Logger {
+        level,
+        program,
+        file,
+    }))
+    .context("Logging was already initialized")?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// Run `f` with the progress bars cleared, e.g. to write to stderr without garbling the bars
+pub fn suspend<F: FnOnce() -> R, R>(f: F) -> R {
+    match BARS.get() {
+        Some(bars) => bars.suspend(f),
+        None => f(),
+    }
+}
+
+fn elapsed() -> Duration {
+    START.get_or_init(Instant::now).elapsed()
+}
+
+struct Logger {
+    level: LevelFilter,
+    program: String,
+    file: Option<Mutex<LineWriter<File>>>,
+}
+
+// Lines look like "[   12.3s INFO  fq2fa] Kept 10 of 12 reads", where the time is since
+// the program started. The name of the program helps if several tools log to the same file.
+fn format_line(elapsed: Duration, program: &str, record: &Record) -> String {
+    format!(
+        "[{:>8.1}s {:<5} {}] {}\n",
+        elapsed.as_secs_f64(),
+        record.level(),
+        program,
+        record.args()
+    )
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_line(elapsed(), &self.program, record);
+        // Logging should never make a program fail, so write errors are ignored
+        match &self.file {
+            Some(file) => {
+                let _ = file.lock().unwrap().write_all(line.as_bytes());
+            }
+            None => match BARS.get() {
+                Some(bars) => bars.suspend(|| {
+                    let _ = stderr().write_all(line.as_bytes());
+                }),
+                None => {
+                    let _ = stderr().write_all(line.as_bytes());
+                }
+            },
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+/// Progress of a long-running task, counted in some unit, e.g. reads or bytes.
+/// The count is kept even if no bar is drawn, and is logged with the rate by [`Progress::finish`].
+/// It can be cloned and shared between threads.
+#[derive(Clone)]
+pub struct Progress {
+    bar: ProgressBar,
+    unit: &'static str,
+    start: Instant,
+}
+
+impl Progress {
+    /// Count items, e.g. reads. If the total is known, the bar shows the fraction done.
+    pub fn new(message: &str, unit: &'static str, total: Option<u64>) -> Self {
+        let template = match total {
+            Some(_) => "{msg} [{elapsed_precise}] {wide_bar} {human_pos}/{human_len} ({per_sec}, eta {eta})",
+            None => "{spinner} {msg} [{elapsed_precise}] {human_pos} ({per_sec})",
+        };
+        Self::with_template(message, unit, total, template)
+    }
+
+    /// Count bytes, e.g. of an input file. Use [`Progress::wrap_read`] to count the bytes read.
+    pub fn bytes(message: &str, total: Option<u64>) -> Self {
+        let template = match total {
+            Some(_) => "{msg} [{elapsed_precise}] {wide_bar} {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec}, eta {eta})",
+            None => "{spinner} {msg} [{elapsed_precise}] {binary_bytes} ({binary_bytes_per_sec})",
+        };
+        Self::with_template(message, "bytes", total, template)
+    }
+
+    fn with_template(
+        message: &str,
+        unit: &'static str,
+        total: Option<u64>,
+        template: &str,
+    ) -> Self {
+        let bar = match BARS.get() {
+            None => ProgressBar::hidden(),
+            Some(bars) => {
+                let bar = bars.add(ProgressBar::no_length());
+                bar.set_style(ProgressStyle::with_template(template).unwrap());
+                if total.is_none() {
+                    bar.enable_steady_tick(Duration::from_millis(200));
+                }
+                bar
+            }
+        };
+        if let Some(n) = total {
+            bar.set_length(n);
+        }
+        bar.set_message(message.to_owned());
+        Self {
+            bar,
+            unit,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn inc(&self, n: u64) {
+        self.bar.inc(n)
+    }
+
+    /// Add to the total, when it is found while the task runs, e.g. while walking directories.
+    /// Start with a total of `Some(0)` to show the fraction done.
+    pub fn inc_length(&self, n: u64) {
+        self.bar.inc_length(n)
+    }
+
+    pub fn set_position(&self, n: u64) {
+        self.bar.set_position(n)
+    }
+
+    pub fn position(&self) -> u64 {
+        self.bar.position()
+    }
+
+    /// Wrap a reader, so the bytes read from it are counted
+    pub fn wrap_read<R: Read>(&self, reader: R) -> ProgressBarIter<R> {
+        self.bar.wrap_read(reader)
+    }
+
+    /// Remove the bar, and log the count and rate
+    pub fn finish(&self) {
+        // Finishing sets the position to the total, which may not have been reached
+        let n = self.position();
+        self.bar.finish_and_clear();
+        info!(
+            "{}",
+            summary(&self.bar.message(), n, self.unit, self.start.elapsed())
+        );
+    }
+}
+
+fn summary(message: &str, n: u64, unit: &str, elapsed: Duration) -> String {
+    let per_sec = n as f64 / elapsed.as_secs_f64().max(0.001);
+    let (count, rate) = if unit == "bytes" {
+        (
+            BinaryBytes(n).to_string(),
+            format!("{}/s", BinaryBytes(per_sec as u64)),
+        )
+    } else {
+        (
+            format!("{} {}", HumanCount(n), unit),
+            format!("{} {}/s", HumanCount(per_sec as u64), unit),
+        )
+    };
+    format!(
+        "{}: {} in {} ({})",
+        message,
+        count,
+        HumanDuration(elapsed),
+        rate
+    )
+}
+
+#[cfg(test)]
+mod test_progress {
+    use crate::{format_line, summary};
+    use std::time::Duration;
+
+    #[test]
+    fn test_format() {
+        // The arguments of a record only live until the end of the statement
+        let line = format_line(
+            Duration::from_millis(12_345),
+            "fq2fa",
+            &log::Record::builder()
+                .args(format_args!("Kept {} reads", 10))
+                .level(log::Level::Info)
+                .build(),
+        );
+        assert_eq!(line, "[    12.3s INFO  fq2fa] Kept 10 reads\n");
+        assert_eq!(
+            summary("Reading", 20_000, "reads", Duration::from_secs(2)),
+            "Reading: 20,000 reads in 2 seconds (10,000 reads/s)"
+        );
+        assert_eq!(
+            summary("Hashing", 3 << 20, "bytes", Duration::from_secs(3)),
+            "Hashing: 3.00 MiB in 3 seconds (1.00 MiB/s)"
+        );
+    }
+}
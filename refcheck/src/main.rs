@@ -0,0 +1,322 @@
+use anyhow::{self, bail, Context, Result};
+use bio::io::fasta::Reader;
+use clap::Parser;
+use fadict::{open_input, sequence_md5};
+use htslite::{AlignmentReader, Header};
+use progress::{info, LogArgs, Progress};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{stdout, BufRead, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let reader = AlignmentReader::open(args.alignment.as_deref())?;
+    let alignment = header_sequences(reader.header());
+    let is_fai = args.reference.extension().is_some_and(|e| e == "fai");
+    if args.md5 && is_fai {
+        bail!("Checking MD5 checksums requires a FASTA file, not a .fai index");
+    }
+    let reference = if is_fai {
+        read_fai(&args.reference)?
+    } else {
+        read_fasta(&args.reference, args.md5)?
+    };
+    // The alignment header is checked for duplicates when it is read
+    check_unique(&reference)?;
+
+    let problems = compare(&alignment, &reference, args.md5, args.ignore_order);
+    let mut out = BufWriter::new(stdout().lock());
+    out.write_all(b"sequence\tproblem\talignment\treference\n")?;
+    for problem in problems.iter() {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            problem.name, problem.kind, problem.alignment, problem.reference
+        )?;
+    }
+    out.flush()?;
+    if !problems.is_empty() {
+        bail!(
+            "Found {} mismatches between alignment header and reference",
+            problems.len()
+        );
+    }
+    info!(
+        "Alignment header matches reference ({} sequences)",
+        reference.len()
+    );
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Sequence {
+    name: String,
+    length: u64,
+    md5: Option<String>,
+}
+
+struct Problem {
+    name: String,
+    kind: &'static str,
+    alignment: String,
+    reference: String,
+}
+
+// The names and lengths are taken from the parsed header, since BAM files store them
+// separately from the text, while MD5s are only present in the text.
+fn header_sequences(header: &Header) -> Vec<Sequence> {
+    let mut md5s: HashMap<&str, &str> = HashMap::new();
+    for line in header.text.lines().filter(|l| l.starts_with("@SQ\t")) {
+        let tag = |t: &str| line.split('\t').find_map(|f| f.strip_prefix(t));
+        if let (Some(name), Some(md5)) = (tag("SN:"), tag("M5:")) {
+            md5s.insert(name, md5);
+        }
+    }
+    header
+        .references
+        .iter()
+        .map(|r| Sequence {
+            name: r.name.clone(),
+            length: r.length,
+            md5: md5s.get(r.name.as_str()).map(|m| m.to_ascii_lowercase()),
+        })
+        .collect()
+}
+
+// A name must identify a single sequence, or its length and MD5 can't be compared
+fn check_unique(sequences: &[Sequence]) -> Result<()> {
+    let mut names: HashSet<&str> = HashSet::new();
+    for seq in sequences.iter() {
+        if !names.insert(&seq.name) {
+            bail!(
+                "Sequence name \"{}\" is present multiple times in the reference",
+                seq.name
+            );
+        }
+    }
+    Ok(())
+}
+
+fn compare(
+    alignment: &[Sequence],
+    reference: &[Sequence],
+    check_md5: bool,
+    ignore_order: bool,
+) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = Vec::new();
+    let problem = |name: &str, kind, alignment: String, reference: String| Problem {
+        name: name.to_owned(),
+        kind,
+        alignment,
+        reference,
+    };
+    let reference_index: HashMap<&str, usize> = reference
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.name.as_str(), i))
+        .collect();
+    let alignment_index: HashMap<&str, usize> = alignment
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.name.as_str(), i))
+        .collect();
+
+    for seq in alignment.iter() {
+        let Some(&i) = reference_index.get(seq.name.as_str()) else {
+            problems.push(problem(
+                &seq.name,
+                "missing_in_reference",
+                seq.length.to_string(),
+                "-".to_owned(),
+            ));
+            continue;
+        };
+        let other = &reference[i];
+        if seq.length != other.length {
+            problems.push(problem(
+                &seq.name,
+                "length",
+                seq.length.to_string(),
+                other.length.to_string(),
+            ));
+        }
+        if check_md5 {
+            match (&seq.md5, &other.md5) {
+                (None, _) => problems.push(problem(
+                    &seq.name,
+                    "no_md5_in_header",
+                    "-".to_owned(),
+                    other.md5.clone().unwrap_or_default(),
+                )),
+                (Some(a), Some(b)) if a != b => {
+                    problems.push(problem(&seq.name, "md5", a.clone(), b.clone()))
+                }
+                _ => (),
+            }
+        }
+    }
+    for seq in reference.iter() {
+        if !alignment_index.contains_key(seq.name.as_str()) {
+            problems.push(problem(
+                &seq.name,
+                "missing_in_alignment",
+                "-".to_owned(),
+                seq.length.to_string(),
+            ));
+        }
+    }
+
+    // Tools like GATK require the same order. Only report the first out of order
+    // sequence, since a single misplaced sequence shifts all others.
+    if !ignore_order {
+        let shared = |seqs: &[Sequence], index: &HashMap<&str, usize>| -> Vec<String> {
+            seqs.iter()
+                .filter(|s| index.contains_key(s.name.as_str()))
+                .map(|s| s.name.clone())
+                .collect()
+        };
+        let (a, r) = (
+            shared(alignment, &reference_index),
+            shared(reference, &alignment_index),
+        );
+        if let Some((x, _)) = a.iter().zip(r.iter()).find(|(x, y)| x != y) {
+            problems.push(problem(
+                x,
+                "order",
+                (alignment_index[x.as_str()] + 1).to_string(),
+                (reference_index[x.as_str()] + 1).to_string(),
+            ));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod test_compare {
+    use crate::{check_unique, compare, Sequence};
+
+    fn seq(name: &str, length: u64, md5: Option<&str>) -> Sequence {
+        Sequence {
+            name: name.to_owned(),
+            length,
+            md5: md5.map(|m| m.to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let reference = [
+            seq("a", 10, Some("aa")),
+            seq("b", 20, Some("bb")),
+            seq("c", 30, Some("cc")),
+        ];
+        assert!(compare(&reference, &reference, true, false).is_empty());
+
+        let alignment = [
+            seq("b", 20, Some("bx")),
+            seq("a", 11, None),
+            seq("d", 5, None),
+        ];
+        let kinds = |md5, ignore_order| -> Vec<&str> {
+            compare(&alignment, &reference, md5, ignore_order)
+                .iter()
+                .map(|p| p.kind)
+                .collect()
+        };
+        assert_eq!(
+            kinds(true, false),
+            [
+                "md5",
+                "length",
+                "no_md5_in_header",
+                "missing_in_reference",
+                "missing_in_alignment",
+                "order"
+            ]
+        );
+        assert_eq!(
+            kinds(false, true),
+            ["length", "missing_in_reference", "missing_in_alignment"]
+        );
+    }
+
+    #[test]
+    fn test_check_unique() {
+        assert!(check_unique(&[seq("a", 10, None), seq("b", 10, None)]).is_ok());
+        assert!(
+            check_unique(&[seq("a", 10, None), seq("b", 5, None), seq("a", 10, None)]).is_err()
+        );
+    }
+}
+
+fn read_fai(path: &Path) -> Result<Vec<Sequence>> {
+    let mut sequences: Vec<Sequence> = Vec::new();
+    for (lineno, line) in open_input(path)?.lines().enumerate() {
+        let line = line.context("Failed to read line from .fai file")?;
+        let mut fields = line.split('\t');
+        let (Some(name), Some(length)) = (fields.next(), fields.next()) else {
+            bail!(
+                "Expected at least two fields on line {} of .fai file",
+                lineno + 1
+            );
+        };
+        let length = length
+            .parse::<u64>()
+            .with_context(|| format!("Invalid length on line {} of .fai file", lineno + 1))?;
+        sequences.push(Sequence {
+            name: name.to_owned(),
+            length,
+            md5: None,
+        });
+    }
+    Ok(sequences)
+}
+
+fn read_fasta(path: &Path, compute_md5: bool) -> Result<Vec<Sequence>> {
+    let mut sequences: Vec<Sequence> = Vec::new();
+    let progress = Progress::new("Reading reference", "sequences", None);
+    for record in Reader::from_bufread(open_input(path)?).records() {
+        let record = record.context("Failed to parse record from FASTA file")?;
+        progress.inc(1);
+        sequences.push(Sequence {
+            name: record.id().to_owned(),
+            length: record.seq().len() as u64,
+            md5: compute_md5.then(|| sequence_md5(record.seq())),
+        });
+    }
+    progress.finish();
+    Ok(sequences)
+}
+
+const LONG_ABOUT: &str =
+    "Check that the reference sequences in a SAM/BAM header match a FASTA file.
+The names, lengths and order of the @SQ lines must match the FASTA file (or its .fai index)
+exactly. With --md5, the M5 tags of the header are also checked against the sequences.
+Mismatches are printed as a TSV table, and the program exits with an error if any are found.
+Usage: refcheck -a aln.bam -r contigs.fna.gz";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Input SAM/BAM file [stdin]
+    #[arg(short)]
+    alignment: Option<PathBuf>,
+
+    /// Reference FASTA file (may be gzipped) or .fai index
+    #[arg(short, long)]
+    reference: PathBuf,
+
+    /// Check M5 tags of the header against the FASTA sequences
+    #[arg(short, long)]
+    md5: bool,
+
+    /// Do not require the sequences to be in the same order
+    #[arg(long)]
+    ignore_order: bool,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
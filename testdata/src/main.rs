@@ -0,0 +1,597 @@
+use anyhow::{self, bail, Context, Result};
+use bio::io::fasta::Reader;
+use clap::{Args, Parser, Subcommand};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use progress::{info, LogArgs, Progress};
+use std::{
+    fs::File,
+    io::{stdout, BufRead, BufReader, BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    progress::init(&cli.logging)?;
+    match cli.command {
+        Command::Contigs(args) => write_contigs(&args),
+        Command::Reads(args) => write_reads(&args),
+        Command::Clusters(args) => write_clusters(&args),
+    }
+}
+
+// The SplitMix64 generator. We implement it here instead of using the rand crate, so the
+// generated files stay the same across versions of dependencies and platforms.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in 0..n. The modulo bias is negligible for the small n used here.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // Uniform in [0, 1)
+    fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Standard normal, by the Box-Muller transform
+    fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.uniform();
+        let v = self.uniform();
+        (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+    }
+
+    fn base(&mut self, gc: f64) -> u8 {
+        let is_gc = self.uniform() < gc;
+        match (is_gc, self.next_u64() & 1 == 0) {
+            (true, true) => b'G',
+            (true, false) => b'C',
+            (false, true) => b'A',
+            (false, false) => b'T',
+        }
+    }
+}
+
+fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|b| match b {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            _ => b'N',
+        })
+        .collect()
+}
+
+// Write to stdout, to a file, or to a gzipped file if the name ends with .gz
+fn open_output(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    let Some(p) = path else {
+        return Ok(Box::new(BufWriter::new(stdout().lock())));
+    };
+    let file = File::create(p)
+        .with_context(|| format!("Could not create output file \"{}\"", p.to_string_lossy()))?;
+    if p.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(GzEncoder::new(
+            BufWriter::new(file),
+            Compression::default(),
+        )))
+    } else {
+        Ok(Box::new(BufWriter::new(file)))
+    }
+}
+
+fn read_fasta(path: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let file = File::open(path)
+        .with_context(|| format!("Could not open FASTA file \"{}\"", path.to_string_lossy()))?;
+    let reader: Box<dyn BufRead> = if path.extension().is_some_and(|e| e == "gz") {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    Reader::from_bufread(reader)
+        .records()
+        .map(|r| {
+            let r = r.context("Failed to parse record from FASTA file")?;
+            Ok((r.id().to_owned(), r.seq().to_ascii_uppercase()))
+        })
+        .collect()
+}
+
+struct Contig {
+    header: String,
+    seq: Vec<u8>,
+}
+
+fn generate_contigs(args: &ContigArgs) -> Result<Vec<Contig>> {
+    if args.min_length > args.max_length {
+        bail!("Minimum length cannot exceed maximum length");
+    }
+    if !(0.0..=1.0).contains(&args.gc) {
+        bail!("GC content must be in 0-1");
+    }
+    if let Some(k) = args.k {
+        if args.min_length <= k {
+            bail!("Minimum length must be larger than k when linking contigs");
+        }
+    }
+    let mut rng = Rng(args.seed);
+    let mut contigs: Vec<Contig> = Vec::with_capacity(args.number);
+    for i in 0..args.number {
+        let length = args.min_length + rng.below(args.max_length - args.min_length + 1);
+        let mut seq: Vec<u8> = Vec::with_capacity(length);
+        // Like in a MEGAHIT assembly, a linked contig begins with the last kmer of the
+        // previous contig in the chain.
+        if let (Some(k), Some(previous)) = (args.k, contigs.last()) {
+            if i % args.chain_length.get() != 0 {
+                seq.extend_from_slice(&previous.seq[previous.seq.len() - k..]);
+            }
+        }
+        while seq.len() < length {
+            seq.push(rng.base(args.gc));
+        }
+        let header = match (args.samples, args.k) {
+            (Some(samples), _) => format!("S{}C{}", i % samples.get() + 1, i / samples.get() + 1),
+            (None, Some(k)) => format!("k{}_{} flag=1 multi=1.0000 len={}", k, i, length),
+            (None, None) => format!("contig_{}", i + 1),
+        };
+        contigs.push(Contig { header, seq });
+    }
+    Ok(contigs)
+}
+
+fn write_contigs(args: &ContigArgs) -> Result<()> {
+    let contigs = generate_contigs(args)?;
+    let mut out = open_output(args.o.as_deref())?;
+    for contig in contigs.iter() {
+        writeln!(out, ">{}", contig.header)?;
+        for line in contig.seq.chunks(80) {
+            out.write_all(line)?;
+            out.write_all(b"\n")?;
+        }
+    }
+    out.flush()?;
+    info!("Wrote {} contigs", contigs.len());
+    Ok(())
+}
+
+struct ReadPair {
+    // Index of the reference, or None for a pair of random sequence
+    ref_id: Option<usize>,
+    // 0-based leftmost position of the fragment
+    pos: usize,
+    fragment_length: usize,
+    // Whether read 1 is on the reverse strand
+    is_reverse: bool,
+    // As sequenced, i.e. read 2 is reverse complemented relative to read 1
+    seq1: Vec<u8>,
+    seq2: Vec<u8>,
+    qual1: Vec<u8>,
+    qual2: Vec<u8>,
+    mismatches1: usize,
+    mismatches2: usize,
+}
+
+// Replace bases with random other bases with the given probability. Substituted bases
+// get a low quality score. Returns the number of substitutions.
+fn add_errors(rng: &mut Rng, seq: &mut [u8], qual: &mut [u8], error_rate: f64) -> usize {
+    let mut n = 0;
+    for (base, q) in seq.iter_mut().zip(qual.iter_mut()) {
+        if rng.uniform() < error_rate {
+            let options: Vec<u8> = b"ACGT".iter().copied().filter(|b| b != base).collect();
+            *base = options[rng.below(options.len())];
+            *q = b'+';
+            n += 1;
+        }
+    }
+    n
+}
+
+fn simulate_pair(rng: &mut Rng, references: &[(String, Vec<u8>)], args: &ReadArgs) -> ReadPair {
+    let length = args.read_length.get();
+    let fragment_length =
+        ((args.insert_size as f64 + args.insert_sd * rng.normal()).round() as usize).max(length);
+    // Choose the reference with probability proportional to the number of possible
+    // fragment positions on it.
+    let weights: Vec<usize> = references
+        .iter()
+        .map(|(_, s)| (s.len() + 1).saturating_sub(fragment_length))
+        .collect();
+    let total: usize = weights.iter().sum();
+    let is_unmapped = total == 0 || rng.uniform() < args.unmapped_fraction;
+    let (ref_id, pos, fragment) = if is_unmapped {
+        let fragment: Vec<u8> = (0..fragment_length).map(|_| rng.base(0.5)).collect();
+        (None, 0, fragment)
+    } else {
+        let mut x = rng.below(total);
+        let ref_id = weights
+            .iter()
+            .position(|&w| {
+                let is_here = x < w;
+                x = x.saturating_sub(w);
+                is_here
+            })
+            .unwrap();
+        let pos = rng.below(weights[ref_id]);
+        let fragment = references[ref_id].1[pos..pos + fragment_length].to_vec();
+        (Some(ref_id), pos, fragment)
+    };
+    let is_reverse = rng.next_u64() & 1 == 1;
+    let fragment = if is_reverse {
+        reverse_complement(&fragment)
+    } else {
+        fragment
+    };
+    let mut seq1 = fragment[..length].to_vec();
+    let mut seq2 = reverse_complement(&fragment[fragment.len() - length..]);
+    let mut qual1 = vec![b'I'; length];
+    let mut qual2 = vec![b'I'; length];
+    let mismatches1 = add_errors(rng, &mut seq1, &mut qual1, args.error_rate);
+    let mismatches2 = add_errors(rng, &mut seq2, &mut qual2, args.error_rate);
+    ReadPair {
+        ref_id,
+        pos,
+        fragment_length,
+        is_reverse,
+        seq1,
+        seq2,
+        qual1,
+        qual2,
+        mismatches1,
+        mismatches2,
+    }
+}
+
+#[cfg(test)]
+mod test_simulate {
+    use crate::{generate_contigs, reverse_complement, simulate_pair, ContigArgs, ReadArgs, Rng};
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn test_linked_contigs() {
+        let args = ContigArgs {
+            o: None,
+            number: 4,
+            min_length: 50,
+            max_length: 100,
+            gc: 0.5,
+            k: Some(21),
+            chain_length: NonZeroUsize::new(2).unwrap(),
+            samples: None,
+            seed: 1,
+        };
+        let contigs = generate_contigs(&args).unwrap();
+        assert!(contigs[1].header.starts_with("k21_1 "));
+        assert!(contigs[0].seq.ends_with(&contigs[1].seq[..21]));
+        assert!(!contigs[1].seq.ends_with(&contigs[2].seq[..21]));
+        assert!(contigs[2].seq.ends_with(&contigs[3].seq[..21]));
+        // Deterministic given the seed
+        assert_eq!(contigs[3].seq, generate_contigs(&args).unwrap()[3].seq);
+    }
+
+    #[test]
+    fn test_pairs() {
+        let mut rng = Rng(0);
+        let reference: Vec<u8> = (0..1000).map(|_| rng.base(0.5)).collect();
+        let references = [("a".to_owned(), reference.clone())];
+        let args = ReadArgs {
+            reference: Default::default(),
+            first: Default::default(),
+            second: Default::default(),
+            sam: None,
+            pairs: 1,
+            read_length: NonZeroUsize::new(50).unwrap(),
+            insert_size: 200,
+            insert_sd: 20.0,
+            error_rate: 0.0,
+            unmapped_fraction: 0.0,
+            seed: 0,
+        };
+        for _ in 0..20 {
+            let pair = simulate_pair(&mut rng, &references, &args);
+            let fragment = &reference[pair.pos..pair.pos + pair.fragment_length];
+            let (left, right) = if pair.is_reverse {
+                (&pair.seq2, &pair.seq1)
+            } else {
+                (&pair.seq1, &pair.seq2)
+            };
+            assert!(fragment.starts_with(left));
+            assert!(fragment.ends_with(&reverse_complement(right)));
+        }
+    }
+}
+
+fn write_reads(args: &ReadArgs) -> Result<()> {
+    if !(0.0..=1.0).contains(&args.error_rate) || !(0.0..=1.0).contains(&args.unmapped_fraction) {
+        bail!("Error rate and unmapped fraction must be in 0-1");
+    }
+    let references = read_fasta(&args.reference)?;
+    let mut rng = Rng(args.seed);
+    let mut out1 = open_output(Some(&args.first))?;
+    let mut out2 = open_output(Some(&args.second))?;
+    let mut sam = match &args.sam {
+        None => None,
+        Some(p) => {
+            let mut w = open_output(Some(p))?;
+            w.write_all(b"@HD\tVN:1.6\tSO:unsorted\n")?;
+            for (name, seq) in references.iter() {
+                writeln!(w, "@SQ\tSN:{}\tLN:{}", name, seq.len())?;
+            }
+            w.write_all(b"@PG\tID:testdata\tPN:testdata\n")?;
+            Some(w)
+        }
+    };
+    let progress = Progress::new("Simulating", "pairs", Some(args.pairs as u64));
+    for i in 0..args.pairs {
+        progress.inc(1);
+        let pair = simulate_pair(&mut rng, &references, args);
+        let name = format!("r{}", i + 1);
+        for (out, seq, qual) in [
+            (&mut out1, &pair.seq1, &pair.qual1),
+            (&mut out2, &pair.seq2, &pair.qual2),
+        ] {
+            writeln!(out, "@{}", name)?;
+            out.write_all(seq)?;
+            out.write_all(b"\n+\n")?;
+            out.write_all(qual)?;
+            out.write_all(b"\n")?;
+        }
+        if let Some(w) = sam.as_mut() {
+            write_sam_pair(w, &name, &pair, &references)?;
+        }
+    }
+    out1.flush()?;
+    out2.flush()?;
+    if let Some(mut w) = sam {
+        w.flush()?;
+    }
+    progress.finish();
+    Ok(())
+}
+
+// The true alignments of the pair. Reads on the reverse strand are reverse complemented,
+// as in any SAM file.
+fn write_sam_pair(
+    w: &mut dyn Write,
+    name: &str,
+    pair: &ReadPair,
+    references: &[(String, Vec<u8>)],
+) -> Result<()> {
+    let reads = [
+        (&pair.seq1, &pair.qual1, pair.mismatches1, pair.is_reverse),
+        (&pair.seq2, &pair.qual2, pair.mismatches2, !pair.is_reverse),
+    ];
+    let length = pair.seq1.len();
+    for (i, &(seq, qual, mismatches, is_reverse)) in reads.iter().enumerate() {
+        let mate_flag = if i == 0 { 0x40 } else { 0x80 };
+        let Some(ref_id) = pair.ref_id else {
+            // Both reads unmapped
+            write!(
+                w,
+                "{}\t{}\t*\t0\t0\t*\t*\t0\t0\t",
+                name,
+                0x1 | 0x4 | 0x8 | mate_flag
+            )?;
+            w.write_all(seq)?;
+            w.write_all(b"\t")?;
+            w.write_all(qual)?;
+            w.write_all(b"\n")?;
+            continue;
+        };
+        let (mut seq, mut qual) = (seq.clone(), qual.clone());
+        if is_reverse {
+            seq = reverse_complement(&seq);
+            qual.reverse();
+        }
+        let mate_is_reverse = reads[1 - i].3;
+        let flag = 0x1
+            | 0x2
+            | mate_flag
+            | if is_reverse { 0x10 } else { 0 }
+            | if mate_is_reverse { 0x20 } else { 0 };
+        let (pos, mate_pos) = if is_reverse {
+            (pair.pos + pair.fragment_length - length, pair.pos)
+        } else {
+            (pair.pos, pair.pos + pair.fragment_length - length)
+        };
+        let tlen = if is_reverse {
+            -(pair.fragment_length as i64)
+        } else {
+            pair.fragment_length as i64
+        };
+        write!(
+            w,
+            "{}\t{}\t{}\t{}\t60\t{}M\t=\t{}\t{}\t",
+            name,
+            flag,
+            references[ref_id].0,
+            pos + 1,
+            length,
+            mate_pos + 1,
+            tlen
+        )?;
+        w.write_all(&seq)?;
+        w.write_all(b"\t")?;
+        w.write_all(&qual)?;
+        writeln!(w, "\tNM:i:{}", mismatches)?;
+    }
+    Ok(())
+}
+
+fn write_clusters(args: &ClusterArgs) -> Result<()> {
+    if !(0.0..=1.0).contains(&args.unbinned_fraction) {
+        bail!("Unbinned fraction must be in 0-1");
+    }
+    let contigs = read_fasta(&args.contigs)?;
+    let mut rng = Rng(args.seed);
+    let mut out = open_output(args.o.as_deref())?;
+    out.write_all(b"clustername\tcontigname\n")?;
+    let mut n_binned = 0usize;
+    for (name, _) in contigs.iter() {
+        if rng.uniform() < args.unbinned_fraction {
+            continue;
+        }
+        let cluster = rng.below(args.clusters.get()) + 1;
+        writeln!(out, "{}{}\t{}", args.prefix, cluster, name)?;
+        n_binned += 1;
+    }
+    out.flush()?;
+    info!(
+        "Assigned {} of {} contigs to {} clusters",
+        n_binned,
+        contigs.len(),
+        args.clusters
+    );
+    Ok(())
+}
+
+const LONG_ABOUT: &str =
+    "Generate small, deterministic test files for the tools in this repository.
+The output depends only on the arguments and the seed, so tests can generate their input
+files instead of storing them. Run e.g. `testdata contigs -h` for help on each command.
+Usage: testdata contigs -n 20 --samples 3 -o contigs.fna
+       testdata reads -r contigs.fna -n 1000 -1 r1.fq -2 r2.fq --sam truth.sam
+       testdata clusters -c contigs.fna -n 5 -o clusters.tsv";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Random contigs, optionally linked by shared kmers like MEGAHIT contigs
+    Contigs(ContigArgs),
+    /// Paired FASTQ reads sampled from a FASTA file, and their true alignments
+    Reads(ReadArgs),
+    /// A Vamb-style cluster file assigning contigs to random clusters
+    Clusters(ClusterArgs),
+}
+
+#[derive(Args)]
+struct ContigArgs {
+    /// Output FASTA file (gzipped if ending in .gz) [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    /// Number of contigs
+    #[arg(short, long, default_value_t = 10)]
+    number: usize,
+
+    /// Minimum contig length
+    #[arg(long, default_value_t = 1000)]
+    min_length: usize,
+
+    /// Maximum contig length
+    #[arg(long, default_value_t = 5000)]
+    max_length: usize,
+
+    /// GC content
+    #[arg(long, default_value_t = 0.5)]
+    gc: f64,
+
+    /// Link contigs by kmers of this length, as megagfa expects, and name them like MEGAHIT
+    #[arg(short)]
+    k: Option<usize>,
+
+    /// Number of contigs in each chain of linked contigs
+    #[arg(long, default_value = "3")]
+    chain_length: NonZeroUsize,
+
+    /// Name contigs as from this many samples, like S1C1, S2C1, ...
+    #[arg(long)]
+    samples: Option<NonZeroUsize>,
+
+    /// Random seed
+    #[arg(short, long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Args)]
+struct ReadArgs {
+    /// FASTA file to sample reads from (may be gzipped)
+    #[arg(short, long)]
+    reference: PathBuf,
+
+    /// Output FASTQ file of first reads (gzipped if ending in .gz)
+    #[arg(short = '1')]
+    first: PathBuf,
+
+    /// Output FASTQ file of second reads (gzipped if ending in .gz)
+    #[arg(short = '2')]
+    second: PathBuf,
+
+    /// Write the true alignments to this SAM file
+    #[arg(long)]
+    sam: Option<PathBuf>,
+
+    /// Number of read pairs
+    #[arg(short = 'n', long, default_value_t = 1000)]
+    pairs: usize,
+
+    /// Read length
+    #[arg(short = 'l', long, default_value = "150")]
+    read_length: NonZeroUsize,
+
+    /// Mean fragment length
+    #[arg(long, default_value_t = 400)]
+    insert_size: usize,
+
+    /// Standard deviation of fragment length
+    #[arg(long, default_value_t = 40.0)]
+    insert_sd: f64,
+
+    /// Probability of substituting each base
+    #[arg(short, long, default_value_t = 0.0)]
+    error_rate: f64,
+
+    /// Fraction of pairs made from random sequence, which are unmapped
+    #[arg(short, long, default_value_t = 0.0)]
+    unmapped_fraction: f64,
+
+    /// Random seed
+    #[arg(short, long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Args)]
+struct ClusterArgs {
+    /// FASTA file of contigs (may be gzipped)
+    #[arg(short, long)]
+    contigs: PathBuf,
+
+    /// Output cluster file [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    /// Number of clusters
+    #[arg(short = 'n', long, default_value = "5")]
+    clusters: NonZeroUsize,
+
+    /// Prefix of cluster names
+    #[arg(short, long, default_value = "")]
+    prefix: String,
+
+    /// Fraction of contigs not assigned to any cluster
+    #[arg(short, long, default_value_t = 0.0)]
+    unbinned_fraction: f64,
+
+    /// Random seed
+    #[arg(short, long, default_value_t = 0)]
+    seed: u64,
+}
@@ -0,0 +1,296 @@
+use anyhow::{self, bail, Context, Result};
+use clap::Parser;
+use flate2::read::MultiGzDecoder;
+use progress::{info, LogArgs};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{stdout, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+    progress::init(&args.logging)?;
+    let left_key = args.left_key.as_deref().unwrap_or(&args.key);
+    let right_key = args.right_key.as_deref().unwrap_or(&args.key);
+    let right = Table::read(open_input(&args.right)?, right_key, !args.no_header)
+        .with_context(|| format!("Error in file \"{}\"", args.right.display()))?;
+
+    let mut out: Box<dyn Write> = match &args.o {
+        None => Box::new(BufWriter::new(stdout().lock())),
+        Some(p) => Box::new(BufWriter::new(File::create(p).with_context(|| {
+            format!("Could not create output file \"{}\"", p.display())
+        })?)),
+    };
+    let (n_rows, n_joined) = join(open_input(&args.left)?, left_key, &right, &args, &mut out)
+        .with_context(|| format!("Error in file \"{}\"", args.left.display()))?;
+    out.flush()?;
+    info!("Matched {} of {} rows", n_joined, n_rows);
+    Ok(())
+}
+
+// Resolve a column given by the user as a 1-based index or a header name
+// to a 0-based index.
+fn resolve_column(column: &str, header: Option<&[&str]>) -> Result<usize> {
+    if let Ok(n) = column.parse::<usize>() {
+        if n == 0 {
+            bail!("Column indices are 1-based, got 0");
+        }
+        return Ok(n - 1);
+    }
+    let Some(header) = header else {
+        bail!(
+            "Column \"{}\" given by name, but tables have no header",
+            column
+        );
+    };
+    header
+        .iter()
+        .position(|&h| h == column)
+        .with_context(|| format!("Column \"{}\" not found in header", column))
+}
+
+// The number of columns and the key column of a table, taken from the header
+// or otherwise from the first row.
+#[derive(Default)]
+struct Layout {
+    n_columns: Option<usize>,
+    key_index: Option<usize>,
+}
+
+impl Layout {
+    fn set_header(&mut self, fields: &[&str], key: &str) -> Result<usize> {
+        let k = resolve_column(key, Some(fields))?;
+        self.n_columns = Some(fields.len());
+        self.key_index = Some(k);
+        Ok(k)
+    }
+
+    // Check the row has the right number of columns, and return the key column
+    fn check(&mut self, fields: &[&str], key: &str, lineno: usize) -> Result<usize> {
+        let n = *self.n_columns.get_or_insert(fields.len());
+        let k = match self.key_index {
+            Some(k) => k,
+            None => *self.key_index.insert(resolve_column(key, None)?),
+        };
+        if fields.len() != n {
+            bail!(
+                "Line {} has {} columns, expected {}",
+                lineno + 1,
+                fields.len(),
+                n
+            );
+        }
+        if k >= n {
+            bail!("Key column {} is beyond the {} columns", k + 1, n);
+        }
+        Ok(k)
+    }
+}
+
+// The right table, which is kept in memory. The rows are stored without the key column,
+// as the remaining fields joined by tabs, so they can be written directly.
+struct Table {
+    header: Option<String>,
+    n_columns: usize,
+    rows: HashMap<String, Vec<String>>,
+}
+
+impl Table {
+    fn read<R: BufRead>(reader: R, key: &str, has_header: bool) -> Result<Self> {
+        let mut lines = reader.lines().enumerate();
+        let mut rows: HashMap<String, Vec<String>> = HashMap::new();
+        let mut header: Option<String> = None;
+        let mut layout = Layout::default();
+        if has_header {
+            let Some((_, line)) = lines.next() else {
+                bail!("File is empty, but expected a header");
+            };
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            let k = layout.set_header(&fields, key)?;
+            header = Some(rest_fields(&fields, k));
+        }
+        for (lineno, line) in lines {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let k = layout.check(&fields, key, lineno)?;
+            rows.entry(fields[k].to_owned())
+                .or_default()
+                .push(rest_fields(&fields, k));
+        }
+        Ok(Self {
+            header,
+            n_columns: layout.n_columns.unwrap_or(1),
+            rows,
+        })
+    }
+}
+
+// All fields except the key, each preceded by a tab
+fn rest_fields(fields: &[&str], key: usize) -> String {
+    let mut s = String::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i != key {
+            s.push('\t');
+            s.push_str(field);
+        }
+    }
+    s
+}
+
+// Stream the left table, writing each row once per matching row in the right table.
+// Returns the number of rows, and the number of rows with at least one match.
+fn join<R: BufRead, W: Write>(
+    reader: R,
+    key: &str,
+    right: &Table,
+    args: &Cli,
+    out: &mut W,
+) -> Result<(usize, usize)> {
+    let mut lines = reader.lines().enumerate();
+    let mut layout = Layout::default();
+    if !args.no_header {
+        let Some((_, line)) = lines.next() else {
+            bail!("File is empty, but expected a header");
+        };
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        layout.set_header(&fields, key)?;
+        writeln!(out, "{}{}", line, right.header.as_deref().unwrap_or(""))?;
+    }
+    let fill = format!("\t{}", args.fill).repeat(right.n_columns - 1);
+    let (mut n_rows, mut n_joined) = (0usize, 0usize);
+    for (lineno, line) in lines {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let k = layout.check(&fields, key, lineno)?;
+        n_rows += 1;
+        match right.rows.get(fields[k]) {
+            Some(matches) => {
+                n_joined += 1;
+                for rest in matches {
+                    writeln!(out, "{}{}", line, rest)?;
+                }
+            }
+            None => {
+                if args.left_join {
+                    writeln!(out, "{}{}", line, fill)?;
+                }
+            }
+        }
+    }
+    Ok((n_rows, n_joined))
+}
+
+#[cfg(test)]
+mod test_join {
+    use crate::{join, Cli, Table};
+    use clap::Parser;
+
+    fn run(args: &[&str], left: &str, right: &str) -> String {
+        let args = Cli::parse_from([&["tsvjoin", "left", "right"], args].concat());
+        let key = args.left_key.as_deref().unwrap_or(&args.key);
+        let right_key = args.right_key.as_deref().unwrap_or(&args.key);
+        let table = Table::read(right.as_bytes(), right_key, !args.no_header).unwrap();
+        let mut out = Vec::new();
+        join(left.as_bytes(), key, &table, &args, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_join() {
+        let depths = "contig\tdepth\nc1\t5.0\nc2\t3.5\nc3\t1.0\n";
+        let clusters = "bin\tcontig\nb1\tc1\nb2\tc3\nb3\tc3\n";
+        assert_eq!(
+            run(&["-k", "contig"], depths, clusters),
+            "contig\tdepth\tbin\nc1\t5.0\tb1\nc3\t1.0\tb2\nc3\t1.0\tb3\n"
+        );
+        assert_eq!(
+            run(
+                &["-k", "contig", "--left-join", "--fill", "-"],
+                depths,
+                clusters
+            ),
+            "contig\tdepth\tbin\nc1\t5.0\tb1\nc2\t3.5\t-\nc3\t1.0\tb2\nc3\t1.0\tb3\n"
+        );
+        assert_eq!(
+            run(&["--no-header", "--right-key", "2"], "c2\tx\n", "b1\tc2\n"),
+            "c2\tx\tb1\n"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Table::read("a\tb\nx\n".as_bytes(), "1", true).is_err());
+        assert!(Table::read("a\tb\n".as_bytes(), "c", true).is_err());
+        assert!(Table::read("x\ty\n".as_bytes(), "a", false).is_err());
+        assert!(Table::read("x\ty\n".as_bytes(), "3", false).is_err());
+    }
+}
+
+// Read from a file, or from a gzipped file if the name ends with .gz
+fn open_input(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file =
+        File::open(path).with_context(|| format!("Could not open file \"{}\"", path.display()))?;
+    if path.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+const LONG_ABOUT: &str = "Join two TSV files on a key column, e.g. a contig or bin name.
+The right table is read into memory, and the left table is streamed, so neither needs to be
+sorted, and the order of the left table is kept. A left row is written once for each right row
+with the same key. The key column of the right table is not repeated in the output.
+Columns can be given by 1-based index or by header name. Input files may be gzipped.
+Usage: tsvjoin -k contig depths.tsv clusters.tsv > joined.tsv";
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = LONG_ABOUT)]
+struct Cli {
+    /// Left table, which is streamed
+    left: PathBuf,
+
+    /// Right table, which is kept in memory
+    right: PathBuf,
+
+    /// Output file [stdout]
+    #[arg(short)]
+    o: Option<PathBuf>,
+
+    /// Key column in both tables
+    #[arg(short, long, default_value = "1")]
+    key: String,
+
+    /// Key column in the left table [--key]
+    #[arg(long)]
+    left_key: Option<String>,
+
+    /// Key column in the right table [--key]
+    #[arg(long)]
+    right_key: Option<String>,
+
+    /// Also write left rows with no match, filling the right columns (left join)
+    #[arg(short, long)]
+    left_join: bool,
+
+    /// Value for missing fields in a left join
+    #[arg(long, default_value = "NA")]
+    fill: String,
+
+    /// Tables have no header line
+    #[arg(long)]
+    no_header: bool,
+
+    #[command(flatten)]
+    logging: LogArgs,
+}